@@ -0,0 +1,17 @@
+-- SQLite cannot add columns defaulting to CURRENT_TIMESTAMP, so rebuild the table
+CREATE TABLE IF NOT EXISTS todos_new (
+  id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+  title VARCHAR(20) NOT NULL,
+  description VARCHAR(200) NOT NULL,
+  completed BOOLEAN NOT NULL DEFAULT FALSE,
+  due_at DATETIME,
+  created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  updated_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+INSERT INTO todos_new (id, title, description)
+SELECT id, title, description FROM todos;
+
+DROP TABLE todos;
+
+ALTER TABLE todos_new RENAME TO todos;
@@ -0,0 +1,2 @@
+-- Incremented on every change to a todo, and exposed as its entity tag
+ALTER TABLE todos ADD COLUMN version INTEGER NOT NULL DEFAULT 1;
@@ -0,0 +1,4 @@
+-- Deleted todos are kept in the trash until restored or purged
+ALTER TABLE todos ADD COLUMN deleted_at DATETIME;
+
+CREATE INDEX IF NOT EXISTS todos_deleted_at ON todos (deleted_at);
@@ -0,0 +1,14 @@
+-- Snapshots of todos before and after every change. Events are not tied to
+-- the todos table so that they outlive purged todos.
+CREATE TABLE IF NOT EXISTS todo_events (
+  id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+  todo_id INTEGER NOT NULL,
+  user_id INTEGER REFERENCES users (id) ON DELETE SET NULL,
+  kind TEXT NOT NULL,
+  version INTEGER NOT NULL,
+  before TEXT,
+  after TEXT,
+  created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS todo_events_todo_id ON todo_events (todo_id, version);
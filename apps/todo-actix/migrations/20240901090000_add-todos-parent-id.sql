@@ -0,0 +1,5 @@
+-- Todos can be broken down into subtasks, which lose their parent once it is
+-- purged from the trash
+ALTER TABLE todos ADD COLUMN parent_id INTEGER REFERENCES todos (id) ON DELETE SET NULL;
+
+CREATE INDEX IF NOT EXISTS todos_parent_id ON todos (parent_id);
@@ -0,0 +1,3 @@
+-- Recurring todos carry the iCalendar RRULE of their schedule, which moves on to
+-- the next occurrence once they are completed
+ALTER TABLE todos ADD COLUMN recurrence TEXT;
@@ -0,0 +1,13 @@
+-- Reminders fire offset_seconds before their todo falls due. fired_for keeps
+-- the due date a reminder last fired for, so that it fires once per due date
+-- and is armed again when the todo is rescheduled.
+CREATE TABLE IF NOT EXISTS reminders (
+  id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+  todo_id INTEGER NOT NULL REFERENCES todos (id) ON DELETE CASCADE,
+  offset_seconds INTEGER NOT NULL,
+  fired_at DATETIME,
+  fired_for DATETIME,
+  created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS reminders_todo_id ON reminders (todo_id);
@@ -0,0 +1,28 @@
+-- Fired reminders are queued for webhooks along with the events, in the same
+-- transaction as they are marked fired. A delivery holds either an event or a
+-- snapshot of the reminder and its todo, as SQLite cannot drop the NOT NULL of
+-- event_id without rebuilding the table.
+CREATE TABLE webhook_deliveries_new (
+  id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+  webhook_id INTEGER NOT NULL REFERENCES webhooks (id) ON DELETE CASCADE,
+  event_id INTEGER REFERENCES todo_events (id) ON DELETE CASCADE,
+  reminder TEXT,
+  attempts INTEGER NOT NULL DEFAULT 0,
+  next_attempt_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  last_error TEXT,
+  failed_at DATETIME,
+  CHECK ((event_id IS NULL) <> (reminder IS NULL))
+);
+
+INSERT INTO webhook_deliveries_new
+  (id, webhook_id, event_id, attempts, next_attempt_at, last_error, failed_at)
+SELECT id, webhook_id, event_id, attempts, next_attempt_at, last_error, failed_at
+FROM webhook_deliveries;
+
+DROP TABLE webhook_deliveries;
+ALTER TABLE webhook_deliveries_new RENAME TO webhook_deliveries;
+
+CREATE INDEX IF NOT EXISTS webhook_deliveries_due ON webhook_deliveries (next_attempt_at)
+WHERE failed_at IS NULL;
+
+CREATE INDEX IF NOT EXISTS webhook_deliveries_webhook_id ON webhook_deliveries (webhook_id);
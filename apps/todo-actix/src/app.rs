@@ -1,18 +1,92 @@
-use crate::routes;
-use actix_web::web::{Data, ServiceConfig};
-use sqlx::SqlitePool;
+use crate::{
+    config::Config, error::ApiError, feed::ChangeFeed, metrics::Metrics,
+    repository::TodoRepository, routes,
+};
+use actix_web::{
+    error::JsonPayloadError,
+    web::{self, Data, JsonConfig, PathConfig, QueryConfig, ServiceConfig},
+};
+use std::sync::Arc;
 
 pub struct AppData {
-    pub db_pool: SqlitePool,
+    pub repository: Arc<dyn TodoRepository>,
+    pub config: Config,
+    pub metrics: Arc<Metrics>,
+    pub feed: Arc<ChangeFeed>,
 }
 
-pub fn configure_app(config: &mut ServiceConfig, db_pool: SqlitePool) {
-    let app_data = Data::new(AppData { db_pool });
+pub fn configure_app(
+    config: &mut ServiceConfig,
+    repository: Arc<dyn TodoRepository>,
+    app_config: Config,
+    metrics: Arc<Metrics>,
+    feed: Arc<ChangeFeed>,
+) {
+    let json_config = JsonConfig::default()
+        .limit(app_config.body_limit)
+        .error_handler(|err, _| match err {
+            JsonPayloadError::Overflow { limit }
+            | JsonPayloadError::OverflowKnownLength { limit, .. } => {
+                ApiError::PayloadTooLarge(limit).into()
+            }
+            err => ApiError::BadRequest(err.to_string()).into(),
+        });
+    let app_data = Data::new(AppData {
+        repository,
+        config: app_config,
+        metrics,
+        feed,
+    });
+    let path_config = PathConfig::default().error_handler(|_, _| ApiError::NotFound.into());
+    let query_config =
+        QueryConfig::default().error_handler(|err, _| ApiError::BadRequest(err.to_string()).into());
     config
         .app_data(app_data)
+        .app_data(json_config)
+        .app_data(path_config)
+        .app_data(query_config)
+        .service(routes::healthz)
+        .service(routes::readyz)
+        .service(routes::metrics)
+        .service(routes::register)
+        .service(routes::login)
         .service(routes::list_todos)
+        .service(routes::search_todos)
+        .service(routes::todo_events)
+        .service(routes::todo_events_ws)
+        .service(routes::batch_todos)
+        .service(routes::export_todos)
+        .service(routes::import_todos)
         .service(routes::get_todo)
         .service(routes::create_todo)
         .service(routes::update_todo)
-        .service(routes::delete_todo);
+        .service(routes::patch_todo)
+        .service(routes::delete_todo)
+        .service(routes::list_trash)
+        .service(routes::restore_todo)
+        .service(routes::list_history)
+        .service(routes::revert_todo)
+        .service(routes::list_children)
+        .service(routes::list_occurrences)
+        .service(routes::list_reminders)
+        .service(routes::create_reminder)
+        .service(routes::delete_reminder)
+        .service(routes::list_todo_tags)
+        .service(routes::attach_tag)
+        .service(routes::detach_tag)
+        .service(routes::list_tags)
+        .service(routes::get_tag)
+        .service(routes::create_tag)
+        .service(routes::update_tag)
+        .service(routes::delete_tag)
+        .service(routes::list_webhooks)
+        .service(routes::get_webhook)
+        .service(routes::create_webhook)
+        .service(routes::delete_webhook)
+        .service(routes::list_dead_letters)
+        .service(routes::retry_dead_letter)
+        .service(routes::openapi)
+        .service(routes::docs)
+        .service(routes::docs_asset)
+        .default_service(web::to(routes::not_found));
 }
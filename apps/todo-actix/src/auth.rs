@@ -0,0 +1,154 @@
+use crate::{app::AppData, error::ApiError};
+use actix_web::{dev::Payload, http::header::AUTHORIZATION, web::Data, FromRequest, HttpRequest};
+use argon2::{
+    password_hash::{
+        rand_core::{OsRng, RngCore},
+        SaltString,
+    },
+    Argon2, PasswordHash, PasswordHasher, PasswordVerifier,
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::future::{ready, Ready};
+
+pub type HmacSha256 = Hmac<Sha256>;
+
+pub fn random_secret() -> String {
+    let mut secret = [0; 32];
+    OsRng.fill_bytes(&mut secret);
+    URL_SAFE_NO_PAD.encode(secret)
+}
+
+pub fn hash_password(password: &str) -> Result<String, argon2::password_hash::Error> {
+    let salt = SaltString::generate(&mut OsRng);
+    let hash = Argon2::default().hash_password(password.as_bytes(), &salt)?;
+    Ok(hash.to_string())
+}
+
+/// Verifies a password against its stored hash. Unknown users are given no
+/// hash, in which case a hash is still computed so that the response time does
+/// not reveal which usernames exist.
+pub fn verify_password(password: &str, hash: Option<&str>) -> bool {
+    let Some(hash) = hash else {
+        let _ = hash_password(password);
+        return false;
+    };
+    PasswordHash::new(hash)
+        .map(|hash| {
+            Argon2::default()
+                .verify_password(password.as_bytes(), &hash)
+                .is_ok()
+        })
+        .unwrap_or(false)
+}
+
+#[derive(Debug, PartialEq, Eq, Deserialize, Serialize)]
+struct Claims {
+    sub: i64,
+    exp: i64,
+}
+
+/// Signs a bearer token as `base64url(claims).base64url(HMAC-SHA256)`.
+pub fn sign_token(secret: &str, user_id: i64, ttl: i64) -> String {
+    let claims = Claims {
+        sub: user_id,
+        exp: Utc::now().timestamp() + ttl,
+    };
+    let claims = URL_SAFE_NO_PAD.encode(serde_json::to_vec(&claims).unwrap_or_default());
+    let mac = hmac_sha256(secret, &[claims.as_bytes()]);
+    let signature = URL_SAFE_NO_PAD.encode(mac.finalize().into_bytes());
+    format!("{claims}.{signature}")
+}
+
+/// Returns the user a token was issued to, if it is authentic and not expired.
+pub fn verify_token(secret: &str, token: &str) -> Option<i64> {
+    let (claims, signature) = token.split_once('.')?;
+    let signature = URL_SAFE_NO_PAD.decode(signature).ok()?;
+    hmac_sha256(secret, &[claims.as_bytes()])
+        .verify_slice(&signature)
+        .ok()?;
+
+    let claims = URL_SAFE_NO_PAD.decode(claims).ok()?;
+    let claims: Claims = serde_json::from_slice(&claims).ok()?;
+    (claims.exp > Utc::now().timestamp()).then_some(claims.sub)
+}
+
+/// HMAC-SHA256 of the parts put together, keyed with a secret, left to be
+/// finalized or verified.
+pub fn hmac_sha256(secret: &str, parts: &[&[u8]]) -> HmacSha256 {
+    // HMAC accepts keys of any length
+    let mut mac = HmacSha256::new_from_slice(secret.as_bytes())
+        .unwrap_or_else(|_| unreachable!("HMAC key of any length"));
+    for part in parts {
+        mac.update(part);
+    }
+    mac
+}
+
+/// The user authenticated by the request's `Authorization: Bearer` token.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AuthUser {
+    pub id: i64,
+}
+
+impl FromRequest for AuthUser {
+    type Error = ApiError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(request: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let user = request
+            .app_data::<Data<AppData>>()
+            .zip(request.headers().get(AUTHORIZATION))
+            .and_then(|(app_data, header)| {
+                let token = header.to_str().ok()?.strip_prefix("Bearer ")?;
+                verify_token(&app_data.config.token_secret, token.trim())
+            })
+            .map(|id| Self { id })
+            .ok_or(ApiError::Unauthorized);
+        ready(user)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::auth::{hash_password, sign_token, verify_password, verify_token};
+
+    #[test]
+    fn password_roundtrip() {
+        let hash = hash_password("password").unwrap();
+        assert_ne!(hash, hash_password("password").unwrap());
+        assert!(verify_password("password", Some(&hash)));
+        assert!(!verify_password("wrong password", Some(&hash)));
+        assert!(!verify_password("password", Some("not a hash")));
+        assert!(!verify_password("password", None));
+    }
+
+    #[test]
+    fn token_roundtrip() {
+        let token = sign_token("secret", 42, 60);
+        assert_eq!(verify_token("secret", &token), Some(42));
+        assert_eq!(verify_token("other secret", &token), None);
+    }
+
+    #[test]
+    fn token_expired() {
+        let token = sign_token("secret", 42, -1);
+        assert_eq!(verify_token("secret", &token), None);
+    }
+
+    #[test]
+    fn token_tampered() {
+        let token = sign_token("secret", 42, 60);
+        let (_, signature) = token.split_once('.').unwrap();
+        let forged = sign_token("secret", 1, 60);
+        let (claims, _) = forged.split_once('.').unwrap();
+        assert_eq!(
+            verify_token("secret", &format!("{claims}.{signature}")),
+            None
+        );
+        assert_eq!(verify_token("secret", "garbage"), None);
+    }
+}
@@ -0,0 +1,135 @@
+use crate::{
+    error::{ApiError, FieldError, InternalError, Problem},
+    etag::IfMatch,
+    history::EventKind,
+    recurrence::Updated,
+    tag::TaggedTodo,
+    todo::{CreateTodo, UpdateTodo},
+    validate::Validate,
+};
+use actix_web::{http::StatusCode, ResponseError};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+pub const MAX_OPERATIONS: usize = 1000;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum BatchMode {
+    /// Either every operation is applied or none is.
+    #[default]
+    Atomic,
+    /// Operations that fail are skipped while the others are applied.
+    BestEffort,
+}
+
+#[derive(Debug, PartialEq, Eq, Deserialize, Serialize, ToSchema)]
+pub struct Batch {
+    #[serde(default)]
+    pub mode: BatchMode,
+    pub operations: Vec<Operation>,
+}
+
+/// A single change of a batch. Updates and deletions may require the todo to
+/// be at a given `version`, like an `If-Match` header would.
+#[derive(Debug, PartialEq, Eq, Deserialize, Serialize, ToSchema)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum Operation {
+    Create {
+        todo: CreateTodo,
+    },
+    Update {
+        id: i64,
+        todo: UpdateTodo,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        version: Option<i64>,
+    },
+    Delete {
+        id: i64,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        version: Option<i64>,
+    },
+}
+
+impl Operation {
+    pub fn kind(&self) -> EventKind {
+        match self {
+            Self::Create { .. } => EventKind::Create,
+            Self::Update { .. } => EventKind::Update,
+            Self::Delete { .. } => EventKind::Delete,
+        }
+    }
+
+    pub fn if_match(version: Option<i64>) -> IfMatch {
+        match version {
+            Some(version) => IfMatch::Versions(vec![version]),
+            None => IfMatch::Any,
+        }
+    }
+}
+
+impl Validate for Operation {
+    fn validate(self) -> Result<Self, Vec<FieldError>> {
+        let operation = match self {
+            Self::Create { todo } => Self::Create {
+                todo: todo.validate()?,
+            },
+            Self::Update { id, todo, version } => Self::Update {
+                id,
+                todo: todo.validate()?,
+                version,
+            },
+            Self::Delete { .. } => self,
+        };
+        Ok(operation)
+    }
+}
+
+/// What came out of running a batch. Atomic batches stop at the first failing
+/// operation, leaving the following ones without a result.
+#[derive(Debug)]
+pub struct Outcome {
+    pub committed: bool,
+    pub results: Vec<Result<Updated, InternalError>>,
+}
+
+#[derive(Debug, PartialEq, Eq, Deserialize, Serialize, ToSchema)]
+pub struct BatchResult {
+    /// Whether the changes of the batch were saved at all.
+    pub committed: bool,
+    /// One result per operation, in the order they were given.
+    pub results: Vec<OperationResult>,
+}
+
+#[derive(Debug, PartialEq, Eq, Deserialize, Serialize, ToSchema)]
+pub struct OperationResult {
+    pub status: u16,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub todo: Option<TaggedTodo>,
+    /// The todo of the next occurrence, when an update completed a recurring
+    /// todo.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next_occurrence: Option<TaggedTodo>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<Problem>,
+}
+
+impl OperationResult {
+    pub fn ok(todo: Option<TaggedTodo>, next_occurrence: Option<TaggedTodo>) -> Self {
+        Self {
+            status: StatusCode::OK.as_u16(),
+            todo,
+            next_occurrence,
+            error: None,
+        }
+    }
+
+    pub fn error(err: &ApiError) -> Self {
+        Self {
+            status: err.status_code().as_u16(),
+            todo: None,
+            next_occurrence: None,
+            error: Some(err.problem()),
+        }
+    }
+}
@@ -0,0 +1,17 @@
+use std::{env, process::ExitCode};
+use todo_actix::{cli, run_cli};
+
+#[actix_web::main]
+async fn main() -> ExitCode {
+    let flags = cli().get_matches();
+    match run_cli(&flags, |key| env::var(key).ok()).await {
+        Ok(output) => {
+            print!("{output}");
+            ExitCode::SUCCESS
+        }
+        Err(err) => {
+            eprintln!("Error: {err}");
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,513 @@
+use crate::{
+    client::TodoClient,
+    config::{with_settings, Layers, Setting},
+    error::{ClientError, InternalError},
+    query::ListQuery,
+    tag::TaggedTodo,
+    todo::{utc, CreateTodo},
+};
+use chrono::NaiveDateTime;
+use clap::{value_parser, Arg, ArgAction, ArgGroup, ArgMatches, Command};
+use serde_json::{Map, Value};
+use std::{fmt::Write, path::Path, str::FromStr};
+
+const SERVER: &str = "http://127.0.0.1:8080";
+
+const SETTINGS: &[Setting] = &[
+    Setting {
+        key: "server",
+        env: "TODO_SERVER",
+        help: "URL of the TODO API",
+        secret: false,
+    },
+    Setting {
+        key: "token",
+        env: "TODO_TOKEN",
+        help: "Bearer token, as returned by POST /login",
+        secret: false,
+    },
+    Setting {
+        key: "output",
+        env: "TODO_OUTPUT",
+        help: "Output format (table, json)",
+        secret: false,
+    },
+];
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+enum Output {
+    #[default]
+    Table,
+    Json,
+}
+
+impl FromStr for Output {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "table" => Ok(Self::Table),
+            "json" => Ok(Self::Json),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Settings of the client, read like the ones of the server.
+struct Settings {
+    server: String,
+    token: Option<String>,
+    output: Output,
+}
+
+impl Settings {
+    /// Reads the settings from the CLI flags, or else the environment, or
+    /// else the file given with `--config`, which defaults to
+    /// `~/.config/todo/config.toml` when it exists.
+    fn read(
+        flags: &ArgMatches,
+        env: &dyn Fn(&str) -> Option<String>,
+    ) -> Result<Self, InternalError> {
+        let mut layers = Layers::new(SETTINGS, flags, env);
+        let path = flags.get_one::<String>("config").cloned();
+        if let Some(path) = path.or_else(|| default_config(env)) {
+            layers.read_file(&path)?;
+        }
+        let settings = Self {
+            server: layers.get("server", SERVER.to_string()),
+            token: layers.optional("token"),
+            output: layers.get("output", Output::default()),
+        };
+        layers.finish()?;
+        Ok(settings)
+    }
+}
+
+/// `$XDG_CONFIG_HOME/todo/config.toml`, or else `$HOME/.config/todo/config.toml`,
+/// when the file exists.
+fn default_config(env: &dyn Fn(&str) -> Option<String>) -> Option<String> {
+    let dir = env("XDG_CONFIG_HOME").or_else(|| Some(format!("{}/.config", env("HOME")?)))?;
+    let path = format!("{dir}/todo/config.toml");
+    Path::new(&path).is_file().then_some(path)
+}
+
+/// Command line of the client, taking the settings before or after the
+/// subcommand.
+pub fn cli() -> Command {
+    let id = || {
+        Arg::new("id")
+            .value_name("ID")
+            .required(true)
+            .value_parser(value_parser!(i64))
+            .help("Id of the todo")
+    };
+    let command = Command::new("todo")
+        .about("Manages todos through the TODO API")
+        .subcommand_required(true)
+        .subcommand(
+            Command::new("list")
+                .about("Lists todos")
+                .arg(
+                    Arg::new("completed")
+                        .long("completed")
+                        .value_name("BOOL")
+                        .value_parser(value_parser!(bool))
+                        .help("Only lists completed todos, or uncompleted ones"),
+                )
+                .arg(
+                    Arg::new("tag")
+                        .long("tag")
+                        .value_name("NAME")
+                        .action(ArgAction::Append)
+                        .help("Only lists todos with the tag, repeated for several"),
+                )
+                .arg(
+                    Arg::new("sort")
+                        .long("sort")
+                        .value_name("FIELDS")
+                        .help("Comma-separated fields to sort by, prefixed with - for descending"),
+                )
+                .arg(
+                    Arg::new("limit")
+                        .long("limit")
+                        .value_name("N")
+                        .value_parser(value_parser!(i64))
+                        .help("Maximum number of todos listed"),
+                ),
+        )
+        .subcommand(Command::new("get").about("Shows a todo").arg(id()))
+        .subcommand(
+            Command::new("add")
+                .about("Creates a todo")
+                .arg(
+                    Arg::new("title")
+                        .value_name("TITLE")
+                        .required(true)
+                        .help("Title of the todo"),
+                )
+                .args(field_args()),
+        )
+        .subcommand(
+            Command::new("edit")
+                .about("Changes fields of a todo, an empty value clearing optional ones")
+                .arg(id())
+                .arg(
+                    Arg::new("title")
+                        .long("title")
+                        .value_name("TITLE")
+                        .help("Title of the todo"),
+                )
+                .args(field_args())
+                .arg(
+                    Arg::new("completed")
+                        .long("completed")
+                        .value_name("BOOL")
+                        .value_parser(value_parser!(bool))
+                        .help("Whether the todo is completed"),
+                )
+                .group(
+                    ArgGroup::new("fields")
+                        .args([
+                            "title",
+                            "description",
+                            "due",
+                            "parent",
+                            "recurrence",
+                            "completed",
+                        ])
+                        .multiple(true)
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            Command::new("done")
+                .about("Marks a todo as completed")
+                .arg(id()),
+        )
+        .subcommand(
+            Command::new("rm")
+                .about("Moves a todo to the trash")
+                .arg(id()),
+        );
+    with_settings(command, SETTINGS)
+}
+
+/// Flags of the optional fields of a todo.
+fn field_args() -> [Arg; 4] {
+    [
+        Arg::new("description")
+            .long("description")
+            .value_name("TEXT")
+            .help("Description of the todo"),
+        Arg::new("due")
+            .long("due")
+            .value_name("TIME")
+            .value_parser(|value: &str| optional(value, utc::parse))
+            .help("RFC 3339 time the todo is due at"),
+        Arg::new("parent")
+            .long("parent")
+            .value_name("ID")
+            .value_parser(|value: &str| optional(value, |id| id.parse::<i64>()))
+            .help("Id of the todo this one is a subtask of"),
+        Arg::new("recurrence")
+            .long("recurrence")
+            .value_name("RRULE")
+            .help("iCalendar RRULE the todo recurs on, such as FREQ=WEEKLY;BYDAY=MO"),
+    ]
+}
+
+/// Parses a flag value, an empty one standing for none.
+fn optional<T, E>(value: &str, parse: impl Fn(&str) -> Result<T, E>) -> Result<Option<T>, E> {
+    match value {
+        "" => Ok(None),
+        value => parse(value).map(Some),
+    }
+}
+
+/// Runs a subcommand, returning what to print.
+pub async fn run_cli(
+    flags: &ArgMatches,
+    env: impl Fn(&str) -> Option<String>,
+) -> Result<String, ClientError> {
+    let settings = Settings::read(flags, &env)?;
+    let client = TodoClient::new(&settings.server, settings.token);
+    let todo = match flags.subcommand() {
+        Some(("list", args)) => {
+            let query = ListQuery {
+                completed: args.get_one("completed").copied(),
+                tags: args.get_many("tag").unwrap_or_default().cloned().collect(),
+                sort: args.get_one("sort").cloned(),
+                limit: args.get_one("limit").copied(),
+                ..ListQuery::default()
+            };
+            let todos = client.list_todos(&query).await?;
+            return render_list(settings.output, &todos);
+        }
+        Some(("get", args)) => client.get_todo(id(args)).await?,
+        Some(("add", args)) => {
+            let todo = CreateTodo {
+                title: args.get_one("title").cloned().unwrap_or_default(),
+                description: args.get_one("description").cloned().unwrap_or_default(),
+                completed: false,
+                due_at: args
+                    .get_one::<Option<NaiveDateTime>>("due")
+                    .copied()
+                    .flatten(),
+                parent_id: args.get_one::<Option<i64>>("parent").copied().flatten(),
+                recurrence: args.get_one::<String>("recurrence").cloned(),
+            };
+            client.create_todo(&todo).await?
+        }
+        Some(("edit", args)) => client.patch_todo(id(args), &patch(args)).await?,
+        Some(("done", args)) => {
+            let patch = serde_json::json!({ "completed": true });
+            client.patch_todo(id(args), &patch).await?
+        }
+        Some(("rm", args)) => client.delete_todo(id(args)).await?,
+        _ => unreachable!("subcommand required"),
+    };
+    render_todo(settings.output, &todo)
+}
+
+fn id(args: &ArgMatches) -> i64 {
+    args.get_one("id").copied().unwrap_or_default()
+}
+
+/// JSON Merge Patch of the fields given to `edit`.
+fn patch(args: &ArgMatches) -> Value {
+    let mut patch = Map::new();
+    for key in ["title", "description"] {
+        if let Some(value) = args.get_one::<String>(key) {
+            patch.insert(key.to_string(), value.clone().into());
+        }
+    }
+    if let Some(completed) = args.get_one::<bool>("completed") {
+        patch.insert("completed".to_string(), (*completed).into());
+    }
+    if let Some(due_at) = args.get_one::<Option<NaiveDateTime>>("due") {
+        let due_at = due_at.as_ref().map(utc::format);
+        patch.insert("due_at".to_string(), due_at.into());
+    }
+    if let Some(parent_id) = args.get_one::<Option<i64>>("parent") {
+        patch.insert("parent_id".to_string(), (*parent_id).into());
+    }
+    if let Some(recurrence) = args.get_one::<String>("recurrence") {
+        let recurrence = Some(recurrence).filter(|rule| !rule.is_empty());
+        patch.insert("recurrence".to_string(), recurrence.cloned().into());
+    }
+    Value::Object(patch)
+}
+
+/// Renders todos as a table with a row per todo, or as a JSON array.
+fn render_list(output: Output, todos: &[TaggedTodo]) -> Result<String, ClientError> {
+    if output == Output::Json {
+        return Ok(serde_json::to_string_pretty(todos)? + "\n");
+    }
+    let rows = todos.iter().map(|tagged| {
+        let todo = &tagged.todo;
+        [
+            todo.id.to_string(),
+            if todo.completed { "x" } else { "" }.to_string(),
+            todo.due_at.as_ref().map(utc::format).unwrap_or_default(),
+            todo.title.clone(),
+            tag_names(tagged),
+        ]
+    });
+    let header = ["ID", "DONE", "DUE", "TITLE", "TAGS"].map(str::to_string);
+    let rows: Vec<_> = [header].into_iter().chain(rows).collect();
+    let mut widths = [0; 5];
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    let mut table = String::new();
+    for row in &rows {
+        let mut line = String::new();
+        for (width, cell) in widths.iter().zip(row) {
+            let _ = write!(line, "{cell:width$}  ");
+        }
+        table.push_str(line.trim_end());
+        table.push('\n');
+    }
+    Ok(table)
+}
+
+/// Renders a todo as a line per field, or as a JSON object.
+fn render_todo(output: Output, tagged: &TaggedTodo) -> Result<String, ClientError> {
+    if output == Output::Json {
+        return Ok(serde_json::to_string_pretty(tagged)? + "\n");
+    }
+    let todo = &tagged.todo;
+    let or_none = |value: Option<String>| {
+        value
+            .filter(|value| !value.is_empty())
+            .unwrap_or_else(|| "-".to_string())
+    };
+    let fields = [
+        ("id", todo.id.to_string()),
+        ("title", todo.title.clone()),
+        ("description", or_none(Some(todo.description.clone()))),
+        ("completed", todo.completed.to_string()),
+        ("due", or_none(todo.due_at.as_ref().map(utc::format))),
+        ("recurrence", or_none(todo.recurrence.clone())),
+        ("parent", or_none(todo.parent_id.map(|id| id.to_string()))),
+        ("tags", or_none(Some(tag_names(tagged)))),
+    ];
+    let mut details = String::new();
+    for (name, value) in fields {
+        let _ = writeln!(details, "{:13}{value}", format!("{name}:"));
+    }
+    Ok(details)
+}
+
+fn tag_names(tagged: &TaggedTodo) -> String {
+    let names: Vec<_> = tagged.tags.iter().map(|tag| tag.name.as_str()).collect();
+    names.join(", ")
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        auth,
+        cli::{cli, run_cli},
+        error::{ClientError, InternalError},
+        tag::TaggedTodo,
+        test::{repositories, server, test_config, timestamp, ALICE},
+    };
+    use actix_test::TestServer;
+    use assert_matches::assert_matches;
+    use sqlx::SqlitePool;
+    use std::{env, fs};
+    use tokio::task::LocalSet;
+
+    fn token() -> String {
+        let config = test_config();
+        auth::sign_token(&config.token_secret, ALICE, config.token_ttl)
+    }
+
+    /// Runs the CLI against a server, with the token of Alice in the
+    /// environment.
+    async fn todo<const N: usize>(
+        server: &TestServer,
+        args: [&str; N],
+    ) -> Result<String, ClientError> {
+        let url = server.url("");
+        let args = ["todo", "--server", &url].into_iter().chain(args);
+        let flags = cli().try_get_matches_from(args).unwrap();
+        let token = token();
+        run_cli(&flags, |key| (key == "TODO_TOKEN").then(|| token.clone())).await
+    }
+
+    #[sqlx::test(fixtures("test/fixtures/users.sql", "test/fixtures/todos.sql"))]
+    async fn commands(pool: SqlitePool) {
+        LocalSet::new()
+            .run_until(async move {
+                for repository in repositories(pool).await {
+                    let server = server(repository);
+                    let table = todo(&server, ["list", "--completed", "false"]).await;
+                    assert_eq!(
+                        table.unwrap(),
+                        "ID  DONE  DUE                   TITLE  TAGS\n\
+                         1                               todo1\n\
+                         3         2024-06-20T12:00:00Z  todo3\n"
+                    );
+
+                    let args = ["add", "ship cli", "--due", "2024-07-01T09:00:00+02:00"];
+                    let details = todo(&server, args).await.unwrap();
+                    assert_eq!(
+                        details,
+                        "id:          4\n\
+                         title:       ship cli\n\
+                         description: -\n\
+                         completed:   false\n\
+                         due:         2024-07-01T07:00:00Z\n\
+                         recurrence:  -\n\
+                         parent:      -\n\
+                         tags:        -\n"
+                    );
+
+                    let args = ["edit", "4", "--title", "cli", "--due", "", "--output", "json"];
+                    let edited = todo(&server, args).await.unwrap();
+                    let edited: TaggedTodo = serde_json::from_str(&edited).unwrap();
+                    assert_eq!(edited.todo.title, "cli");
+                    assert_eq!(edited.todo.due_at, None);
+
+                    let done = todo(&server, ["done", "4", "--output", "json"]).await;
+                    let done: TaggedTodo = serde_json::from_str(&done.unwrap()).unwrap();
+                    assert!(done.todo.completed);
+
+                    let removed = todo(&server, ["--output=json", "rm", "4"]).await;
+                    let removed: TaggedTodo = serde_json::from_str(&removed.unwrap()).unwrap();
+                    assert!(removed.todo.deleted_at.is_some());
+                    let missing = todo(&server, ["get", "4"]).await;
+                    assert_matches!(missing, Err(ClientError::Api(problem)) if problem.status == 404);
+                }
+            })
+            .await;
+    }
+
+    #[sqlx::test(fixtures("test/fixtures/users.sql", "test/fixtures/todos.sql"))]
+    async fn api_errors(pool: SqlitePool) {
+        LocalSet::new()
+            .run_until(async move {
+                for repository in repositories(pool).await {
+                    let server = server(repository);
+                    let invalid = todo(&server, ["add", "a title much too long"]).await;
+                    let message = invalid.unwrap_err().to_string();
+                    let expected = "422 Unprocessable Entity: One or more fields are invalid\n  title: ";
+                    assert!(message.starts_with(expected));
+
+                    let url = server.url("");
+                    let flags = cli().try_get_matches_from(["todo", "get", "1", "--server", &url]);
+                    let result = run_cli(&flags.unwrap(), |_| None).await;
+                    assert_matches!(result, Err(ClientError::Api(problem)) if problem.status == 401);
+                }
+            })
+            .await;
+
+        let result = cli().try_get_matches_from(["todo", "edit", "1"]);
+        assert!(result.is_err());
+        let result = cli().try_get_matches_from(["todo", "add", "title", "--due", "tomorrow"]);
+        assert!(result.is_err());
+    }
+
+    #[sqlx::test(fixtures("test/fixtures/users.sql", "test/fixtures/todos.sql"))]
+    async fn settings(pool: SqlitePool) {
+        LocalSet::new()
+            .run_until(async move {
+                let [repository, _] = repositories(pool).await;
+                let server = server(repository);
+                let dir = env::temp_dir().join(format!("todo-cli-{}", std::process::id()));
+                fs::create_dir_all(dir.join("todo")).unwrap();
+                let contents = format!(
+                    "server = \"{}\"\ntoken = \"{}\"\noutput = \"table\"\n",
+                    server.url(""),
+                    token()
+                );
+                fs::write(dir.join("todo/config.toml"), contents).unwrap();
+                let home = dir.to_str().unwrap().to_string();
+
+                // The default config file, overridden by the environment
+                let env = |key: &str| match key {
+                    "XDG_CONFIG_HOME" => Some(home.clone()),
+                    "TODO_OUTPUT" => Some("json".to_string()),
+                    _ => None,
+                };
+                let flags = cli().try_get_matches_from(["todo", "get", "2"]).unwrap();
+                let output = run_cli(&flags, env).await.unwrap();
+                let todo: TaggedTodo = serde_json::from_str(&output).unwrap();
+                assert_eq!(todo.todo.due_at, Some(timestamp("2024-06-10 12:00:00")));
+
+                // Flags after the subcommand override the environment
+                let flags = cli().try_get_matches_from(["todo", "get", "2", "--output", "table"]);
+                let output = run_cli(&flags.unwrap(), env).await.unwrap();
+                assert!(output.starts_with("id:          2\n"));
+
+                let flags = cli().try_get_matches_from(["todo", "get", "2", "--output", "xml"]);
+                let result = run_cli(&flags.unwrap(), env).await;
+                assert_matches!(result, Err(ClientError::Config(InternalError::ParseConfig(errors))) if errors == ["Invalid '--output' value 'xml'"]);
+            })
+            .await;
+    }
+}
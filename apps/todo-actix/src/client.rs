@@ -0,0 +1,116 @@
+use crate::{
+    error::{ClientError, Problem},
+    query::ListQuery,
+    tag::TaggedTodo,
+    todo::CreateTodo,
+};
+use actix_web::http::{
+    header::{AUTHORIZATION, CONTENT_TYPE},
+    Method, StatusCode,
+};
+use awc::{Client, ClientRequest};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+/// Largest response read, well above a page of the largest size.
+const RESPONSE_LIMIT: usize = 16 * 1024 * 1024;
+
+/// Client of the todos API, authenticated with a bearer token.
+pub struct TodoClient {
+    client: Client,
+    server: String,
+    token: Option<String>,
+}
+
+impl TodoClient {
+    pub fn new(server: &str, token: Option<String>) -> Self {
+        Self {
+            client: Client::default(),
+            server: server.trim_end_matches('/').to_string(),
+            token,
+        }
+    }
+
+    pub async fn list_todos(&self, query: &ListQuery) -> Result<Vec<TaggedTodo>, ClientError> {
+        let path = match query.encode() {
+            query if query.is_empty() => "/todos".to_string(),
+            query => format!("/todos?{query}"),
+        };
+        self.send(self.request(Method::GET, &path), None).await
+    }
+
+    pub async fn get_todo(&self, id: i64) -> Result<TaggedTodo, ClientError> {
+        let request = self.request(Method::GET, &format!("/todos/{id}"));
+        self.send(request, None).await
+    }
+
+    pub async fn create_todo(&self, todo: &CreateTodo) -> Result<TaggedTodo, ClientError> {
+        let body = serde_json::to_value(todo)?;
+        let request = self
+            .request(Method::POST, "/todos")
+            .insert_header((CONTENT_TYPE, "application/json"));
+        self.send(request, Some(&body)).await
+    }
+
+    /// Changes the fields of a todo set in a JSON Merge Patch.
+    pub async fn patch_todo(&self, id: i64, patch: &Value) -> Result<TaggedTodo, ClientError> {
+        let request = self
+            .request(Method::PATCH, &format!("/todos/{id}"))
+            .insert_header((CONTENT_TYPE, "application/merge-patch+json"));
+        self.send(request, Some(patch)).await
+    }
+
+    /// Moves a todo to the trash, returning it.
+    pub async fn delete_todo(&self, id: i64) -> Result<TaggedTodo, ClientError> {
+        let request = self.request(Method::DELETE, &format!("/todos/{id}"));
+        self.send(request, None).await
+    }
+
+    fn request(&self, method: Method, path: &str) -> ClientRequest {
+        let request = self
+            .client
+            .request(method, format!("{}{path}", self.server));
+        match &self.token {
+            Some(token) => request.insert_header((AUTHORIZATION, format!("Bearer {token}"))),
+            None => request,
+        }
+    }
+
+    /// Sends a request, reading the JSON body of a successful response, or
+    /// else the problem it describes.
+    async fn send<T: DeserializeOwned>(
+        &self,
+        request: ClientRequest,
+        body: Option<&Value>,
+    ) -> Result<T, ClientError> {
+        let body = body.map(Value::to_string).unwrap_or_default();
+        let mut response = request
+            .send_body(body)
+            .await
+            .map_err(|err| ClientError::Request(err.to_string()))?;
+        let status = response.status();
+        let body = response
+            .body()
+            .limit(RESPONSE_LIMIT)
+            .await
+            .map_err(|err| ClientError::Request(err.to_string()))?;
+        if !status.is_success() {
+            let problem = serde_json::from_slice(&body).unwrap_or_else(|_| problem(status));
+            return Err(ClientError::Api(problem));
+        }
+        serde_json::from_slice(&body).map_err(|err| {
+            ClientError::Request(format!("Invalid response from {}: {err}", self.server))
+        })
+    }
+}
+
+/// The problem standing for an error answered without details.
+fn problem(status: StatusCode) -> Problem {
+    Problem {
+        kind: "about:blank".to_string(),
+        title: status.canonical_reason().unwrap_or_default().to_string(),
+        status: status.as_u16(),
+        detail: None,
+        errors: Vec::new(),
+    }
+}
@@ -1,39 +1,689 @@
-use crate::error::InternalError;
+use crate::{
+    auth,
+    error::InternalError,
+    subtask::DeletePolicy,
+    webhook::{AddressPolicy, Network},
+};
+use actix_web::http::Uri;
+use clap::{Arg, ArgMatches, Command};
 use log::LevelFilter;
-use std::{env, str::FromStr};
+use std::{env, fmt::Display, fs, ops::RangeInclusive, str::FromStr};
+use toml::{Table, Value};
 
 const HOST: &str = "127.0.0.1";
 const PORT: u16 = 8080;
 const DATABASE_URL: &str = "sqlite://todos.db";
 const RUST_LOG: LevelFilter = LevelFilter::Debug;
+const TOKEN_TTL: i64 = 24 * 60 * 60;
+const TOKEN_TTLS: RangeInclusive<i64> = 60..=365 * 24 * 60 * 60;
+const TRASH_RETENTION: i64 = 30 * 24 * 60 * 60;
+const DRAIN_TIMEOUT: u64 = 30;
+const RATE_LIMIT_BURST: u32 = 60;
+const RATE_LIMIT_PER_SECOND: f64 = 1.0;
+const POOL_SIZE: u32 = 10;
+const POOL_SIZES: RangeInclusive<u32> = 1..=100;
+const BODY_LIMIT: usize = 2 * 1024 * 1024;
+const BODY_LIMITS: RangeInclusive<usize> = 1024..=1024 * 1024 * 1024;
+const WORKERS: usize = 0;
 
+/// A setting, as named in the config file and the environment. Its CLI flag
+/// is the key with dashes, such as `--rate-limit-burst`.
+pub(crate) struct Setting {
+    pub(crate) key: &'static str,
+    pub(crate) env: &'static str,
+    pub(crate) help: &'static str,
+    /// Secrets have no CLI flag, as other users can read the command line of
+    /// the process.
+    pub(crate) secret: bool,
+}
+
+const SETTINGS: &[Setting] = &[
+    Setting {
+        key: "host",
+        env: "HOST",
+        help: "Address of the server that serves the app",
+        secret: false,
+    },
+    Setting {
+        key: "port",
+        env: "PORT",
+        help: "Port the server will listen at",
+        secret: false,
+    },
+    Setting {
+        key: "database_url",
+        env: "DATABASE_URL",
+        help: "URL pointing to a SQL database server",
+        secret: false,
+    },
+    Setting {
+        key: "log_level",
+        env: "RUST_LOG",
+        help: "Level of verbosity for the logger",
+        secret: false,
+    },
+    Setting {
+        key: "token_secret",
+        env: "TOKEN_SECRET",
+        help: "Secret used to sign bearer tokens",
+        secret: true,
+    },
+    Setting {
+        key: "token_ttl",
+        env: "TOKEN_TTL",
+        help: "Lifetime of bearer tokens, in seconds",
+        secret: false,
+    },
+    Setting {
+        key: "trash_retention",
+        env: "TRASH_RETENTION",
+        help: "Time deleted todos are kept in the trash, in seconds",
+        secret: false,
+    },
+    Setting {
+        key: "drain_timeout",
+        env: "DRAIN_TIMEOUT",
+        help: "Time given to requests in flight to finish on shutdown, in seconds",
+        secret: false,
+    },
+    Setting {
+        key: "rate_limit_burst",
+        env: "RATE_LIMIT_BURST",
+        help: "Requests a client can make at once, or 0 to disable rate limiting",
+        secret: false,
+    },
+    Setting {
+        key: "rate_limit_per_second",
+        env: "RATE_LIMIT_PER_SECOND",
+        help: "Requests a client regains every second",
+        secret: false,
+    },
+    Setting {
+        key: "subtask_delete_policy",
+        env: "SUBTASK_DELETE_POLICY",
+        help: "What becomes of the subtasks of a deleted todo",
+        secret: false,
+    },
+    Setting {
+        key: "pool_size",
+        env: "POOL_SIZE",
+        help: "Maximum number of database connections",
+        secret: false,
+    },
+    Setting {
+        key: "body_limit",
+        env: "BODY_LIMIT",
+        help: "Maximum size of request bodies, in bytes",
+        secret: false,
+    },
+    Setting {
+        key: "workers",
+        env: "WORKERS",
+        help: "Number of worker threads, or 0 for one per CPU core",
+        secret: false,
+    },
+    Setting {
+        key: "cors_origins",
+        env: "CORS_ORIGINS",
+        help: "Comma-separated origins allowed to make cross-origin requests, or * for any",
+        secret: false,
+    },
+    Setting {
+        key: "tls_cert",
+        env: "TLS_CERT",
+        help: "PEM file of the certificate chain, serving HTTPS when set along with the key",
+        secret: false,
+    },
+    Setting {
+        key: "tls_key",
+        env: "TLS_KEY",
+        help: "PEM file of the private key of the certificate",
+        secret: false,
+    },
+    Setting {
+        key: "webhook_denied_networks",
+        env: "WEBHOOK_DENIED_NETWORKS",
+        help: "Comma-separated networks webhooks are not delivered to, such as 10.0.0.0/8",
+        secret: false,
+    },
+    Setting {
+        key: "webhook_allowed_networks",
+        env: "WEBHOOK_ALLOWED_NETWORKS",
+        help: "Comma-separated networks webhooks are delivered to even when denied",
+        secret: false,
+    },
+    Setting {
+        key: "orphan_owner",
+        env: "ORPHAN_OWNER",
+        help: "User given the todos created before accounts existed, on startup",
+        secret: false,
+    },
+    Setting {
+        key: "redirect_port",
+        env: "REDIRECT_PORT",
+        help: "Port redirecting plain HTTP requests to HTTPS, which requires TLS",
+        secret: false,
+    },
+];
+
+#[derive(Clone)]
 pub struct Config {
     pub host: String,
     pub port: u16,
     pub db_url: String,
     pub log_level: LevelFilter,
+    pub token_secret: String,
+    /// Whether the token secret was generated for lack of one being set, the
+    /// tokens signed with it being invalidated on restart.
+    pub token_secret_generated: bool,
+    pub token_ttl: i64,
+    pub trash_retention: i64,
+    /// Seconds given to in-flight requests to finish on shutdown.
+    pub drain_timeout: u64,
+    /// Requests a client can make at once, or 0 to disable rate limiting.
+    pub rate_limit_burst: u32,
+    /// Requests a client regains every second.
+    pub rate_limit_per_second: f64,
+    /// What becomes of the subtasks of a deleted todo.
+    pub subtask_delete_policy: DeletePolicy,
+    /// Maximum number of connections of the database pool.
+    pub pool_size: u32,
+    /// Maximum size of request bodies, in bytes.
+    pub body_limit: usize,
+    /// Number of worker threads, or 0 for one per CPU core.
+    pub workers: usize,
+    /// Origins allowed to make cross-origin requests, `*` standing for any.
+    /// CORS is disabled when there are none.
+    pub cors_origins: Vec<String>,
+    /// PEM files of the certificate chain and its private key, the server
+    /// serving HTTPS when both are set.
+    pub tls_cert: Option<String>,
+    pub tls_key: Option<String>,
+    /// Port listening for plain HTTP requests to redirect them to HTTPS.
+    pub redirect_port: Option<u16>,
+    /// Addresses webhooks may be delivered to.
+    pub webhook_policy: AddressPolicy,
+    /// Username of the user given the todos created before accounts existed.
+    pub orphan_owner: Option<String>,
 }
 
 impl Config {
-    pub fn from_env() -> Result<Self, InternalError> {
-        let host = env_var("HOST", HOST.to_string())?;
-        let port = env_var("PORT", PORT)?;
-        let db_url = env_var("DATABASE_URL", DATABASE_URL.to_string())?;
-        let log_level = env_var("RUST_LOG", RUST_LOG)?;
-        Ok(Self {
-            host,
-            port,
-            db_url,
-            log_level,
+    /// Reads the config from the command line arguments, exiting on `--help`
+    /// or unknown arguments. Only debug builds generate a token secret when
+    /// none is set.
+    pub fn from_args() -> Result<Self, InternalError> {
+        let flags = command().get_matches();
+        let config = Self::from_layers(&flags, |key| env::var(key).ok())?;
+        if config.token_secret_generated && !cfg!(debug_assertions) {
+            return Err(InternalError::ParseConfig(vec![
+                "'TOKEN_SECRET' must be set".to_string(),
+            ]));
+        }
+        Ok(config)
+    }
+
+    /// Reads every setting from the CLI flags, or else the environment, or
+    /// else the file given with `--config`, reporting every invalid one at
+    /// once.
+    fn from_layers(
+        flags: &ArgMatches,
+        env: impl Fn(&str) -> Option<String>,
+    ) -> Result<Self, InternalError> {
+        let mut layers = Layers::new(SETTINGS, flags, &env);
+        if let Some(path) = flags.get_one::<String>("config") {
+            layers.read_file(path)?;
+        }
+        let token_secret: Option<String> = layers.optional("token_secret");
+        let config = Self {
+            host: layers.get("host", HOST.to_string()),
+            port: layers.get("port", PORT),
+            db_url: layers.get("database_url", DATABASE_URL.to_string()),
+            log_level: layers.get("log_level", RUST_LOG),
+            token_secret_generated: token_secret.is_none(),
+            token_secret: token_secret.unwrap_or_else(auth::random_secret),
+            token_ttl: layers.bounded("token_ttl", TOKEN_TTL, TOKEN_TTLS),
+            trash_retention: layers.get("trash_retention", TRASH_RETENTION),
+            drain_timeout: layers.get("drain_timeout", DRAIN_TIMEOUT),
+            rate_limit_burst: layers.get("rate_limit_burst", RATE_LIMIT_BURST),
+            rate_limit_per_second: layers.get("rate_limit_per_second", RATE_LIMIT_PER_SECOND),
+            subtask_delete_policy: layers.get("subtask_delete_policy", DeletePolicy::default()),
+            pool_size: layers.bounded("pool_size", POOL_SIZE, POOL_SIZES),
+            body_limit: layers.bounded("body_limit", BODY_LIMIT, BODY_LIMITS),
+            workers: layers.get("workers", WORKERS),
+            cors_origins: layers.origins("cors_origins"),
+            tls_cert: layers.optional("tls_cert"),
+            tls_key: layers.optional("tls_key"),
+            redirect_port: layers.optional("redirect_port"),
+            webhook_policy: AddressPolicy {
+                denied: layers.list("webhook_denied_networks", AddressPolicy::default().denied),
+                allowed: layers.list("webhook_allowed_networks", Vec::<Network>::new()),
+            },
+            orphan_owner: layers.optional("orphan_owner"),
+        };
+        if config.tls_cert.is_some() != config.tls_key.is_some() {
+            layers.error("'tls_cert' and 'tls_key' must be set together");
+        } else if config.redirect_port.is_some() && config.tls_cert.is_none() {
+            layers.error("'redirect_port' requires 'tls_cert' and 'tls_key'");
+        }
+        layers.finish()?;
+        Ok(config)
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            host: HOST.to_string(),
+            port: PORT,
+            db_url: DATABASE_URL.to_string(),
+            log_level: RUST_LOG,
+            token_secret: auth::random_secret(),
+            token_secret_generated: true,
+            token_ttl: TOKEN_TTL,
+            trash_retention: TRASH_RETENTION,
+            drain_timeout: DRAIN_TIMEOUT,
+            rate_limit_burst: RATE_LIMIT_BURST,
+            rate_limit_per_second: RATE_LIMIT_PER_SECOND,
+            subtask_delete_policy: DeletePolicy::default(),
+            pool_size: POOL_SIZE,
+            body_limit: BODY_LIMIT,
+            workers: WORKERS,
+            cors_origins: Vec::new(),
+            tls_cert: None,
+            tls_key: None,
+            redirect_port: None,
+            webhook_policy: AddressPolicy::default(),
+            orphan_owner: None,
+        }
+    }
+}
+
+/// Command line of the server, taking a flag per setting.
+fn command() -> Command {
+    let command = Command::new("todo-actix").about("TODO API server");
+    with_settings(command, SETTINGS)
+}
+
+/// Adds `--config` and a flag per setting to a command, which are also taken
+/// after its subcommands.
+pub(crate) fn with_settings(command: Command, settings: &[Setting]) -> Command {
+    let command = command.arg(
+        Arg::new("config")
+            .long("config")
+            .value_name("FILE")
+            .global(true)
+            .help("TOML file to read settings from"),
+    );
+    let flags = settings.iter().filter(|setting| !setting.secret);
+    flags.fold(command, |command, setting| {
+        command.arg(
+            Arg::new(setting.key)
+                .long(setting.key.replace('_', "-"))
+                .value_name("VALUE")
+                .global(true)
+                .help(format!("{} [env: {}]", setting.help, setting.env)),
+        )
+    })
+}
+
+/// The sources settings are read from, along with the errors met so far.
+pub(crate) struct Layers<'a> {
+    settings: &'a [Setting],
+    flags: &'a ArgMatches,
+    env: &'a dyn Fn(&str) -> Option<String>,
+    /// Path and contents of the config file.
+    file: Option<(String, Table)>,
+    errors: Vec<String>,
+}
+
+impl<'a> Layers<'a> {
+    pub(crate) fn new(
+        settings: &'a [Setting],
+        flags: &'a ArgMatches,
+        env: &'a dyn Fn(&str) -> Option<String>,
+    ) -> Self {
+        Self {
+            settings,
+            flags,
+            env,
+            file: None,
+            errors: Vec::new(),
+        }
+    }
+
+    /// Reads the config file, reporting keys which are not settings as
+    /// invalid.
+    pub(crate) fn read_file(&mut self, path: &str) -> Result<(), InternalError> {
+        let table = fs::read_to_string(path)
+            .map_err(|err| err.to_string())
+            .and_then(|contents| contents.parse::<Table>().map_err(|err| err.to_string()))
+            .map_err(|err| {
+                InternalError::ParseConfig(vec![format!("Invalid config file '{path}': {err}")])
+            })?;
+        for key in table.keys() {
+            if !self.settings.iter().any(|setting| setting.key == key) {
+                self.errors.push(format!("Unknown key '{key}' in '{path}'"));
+            }
+        }
+        self.file = Some((path.to_string(), table));
+        Ok(())
+    }
+
+    pub(crate) fn get<T: FromStr>(&mut self, key: &str, default: T) -> T {
+        let Some((value, source)) = self.raw(key) else {
+            return default;
+        };
+        value.parse().unwrap_or_else(|_| {
+            self.errors.push(format!("Invalid {source}"));
+            default
         })
     }
+
+    /// Reads a setting which must be within a range.
+    pub(crate) fn bounded<T: FromStr + PartialOrd + Display>(
+        &mut self,
+        key: &str,
+        default: T,
+        range: RangeInclusive<T>,
+    ) -> T {
+        let Some((value, source)) = self.raw(key) else {
+            return default;
+        };
+        match value.parse() {
+            Ok(value) if range.contains(&value) => value,
+            Ok(_) => {
+                let (min, max) = (range.start(), range.end());
+                self.errors
+                    .push(format!("Invalid {source}, must be from {min} to {max}"));
+                default
+            }
+            Err(_) => {
+                self.errors.push(format!("Invalid {source}"));
+                default
+            }
+        }
+    }
+
+    pub(crate) fn optional<T: FromStr>(&mut self, key: &str) -> Option<T> {
+        let (value, source) = self.raw(key)?;
+        value
+            .parse()
+            .map_err(|_| self.errors.push(format!("Invalid {source}")))
+            .ok()
+    }
+
+    /// Reads a comma-separated list, which may be empty.
+    fn list<T: FromStr>(&mut self, key: &str, default: Vec<T>) -> Vec<T> {
+        let Some((value, source)) = self.raw(key) else {
+            return default;
+        };
+        let values = value
+            .split(',')
+            .map(str::trim)
+            .filter(|value| !value.is_empty())
+            .map(str::parse)
+            .collect::<Result<_, _>>();
+        values.unwrap_or_else(|_| {
+            self.errors.push(format!("Invalid {source}"));
+            default
+        })
+    }
+
+    /// Reads a comma-separated list of origins, checking each is `*` or an
+    /// HTTP(S) origin without a path.
+    fn origins(&mut self, key: &str) -> Vec<String> {
+        let Some((value, source)) = self.raw(key) else {
+            return Vec::new();
+        };
+        let origins: Vec<String> = value
+            .split(',')
+            .map(str::trim)
+            .filter(|origin| !origin.is_empty())
+            .map(str::to_string)
+            .collect();
+        let valid = origins.iter().all(|origin| {
+            origin == "*"
+                || origin.parse::<Uri>().is_ok_and(|uri| {
+                    matches!(uri.scheme_str(), Some("http" | "https"))
+                        && uri.host().is_some()
+                        && uri.path() == "/"
+                        && !origin.ends_with('/')
+                })
+        });
+        if !valid {
+            self.errors.push(format!("Invalid {source}"));
+            return Vec::new();
+        }
+        origins
+    }
+
+    /// The value of a setting in the highest layer setting it, along with a
+    /// description of it and where it comes from for error messages.
+    fn raw(&self, key: &str) -> Option<(String, String)> {
+        let setting = self.settings.iter().find(|setting| setting.key == key)?;
+        let flag = (!setting.secret).then(|| self.flags.get_one::<String>(key));
+        if let Some(value) = flag.flatten() {
+            let flag = key.replace('_', "-");
+            return Some((value.clone(), format!("'--{flag}' value '{value}'")));
+        }
+        if let Some(value) = (self.env)(setting.env) {
+            let source = format!("'{}' value '{value}'", setting.env);
+            return Some((value, source));
+        }
+        let (path, table) = self.file.as_ref()?;
+        let value = to_string(table.get(key)?);
+        let source = format!("'{key}' value '{value}' in '{path}'");
+        Some((value, source))
+    }
+
+    /// Reports an invalid combination of settings.
+    pub(crate) fn error(&mut self, message: &str) {
+        self.errors.push(message.to_string());
+    }
+
+    /// Fails with every error met while reading the settings.
+    pub(crate) fn finish(self) -> Result<(), InternalError> {
+        if !self.errors.is_empty() {
+            return Err(InternalError::ParseConfig(self.errors));
+        }
+        Ok(())
+    }
+}
+
+/// Formats a TOML value like it would be set in the environment, lists being
+/// comma-separated. Tables are formatted as `{...}`, which no setting accepts.
+fn to_string(value: &Value) -> String {
+    match value {
+        Value::String(value) => value.clone(),
+        Value::Integer(value) => value.to_string(),
+        Value::Float(value) => value.to_string(),
+        Value::Boolean(value) => value.to_string(),
+        Value::Datetime(value) => value.to_string(),
+        Value::Array(values) => values.iter().map(to_string).collect::<Vec<_>>().join(","),
+        Value::Table(_) => "{...}".to_string(),
+    }
 }
 
-fn env_var<T: FromStr>(key: &str, default: T) -> Result<T, InternalError> {
-    match env::var(key) {
-        Err(_) => Ok(default),
-        Ok(var) => var
-            .parse::<T>()
-            .map_err(|_| InternalError::ParseConfig(format!("Invalid '{key}' value '{var}'"))),
+#[cfg(test)]
+mod test {
+    use crate::{
+        config::{command, Config},
+        error::InternalError,
+        subtask::DeletePolicy,
+        webhook::AddressPolicy,
+    };
+    use assert_matches::assert_matches;
+    use clap::{error::ErrorKind, ArgMatches};
+    use std::{collections::HashMap, env, ffi::OsString, fs, path::PathBuf};
+
+    /// Arguments as given on the command line.
+    fn flags<const N: usize>(args: [&str; N]) -> ArgMatches {
+        let args = ["todo-actix"].into_iter().chain(args).map(OsString::from);
+        command().get_matches_from(args)
+    }
+
+    /// Writes a config file unique to the test.
+    fn config_file(name: &str, contents: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("todo-actix-{}-{name}.toml", std::process::id()));
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    fn environment<const N: usize>(vars: [(&str, &str); N]) -> impl Fn(&str) -> Option<String> {
+        let vars: HashMap<String, String> = vars
+            .into_iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+        move |key| vars.get(key).cloned()
+    }
+
+    #[test]
+    fn defaults() {
+        let config = Config::from_layers(&flags([]), environment([])).unwrap();
+        assert_eq!(config.port, 8080);
+        assert_eq!(config.pool_size, 10);
+        assert_eq!(config.body_limit, 2 * 1024 * 1024);
+        assert_eq!(config.workers, 0);
+        assert_eq!(config.cors_origins, Vec::<String>::new());
+        assert_eq!(config.tls_cert, None);
+        assert_eq!(config.redirect_port, None);
+        assert_eq!(config.orphan_owner, None);
+        assert!(config.token_secret_generated);
+        assert_eq!(config.webhook_policy, AddressPolicy::default());
+    }
+
+    #[test]
+    fn token_secret() {
+        let env = environment([("TOKEN_SECRET", "secret")]);
+        let config = Config::from_layers(&flags([]), env).unwrap();
+        assert_eq!(config.token_secret, "secret");
+        assert!(!config.token_secret_generated);
+
+        let args = ["todo-actix", "--token-secret", "secret"];
+        let err = command().try_get_matches_from(args).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnknownArgument);
+    }
+
+    #[test]
+    fn bounds() {
+        let env = environment([
+            ("POOL_SIZE", "0"),
+            ("BODY_LIMIT", "1099511627776"),
+            ("TOKEN_TTL", "-1"),
+        ]);
+        let result = Config::from_layers(&flags([]), env);
+        assert_matches!(result.err(), Some(InternalError::ParseConfig(errors)) if errors == [
+            "Invalid 'TOKEN_TTL' value '-1', must be from 60 to 31536000",
+            "Invalid 'POOL_SIZE' value '0', must be from 1 to 100",
+            "Invalid 'BODY_LIMIT' value '1099511627776', must be from 1024 to 1073741824",
+        ]);
+    }
+
+    #[test]
+    fn precedence() {
+        let path = config_file(
+            "precedence",
+            r#"
+            host = "0.0.0.0"
+            port = 1000
+            workers = 2
+            rate_limit_per_second = 0.5
+            subtask_delete_policy = "cascade"
+            cors_origins = ["https://example.com", "http://localhost:3000"]
+            webhook_denied_networks = ["10.0.0.0/8", "fe80::/10"]
+            "#,
+        );
+        let path = path.to_str().unwrap();
+        let flags = flags(["--config", path, "--port", "3000", "--pool-size=4"]);
+        let env = environment([
+            ("PORT", "2000"),
+            ("WORKERS", "8"),
+            ("POOL_SIZE", "2"),
+            ("ORPHAN_OWNER", "admin"),
+            ("WEBHOOK_ALLOWED_NETWORKS", ""),
+        ]);
+        let config = Config::from_layers(&flags, env).unwrap();
+        assert_eq!(config.host, "0.0.0.0");
+        assert_eq!(config.port, 3000);
+        assert_eq!(config.workers, 8);
+        assert_eq!(config.pool_size, 4);
+        assert_eq!(config.rate_limit_per_second, 0.5);
+        assert_eq!(config.subtask_delete_policy, DeletePolicy::Cascade);
+        assert_eq!(config.orphan_owner.as_deref(), Some("admin"));
+        let networks =
+            |values: &[&str]| values.iter().map(|value| value.parse().unwrap()).collect();
+        assert_eq!(
+            config.webhook_policy,
+            AddressPolicy {
+                denied: networks(&["10.0.0.0/8", "fe80::/10"]),
+                allowed: networks(&[]),
+            }
+        );
+        assert_eq!(
+            config.cors_origins,
+            ["https://example.com", "http://localhost:3000"]
+        );
+    }
+
+    #[test]
+    fn every_invalid_key() {
+        let path = config_file(
+            "invalid",
+            r#"
+            port = "http"
+            body_limit = -1
+            rate_limits = 10
+            "#,
+        );
+        let path = path.to_str().unwrap();
+        let flags = flags(["--config", path, "--cors-origins", "example.com"]);
+        let env = environment([
+            ("RUST_LOG", "loud"),
+            ("WORKERS", "many"),
+            ("WEBHOOK_DENIED_NETWORKS", "10.0.0.0/8,10.0.0.0/64"),
+        ]);
+        let result = Config::from_layers(&flags, env);
+        assert_matches!(result.err(), Some(InternalError::ParseConfig(errors)) if errors == vec![
+            format!("Unknown key 'rate_limits' in '{path}'"),
+            format!("Invalid 'port' value 'http' in '{path}'"),
+            "Invalid 'RUST_LOG' value 'loud'".to_string(),
+            format!("Invalid 'body_limit' value '-1' in '{path}'"),
+            "Invalid 'WORKERS' value 'many'".to_string(),
+            "Invalid '--cors-origins' value 'example.com'".to_string(),
+            "Invalid 'WEBHOOK_DENIED_NETWORKS' value '10.0.0.0/8,10.0.0.0/64'".to_string(),
+        ]);
+    }
+
+    #[test]
+    fn tls() {
+        let tls = flags(["--tls-cert", "todo.crt", "--tls-key", "todo.key"]);
+        let config = Config::from_layers(&tls, environment([("REDIRECT_PORT", "80")])).unwrap();
+        assert_eq!(config.tls_cert.as_deref(), Some("todo.crt"));
+        assert_eq!(config.tls_key.as_deref(), Some("todo.key"));
+        assert_eq!(config.redirect_port, Some(80));
+
+        let env = environment([("TLS_CERT", "todo.crt"), ("REDIRECT_PORT", "http")]);
+        let result = Config::from_layers(&flags([]), env);
+        assert_matches!(result.err(), Some(InternalError::ParseConfig(errors)) if errors == [
+            "Invalid 'REDIRECT_PORT' value 'http'",
+            "'tls_cert' and 'tls_key' must be set together",
+        ]);
+
+        let env = environment([("REDIRECT_PORT", "80")]);
+        let result = Config::from_layers(&flags([]), env);
+        assert_matches!(result.err(), Some(InternalError::ParseConfig(errors)) if errors == [
+            "'redirect_port' requires 'tls_cert' and 'tls_key'",
+        ]);
+    }
+
+    #[test]
+    fn invalid_file() {
+        let path = config_file("syntax", "port = ");
+        let path = path.to_str().unwrap();
+        let result = Config::from_layers(&flags(["--config", path]), environment([]));
+        assert_matches!(result.err(), Some(InternalError::ParseConfig(errors)) if errors.len() == 1 && errors[0].starts_with("Invalid config file"));
+
+        let result = Config::from_layers(&flags(["--config", "missing.toml"]), environment([]));
+        assert_matches!(result.err(), Some(InternalError::ParseConfig(errors)) if errors.len() == 1);
     }
 }
@@ -0,0 +1,48 @@
+use crate::{
+    rate_limit::{RATELIMIT_LIMIT, RATELIMIT_REMAINING, RATELIMIT_RESET},
+    routes::{LAST_EVENT_ID, NEXT_CURSOR},
+};
+use actix_cors::Cors;
+use actix_web::http::{
+    header::{
+        AUTHORIZATION, CONTENT_DISPOSITION, CONTENT_TYPE, ETAG, IF_MATCH, IF_NONE_MATCH, LINK,
+        RETRY_AFTER,
+    },
+    Method,
+};
+
+/// How long browsers may cache the answer to a preflight request, in seconds.
+const MAX_AGE: usize = 60 * 60;
+
+/// Allows browsers on the given origins to call the API, `*` standing for any
+/// origin. Requests authenticate with bearer tokens rather than cookies, so
+/// credentials are not allowed.
+pub fn cors(origins: &[String]) -> Cors {
+    let cors = if origins.iter().any(|origin| origin == "*") {
+        Cors::default().allow_any_origin()
+    } else {
+        origins
+            .iter()
+            .fold(Cors::default(), |cors, origin| cors.allowed_origin(origin))
+    };
+    cors.allowed_methods([
+        Method::GET,
+        Method::POST,
+        Method::PUT,
+        Method::PATCH,
+        Method::DELETE,
+    ])
+    .allowed_headers([AUTHORIZATION, CONTENT_TYPE, IF_MATCH, IF_NONE_MATCH])
+    .allowed_header(LAST_EVENT_ID)
+    .expose_headers([
+        CONTENT_DISPOSITION,
+        ETAG,
+        LINK,
+        RETRY_AFTER,
+        RATELIMIT_LIMIT,
+        RATELIMIT_REMAINING,
+        RATELIMIT_RESET,
+    ])
+    .expose_headers([NEXT_CURSOR])
+    .max_age(MAX_AGE)
+}
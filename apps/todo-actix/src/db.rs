@@ -1,127 +1,1843 @@
 use crate::{
-    error::InternalError,
+    batch::{Batch, BatchMode, Operation, Outcome},
+    error::{ApiError, FieldError, InternalError},
+    etag::IfMatch,
+    history::{EventKind, TodoEvent},
+    query::{CursorValue, ListParams, Page, Position},
+    recurrence::{self, Updated},
+    reminder::{CreateReminder, FiredReminder, Reminder, ReminderEvent},
+    repository::Patcher,
+    search::{Highlights, SearchHit, SearchParams, HIGHLIGHT_END, HIGHLIGHT_START},
+    subtask::{self, DeletePolicy, Deleted},
+    tag::{self, ChangedTag, CreateTag, Tag, TaggedTodo, UpdateTag},
     todo::{CreateTodo, Todo, UpdateTodo},
+    transfer::Imported,
+    user::{self, User},
+    webhook::{CreateWebhook, Delivery, Payload, Webhook},
 };
-use sqlx::SqlitePool;
+use chrono::NaiveDateTime;
+use sqlx::{
+    migrate::Migrator, types::Json, Connection, Executor, FromRow, QueryBuilder, Sqlite,
+    SqliteConnection, SqlitePool, Transaction,
+};
+use std::collections::{HashMap, HashSet};
+
+static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
+
+/// Checks that the database answers queries and that every migration known to
+/// this build has been applied.
+pub async fn check_ready(pool: &SqlitePool) -> Result<(), InternalError> {
+    let applied: Vec<i64> =
+        sqlx::query_scalar("SELECT version FROM _sqlx_migrations WHERE success = TRUE")
+            .fetch_all(pool)
+            .await?;
+    let pending = MIGRATOR
+        .iter()
+        .filter(|migration| !migration.migration_type.is_down_migration())
+        .filter(|migration| !applied.contains(&migration.version))
+        .count();
+    if pending > 0 {
+        return Err(InternalError::NotReady(format!(
+            "Database has {pending} pending migration(s)"
+        )));
+    }
+    Ok(())
+}
+
+pub async fn list_todos(
+    pool: &SqlitePool,
+    user_id: i64,
+    params: &ListParams,
+) -> Result<Page, InternalError> {
+    let mut query =
+        QueryBuilder::new("SELECT * FROM todos WHERE deleted_at IS NULL AND user_id = ");
+    query.push_bind(user_id);
+    if let Some(title) = &params.title {
+        push_contains(&mut query, "title", title);
+    }
+    if let Some(description) = &params.description {
+        push_contains(&mut query, "description", description);
+    }
+    if let Some(completed) = params.completed {
+        query.push(" AND completed = ").push_bind(completed);
+    }
+    if let Some(due_before) = params.due_before {
+        query.push(" AND due_at < ").push_bind(due_before);
+    }
+    if !params.tags.is_empty() {
+        push_tagged(&mut query, &params.tags);
+    }
+    if let Position::After(cursor) = &params.position {
+        push_after(&mut query, params, cursor.values());
+    }
+
+    query.push(" ORDER BY ");
+    let mut order = query.separated(", ");
+    for key in params.sort.keys() {
+        let direction = if key.descending { "DESC" } else { "ASC" };
+        order.push(format!("{} {direction}", key.field.column()));
+    }
+
+    // Fetch one extra row to know whether there is a next page
+    query.push(" LIMIT ").push_bind(params.limit + 1);
+    if let Position::Offset(offset) = params.position {
+        query.push(" OFFSET ").push_bind(offset);
+    }
+
+    let todos: Vec<Todo> = query.build_query_as().fetch_all(pool).await?;
+    Ok(Page::new(params, todos))
+}
+
+pub async fn get_todo(pool: &SqlitePool, user_id: i64, id: i64) -> Result<Todo, InternalError> {
+    let todo = sqlx::query_as!(
+        Todo,
+        "SELECT * FROM todos WHERE id = ? AND user_id = ? AND deleted_at IS NULL",
+        id,
+        user_id
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok(todo)
+}
+
+pub async fn create_todo(
+    pool: &SqlitePool,
+    user_id: i64,
+    todo: CreateTodo,
+) -> Result<Todo, InternalError> {
+    let mut tx = begin_write(pool).await?;
+    let todo = insert_todo(&mut tx, user_id, todo).await?;
+    tx.commit().await?;
+    Ok(todo)
+}
+
+async fn insert_todo(
+    conn: &mut SqliteConnection,
+    user_id: i64,
+    todo: CreateTodo,
+) -> Result<Todo, InternalError> {
+    check_parent(conn, user_id, None, todo.parent_id).await?;
+    let todo = sqlx::query_as!(
+        Todo,
+        r#"
+        INSERT INTO todos (user_id, title, description, completed, due_at, parent_id, recurrence)
+        VALUES (?, ?, ?, ?, ?, ?, ?)
+        RETURNING *
+        "#,
+        user_id,
+        todo.title,
+        todo.description,
+        todo.completed,
+        todo.due_at,
+        todo.parent_id,
+        todo.recurrence,
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+    record_event(conn, Some(user_id), EventKind::Create, None, Some(&todo)).await?;
+    Ok(todo)
+}
+
+pub async fn update_todo(
+    pool: &SqlitePool,
+    user_id: i64,
+    id: i64,
+    todo: UpdateTodo,
+    if_match: &IfMatch,
+) -> Result<Updated, InternalError> {
+    let mut tx = begin_write(pool).await?;
+    let updated = replace_todo(&mut tx, user_id, id, todo, if_match).await?;
+    tx.commit().await?;
+    Ok(updated)
+}
+
+pub async fn patch_todo(
+    pool: &SqlitePool,
+    user_id: i64,
+    id: i64,
+    if_match: &IfMatch,
+    patch: &Patcher<'_>,
+) -> Result<Result<Updated, ApiError>, InternalError> {
+    let mut tx = begin_write(pool).await?;
+    let before = current_todo(&mut tx, user_id, id, if_match).await?;
+    let todo = match patch(before) {
+        Ok(todo) => todo,
+        Err(err) => return Ok(Err(err)),
+    };
+    let updated = replace_todo(&mut tx, user_id, id, todo, &IfMatch::Any).await?;
+    tx.commit().await?;
+    Ok(Ok(updated))
+}
+
+/// Begins a transaction holding the write lock from the start. SQLite fails
+/// rather than waits when a transaction which has read tries to write while
+/// another one is writing, so those reading what they then write take it first.
+async fn begin_write(pool: &SqlitePool) -> Result<Transaction<'_, Sqlite>, InternalError> {
+    let mut tx = pool.begin().await?;
+    // Updating nothing is enough to take the lock, waiting for it if needed.
+    // Only the searched columns have triggers, which would read first.
+    sqlx::query!("UPDATE todos SET version = version WHERE FALSE")
+        .execute(&mut *tx)
+        .await?;
+    Ok(tx)
+}
+
+async fn replace_todo(
+    conn: &mut SqliteConnection,
+    user_id: i64,
+    id: i64,
+    mut todo: UpdateTodo,
+    if_match: &IfMatch,
+) -> Result<Updated, InternalError> {
+    let before = current_todo(conn, user_id, id, if_match).await?;
+    let next = recurrence::complete(&before, &mut todo);
+    let todo = update_todo_row(conn, user_id, id, todo).await?;
+    record_event(
+        conn,
+        Some(user_id),
+        EventKind::Update,
+        Some(&before),
+        Some(&todo),
+    )
+    .await?;
+    let next = match next {
+        Some(next) => {
+            let next = insert_todo(conn, user_id, next).await?;
+            copy_reminders(conn, id, next.id).await?;
+            Some(next)
+        }
+        None => None,
+    };
+    Ok(Updated { todo, next })
+}
+
+async fn update_todo_row(
+    conn: &mut SqliteConnection,
+    user_id: i64,
+    id: i64,
+    todo: UpdateTodo,
+) -> Result<Todo, InternalError> {
+    check_parent(conn, user_id, Some(id), todo.parent_id).await?;
+    let todo = sqlx::query_as!(
+        Todo,
+        r#"
+        UPDATE todos
+        SET title = ?, description = ?, completed = ?, due_at = ?, parent_id = ?,
+            recurrence = ?, updated_at = CURRENT_TIMESTAMP, version = version + 1
+        WHERE id = ? AND user_id = ? AND deleted_at IS NULL
+        RETURNING
+            id AS "id!", user_id, title, description, completed, due_at, created_at, updated_at,
+            version, deleted_at, parent_id, recurrence
+        "#,
+        todo.title,
+        todo.description,
+        todo.completed,
+        todo.due_at,
+        todo.parent_id,
+        todo.recurrence,
+        id,
+        user_id,
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+    Ok(todo)
+}
+
+pub async fn delete_todo(
+    pool: &SqlitePool,
+    user_id: i64,
+    id: i64,
+    if_match: &IfMatch,
+    policy: DeletePolicy,
+) -> Result<Deleted, InternalError> {
+    let mut tx = begin_write(pool).await?;
+    let deleted = trash_todo(&mut tx, user_id, id, if_match, policy).await?;
+    tx.commit().await?;
+    Ok(deleted)
+}
+
+/// Moves a todo to the trash, applying the delete policy to its subtasks.
+async fn trash_todo(
+    conn: &mut SqliteConnection,
+    user_id: i64,
+    id: i64,
+    if_match: &IfMatch,
+    policy: DeletePolicy,
+) -> Result<Deleted, InternalError> {
+    let before = current_todo(conn, user_id, id, if_match).await?;
+    let children = sqlx::query_as!(
+        Todo,
+        "SELECT * FROM todos WHERE parent_id = ? AND deleted_at IS NULL ORDER BY id",
+        id
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+    let mut subtasks = Vec::new();
+    match policy {
+        _ if children.is_empty() => {}
+        DeletePolicy::Reject => {
+            return Err(InternalError::Conflict(subtask::HAS_SUBTASKS.to_string()))
+        }
+        DeletePolicy::Orphan => {
+            for child in children {
+                subtasks.push(detach_todo(conn, user_id, child).await?);
+            }
+        }
+        DeletePolicy::Cascade => {
+            let descendants: Vec<Todo> = sqlx::query_as(
+                r#"
+                WITH RECURSIVE descendants(id) AS (
+                    SELECT id FROM todos WHERE parent_id = ? AND deleted_at IS NULL
+                    UNION
+                    SELECT todos.id FROM todos JOIN descendants ON todos.parent_id = descendants.id
+                    WHERE todos.deleted_at IS NULL
+                )
+                SELECT todos.* FROM todos JOIN descendants USING (id) ORDER BY id
+                "#,
+            )
+            .bind(id)
+            .fetch_all(&mut *conn)
+            .await?;
+            for descendant in descendants {
+                subtasks.push(trash_todo_row(conn, user_id, descendant).await?);
+            }
+        }
+    }
+    let todo = trash_todo_row(conn, user_id, before).await?;
+    Ok(Deleted { todo, subtasks })
+}
+
+async fn trash_todo_row(
+    conn: &mut SqliteConnection,
+    user_id: i64,
+    before: Todo,
+) -> Result<Todo, InternalError> {
+    let todo = sqlx::query_as!(
+        Todo,
+        r#"
+        UPDATE todos
+        SET deleted_at = CURRENT_TIMESTAMP, version = version + 1
+        WHERE id = ? AND user_id = ? AND deleted_at IS NULL
+        RETURNING
+            id AS "id!", user_id, title, description, completed, due_at, created_at, updated_at,
+            version, deleted_at, parent_id, recurrence
+        "#,
+        before.id,
+        user_id
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+    record_event(
+        conn,
+        Some(user_id),
+        EventKind::Delete,
+        Some(&before),
+        Some(&todo),
+    )
+    .await?;
+    Ok(todo)
+}
+
+/// Makes a subtask a top-level todo.
+async fn detach_todo(
+    conn: &mut SqliteConnection,
+    user_id: i64,
+    before: Todo,
+) -> Result<Todo, InternalError> {
+    let todo = sqlx::query_as!(
+        Todo,
+        r#"
+        UPDATE todos
+        SET parent_id = NULL, updated_at = CURRENT_TIMESTAMP, version = version + 1
+        WHERE id = ?
+        RETURNING
+            id AS "id!", user_id, title, description, completed, due_at, created_at, updated_at,
+            version, deleted_at, parent_id, recurrence
+        "#,
+        before.id
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+    record_event(
+        conn,
+        Some(user_id),
+        EventKind::Update,
+        Some(&before),
+        Some(&todo),
+    )
+    .await?;
+    Ok(todo)
+}
+
+/// Checks that a todo can be placed under the given parent, which must be
+/// another live todo of the user that is not one of its subtasks, without
+/// nesting subtasks too deep. New todos have no id yet.
+async fn check_parent(
+    conn: &mut SqliteConnection,
+    user_id: i64,
+    id: Option<i64>,
+    parent_id: Option<i64>,
+) -> Result<(), InternalError> {
+    let Some(parent_id) = parent_id else {
+        return Ok(());
+    };
+    sqlx::query_scalar!(
+        "SELECT id FROM todos WHERE id = ? AND user_id = ? AND deleted_at IS NULL",
+        parent_id,
+        user_id
+    )
+    .fetch_optional(&mut *conn)
+    .await?
+    .ok_or_else(|| InternalError::Constraint(subtask::unknown_parent()))?;
+
+    // The parent along with its own ancestors
+    let ancestors: Vec<i64> = sqlx::query_scalar(
+        r#"
+        WITH RECURSIVE ancestors(id) AS (
+            SELECT ?
+            UNION
+            SELECT todos.parent_id FROM todos JOIN ancestors ON todos.id = ancestors.id
+            WHERE todos.parent_id IS NOT NULL
+        )
+        SELECT id FROM ancestors
+        "#,
+    )
+    .bind(parent_id)
+    .fetch_all(&mut *conn)
+    .await?;
+    let Some(id) = id else {
+        return subtask::check_depth(ancestors.len() + 1);
+    };
+    if ancestors.contains(&id) {
+        return Err(InternalError::Constraint(subtask::cycle()));
+    }
+
+    // Levels of subtasks the todo brings along
+    let height: i64 = sqlx::query_scalar(
+        r#"
+        WITH RECURSIVE descendants(id, depth) AS (
+            SELECT ?, 0
+            UNION
+            SELECT todos.id, descendants.depth + 1
+            FROM todos JOIN descendants ON todos.parent_id = descendants.id
+            WHERE todos.deleted_at IS NULL AND descendants.depth < ?
+        )
+        SELECT MAX(depth) FROM descendants
+        "#,
+    )
+    .bind(id)
+    .bind(subtask::MAX_DEPTH as i64)
+    .fetch_one(&mut *conn)
+    .await?;
+    subtask::check_depth(ancestors.len() + 1 + height as usize)
+}
+
+/// Lists the subtasks of a todo, oldest first.
+pub async fn list_children(
+    pool: &SqlitePool,
+    user_id: i64,
+    id: i64,
+) -> Result<Vec<Todo>, InternalError> {
+    let mut tx = pool.begin().await?;
+    current_todo(&mut tx, user_id, id, &IfMatch::Any).await?;
+    let todos = sqlx::query_as!(
+        Todo,
+        "SELECT * FROM todos WHERE parent_id = ? AND deleted_at IS NULL ORDER BY id",
+        id
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    Ok(todos)
+}
+
+/// Lists every todo of a user, each level of subtasks after the one above it,
+/// and oldest first within a level.
+pub async fn todo_tree(pool: &SqlitePool, user_id: i64) -> Result<Vec<Todo>, InternalError> {
+    let todos = sqlx::query_as(
+        r#"
+        WITH RECURSIVE tree(id, depth) AS (
+            SELECT id, 0 FROM todos
+            WHERE user_id = ? AND parent_id IS NULL AND deleted_at IS NULL
+            UNION ALL
+            SELECT todos.id, tree.depth + 1 FROM todos JOIN tree ON todos.parent_id = tree.id
+            WHERE todos.deleted_at IS NULL AND tree.depth < ?
+        )
+        SELECT todos.* FROM todos JOIN tree USING (id) ORDER BY tree.depth, todos.id
+        "#,
+    )
+    .bind(user_id)
+    .bind(subtask::MAX_DEPTH as i64)
+    .fetch_all(pool)
+    .await?;
+    Ok(todos)
+}
+
+/// Runs the operations of a batch in a single transaction, each within its own
+/// savepoint so that a failing operation can be undone on its own.
+pub async fn run_batch(
+    pool: &SqlitePool,
+    user_id: i64,
+    batch: Batch,
+    policy: DeletePolicy,
+) -> Result<Outcome, InternalError> {
+    let mut tx = begin_write(pool).await?;
+    let mut results = Vec::with_capacity(batch.operations.len());
+    let mut committed = true;
+    for operation in batch.operations {
+        let mut savepoint = Connection::begin(&mut *tx).await?;
+        let result = run_operation(&mut savepoint, user_id, operation, policy).await;
+        match result {
+            Ok(_) => savepoint.commit().await?,
+            Err(_) => savepoint.rollback().await?,
+        }
+        let failed = result.is_err();
+        results.push(result);
+        if failed && batch.mode == BatchMode::Atomic {
+            committed = false;
+            break;
+        }
+    }
+
+    if committed {
+        tx.commit().await?;
+    } else {
+        tx.rollback().await?;
+    }
+    Ok(Outcome { committed, results })
+}
+
+async fn run_operation(
+    conn: &mut SqliteConnection,
+    user_id: i64,
+    operation: Operation,
+    policy: DeletePolicy,
+) -> Result<Updated, InternalError> {
+    match operation {
+        Operation::Create { todo } => Ok(insert_todo(conn, user_id, todo).await?.into()),
+        Operation::Update { id, todo, version } => {
+            let if_match = Operation::if_match(version);
+            replace_todo(conn, user_id, id, todo, &if_match).await
+        }
+        Operation::Delete { id, version } => {
+            let if_match = Operation::if_match(version);
+            let deleted = trash_todo(conn, user_id, id, &if_match, policy).await?;
+            Ok(deleted.todo.into())
+        }
+    }
+}
+
+/// Lists every todo of a user, oldest first.
+pub async fn export_todos(pool: &SqlitePool, user_id: i64) -> Result<Vec<Todo>, InternalError> {
+    let todos = sqlx::query_as!(
+        Todo,
+        "SELECT * FROM todos WHERE user_id = ? AND deleted_at IS NULL ORDER BY id",
+        user_id
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(todos)
+}
+
+/// Creates the given todos in a single transaction, skipping those which
+/// duplicate an existing todo or one created before them.
+pub async fn import_todos(
+    pool: &SqlitePool,
+    user_id: i64,
+    todos: Vec<CreateTodo>,
+) -> Result<Vec<Imported>, InternalError> {
+    let mut tx = begin_write(pool).await?;
+    let mut imported = Vec::with_capacity(todos.len());
+    for todo in todos {
+        let existing = sqlx::query_as!(
+            Todo,
+            r#"
+            SELECT * FROM todos
+            WHERE user_id = ? AND deleted_at IS NULL
+            AND title = ? AND description = ? AND due_at IS ?
+            ORDER BY id
+            LIMIT 1
+            "#,
+            user_id,
+            todo.title,
+            todo.description,
+            todo.due_at
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+        imported.push(match existing {
+            Some(existing) => Imported::Duplicate(existing),
+            None => Imported::Created(insert_todo(&mut tx, user_id, todo).await?),
+        });
+    }
+    tx.commit().await?;
+    Ok(imported)
+}
+
+/// Lists trashed todos, most recently deleted first.
+pub async fn list_trash(pool: &SqlitePool, user_id: i64) -> Result<Vec<Todo>, InternalError> {
+    let todos = sqlx::query_as!(
+        Todo,
+        r#"
+        SELECT * FROM todos
+        WHERE user_id = ? AND deleted_at IS NOT NULL
+        ORDER BY deleted_at DESC, id DESC
+        "#,
+        user_id
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(todos)
+}
+
+pub async fn restore_todo(pool: &SqlitePool, user_id: i64, id: i64) -> Result<Todo, InternalError> {
+    let mut tx = begin_write(pool).await?;
+    let before = sqlx::query_as!(
+        Todo,
+        "SELECT * FROM todos WHERE id = ? AND user_id = ? AND deleted_at IS NOT NULL",
+        id,
+        user_id
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    let todo = sqlx::query_as!(
+        Todo,
+        r#"
+        UPDATE todos
+        SET deleted_at = NULL, version = version + 1,
+            parent_id = (
+                SELECT parent.id FROM todos parent
+                WHERE parent.id = todos.parent_id AND parent.deleted_at IS NULL
+            )
+        WHERE id = ? AND user_id = ? AND deleted_at IS NOT NULL
+        RETURNING
+            id AS "id!", user_id, title, description, completed, due_at, created_at, updated_at,
+            version, deleted_at, parent_id, recurrence
+        "#,
+        id,
+        user_id
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    record_event(
+        &mut tx,
+        Some(user_id),
+        EventKind::Restore,
+        Some(&before),
+        Some(&todo),
+    )
+    .await?;
+    tx.commit().await?;
+    Ok(todo)
+}
+
+/// Permanently removes every todo trashed before the given time, returning
+/// how many were removed.
+pub async fn purge_trash(pool: &SqlitePool, before: NaiveDateTime) -> Result<u64, InternalError> {
+    let mut tx = begin_write(pool).await?;
+    let todos = sqlx::query_as!(
+        Todo,
+        "SELECT * FROM todos WHERE deleted_at IS NOT NULL AND deleted_at < ?",
+        before
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+    for todo in &todos {
+        record_event(&mut tx, None, EventKind::Purge, Some(todo), None).await?;
+        sqlx::query!("DELETE FROM todos WHERE id = ?", todo.id)
+            .execute(&mut *tx)
+            .await?;
+    }
+    tx.commit().await?;
+    Ok(todos.len() as u64)
+}
+
+/// Lists the changes made to a todo, oldest first. The history of trashed
+/// todos remains available.
+pub async fn list_history(
+    pool: &SqlitePool,
+    user_id: i64,
+    todo_id: i64,
+) -> Result<Vec<TodoEvent>, InternalError> {
+    let mut tx = pool.begin().await?;
+    sqlx::query_scalar!(
+        "SELECT id FROM todos WHERE id = ? AND user_id = ?",
+        todo_id,
+        user_id
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    let events = sqlx::query_as!(
+        TodoEvent,
+        r#"
+        SELECT
+            id, todo_id, user_id, kind AS "kind: EventKind", version,
+            before AS "before: Json<Todo>", after AS "after: Json<Todo>", created_at
+        FROM todo_events
+        WHERE todo_id = ?
+        ORDER BY id
+        "#,
+        todo_id
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    Ok(events)
+}
+
+/// Brings the fields of a todo back to how they were at a previous version.
+pub async fn revert_todo(
+    pool: &SqlitePool,
+    user_id: i64,
+    id: i64,
+    version: i64,
+    if_match: &IfMatch,
+) -> Result<Todo, InternalError> {
+    let mut tx = begin_write(pool).await?;
+    let before = current_todo(&mut tx, user_id, id, if_match).await?;
+    let snapshot = sqlx::query_scalar!(
+        r#"
+        SELECT after AS "after!: Json<Todo>" FROM todo_events
+        WHERE todo_id = ? AND version = ? AND after IS NOT NULL
+        "#,
+        id,
+        version
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or_else(|| InternalError::Constraint(FieldError::new("version", "Unknown version")))?;
+
+    let todo = update_todo_row(&mut tx, user_id, id, UpdateTodo::from(snapshot.0)).await?;
+    record_event(
+        &mut tx,
+        Some(user_id),
+        EventKind::Revert,
+        Some(&before),
+        Some(&todo),
+    )
+    .await?;
+    tx.commit().await?;
+    Ok(todo)
+}
+
+/// Records a change made to a todo, given its state before and after, and
+/// queues it for the webhooks of the todo's owner.
+async fn record_event(
+    conn: &mut SqliteConnection,
+    user_id: Option<i64>,
+    kind: EventKind,
+    before: Option<&Todo>,
+    after: Option<&Todo>,
+) -> Result<(), InternalError> {
+    let Some(todo) = after.or(before) else {
+        return Ok(());
+    };
+    let owner_id = todo.user_id;
+    let before = before.map(Json);
+    let after = after.map(Json);
+    let event_id = sqlx::query_scalar!(
+        r#"
+        INSERT INTO todo_events (todo_id, user_id, kind, version, before, after)
+        VALUES (?, ?, ?, ?, ?, ?)
+        RETURNING id AS "id!"
+        "#,
+        todo.id,
+        user_id,
+        kind,
+        todo.version,
+        before,
+        after
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+    // Queued along with the change, so that no event is lost if the server
+    // stops before delivering it
+    sqlx::query!(
+        "INSERT INTO webhook_deliveries (webhook_id, event_id) SELECT id, ? FROM webhooks WHERE user_id = ?",
+        event_id,
+        owner_id
+    )
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+/// Reads a todo about to be changed, failing unless its current version is
+/// matched.
+async fn current_todo(
+    conn: &mut SqliteConnection,
+    user_id: i64,
+    id: i64,
+    if_match: &IfMatch,
+) -> Result<Todo, InternalError> {
+    let todo = sqlx::query_as!(
+        Todo,
+        "SELECT * FROM todos WHERE id = ? AND user_id = ? AND deleted_at IS NULL",
+        id,
+        user_id
+    )
+    .fetch_one(conn)
+    .await?;
+    if !if_match.matches(todo.version) {
+        return Err(InternalError::PreconditionFailed);
+    }
+    Ok(todo)
+}
+
+/// Bumps the version of a todo whose tags changed, as its representation
+/// embeds them, and records the change. Like any other update, it is queued
+/// for the webhooks of the owner.
+async fn touch_todo(
+    conn: &mut SqliteConnection,
+    user_id: i64,
+    id: i64,
+) -> Result<Todo, InternalError> {
+    let before = sqlx::query_as!(
+        Todo,
+        "SELECT * FROM todos WHERE id = ? AND user_id = ?",
+        id,
+        user_id
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+    let after = Todo {
+        version: before.version + 1,
+        ..before.clone()
+    };
+    sqlx::query!(
+        "UPDATE todos SET version = ? WHERE id = ?",
+        after.version,
+        id
+    )
+    .execute(&mut *conn)
+    .await?;
+    record_event(
+        conn,
+        Some(user_id),
+        EventKind::Update,
+        Some(&before),
+        Some(&after),
+    )
+    .await?;
+    Ok(after)
+}
+
+/// Touches the todos carrying a tag which is renamed or deleted, leaving those
+/// in the trash alone.
+async fn touch_tagged_todos(
+    conn: &mut SqliteConnection,
+    user_id: i64,
+    tag_id: i64,
+) -> Result<Vec<Todo>, InternalError> {
+    let ids = sqlx::query_scalar!(
+        r#"
+        SELECT todos.id AS "id!" FROM todos JOIN todo_tags ON todo_tags.todo_id = todos.id
+        WHERE todos.user_id = ? AND todo_tags.tag_id = ? AND todos.deleted_at IS NULL
+        ORDER BY todos.id
+        "#,
+        user_id,
+        tag_id
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+    let mut todos = Vec::new();
+    for id in ids {
+        todos.push(touch_todo(conn, user_id, id).await?);
+    }
+    Ok(todos)
+}
+
+pub async fn create_user(
+    pool: &SqlitePool,
+    username: &str,
+    password_hash: &str,
+) -> Result<User, InternalError> {
+    let mut tx = begin_write(pool).await?;
+    let user = sqlx::query_as!(
+        User,
+        r#"
+        INSERT INTO users (username, password_hash)
+        VALUES (?, ?)
+        RETURNING id AS "id!", username, created_at
+        "#,
+        username,
+        password_hash
+    )
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(conflict(user::USERNAME_TAKEN))?;
+    tx.commit().await?;
+    Ok(user)
+}
+
+/// Reports a write breaking a unique constraint as a conflict with the given
+/// message, rather than with SQLite's, which names the schema.
+fn conflict(message: &'static str) -> impl FnOnce(sqlx::Error) -> InternalError {
+    move |err| match err {
+        sqlx::Error::Database(err) if err.is_unique_violation() => {
+            InternalError::Conflict(message.to_string())
+        }
+        err => InternalError::Sql(err),
+    }
+}
+
+/// Returns the id and password hash of the user with the given username.
+pub async fn get_user_password_hash(
+    pool: &SqlitePool,
+    username: &str,
+) -> Result<Option<(i64, String)>, InternalError> {
+    let user = sqlx::query!(
+        "SELECT id, password_hash FROM users WHERE username = ?",
+        username
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(user.map(|user| (user.id, user.password_hash)))
+}
+
+pub async fn claim_orphans(
+    pool: &SqlitePool,
+    username: &str,
+) -> Result<Option<u64>, InternalError> {
+    let mut tx = begin_write(pool).await?;
+    let Some(user_id) = sqlx::query_scalar!("SELECT id FROM users WHERE username = ?", username)
+        .fetch_optional(&mut *tx)
+        .await?
+    else {
+        return Ok(None);
+    };
+    let claimed = sqlx::query!(
+        "UPDATE todos SET user_id = ? WHERE user_id IS NULL",
+        user_id
+    )
+    .execute(&mut *tx)
+    .await?
+    .rows_affected();
+    tx.commit().await?;
+    Ok(Some(claimed))
+}
+
+#[derive(FromRow)]
+struct SearchRow {
+    #[sqlx(flatten)]
+    todo: Todo,
+    title_highlight: String,
+    description_snippet: String,
+}
+
+/// Searches the todos text, best matches first, with titles weighted above
+/// descriptions.
+pub async fn search_todos(
+    pool: &SqlitePool,
+    user_id: i64,
+    params: &SearchParams,
+) -> Result<Vec<SearchHit>, InternalError> {
+    let rows: Vec<SearchRow> = sqlx::query_as(
+        r#"
+        SELECT todos.*,
+          highlight(todos_fts, 0, ?1, ?2) AS title_highlight,
+          snippet(todos_fts, 1, ?1, ?2, '…', 16) AS description_snippet
+        FROM todos_fts JOIN todos ON todos.id = todos_fts.rowid
+        WHERE todos_fts MATCH ?3 AND todos.user_id = ?4 AND todos.deleted_at IS NULL
+        ORDER BY bm25(todos_fts, 10.0, 1.0), todos.id
+        LIMIT ?5
+        "#,
+    )
+    .bind(HIGHLIGHT_START)
+    .bind(HIGHLIGHT_END)
+    .bind(params.expression())
+    .bind(user_id)
+    .bind(params.limit)
+    .fetch_all(pool)
+    .await?;
+
+    let (todos, highlights): (Vec<_>, Vec<_>) = rows
+        .into_iter()
+        .map(|row| {
+            let highlights = Highlights {
+                title: row.title_highlight,
+                description: row.description_snippet,
+            };
+            (row.todo, highlights)
+        })
+        .unzip();
+    let todos = tag_todos(pool, todos).await?;
+    let hits = todos
+        .into_iter()
+        .zip(highlights)
+        .map(|(todo, highlights)| SearchHit { todo, highlights })
+        .collect();
+    Ok(hits)
+}
+
+pub async fn list_tags(pool: &SqlitePool, user_id: i64) -> Result<Vec<Tag>, InternalError> {
+    let tags = sqlx::query_as!(
+        Tag,
+        "SELECT id, name FROM tags WHERE user_id = ? ORDER BY name",
+        user_id
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(tags)
+}
+
+pub async fn get_tag(pool: &SqlitePool, user_id: i64, id: i64) -> Result<Tag, InternalError> {
+    let tag = sqlx::query_as!(
+        Tag,
+        "SELECT id, name FROM tags WHERE id = ? AND user_id = ?",
+        id,
+        user_id
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok(tag)
+}
+
+pub async fn create_tag(
+    pool: &SqlitePool,
+    user_id: i64,
+    tag: CreateTag,
+) -> Result<Tag, InternalError> {
+    let mut tx = begin_write(pool).await?;
+    let tag = sqlx::query_as!(
+        Tag,
+        r#"INSERT INTO tags (user_id, name) VALUES (?, ?) RETURNING id AS "id!", name"#,
+        user_id,
+        tag.name
+    )
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(conflict(tag::NAME_TAKEN))?;
+    tx.commit().await?;
+    Ok(tag)
+}
+
+pub async fn update_tag(
+    pool: &SqlitePool,
+    user_id: i64,
+    id: i64,
+    tag: UpdateTag,
+) -> Result<ChangedTag, InternalError> {
+    let mut tx = begin_write(pool).await?;
+    let tag = sqlx::query_as!(
+        Tag,
+        r#"UPDATE tags SET name = ? WHERE id = ? AND user_id = ? RETURNING id AS "id!", name"#,
+        tag.name,
+        id,
+        user_id
+    )
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(conflict(tag::NAME_TAKEN))?;
+    let todos = touch_tagged_todos(&mut tx, user_id, id).await?;
+    tx.commit().await?;
+    Ok(ChangedTag { tag, todos })
+}
+
+pub async fn delete_tag(
+    pool: &SqlitePool,
+    user_id: i64,
+    id: i64,
+) -> Result<ChangedTag, InternalError> {
+    let mut tx = begin_write(pool).await?;
+    let todos = touch_tagged_todos(&mut tx, user_id, id).await?;
+    let tag = sqlx::query_as!(
+        Tag,
+        r#"DELETE FROM tags WHERE id = ? AND user_id = ? RETURNING id AS "id!", name"#,
+        id,
+        user_id
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    Ok(ChangedTag { tag, todos })
+}
+
+pub async fn list_todo_tags(
+    pool: &SqlitePool,
+    user_id: i64,
+    todo_id: i64,
+) -> Result<Vec<Tag>, InternalError> {
+    let todo = get_todo(pool, user_id, todo_id).await?;
+    let todo = tag_todo(pool, todo).await?;
+    Ok(todo.tags)
+}
+
+pub async fn attach_tag(
+    pool: &SqlitePool,
+    user_id: i64,
+    todo_id: i64,
+    tag_id: i64,
+) -> Result<TaggedTodo, InternalError> {
+    let mut tx = begin_write(pool).await?;
+    let result = sqlx::query!(
+        r#"
+        INSERT OR IGNORE INTO todo_tags (todo_id, tag_id)
+        SELECT todos.id, tags.id FROM todos, tags
+        WHERE todos.id = ? AND todos.user_id = ? AND todos.deleted_at IS NULL
+          AND tags.id = ? AND tags.user_id = ?
+        "#,
+        todo_id,
+        user_id,
+        tag_id,
+        user_id
+    )
+    .execute(&mut *tx)
+    .await?;
+    if result.rows_affected() > 0 {
+        touch_todo(&mut tx, user_id, todo_id).await?;
+    }
+    let todo = get_tagged_todo(&mut tx, user_id, todo_id).await?;
+    if !todo.tags.iter().any(|tag| tag.id == tag_id) {
+        return Err(sqlx::Error::RowNotFound.into());
+    }
+    tx.commit().await?;
+    Ok(todo)
+}
+
+pub async fn detach_tag(
+    pool: &SqlitePool,
+    user_id: i64,
+    todo_id: i64,
+    tag_id: i64,
+) -> Result<TaggedTodo, InternalError> {
+    let mut tx = begin_write(pool).await?;
+    let result = sqlx::query!(
+        r#"
+        DELETE FROM todo_tags
+        WHERE tag_id = ? AND todo_id = (
+          SELECT id FROM todos WHERE id = ? AND user_id = ? AND deleted_at IS NULL
+        )
+        "#,
+        tag_id,
+        todo_id,
+        user_id
+    )
+    .execute(&mut *tx)
+    .await?;
+    if result.rows_affected() == 0 {
+        return Err(sqlx::Error::RowNotFound.into());
+    }
+    touch_todo(&mut tx, user_id, todo_id).await?;
+    let todo = get_tagged_todo(&mut tx, user_id, todo_id).await?;
+    tx.commit().await?;
+    Ok(todo)
+}
+
+async fn get_tagged_todo(
+    conn: &mut SqliteConnection,
+    user_id: i64,
+    id: i64,
+) -> Result<TaggedTodo, InternalError> {
+    let todo = sqlx::query_as!(
+        Todo,
+        "SELECT * FROM todos WHERE id = ? AND user_id = ? AND deleted_at IS NULL",
+        id,
+        user_id
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+    let mut todos = tag_todos(conn, vec![todo]).await?;
+    todos.pop().ok_or(sqlx::Error::RowNotFound.into())
+}
+
+/// Embeds the tags of every given todo, fetching them in a single query.
+pub async fn tag_todos<'c, E>(
+    executor: E,
+    todos: Vec<Todo>,
+) -> Result<Vec<TaggedTodo>, InternalError>
+where
+    E: Executor<'c, Database = Sqlite>,
+{
+    if todos.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let mut query = QueryBuilder::new(
+        "SELECT todo_tags.todo_id, tags.id, tags.name FROM todo_tags \
+         JOIN tags ON tags.id = todo_tags.tag_id WHERE todo_tags.todo_id IN (",
+    );
+    let mut ids = query.separated(", ");
+    for todo in &todos {
+        ids.push_bind(todo.id);
+    }
+    query.push(") ORDER BY tags.name");
+    let rows: Vec<(i64, i64, String)> = query.build_query_as().fetch_all(executor).await?;
+
+    let mut tags: HashMap<i64, Vec<Tag>> = HashMap::new();
+    for (todo_id, id, name) in rows {
+        tags.entry(todo_id).or_default().push(Tag { id, name });
+    }
+    let todos = todos
+        .into_iter()
+        .map(|todo| TaggedTodo {
+            tags: tags.remove(&todo.id).unwrap_or_default(),
+            todo,
+        })
+        .collect();
+    Ok(todos)
+}
+
+pub async fn tag_todo(pool: &SqlitePool, todo: Todo) -> Result<TaggedTodo, InternalError> {
+    let mut todos = tag_todos(pool, vec![todo]).await?;
+    todos.pop().ok_or(sqlx::Error::RowNotFound.into())
+}
+
+/// Restricts rows to todos carrying every one of the given tag names.
+fn push_tagged(query: &mut QueryBuilder<Sqlite>, tags: &[String]) {
+    query.push(
+        " AND id IN (SELECT todo_tags.todo_id FROM todo_tags \
+         JOIN tags ON tags.id = todo_tags.tag_id WHERE tags.name IN (",
+    );
+    let mut names = query.separated(", ");
+    for tag in tags {
+        names.push_bind(tag.clone());
+    }
+    let distinct = tags.iter().collect::<HashSet<_>>().len() as i64;
+    query
+        .push(") GROUP BY todo_tags.todo_id HAVING COUNT(DISTINCT tags.name) = ")
+        .push_bind(distinct)
+        .push(")");
+}
+
+fn push_contains(query: &mut QueryBuilder<Sqlite>, column: &str, value: &str) {
+    let escaped = value
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_");
+    query
+        .push(format!(" AND {column} LIKE "))
+        .push_bind(format!("%{escaped}%"))
+        .push(" ESCAPE '\\'");
+}
+
+/// Restricts rows to those strictly after the cursor in sort order, i.e.
+/// `(k1 > v1) OR (k1 IS v1 AND k2 > v2) OR ...` with `<` for descending keys.
+fn push_after(query: &mut QueryBuilder<Sqlite>, params: &ListParams, values: &[CursorValue]) {
+    let keys = params.sort.keys();
+    query.push(" AND (");
+    for (i, key) in keys.iter().enumerate() {
+        if i > 0 {
+            query.push(" OR ");
+        }
+        query.push("(");
+        for (previous, value) in keys.iter().zip(values).take(i) {
+            query.push(format!("{} IS ", previous.field.column()));
+            push_value(query, value);
+            query.push(" AND ");
+        }
+        push_beyond(query, key.field.column(), key.descending, &values[i]);
+        query.push(")");
+    }
+    query.push(")");
+}
+
+/// SQLite sorts NULLs first, so they precede every value in ascending order
+/// and follow every value in descending order.
+fn push_beyond(
+    query: &mut QueryBuilder<Sqlite>,
+    column: &str,
+    descending: bool,
+    value: &CursorValue,
+) {
+    match (value, descending) {
+        (CursorValue::Null, false) => {
+            query.push(format!("{column} IS NOT NULL"));
+        }
+        (CursorValue::Null, true) => {
+            query.push("FALSE");
+        }
+        (value, false) => {
+            query.push(format!("{column} > "));
+            push_value(query, value);
+        }
+        (value, true) => {
+            query.push(format!("({column} IS NULL OR {column} < "));
+            push_value(query, value);
+            query.push(")");
+        }
+    }
+}
+
+fn push_value(query: &mut QueryBuilder<Sqlite>, value: &CursorValue) {
+    match value {
+        CursorValue::Null => query.push("NULL"),
+        CursorValue::Integer(value) => query.push_bind(*value),
+        CursorValue::Text(value) => query.push_bind(value.clone()),
+    };
+}
+
+pub async fn list_webhooks(pool: &SqlitePool, user_id: i64) -> Result<Vec<Webhook>, InternalError> {
+    let webhooks = sqlx::query_as!(
+        Webhook,
+        "SELECT id, url, secret, created_at FROM webhooks WHERE user_id = ? ORDER BY id",
+        user_id
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(webhooks)
+}
+
+pub async fn get_webhook(
+    pool: &SqlitePool,
+    user_id: i64,
+    id: i64,
+) -> Result<Webhook, InternalError> {
+    let webhook = sqlx::query_as!(
+        Webhook,
+        "SELECT id, url, secret, created_at FROM webhooks WHERE id = ? AND user_id = ?",
+        id,
+        user_id
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok(webhook)
+}
+
+pub async fn create_webhook(
+    pool: &SqlitePool,
+    user_id: i64,
+    webhook: CreateWebhook,
+    secret: &str,
+) -> Result<Webhook, InternalError> {
+    let mut tx = begin_write(pool).await?;
+    let webhook = sqlx::query_as!(
+        Webhook,
+        r#"
+        INSERT INTO webhooks (user_id, url, secret) VALUES (?, ?, ?)
+        RETURNING id AS "id!", url, secret, created_at
+        "#,
+        user_id,
+        webhook.url,
+        secret
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    Ok(webhook)
+}
+
+pub async fn delete_webhook(
+    pool: &SqlitePool,
+    user_id: i64,
+    id: i64,
+) -> Result<Webhook, InternalError> {
+    let mut tx = begin_write(pool).await?;
+    let webhook = sqlx::query_as!(
+        Webhook,
+        r#"
+        DELETE FROM webhooks WHERE id = ? AND user_id = ?
+        RETURNING id AS "id!", url, secret, created_at
+        "#,
+        id,
+        user_id
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    Ok(webhook)
+}
+
+/// Row of a delivery joined with its webhook and event, which is missing for
+/// reminders.
+#[derive(FromRow)]
+struct DeliveryRow {
+    id: i64,
+    webhook_id: i64,
+    attempts: i64,
+    next_attempt_at: NaiveDateTime,
+    last_error: Option<String>,
+    failed_at: Option<NaiveDateTime>,
+    url: String,
+    secret: String,
+    webhook_created_at: NaiveDateTime,
+    reminder: Option<Json<ReminderEvent>>,
+    event_id: Option<i64>,
+    todo_id: Option<i64>,
+    user_id: Option<i64>,
+    kind: Option<EventKind>,
+    version: Option<i64>,
+    before: Option<Json<Todo>>,
+    after: Option<Json<Todo>>,
+    created_at: Option<NaiveDateTime>,
+}
+
+impl DeliveryRow {
+    const SELECT: &'static str = r#"
+        SELECT
+            d.id, d.webhook_id, d.attempts, d.next_attempt_at, d.last_error, d.failed_at,
+            w.url, w.secret, w.created_at AS webhook_created_at, d.reminder,
+            e.id AS event_id, e.todo_id, e.user_id, e.kind, e.version, e.before, e.after,
+            e.created_at
+        FROM webhook_deliveries d
+        JOIN webhooks w ON w.id = d.webhook_id
+        LEFT JOIN todo_events e ON e.id = d.event_id
+    "#;
+
+    fn split(self) -> Result<(Webhook, Delivery), InternalError> {
+        let webhook = Webhook {
+            id: self.webhook_id,
+            url: self.url,
+            secret: self.secret,
+            created_at: self.webhook_created_at,
+        };
+        let event = match (
+            self.reminder,
+            self.event_id,
+            self.todo_id,
+            self.kind,
+            self.version,
+            self.created_at,
+        ) {
+            (Some(Json(reminder)), ..) => Payload::Reminder(reminder),
+            (None, Some(id), Some(todo_id), Some(kind), Some(version), Some(created_at)) => {
+                Payload::Event(TodoEvent {
+                    id,
+                    todo_id,
+                    user_id: self.user_id,
+                    kind,
+                    version,
+                    before: self.before,
+                    after: self.after,
+                    created_at,
+                })
+            }
+            // Ruled out by the check of the table
+            _ => {
+                let err = "Delivery of neither an event nor a reminder".into();
+                return Err(sqlx::Error::Decode(err).into());
+            }
+        };
+        let delivery = Delivery {
+            id: self.id,
+            webhook_id: self.webhook_id,
+            event,
+            attempts: self.attempts,
+            next_attempt_at: self.next_attempt_at,
+            last_error: self.last_error,
+            failed_at: self.failed_at,
+        };
+        Ok((webhook, delivery))
+    }
+}
+
+/// Lists the deliveries to a webhook which were given up, oldest first.
+pub async fn list_dead_letters(
+    pool: &SqlitePool,
+    user_id: i64,
+    webhook_id: i64,
+) -> Result<Vec<Delivery>, InternalError> {
+    let mut tx = pool.begin().await?;
+    sqlx::query_scalar!(
+        "SELECT id FROM webhooks WHERE id = ? AND user_id = ?",
+        webhook_id,
+        user_id
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    let query = format!(
+        "{} WHERE d.webhook_id = ? AND d.failed_at IS NOT NULL ORDER BY d.id",
+        DeliveryRow::SELECT
+    );
+    let rows: Vec<DeliveryRow> = sqlx::query_as(&query)
+        .bind(webhook_id)
+        .fetch_all(&mut *tx)
+        .await?;
+    tx.commit().await?;
+    rows.into_iter().map(|row| Ok(row.split()?.1)).collect()
+}
+
+/// Queues a dead letter again, as a new delivery.
+pub async fn retry_dead_letter(
+    pool: &SqlitePool,
+    user_id: i64,
+    webhook_id: i64,
+    id: i64,
+) -> Result<Delivery, InternalError> {
+    let mut tx = begin_write(pool).await?;
+    sqlx::query!(
+        r#"
+        UPDATE webhook_deliveries
+        SET attempts = 0, next_attempt_at = CURRENT_TIMESTAMP, last_error = NULL, failed_at = NULL
+        WHERE id = ? AND webhook_id = ? AND failed_at IS NOT NULL
+        AND webhook_id IN (SELECT id FROM webhooks WHERE user_id = ?)
+        RETURNING id
+        "#,
+        id,
+        webhook_id,
+        user_id
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    let query = format!("{} WHERE d.id = ?", DeliveryRow::SELECT);
+    let row: DeliveryRow = sqlx::query_as(&query).bind(id).fetch_one(&mut *tx).await?;
+    tx.commit().await?;
+    Ok(row.split()?.1)
+}
 
-pub async fn list_todos(pool: &SqlitePool) -> Result<Vec<Todo>, InternalError> {
-    let todos: Vec<Todo> = sqlx::query_as!(Todo, "SELECT * FROM todos")
+/// Lists the deliveries due at the given time, oldest first, along with their
+/// webhook.
+pub async fn due_deliveries(
+    pool: &SqlitePool,
+    now: NaiveDateTime,
+    limit: u32,
+) -> Result<Vec<(Webhook, Delivery)>, InternalError> {
+    let query = format!(
+        "{} WHERE d.failed_at IS NULL AND d.next_attempt_at <= ? ORDER BY d.id LIMIT ?",
+        DeliveryRow::SELECT
+    );
+    let rows: Vec<DeliveryRow> = sqlx::query_as(&query)
+        .bind(now)
+        .bind(limit)
         .fetch_all(pool)
         .await?;
-    Ok(todos)
+    rows.into_iter().map(DeliveryRow::split).collect()
 }
 
-pub async fn get_todo(pool: &SqlitePool, id: i64) -> Result<Todo, InternalError> {
-    let todo = sqlx::query_as!(Todo, "SELECT * FROM todos WHERE id = ?", id)
-        .fetch_one(pool)
+pub async fn complete_delivery(pool: &SqlitePool, id: i64) -> Result<(), InternalError> {
+    sqlx::query!("DELETE FROM webhook_deliveries WHERE id = ?", id)
+        .execute(pool)
         .await?;
-    Ok(todo)
+    Ok(())
 }
 
-pub async fn create_todo(pool: &SqlitePool, todo: CreateTodo) -> Result<Todo, InternalError> {
-    let todo = sqlx::query_as!(
-        Todo,
-        "INSERT INTO todos (title, description) VALUES (?, ?) RETURNING *",
-        todo.title,
-        todo.description
+/// Records a failed attempt at a delivery, which is retried at the given time,
+/// or else given up.
+pub async fn fail_delivery(
+    pool: &SqlitePool,
+    id: i64,
+    error: &str,
+    retry_at: Option<NaiveDateTime>,
+) -> Result<(), InternalError> {
+    sqlx::query!(
+        r#"
+        UPDATE webhook_deliveries
+        SET attempts = attempts + 1, last_error = ?,
+            next_attempt_at = COALESCE(?, next_attempt_at),
+            failed_at = CASE WHEN ? IS NULL THEN CURRENT_TIMESTAMP END
+        WHERE id = ?
+        "#,
+        error,
+        retry_at,
+        retry_at,
+        id
     )
-    .fetch_one(pool)
+    .execute(pool)
     .await?;
-    Ok(todo)
+    Ok(())
 }
 
-pub async fn update_todo(
+/// Lists the reminders of a todo, oldest first.
+pub async fn list_reminders(
     pool: &SqlitePool,
+    user_id: i64,
+    todo_id: i64,
+) -> Result<Vec<Reminder>, InternalError> {
+    let mut tx = pool.begin().await?;
+    current_todo(&mut tx, user_id, todo_id, &IfMatch::Any).await?;
+    let reminders = sqlx::query_as!(
+        Reminder,
+        "SELECT * FROM reminders WHERE todo_id = ? ORDER BY id",
+        todo_id
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    Ok(reminders)
+}
+
+pub async fn create_reminder(
+    pool: &SqlitePool,
+    user_id: i64,
+    todo_id: i64,
+    reminder: CreateReminder,
+) -> Result<Reminder, InternalError> {
+    let mut tx = begin_write(pool).await?;
+    current_todo(&mut tx, user_id, todo_id, &IfMatch::Any).await?;
+    let reminder = sqlx::query_as!(
+        Reminder,
+        r#"
+        INSERT INTO reminders (todo_id, offset_seconds) VALUES (?, ?)
+        RETURNING id AS "id!", todo_id, offset_seconds, fired_at, fired_for, created_at
+        "#,
+        todo_id,
+        reminder.offset_seconds
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    Ok(reminder)
+}
+
+pub async fn delete_reminder(
+    pool: &SqlitePool,
+    user_id: i64,
+    todo_id: i64,
     id: i64,
-    todo: UpdateTodo,
-) -> Result<Todo, InternalError> {
-    sqlx::query_as!(
-        Todo,
-        "UPDATE todos SET title = ?, description = ? WHERE id = ?",
-        todo.title,
-        todo.description,
+) -> Result<Reminder, InternalError> {
+    let mut tx = begin_write(pool).await?;
+    current_todo(&mut tx, user_id, todo_id, &IfMatch::Any).await?;
+    let reminder = sqlx::query_as!(
+        Reminder,
+        r#"
+        DELETE FROM reminders WHERE id = ? AND todo_id = ?
+        RETURNING id AS "id!", todo_id, offset_seconds, fired_at, fired_for, created_at
+        "#,
         id,
+        todo_id
     )
-    .execute(pool)
+    .fetch_one(&mut *tx)
     .await?;
-    let todo = get_todo(pool, id).await?;
-    Ok(todo)
+    tx.commit().await?;
+    Ok(reminder)
+}
+
+/// Gives the next occurrence of a recurring todo the reminders of the previous
+/// one.
+async fn copy_reminders(
+    conn: &mut SqliteConnection,
+    from_id: i64,
+    to_id: i64,
+) -> Result<(), InternalError> {
+    sqlx::query!(
+        r#"
+        INSERT INTO reminders (todo_id, offset_seconds)
+        SELECT ?, offset_seconds FROM reminders WHERE todo_id = ? ORDER BY id
+        "#,
+        to_id,
+        from_id
+    )
+    .execute(conn)
+    .await?;
+    Ok(())
 }
 
-pub async fn delete_todo(pool: &SqlitePool, id: i64) -> Result<Todo, InternalError> {
-    let todo = sqlx::query_as!(Todo, "DELETE FROM todos WHERE id = ? RETURNING *", id)
-        .fetch_one(pool)
+/// Marks the reminders due at the given time as fired and returns them, oldest
+/// first. A single statement selects and marks them, so that concurrent
+/// callers never fire the same reminder twice. They are queued for the
+/// webhooks of their todo's owner in the same transaction.
+pub async fn fire_due_reminders(
+    pool: &SqlitePool,
+    now: NaiveDateTime,
+    limit: u32,
+) -> Result<Vec<FiredReminder>, InternalError> {
+    let mut tx = begin_write(pool).await?;
+    let mut reminders = sqlx::query_as!(
+        Reminder,
+        r#"
+        UPDATE reminders
+        SET fired_at = ?1,
+            fired_for = (SELECT due_at FROM todos WHERE todos.id = reminders.todo_id)
+        WHERE id IN (
+            SELECT r.id FROM reminders r
+            JOIN todos t ON t.id = r.todo_id
+            WHERE t.deleted_at IS NULL AND NOT t.completed AND t.due_at IS NOT NULL
+            AND r.fired_for IS NOT t.due_at
+            AND datetime(t.due_at, '-' || r.offset_seconds || ' seconds') <= datetime(?1)
+            ORDER BY r.id
+            LIMIT ?2
+        )
+        RETURNING id AS "id!", todo_id, offset_seconds, fired_at, fired_for, created_at
+        "#,
+        now,
+        limit
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+    reminders.sort_by_key(|reminder| reminder.id);
+
+    let mut fired = Vec::with_capacity(reminders.len());
+    for reminder in reminders {
+        let todo = sqlx::query_as!(Todo, "SELECT * FROM todos WHERE id = ?", reminder.todo_id)
+            .fetch_one(&mut *tx)
+            .await?;
+        let fired_reminder = FiredReminder { reminder, todo };
+        let event = Json(ReminderEvent::from(fired_reminder.clone()));
+        sqlx::query!(
+            "INSERT INTO webhook_deliveries (webhook_id, reminder) SELECT id, ? FROM webhooks WHERE user_id = ?",
+            event,
+            fired_reminder.todo.user_id
+        )
+        .execute(&mut *tx)
         .await?;
-    Ok(todo)
+        fired.push(fired_reminder);
+    }
+    tx.commit().await?;
+    Ok(fired)
 }
 
 #[cfg(test)]
 mod test {
     use crate::{
+        batch::{Batch, BatchMode, Operation},
         db,
-        error::InternalError,
+        error::{FieldError, InternalError},
+        etag::IfMatch,
+        history::EventKind,
+        query::{ListParams, Position},
+        reminder::CreateReminder,
+        search::{SearchParams, SearchQuery},
+        subtask::DeletePolicy,
+        tag::{CreateTag, Tag, UpdateTag, NAME_TAKEN},
+        test::{fixture_todos, timestamp, ALICE, BOB},
         todo::{CreateTodo, Todo, UpdateTodo},
+        user::USERNAME_TAKEN,
+        webhook::CreateWebhook,
     };
     use assert_matches::assert_matches;
     use sqlx::SqlitePool;
 
-    #[sqlx::test(fixtures("test/fixtures/todos.sql"))]
+    #[sqlx::test(fixtures("test/fixtures/users.sql", "test/fixtures/todos.sql"))]
     async fn list_todos(pool: SqlitePool) {
-        let todos = db::list_todos(&pool).await.unwrap();
-        assert_eq!(
-            todos,
-            vec![
-                Todo {
-                    id: 1,
-                    title: "todo1".to_string(),
-                    description: "description1".to_string()
-                },
-                Todo {
-                    id: 2,
-                    title: "todo2".to_string(),
-                    description: "description2".to_string()
-                },
-                Todo {
-                    id: 3,
-                    title: "todo3".to_string(),
-                    description: "description3".to_string()
-                }
-            ]
-        );
+        let todos = db::list_todos(&pool, ALICE, &ListParams::default())
+            .await
+            .unwrap()
+            .todos;
+        assert_eq!(todos, fixture_todos());
     }
 
     #[sqlx::test]
     async fn list_todos_empty(pool: SqlitePool) {
-        let todos = db::list_todos(&pool).await.unwrap();
+        let todos = db::list_todos(&pool, ALICE, &ListParams::default())
+            .await
+            .unwrap()
+            .todos;
         assert_eq!(todos, vec![]);
     }
 
-    #[sqlx::test(fixtures("test/fixtures/todos.sql"))]
+    #[sqlx::test(fixtures("test/fixtures/users.sql", "test/fixtures/todos.sql"))]
+    async fn list_todos_filtered_sorted(pool: SqlitePool) {
+        let params = ListParams {
+            sort: "-id".parse().unwrap(),
+            description: Some("description".to_string()),
+            ..Default::default()
+        };
+        let page = db::list_todos(&pool, ALICE, &params).await.unwrap();
+        let ids: Vec<i64> = page.todos.iter().map(|todo| todo.id).collect();
+        assert_eq!(ids, vec![3, 2, 1]);
+        assert_eq!(page.next, None);
+
+        let params = ListParams {
+            title: Some("2".to_string()),
+            ..Default::default()
+        };
+        let page = db::list_todos(&pool, ALICE, &params).await.unwrap();
+        let ids: Vec<i64> = page.todos.iter().map(|todo| todo.id).collect();
+        assert_eq!(ids, vec![2]);
+
+        let params = ListParams {
+            title: Some("%".to_string()),
+            ..Default::default()
+        };
+        let page = db::list_todos(&pool, ALICE, &params).await.unwrap();
+        assert_eq!(page.todos, vec![]);
+    }
+
+    #[sqlx::test(fixtures("test/fixtures/users.sql", "test/fixtures/todos.sql"))]
+    async fn list_todos_completed_due_before(pool: SqlitePool) {
+        let params = ListParams {
+            completed: Some(false),
+            ..Default::default()
+        };
+        let page = db::list_todos(&pool, ALICE, &params).await.unwrap();
+        let ids: Vec<i64> = page.todos.iter().map(|todo| todo.id).collect();
+        assert_eq!(ids, vec![1, 3]);
+
+        let params = ListParams {
+            due_before: Some(timestamp("2024-06-15 00:00:00")),
+            ..Default::default()
+        };
+        let page = db::list_todos(&pool, ALICE, &params).await.unwrap();
+        let ids: Vec<i64> = page.todos.iter().map(|todo| todo.id).collect();
+        assert_eq!(ids, vec![2]);
+
+        let params = ListParams {
+            completed: Some(false),
+            due_before: Some(timestamp("2024-07-01 00:00:00")),
+            ..Default::default()
+        };
+        let page = db::list_todos(&pool, ALICE, &params).await.unwrap();
+        let ids: Vec<i64> = page.todos.iter().map(|todo| todo.id).collect();
+        assert_eq!(ids, vec![3]);
+    }
+
+    #[sqlx::test(fixtures("test/fixtures/users.sql", "test/fixtures/todos.sql"))]
+    async fn list_todos_offset(pool: SqlitePool) {
+        let params = ListParams {
+            limit: 2,
+            position: Position::Offset(1),
+            ..Default::default()
+        };
+        let page = db::list_todos(&pool, ALICE, &params).await.unwrap();
+        let ids: Vec<i64> = page.todos.iter().map(|todo| todo.id).collect();
+        assert_eq!(ids, vec![2, 3]);
+        assert_eq!(page.next, None);
+    }
+
+    #[sqlx::test(fixtures("test/fixtures/users.sql", "test/fixtures/todos.sql"))]
+    async fn list_todos_cursor(pool: SqlitePool) {
+        let mut params = ListParams {
+            limit: 2,
+            sort: "-title".parse().unwrap(),
+            ..Default::default()
+        };
+        let page = db::list_todos(&pool, ALICE, &params).await.unwrap();
+        let ids: Vec<i64> = page.todos.iter().map(|todo| todo.id).collect();
+        assert_eq!(ids, vec![3, 2]);
+        assert_matches!(page.next, Some(Position::After(_)));
+
+        params.position = page.next.unwrap();
+        let page = db::list_todos(&pool, ALICE, &params).await.unwrap();
+        let ids: Vec<i64> = page.todos.iter().map(|todo| todo.id).collect();
+        assert_eq!(ids, vec![1]);
+        assert_eq!(page.next, None);
+    }
+
+    #[sqlx::test(fixtures("test/fixtures/users.sql", "test/fixtures/todos.sql"))]
+    async fn list_todos_cursor_nullable(pool: SqlitePool) {
+        for (sort, expected) in [("due_at", vec![1, 2, 3]), ("-due_at", vec![3, 2, 1])] {
+            let mut params = ListParams {
+                limit: 1,
+                sort: sort.parse().unwrap(),
+                ..Default::default()
+            };
+            let mut ids = Vec::new();
+            loop {
+                let page = db::list_todos(&pool, ALICE, &params).await.unwrap();
+                ids.extend(page.todos.iter().map(|todo| todo.id));
+                match page.next {
+                    Some(next) => params.position = next,
+                    None => break,
+                }
+            }
+            assert_eq!(ids, expected, "{sort}");
+        }
+    }
+
+    #[sqlx::test(fixtures("test/fixtures/users.sql", "test/fixtures/todos.sql"))]
     async fn get_todo(pool: SqlitePool) {
-        let todo = db::get_todo(&pool, 2).await.unwrap();
-        assert_eq!(
-            todo,
-            Todo {
-                id: 2,
-                title: "todo2".to_string(),
-                description: "description2".to_string()
-            },
-        );
+        let todo = db::get_todo(&pool, ALICE, 2).await.unwrap();
+        assert_eq!(todo, fixture_todos()[1]);
     }
 
     #[sqlx::test]
     async fn get_todo_not_found(pool: SqlitePool) {
-        let err = db::get_todo(&pool, -1).await;
+        let err = db::get_todo(&pool, ALICE, -1).await;
         assert_matches!(err, Err(InternalError::Sql(sqlx::Error::RowNotFound)));
     }
 
-    #[sqlx::test]
+    #[sqlx::test(fixtures("test/fixtures/users.sql"))]
     async fn create_todo(pool: SqlitePool) {
         let created = db::create_todo(
             &pool,
+            ALICE,
             CreateTodo {
                 title: "title".to_string(),
                 description: "description".to_string(),
+                completed: false,
+                due_at: Some(timestamp("2024-06-30 18:00:00")),
+                parent_id: None,
+                recurrence: None,
             },
         )
         .await
@@ -130,47 +1846,66 @@ mod test {
             created,
             Todo {
                 id: 1,
+                user_id: Some(ALICE),
                 title: "title".to_string(),
                 description: "description".to_string(),
+                completed: false,
+                due_at: Some(timestamp("2024-06-30 18:00:00")),
+                created_at: created.created_at,
+                updated_at: created.created_at,
+                version: 1,
+                deleted_at: None,
+                parent_id: None,
+                recurrence: None,
             }
         );
 
-        let todo = db::get_todo(&pool, 1).await.unwrap();
+        let todo = db::get_todo(&pool, ALICE, 1).await.unwrap();
         assert_eq!(todo, created);
     }
 
-    #[sqlx::test(fixtures("test/fixtures/todos.sql"))]
+    #[sqlx::test(fixtures("test/fixtures/users.sql", "test/fixtures/todos.sql"))]
     async fn update_todo(pool: SqlitePool) {
-        let todo = db::get_todo(&pool, 2).await.unwrap();
-        assert_eq!(
-            todo,
-            Todo {
-                id: 2,
-                title: "todo2".to_string(),
-                description: "description2".to_string()
-            },
-        );
+        let todo = db::get_todo(&pool, ALICE, 2).await.unwrap();
+        assert_eq!(todo, fixture_todos()[1]);
 
         let updated = db::update_todo(
             &pool,
+            ALICE,
             2,
             UpdateTodo {
                 title: "title".to_string(),
                 description: "description".to_string(),
+                completed: false,
+                due_at: None,
+                parent_id: None,
+                recurrence: None,
             },
+            &IfMatch::Any,
         )
         .await
-        .unwrap();
+        .unwrap()
+        .todo;
         assert_eq!(
             updated,
             Todo {
                 id: 2,
+                user_id: Some(ALICE),
                 title: "title".to_string(),
                 description: "description".to_string(),
+                completed: false,
+                due_at: None,
+                created_at: todo.created_at,
+                updated_at: updated.updated_at,
+                version: 2,
+                deleted_at: None,
+                parent_id: None,
+                recurrence: None,
             }
         );
+        assert!(updated.updated_at > todo.updated_at);
 
-        let todo = db::get_todo(&pool, 2).await.unwrap();
+        let todo = db::get_todo(&pool, ALICE, 2).await.unwrap();
         assert_eq!(todo, updated);
     }
 
@@ -178,38 +1913,651 @@ mod test {
     async fn update_todo_not_found(pool: SqlitePool) {
         let err = db::update_todo(
             &pool,
+            ALICE,
             999,
             UpdateTodo {
                 title: "title".to_string(),
                 description: "description".to_string(),
+                completed: false,
+                due_at: None,
+                parent_id: None,
+                recurrence: None,
             },
+            &IfMatch::Any,
         )
         .await;
         assert_matches!(err, Err(InternalError::Sql(sqlx::Error::RowNotFound)));
     }
 
-    #[sqlx::test(fixtures("test/fixtures/todos.sql"))]
+    #[sqlx::test(fixtures("test/fixtures/users.sql", "test/fixtures/todos.sql"))]
     async fn delete_todo(pool: SqlitePool) {
-        let todo = db::get_todo(&pool, 2).await.unwrap();
+        let todo = db::get_todo(&pool, ALICE, 2).await.unwrap();
+        assert_eq!(todo, fixture_todos()[1]);
+
+        let deleted = db::delete_todo(&pool, ALICE, 2, &IfMatch::Any, DeletePolicy::Reject)
+            .await
+            .unwrap();
+        assert_eq!(deleted.subtasks, vec![]);
+        let deleted = deleted.todo;
+        assert!(deleted.deleted_at.is_some());
         assert_eq!(
-            todo,
+            deleted,
             Todo {
-                id: 2,
-                title: "todo2".to_string(),
-                description: "description2".to_string()
-            },
+                version: 2,
+                deleted_at: deleted.deleted_at,
+                ..todo
+            }
         );
 
-        let deleted = db::delete_todo(&pool, 2).await.unwrap();
-        assert_eq!(todo, deleted,);
-
-        let err = db::get_todo(&pool, 2).await;
+        let err = db::get_todo(&pool, ALICE, 2).await;
+        assert_matches!(err, Err(InternalError::Sql(sqlx::Error::RowNotFound)));
+        let err = db::delete_todo(&pool, ALICE, 2, &IfMatch::Any, DeletePolicy::Reject).await;
         assert_matches!(err, Err(InternalError::Sql(sqlx::Error::RowNotFound)));
     }
 
     #[sqlx::test]
     async fn delete_todo_not_found(pool: SqlitePool) {
-        let err = db::delete_todo(&pool, -1).await;
+        let err = db::delete_todo(&pool, ALICE, -1, &IfMatch::Any, DeletePolicy::Reject).await;
+        assert_matches!(err, Err(InternalError::Sql(sqlx::Error::RowNotFound)));
+    }
+
+    #[sqlx::test(fixtures("test/fixtures/users.sql", "test/fixtures/todos.sql"))]
+    async fn todos_cross_user(pool: SqlitePool) {
+        let todos = db::list_todos(&pool, BOB, &ListParams::default())
+            .await
+            .unwrap()
+            .todos;
+        assert_eq!(todos, vec![]);
+
+        let err = db::get_todo(&pool, BOB, 1).await;
+        assert_matches!(err, Err(InternalError::Sql(sqlx::Error::RowNotFound)));
+
+        let err = db::delete_todo(&pool, BOB, 1, &IfMatch::Any, DeletePolicy::Reject).await;
+        assert_matches!(err, Err(InternalError::Sql(sqlx::Error::RowNotFound)));
+
+        let todo = db::get_todo(&pool, ALICE, 1).await.unwrap();
+        assert_eq!(todo, fixture_todos()[0]);
+    }
+
+    #[sqlx::test(fixtures("test/fixtures/users.sql"))]
+    async fn create_user(pool: SqlitePool) {
+        let user = db::create_user(&pool, "carol", "hash").await.unwrap();
+        assert_eq!(user.username, "carol");
+
+        let hash = db::get_user_password_hash(&pool, "carol").await.unwrap();
+        assert_eq!(hash, Some((user.id, "hash".to_string())));
+
+        let hash = db::get_user_password_hash(&pool, "dave").await.unwrap();
+        assert_eq!(hash, None);
+
+        let err = db::create_user(&pool, "carol", "hash").await;
+        assert_matches!(err, Err(InternalError::Conflict(message)) if message == USERNAME_TAKEN);
+    }
+
+    #[sqlx::test(fixtures("test/fixtures/users.sql", "test/fixtures/todos.sql"))]
+    async fn claim_orphans(pool: SqlitePool) {
+        sqlx::query("INSERT INTO todos (title, description) VALUES ('orphan', '')")
+            .execute(&pool)
+            .await
+            .unwrap();
+        assert_eq!(db::claim_orphans(&pool, "carol").await.unwrap(), None);
+        let err = db::get_todo(&pool, BOB, 4).await;
+        assert_matches!(err, Err(InternalError::Sql(sqlx::Error::RowNotFound)));
+
+        assert_eq!(db::claim_orphans(&pool, "bob").await.unwrap(), Some(1));
+        assert_eq!(db::get_todo(&pool, BOB, 4).await.unwrap().title, "orphan");
+        // The todos of other users are left alone
+        assert_eq!(
+            db::get_todo(&pool, ALICE, 1).await.unwrap(),
+            fixture_todos()[0]
+        );
+        assert_eq!(db::claim_orphans(&pool, "bob").await.unwrap(), Some(0));
+    }
+
+    /// Rows returned by a write are seen right away from every connection, and
+    /// not only once the statement was reset on its own connection.
+    #[sqlx::test(fixtures("test/fixtures/users.sql"))]
+    async fn returned_rows_committed(pool: SqlitePool) {
+        for i in 0..100 {
+            let user = db::create_user(&pool, &format!("user{i}"), "hash")
+                .await
+                .unwrap();
+            let hash = db::get_user_password_hash(&pool, &user.username).await;
+            assert_eq!(hash.unwrap(), Some((user.id, "hash".to_string())));
+
+            let tag = CreateTag {
+                name: format!("tag{i}"),
+            };
+            let tag = db::create_tag(&pool, ALICE, tag).await.unwrap();
+            assert_eq!(db::get_tag(&pool, ALICE, tag.id).await.unwrap(), tag);
+
+            let webhook = CreateWebhook {
+                url: format!("https://example.com/{i}"),
+            };
+            let webhook = db::create_webhook(&pool, ALICE, webhook, "secret")
+                .await
+                .unwrap();
+            assert_eq!(
+                db::get_webhook(&pool, ALICE, webhook.id).await.unwrap(),
+                webhook
+            );
+            db::delete_webhook(&pool, ALICE, webhook.id).await.unwrap();
+            let err = db::get_webhook(&pool, ALICE, webhook.id).await;
+            assert_matches!(err, Err(InternalError::Sql(sqlx::Error::RowNotFound)));
+        }
+    }
+
+    #[sqlx::test(fixtures(
+        "test/fixtures/users.sql",
+        "test/fixtures/todos.sql",
+        "test/fixtures/tags.sql"
+    ))]
+    async fn list_todos_tagged(pool: SqlitePool) {
+        let ids = |tags: &[&str]| {
+            let params = ListParams {
+                tags: tags.iter().map(|tag| tag.to_string()).collect(),
+                ..Default::default()
+            };
+            let pool = pool.clone();
+            async move {
+                let page = db::list_todos(&pool, ALICE, &params).await.unwrap();
+                page.todos.iter().map(|todo| todo.id).collect::<Vec<_>>()
+            }
+        };
+        assert_eq!(ids(&["home"]).await, vec![1, 2]);
+        assert_eq!(ids(&["home", "work"]).await, vec![2]);
+        assert_eq!(ids(&["work", "work"]).await, vec![2]);
+        assert_eq!(ids(&["unknown"]).await, Vec::<i64>::new());
+    }
+
+    #[sqlx::test(fixtures(
+        "test/fixtures/users.sql",
+        "test/fixtures/todos.sql",
+        "test/fixtures/tags.sql"
+    ))]
+    async fn tag_todos(pool: SqlitePool) {
+        let todos = db::tag_todos(&pool, fixture_todos()).await.unwrap();
+        let tags: Vec<Vec<&str>> = todos
+            .iter()
+            .map(|todo| todo.tags.iter().map(|tag| tag.name.as_str()).collect())
+            .collect();
+        assert_eq!(tags, vec![vec!["home"], vec!["home", "work"], vec![]]);
+        assert_eq!(todos[2].todo, fixture_todos()[2]);
+    }
+
+    #[sqlx::test(fixtures("test/fixtures/users.sql"))]
+    async fn tags_crud(pool: SqlitePool) {
+        let name = |name: &str| name.to_string();
+        let created = db::create_tag(&pool, ALICE, CreateTag { name: name("home") })
+            .await
+            .unwrap();
+        assert_eq!(
+            db::get_tag(&pool, ALICE, created.id).await.unwrap(),
+            created
+        );
+
+        let err = db::create_tag(&pool, ALICE, CreateTag { name: name("home") }).await;
+        assert_matches!(err, Err(InternalError::Conflict(message)) if message == NAME_TAKEN);
+        db::create_tag(&pool, BOB, CreateTag { name: name("home") })
+            .await
+            .unwrap();
+
+        let updated = db::update_tag(
+            &pool,
+            ALICE,
+            created.id,
+            UpdateTag {
+                name: name("house"),
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(updated.todos, vec![]);
+        let updated = updated.tag;
+        assert_eq!(
+            updated,
+            Tag {
+                id: created.id,
+                name: name("house")
+            }
+        );
+        assert_eq!(
+            db::list_tags(&pool, ALICE).await.unwrap(),
+            vec![updated.clone()]
+        );
+
+        let deleted = db::delete_tag(&pool, ALICE, created.id).await.unwrap();
+        assert_eq!(deleted.tag, updated);
+        let err = db::get_tag(&pool, ALICE, created.id).await;
+        assert_matches!(err, Err(InternalError::Sql(sqlx::Error::RowNotFound)));
+    }
+
+    #[sqlx::test(fixtures(
+        "test/fixtures/users.sql",
+        "test/fixtures/todos.sql",
+        "test/fixtures/tags.sql"
+    ))]
+    async fn attach_detach_tag(pool: SqlitePool) {
+        let todo = db::attach_tag(&pool, ALICE, 3, 2).await.unwrap();
+        assert_eq!(
+            todo.tags,
+            vec![Tag {
+                id: 2,
+                name: "work".to_string()
+            }]
+        );
+
+        // Attaching twice is a no-op
+        let todo = db::attach_tag(&pool, ALICE, 3, 2).await.unwrap();
+        assert_eq!(todo.tags.len(), 1);
+
+        let todo = db::detach_tag(&pool, ALICE, 3, 2).await.unwrap();
+        assert_eq!(todo.tags, vec![]);
+
+        let err = db::detach_tag(&pool, ALICE, 3, 2).await;
+        assert_matches!(err, Err(InternalError::Sql(sqlx::Error::RowNotFound)));
+    }
+
+    #[sqlx::test(fixtures(
+        "test/fixtures/users.sql",
+        "test/fixtures/todos.sql",
+        "test/fixtures/tags.sql"
+    ))]
+    async fn tags_cross_user(pool: SqlitePool) {
+        let err = db::get_tag(&pool, BOB, 1).await;
+        assert_matches!(err, Err(InternalError::Sql(sqlx::Error::RowNotFound)));
+
+        // Bob's tag cannot be attached to Alice's todo, nor the other way around
+        let err = db::attach_tag(&pool, ALICE, 3, 3).await;
+        assert_matches!(err, Err(InternalError::Sql(sqlx::Error::RowNotFound)));
+        let err = db::attach_tag(&pool, BOB, 3, 3).await;
+        assert_matches!(err, Err(InternalError::Sql(sqlx::Error::RowNotFound)));
+
+        let err = db::detach_tag(&pool, BOB, 1, 1).await;
+        assert_matches!(err, Err(InternalError::Sql(sqlx::Error::RowNotFound)));
+        assert_eq!(db::list_todo_tags(&pool, ALICE, 1).await.unwrap().len(), 1);
+    }
+
+    #[sqlx::test(fixtures("test/fixtures/users.sql"))]
+    async fn search_todos(pool: SqlitePool) {
+        let texts = [
+            ("Groceries", "Buy milk and bread"),
+            ("Milk run", "Before the shop closes"),
+            ("Laundry", "Wash the milky white shirts"),
+        ];
+        for (title, description) in texts {
+            let todo = CreateTodo {
+                title: title.to_string(),
+                description: description.to_string(),
+                completed: false,
+                due_at: None,
+                parent_id: None,
+                recurrence: None,
+            };
+            db::create_todo(&pool, ALICE, todo).await.unwrap();
+        }
+        let search = |q: &str, user_id| {
+            let query = SearchQuery {
+                q: Some(q.to_string()),
+                limit: Some(10),
+            };
+            let params = SearchParams::try_from(query).unwrap();
+            let pool = pool.clone();
+            async move { db::search_todos(&pool, user_id, &params).await.unwrap() }
+        };
+
+        // Title matches rank first
+        let hits = search("milk", ALICE).await;
+        let ids: Vec<i64> = hits.iter().map(|hit| hit.todo.todo.id).collect();
+        assert_eq!(ids, vec![2, 1]);
+        assert_eq!(hits[0].highlights.title, "<mark>Milk</mark> run");
+        assert_eq!(
+            hits[1].highlights.description,
+            "Buy <mark>milk</mark> and bread"
+        );
+
+        let hits = search("mil*", ALICE).await;
+        assert_eq!(hits.len(), 3);
+        assert_eq!(search("milk", BOB).await, vec![]);
+
+        // The index follows updates and deletions
+        let todo = UpdateTodo {
+            title: "Dairy run".to_string(),
+            description: "Before the shop closes".to_string(),
+            completed: false,
+            due_at: None,
+            parent_id: None,
+            recurrence: None,
+        };
+        db::update_todo(&pool, ALICE, 2, todo, &IfMatch::Any)
+            .await
+            .unwrap();
+        db::delete_todo(&pool, ALICE, 1, &IfMatch::Any, DeletePolicy::Reject)
+            .await
+            .unwrap();
+        assert_eq!(search("milk", ALICE).await, vec![]);
+        let hits = search("dairy", ALICE).await;
+        assert_eq!(hits[0].todo.todo.id, 2);
+    }
+
+    #[sqlx::test(fixtures("test/fixtures/users.sql", "test/fixtures/todos.sql"))]
+    async fn trash(pool: SqlitePool) {
+        db::delete_todo(&pool, ALICE, 1, &IfMatch::Any, DeletePolicy::Reject)
+            .await
+            .unwrap();
+        db::delete_todo(&pool, ALICE, 3, &IfMatch::Any, DeletePolicy::Reject)
+            .await
+            .unwrap();
+
+        let todos = db::list_todos(&pool, ALICE, &ListParams::default())
+            .await
+            .unwrap()
+            .todos;
+        assert_eq!(todos, vec![fixture_todos()[1].clone()]);
+        let err = db::update_todo(
+            &pool,
+            ALICE,
+            1,
+            fixture_todos()[0].clone().into(),
+            &IfMatch::Any,
+        )
+        .await;
+        assert_matches!(err, Err(InternalError::Sql(sqlx::Error::RowNotFound)));
+
+        let trash = db::list_trash(&pool, ALICE).await.unwrap();
+        let ids: Vec<i64> = trash.iter().map(|todo| todo.id).collect();
+        assert_eq!(ids, vec![3, 1]);
+        assert_eq!(db::list_trash(&pool, BOB).await.unwrap(), vec![]);
+
+        let restored = db::restore_todo(&pool, ALICE, 1).await.unwrap();
+        assert_eq!(
+            restored,
+            Todo {
+                version: 3,
+                ..fixture_todos()[0].clone()
+            }
+        );
+        assert_eq!(db::get_todo(&pool, ALICE, 1).await.unwrap(), restored);
+
+        let err = db::restore_todo(&pool, ALICE, 1).await;
+        assert_matches!(err, Err(InternalError::Sql(sqlx::Error::RowNotFound)));
+        let err = db::restore_todo(&pool, BOB, 3).await;
+        assert_matches!(err, Err(InternalError::Sql(sqlx::Error::RowNotFound)));
+    }
+
+    #[sqlx::test(fixtures("test/fixtures/users.sql", "test/fixtures/todos.sql"))]
+    async fn purge_trash(pool: SqlitePool) {
+        db::delete_todo(&pool, ALICE, 1, &IfMatch::Any, DeletePolicy::Reject)
+            .await
+            .unwrap();
+        sqlx::query!("UPDATE todos SET deleted_at = '2024-06-01 10:00:00' WHERE id = 1")
+            .execute(&pool)
+            .await
+            .unwrap();
+        db::delete_todo(&pool, ALICE, 2, &IfMatch::Any, DeletePolicy::Reject)
+            .await
+            .unwrap();
+
+        let purged = db::purge_trash(&pool, timestamp("2024-06-02 00:00:00"))
+            .await
+            .unwrap();
+        assert_eq!(purged, 1);
+
+        let trash = db::list_trash(&pool, ALICE).await.unwrap();
+        let ids: Vec<i64> = trash.iter().map(|todo| todo.id).collect();
+        assert_eq!(ids, vec![2]);
+        let err = db::restore_todo(&pool, ALICE, 1).await;
+        assert_matches!(err, Err(InternalError::Sql(sqlx::Error::RowNotFound)));
+    }
+
+    #[sqlx::test(fixtures("test/fixtures/users.sql", "test/fixtures/todos.sql"))]
+    async fn write_after_failed_write(pool: SqlitePool) {
+        // A failed transaction is rolled back after its connection is returned
+        // to the pool, still holding the lock the next one waits for.
+        for _ in 0..100 {
+            let reminder = CreateReminder {
+                offset_seconds: 3600,
+            };
+            let reminder = db::create_reminder(&pool, ALICE, 3, reminder)
+                .await
+                .unwrap();
+            let err = db::delete_reminder(&pool, ALICE, 1, reminder.id).await;
+            assert_matches!(err, Err(InternalError::Sql(sqlx::Error::RowNotFound)));
+            db::delete_reminder(&pool, ALICE, 3, reminder.id)
+                .await
+                .unwrap();
+        }
+    }
+
+    #[sqlx::test(fixtures("test/fixtures/users.sql", "test/fixtures/todos.sql"))]
+    async fn trash_subtasks(pool: SqlitePool) {
+        let subtask = CreateTodo {
+            title: "subtask".to_string(),
+            description: String::new(),
+            completed: false,
+            due_at: None,
+            parent_id: Some(1),
+            recurrence: None,
+        };
+        let subtask = db::create_todo(&pool, ALICE, subtask).await.unwrap();
+        let err = db::delete_todo(&pool, ALICE, 1, &IfMatch::Any, DeletePolicy::Reject).await;
+        assert_matches!(err, Err(InternalError::Conflict(_)));
+
+        db::delete_todo(&pool, ALICE, 1, &IfMatch::Any, DeletePolicy::Cascade)
+            .await
+            .unwrap();
+        let trash = db::list_trash(&pool, ALICE).await.unwrap();
+        let mut ids: Vec<i64> = trash.iter().map(|todo| todo.id).collect();
+        ids.sort();
+        assert_eq!(ids, vec![1, subtask.id]);
+
+        // Restored without its parent, the subtask becomes a top-level todo
+        let restored = db::restore_todo(&pool, ALICE, subtask.id).await.unwrap();
+        assert_eq!(restored.parent_id, None);
+        let children = db::list_children(&pool, ALICE, 1).await;
+        assert_matches!(children, Err(InternalError::Sql(sqlx::Error::RowNotFound)));
+    }
+
+    #[sqlx::test(fixtures("test/fixtures/users.sql"))]
+    async fn history(pool: SqlitePool) {
+        let created = db::create_todo(
+            &pool,
+            ALICE,
+            CreateTodo {
+                title: "title".to_string(),
+                description: "description".to_string(),
+                completed: false,
+                due_at: None,
+                parent_id: None,
+                recurrence: None,
+            },
+        )
+        .await
+        .unwrap();
+        let update = UpdateTodo {
+            completed: true,
+            ..UpdateTodo::from(created.clone())
+        };
+        let patched = db::update_todo(&pool, ALICE, created.id, update, &IfMatch::Any)
+            .await
+            .unwrap()
+            .todo;
+        let deleted = db::delete_todo(
+            &pool,
+            ALICE,
+            created.id,
+            &IfMatch::Any,
+            DeletePolicy::Reject,
+        )
+        .await
+        .unwrap()
+        .todo;
+        let restored = db::restore_todo(&pool, ALICE, created.id).await.unwrap();
+
+        let events = db::list_history(&pool, ALICE, created.id).await.unwrap();
+        let kinds: Vec<(EventKind, i64)> = events
+            .iter()
+            .map(|event| (event.kind, event.version))
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                (EventKind::Create, 1),
+                (EventKind::Update, 2),
+                (EventKind::Delete, 3),
+                (EventKind::Restore, 4),
+            ]
+        );
+        assert_eq!(events[0].user_id, Some(ALICE));
+        assert_eq!(events[0].before, None);
+        assert_eq!(events[1].before.as_ref().unwrap().0, created);
+        assert_eq!(events[1].after.as_ref().unwrap().0, patched);
+        assert_eq!(events[2].after.as_ref().unwrap().0, deleted);
+        assert_eq!(events[3].after.as_ref().unwrap().0, restored);
+
+        let err = db::list_history(&pool, BOB, created.id).await;
         assert_matches!(err, Err(InternalError::Sql(sqlx::Error::RowNotFound)));
     }
+
+    #[sqlx::test(fixtures("test/fixtures/users.sql"))]
+    async fn revert_todo(pool: SqlitePool) {
+        let created = db::create_todo(
+            &pool,
+            ALICE,
+            CreateTodo {
+                title: "title".to_string(),
+                description: "description".to_string(),
+                completed: false,
+                due_at: None,
+                parent_id: None,
+                recurrence: None,
+            },
+        )
+        .await
+        .unwrap();
+        let update = UpdateTodo {
+            title: "changed".to_string(),
+            completed: true,
+            ..UpdateTodo::from(created.clone())
+        };
+        db::update_todo(&pool, ALICE, created.id, update, &IfMatch::Any)
+            .await
+            .unwrap();
+
+        let reverted = db::revert_todo(&pool, ALICE, created.id, 1, &IfMatch::Any)
+            .await
+            .unwrap();
+        assert_eq!(
+            reverted,
+            Todo {
+                version: 3,
+                updated_at: reverted.updated_at,
+                ..created.clone()
+            }
+        );
+        let events = db::list_history(&pool, ALICE, created.id).await.unwrap();
+        assert_eq!(events[2].kind, EventKind::Revert);
+
+        let err = db::revert_todo(&pool, ALICE, created.id, 42, &IfMatch::Any).await;
+        assert_matches!(err, Err(InternalError::Constraint(error)) if error == FieldError::new("version", "Unknown version"));
+        let err = db::revert_todo(&pool, ALICE, created.id, 1, &IfMatch::Versions(vec![1])).await;
+        assert_matches!(err, Err(InternalError::PreconditionFailed));
+    }
+
+    #[sqlx::test(fixtures("test/fixtures/users.sql", "test/fixtures/todos.sql"))]
+    async fn purge_trash_history(pool: SqlitePool) {
+        db::delete_todo(&pool, ALICE, 1, &IfMatch::Any, DeletePolicy::Reject)
+            .await
+            .unwrap();
+        db::purge_trash(&pool, timestamp("2100-01-01 00:00:00"))
+            .await
+            .unwrap();
+
+        let events: Vec<(i64, Option<i64>, EventKind)> =
+            sqlx::query_as("SELECT todo_id, user_id, kind FROM todo_events ORDER BY id")
+                .fetch_all(&pool)
+                .await
+                .unwrap();
+        assert_eq!(
+            events,
+            vec![
+                (1, Some(ALICE), EventKind::Delete),
+                (1, None, EventKind::Purge)
+            ]
+        );
+    }
+
+    fn batch(mode: BatchMode) -> Batch {
+        Batch {
+            mode,
+            operations: vec![
+                Operation::Create {
+                    todo: CreateTodo {
+                        title: "title".to_string(),
+                        description: "description".to_string(),
+                        completed: false,
+                        due_at: None,
+                        parent_id: None,
+                        recurrence: None,
+                    },
+                },
+                Operation::Delete {
+                    id: 1,
+                    version: None,
+                },
+                Operation::Delete {
+                    id: 2,
+                    version: Some(42),
+                },
+                Operation::Update {
+                    id: 3,
+                    todo: UpdateTodo::from(fixture_todos()[2].clone()),
+                    version: Some(1),
+                },
+            ],
+        }
+    }
+
+    #[sqlx::test(fixtures("test/fixtures/users.sql", "test/fixtures/todos.sql"))]
+    async fn run_batch_atomic(pool: SqlitePool) {
+        let outcome = db::run_batch(&pool, ALICE, batch(BatchMode::Atomic), DeletePolicy::Reject)
+            .await
+            .unwrap();
+        assert!(!outcome.committed);
+        assert_eq!(outcome.results.len(), 3);
+        assert_matches!(outcome.results[2], Err(InternalError::PreconditionFailed));
+
+        let todos = db::list_todos(&pool, ALICE, &ListParams::default())
+            .await
+            .unwrap()
+            .todos;
+        assert_eq!(todos, fixture_todos());
+    }
+
+    #[sqlx::test(fixtures("test/fixtures/users.sql", "test/fixtures/todos.sql"))]
+    async fn run_batch_best_effort(pool: SqlitePool) {
+        let outcome = db::run_batch(
+            &pool,
+            ALICE,
+            batch(BatchMode::BestEffort),
+            DeletePolicy::Reject,
+        )
+        .await
+        .unwrap();
+        assert!(outcome.committed);
+        let ids: Vec<Option<i64>> = outcome
+            .results
+            .iter()
+            .map(|result| result.as_ref().ok().map(|updated| updated.todo.id))
+            .collect();
+        assert_eq!(ids, vec![Some(4), Some(1), None, Some(3)]);
+
+        let todos = db::list_todos(&pool, ALICE, &ListParams::default())
+            .await
+            .unwrap()
+            .todos;
+        let ids: Vec<i64> = todos.iter().map(|todo| todo.id).collect();
+        assert_eq!(ids, vec![2, 3, 4]);
+        assert_eq!(todos[1].version, 2);
+    }
 }
@@ -1,44 +1,249 @@
-use actix_web::{body::BoxBody, http::StatusCode, HttpResponse};
+use actix_web::{
+    body::BoxBody,
+    error::BlockingError,
+    http::{
+        header::{RETRY_AFTER, WWW_AUTHENTICATE},
+        StatusCode,
+    },
+    HttpResponse,
+};
 use serde::{Deserialize, Serialize};
+use std::fmt;
 use thiserror::Error;
+use utoipa::ToSchema;
+
+pub const PROBLEM_JSON: &str = "application/problem+json";
 
 #[derive(Error, Debug)]
 pub enum InternalError {
-    #[error("Invalid config value")]
-    ParseConfig(String),
+    /// Holds a message per invalid setting.
+    #[error("Invalid config: {}", .0.join(", "))]
+    ParseConfig(Vec<String>),
 
     #[error("SQL error")]
     Sql(#[from] sqlx::Error),
+
+    /// A JSON Merge Patch which does not leave a valid todo.
+    #[error("Invalid patch")]
+    Patch(serde_json::Error),
+
+    #[error("JSON error")]
+    Json(#[from] serde_json::Error),
+
+    /// A field breaking a rule which depends on the stored todos, such as a
+    /// subtask cycle. Payloads are validated before reaching the storage.
+    #[error("Constraint violated: {}", .0.message)]
+    Constraint(FieldError),
+
+    #[error("Not found")]
+    NotFound,
+
+    #[error("Conflict: {0}")]
+    Conflict(String),
+
+    #[error("Precondition failed")]
+    PreconditionFailed,
+
+    #[error("{0}")]
+    NotReady(String),
+
+    #[error("Password hashing error")]
+    PasswordHash(#[from] argon2::password_hash::Error),
+
+    #[error("Blocking task error")]
+    Blocking(#[from] BlockingError),
+
+    #[error("Metrics error")]
+    Metrics(#[from] prometheus::Error),
+
+    #[error("CSV error")]
+    Csv(#[from] csv::Error),
+
+    #[error("TLS error: {0}")]
+    Tls(String),
+}
+
+/// Errors met by the command-line client.
+#[derive(Error, Debug)]
+pub enum ClientError {
+    #[error(transparent)]
+    Config(#[from] InternalError),
+
+    #[error("Request failed: {0}")]
+    Request(String),
+
+    #[error("Invalid JSON: {0}")]
+    Json(#[from] serde_json::Error),
+
+    /// A problem answered by the API.
+    #[error("{0}")]
+    Api(Problem),
 }
 
 #[derive(Error, Debug, PartialEq, Eq, Deserialize, Serialize)]
 pub enum ApiError {
+    #[error("Bad Request: {0}")]
+    BadRequest(String),
+
+    #[error("Unauthorized")]
+    Unauthorized,
+
     #[error("Not Found")]
     NotFound,
 
+    #[error("Conflict: {0}")]
+    Conflict(String),
+
+    #[error("Precondition Failed")]
+    PreconditionFailed,
+
+    #[error("Failed Dependency: {0}")]
+    FailedDependency(String),
+
+    #[error("Unprocessable Entity")]
+    UnprocessableEntity(Vec<FieldError>),
+
+    /// Holds the maximum size of request bodies, in bytes.
+    #[error("Payload Too Large")]
+    PayloadTooLarge(usize),
+
+    /// Holds the number of seconds to wait before retrying.
+    #[error("Too Many Requests")]
+    TooManyRequests(u64),
+
+    #[error("Service Unavailable: {0}")]
+    ServiceUnavailable(String),
+
     #[error("Internal Server Error")]
     Internal,
 }
 
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, ToSchema)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
+impl FieldError {
+    pub fn new(field: &str, message: impl Into<String>) -> Self {
+        Self {
+            field: field.to_string(),
+            message: message.into(),
+        }
+    }
+}
+
+/// Problem details document (RFC 7807) rendered for every API error.
+#[derive(Debug, PartialEq, Eq, Deserialize, Serialize, ToSchema)]
+pub struct Problem {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub title: String,
+    pub status: u16,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub errors: Vec<FieldError>,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.status, self.title)?;
+        if let Some(detail) = &self.detail {
+            write!(f, ": {detail}")?;
+        }
+        for error in &self.errors {
+            write!(f, "\n  {}: {}", error.field, error.message)?;
+        }
+        Ok(())
+    }
+}
+
 impl From<InternalError> for ApiError {
     fn from(err: InternalError) -> Self {
         match err {
             InternalError::Sql(sqlx::Error::RowNotFound) => Self::NotFound,
             InternalError::Sql(_) => Self::Internal,
+            InternalError::NotFound => Self::NotFound,
+            InternalError::Conflict(message) => Self::Conflict(message),
+            InternalError::Patch(err) => Self::BadRequest(err.to_string()),
+            InternalError::Constraint(error) => Self::UnprocessableEntity(vec![error]),
+            InternalError::PreconditionFailed => Self::PreconditionFailed,
+            InternalError::NotReady(reason) => Self::ServiceUnavailable(reason),
+            InternalError::PasswordHash(_)
+            | InternalError::Blocking(_)
+            | InternalError::Metrics(_)
+            | InternalError::Csv(_)
+            | InternalError::Json(_)
+            | InternalError::Tls(_) => Self::Internal,
             InternalError::ParseConfig(_) => unreachable!(),
         }
     }
 }
 
+impl ApiError {
+    pub fn problem(&self) -> Problem {
+        let status = actix_web::ResponseError::status_code(self);
+        let (detail, errors) = match self {
+            Self::BadRequest(detail)
+            | Self::Conflict(detail)
+            | Self::FailedDependency(detail)
+            | Self::ServiceUnavailable(detail) => (Some(detail.clone()), vec![]),
+            Self::UnprocessableEntity(errors) => (
+                Some("One or more fields are invalid".to_string()),
+                errors.clone(),
+            ),
+            Self::PayloadTooLarge(limit) => (
+                Some(format!("Request body larger than {limit} bytes")),
+                vec![],
+            ),
+            Self::TooManyRequests(retry_after) => (
+                Some(format!("Rate limit exceeded, retry in {retry_after}s")),
+                vec![],
+            ),
+            Self::Unauthorized | Self::NotFound | Self::PreconditionFailed | Self::Internal => {
+                (None, vec![])
+            }
+        };
+        Problem {
+            kind: "about:blank".to_string(),
+            title: status.canonical_reason().unwrap_or_default().to_string(),
+            status: status.as_u16(),
+            detail,
+            errors,
+        }
+    }
+}
+
 impl actix_web::ResponseError for ApiError {
     fn status_code(&self) -> StatusCode {
         match self {
+            Self::BadRequest(_) => StatusCode::BAD_REQUEST,
+            Self::Unauthorized => StatusCode::UNAUTHORIZED,
             Self::NotFound => StatusCode::NOT_FOUND,
+            Self::Conflict(_) => StatusCode::CONFLICT,
+            Self::PreconditionFailed => StatusCode::PRECONDITION_FAILED,
+            Self::FailedDependency(_) => StatusCode::FAILED_DEPENDENCY,
+            Self::UnprocessableEntity(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            Self::PayloadTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            Self::TooManyRequests(_) => StatusCode::TOO_MANY_REQUESTS,
+            Self::ServiceUnavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
             Self::Internal => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
 
     fn error_response(&self) -> HttpResponse<BoxBody> {
-        HttpResponse::build(self.status_code()).body(self.to_string())
+        let body = serde_json::to_string(&self.problem()).unwrap_or_default();
+        let mut response = HttpResponse::build(self.status_code());
+        match self {
+            Self::Unauthorized => {
+                response.insert_header((WWW_AUTHENTICATE, "Bearer"));
+            }
+            Self::TooManyRequests(retry_after) => {
+                response.insert_header((RETRY_AFTER, *retry_after));
+            }
+            _ => {}
+        }
+        response.content_type(PROBLEM_JSON).body(body)
     }
 }
@@ -0,0 +1,125 @@
+use crate::{error::ApiError, todo::Todo};
+use actix_web::{
+    dev::Payload,
+    http::header::{self, EntityTag, Header},
+    FromRequest, HttpRequest,
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use sha2::{Digest, Sha256};
+use std::future::{ready, Ready};
+
+/// Strong entity tag of a todo, which changes along with its version.
+pub fn todo(todo: &Todo) -> EntityTag {
+    EntityTag::new_strong(todo.version.to_string())
+}
+
+/// Strong entity tag of any other representation, derived from its bytes.
+pub fn body(body: &[u8]) -> EntityTag {
+    let digest = Sha256::digest(body);
+    EntityTag::new_strong(URL_SAFE_NO_PAD.encode(&digest[..16]))
+}
+
+/// Whether the request's `If-None-Match` header matches the current entity
+/// tag, using the weak comparison as required for GET.
+pub fn not_modified(request: &HttpRequest, etag: &EntityTag) -> bool {
+    match header::IfNoneMatch::parse(request) {
+        Ok(header::IfNoneMatch::Any) => true,
+        Ok(header::IfNoneMatch::Items(items)) => items.iter().any(|item| item.weak_eq(etag)),
+        Err(_) => false,
+    }
+}
+
+/// The todo versions a request's `If-Match` header allows modifying.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum IfMatch {
+    /// No header or `*`, either way any existing todo is a match.
+    #[default]
+    Any,
+    Versions(Vec<i64>),
+}
+
+impl IfMatch {
+    pub fn matches(&self, version: i64) -> bool {
+        match self {
+            Self::Any => true,
+            Self::Versions(versions) => versions.contains(&version),
+        }
+    }
+}
+
+impl FromRequest for IfMatch {
+    type Error = ApiError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(request: &HttpRequest, _: &mut Payload) -> Self::Future {
+        if !request.headers().contains_key(header::IF_MATCH) {
+            return ready(Ok(Self::Any));
+        }
+        let if_match = match header::IfMatch::parse(request) {
+            Ok(header::IfMatch::Any) => Ok(Self::Any),
+            // Tags are compared strongly, so weak or foreign tags never match
+            Ok(header::IfMatch::Items(items)) => Ok(Self::Versions(
+                items
+                    .iter()
+                    .filter(|item| !item.weak)
+                    .filter_map(|item| item.tag().parse().ok())
+                    .collect(),
+            )),
+            Err(_) => Err(ApiError::BadRequest(
+                "Invalid 'If-Match' header".to_string(),
+            )),
+        };
+        ready(if_match)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::etag::{self, IfMatch};
+    use actix_web::{
+        http::header::{EntityTag, IF_MATCH, IF_NONE_MATCH},
+        test::TestRequest,
+        FromRequest,
+    };
+
+    #[actix_web::test]
+    async fn if_match() {
+        let cases = [
+            (None, IfMatch::Any),
+            (Some("*"), IfMatch::Any),
+            (
+                Some("\"1\", W/\"2\", \"x\", \"3\""),
+                IfMatch::Versions(vec![1, 3]),
+            ),
+        ];
+        for (header, expected) in cases {
+            let mut request = TestRequest::default();
+            if let Some(header) = header {
+                request = request.insert_header((IF_MATCH, header));
+            }
+            let if_match = IfMatch::extract(&request.to_http_request()).await.unwrap();
+            assert_eq!(if_match, expected);
+        }
+        assert!(!IfMatch::Versions(vec![1, 3]).matches(2));
+    }
+
+    #[test]
+    fn not_modified() {
+        let etag = EntityTag::new_strong("1".to_string());
+        let cases = [
+            (None, false),
+            (Some("*"), true),
+            (Some("W/\"1\""), true),
+            (Some("\"2\", \"1\""), true),
+            (Some("\"2\""), false),
+        ];
+        for (header, expected) in cases {
+            let mut request = TestRequest::default();
+            if let Some(header) = header {
+                request = request.insert_header((IF_NONE_MATCH, header));
+            }
+            let request = request.to_http_request();
+            assert_eq!(etag::not_modified(&request, &etag), expected);
+        }
+    }
+}
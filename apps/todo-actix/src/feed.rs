@@ -0,0 +1,303 @@
+use crate::{error::ApiError, history::EventKind, tag::TaggedTodo};
+use actix_web::{rt::time, web::Bytes};
+use actix_ws::{Message, MessageStream, Session};
+use futures_util::{stream, Stream};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::VecDeque,
+    convert::Infallible,
+    sync::{Mutex, PoisonError},
+    time::Duration,
+};
+use tokio::sync::broadcast::{self, Receiver, Sender};
+use utoipa::{IntoParams, ToSchema};
+
+/// Number of changes kept for clients resuming with `Last-Event-ID`, which is
+/// also how far behind clients can fall before being disconnected.
+const BACKLOG: usize = 1024;
+
+/// Interval at which idle connections are written to, so that dead ones are
+/// noticed and proxies do not close live ones.
+const KEEP_ALIVE: Duration = Duration::from_secs(15);
+
+/// A change made to a todo, as sent to the clients of the feed.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, ToSchema)]
+pub struct Change {
+    /// Position of the change in the feed, to resume after with
+    /// `Last-Event-ID`.
+    pub id: u64,
+    pub kind: EventKind,
+    /// The todo once changed.
+    pub todo: TaggedTodo,
+}
+
+impl Change {
+    /// Formats the change as a Server-Sent Event named after its kind.
+    pub fn to_event(&self) -> Bytes {
+        let kind = serde_json::to_value(self.kind).unwrap_or_default();
+        let data = serde_json::to_string(self).unwrap_or_default();
+        let kind = kind.as_str().unwrap_or_default();
+        Bytes::from(format!("id: {}\nevent: {kind}\ndata: {data}\n\n", self.id))
+    }
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct FeedQuery {
+    /// Only send the changes of this todo.
+    pub todo_id: Option<i64>,
+    /// Resume after this change, for clients unable to send `Last-Event-ID`.
+    pub last_event_id: Option<u64>,
+}
+
+/// Message sent by WebSocket clients to change which todos they follow.
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
+pub struct Follow {
+    /// Todos to send the changes of, or every todo if empty.
+    pub todo_ids: Vec<i64>,
+}
+
+/// Which changes a client is sent: those of its own todos, optionally narrowed
+/// down to some of them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Filter {
+    pub user_id: i64,
+    pub todo_ids: Vec<i64>,
+}
+
+impl Filter {
+    pub fn new(user_id: i64, todo_id: Option<i64>) -> Self {
+        Self {
+            user_id,
+            todo_ids: todo_id.into_iter().collect(),
+        }
+    }
+
+    fn matches(&self, change: &Change) -> bool {
+        let todo = &change.todo.todo;
+        todo.user_id == Some(self.user_id)
+            && (self.todo_ids.is_empty() || self.todo_ids.contains(&todo.id))
+    }
+}
+
+#[derive(Default)]
+struct Backlog {
+    last_id: u64,
+    changes: VecDeque<Change>,
+}
+
+/// Broadcast of the changes made to todos, shared by every worker.
+pub struct ChangeFeed {
+    sender: Sender<Change>,
+    backlog: Mutex<Backlog>,
+}
+
+impl Default for ChangeFeed {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ChangeFeed {
+    pub fn new() -> Self {
+        Self {
+            sender: broadcast::channel(BACKLOG).0,
+            backlog: Mutex::default(),
+        }
+    }
+
+    pub fn publish(&self, kind: EventKind, todo: TaggedTodo) {
+        let mut backlog = self.backlog.lock().unwrap_or_else(PoisonError::into_inner);
+        backlog.last_id += 1;
+        let change = Change {
+            id: backlog.last_id,
+            kind,
+            todo,
+        };
+        if backlog.changes.len() == BACKLOG {
+            backlog.changes.pop_front();
+        }
+        backlog.changes.push_back(change.clone());
+        // Sending fails only when nobody is listening
+        let _ = self.sender.send(change);
+    }
+
+    /// Subscribes to the changes to come, preceded by the ones made after
+    /// `last_event_id` that are still in the backlog.
+    pub fn subscribe(&self, filter: Filter, last_event_id: Option<u64>) -> Subscription {
+        // Subscribing while holding the backlog means no change is missed or
+        // sent twice between the two
+        let backlog = self.backlog.lock().unwrap_or_else(PoisonError::into_inner);
+        let receiver = self.sender.subscribe();
+        let missed = match last_event_id {
+            None => VecDeque::new(),
+            Some(last_event_id) => backlog
+                .changes
+                .iter()
+                .filter(|change| change.id > last_event_id && filter.matches(change))
+                .cloned()
+                .collect(),
+        };
+        Subscription {
+            filter,
+            missed,
+            receiver,
+        }
+    }
+}
+
+pub struct Subscription {
+    pub filter: Filter,
+    missed: VecDeque<Change>,
+    receiver: Receiver<Change>,
+}
+
+impl Subscription {
+    /// Waits for the next change matching the filter. Returns `None` once the
+    /// client fell too far behind, which is then expected to reconnect and
+    /// resume from the last change it was sent.
+    pub async fn next(&mut self) -> Option<Change> {
+        if let Some(change) = self.missed.pop_front() {
+            return Some(change);
+        }
+        loop {
+            let change = self.receiver.recv().await.ok()?;
+            if self.filter.matches(&change) {
+                return Some(change);
+            }
+        }
+    }
+}
+
+/// Body of a Server-Sent Events response, sending changes as they are made.
+pub fn events(subscription: Subscription) -> impl Stream<Item = Result<Bytes, Infallible>> {
+    let keep_alive = time::interval(KEEP_ALIVE);
+    stream::unfold(
+        (subscription, keep_alive),
+        |(mut subscription, mut keep_alive)| async move {
+            let event = tokio::select! {
+                change = subscription.next() => change?.to_event(),
+                _ = keep_alive.tick() => Bytes::from_static(b": keep-alive\n\n"),
+            };
+            Some((Ok(event), (subscription, keep_alive)))
+        },
+    )
+}
+
+/// Sends changes to a WebSocket client as they are made, until it leaves or
+/// falls behind. Clients can change the todos they follow by sending a
+/// `Follow` message.
+pub async fn forward(
+    mut subscription: Subscription,
+    mut session: Session,
+    mut messages: MessageStream,
+) {
+    let mut keep_alive = time::interval(KEEP_ALIVE);
+    loop {
+        let sent = tokio::select! {
+            change = subscription.next() => match change {
+                Some(change) => {
+                    let change = serde_json::to_string(&change).unwrap_or_default();
+                    session.text(change).await
+                }
+                None => break,
+            },
+            message = messages.recv() => match message {
+                Some(Ok(Message::Text(text))) => match serde_json::from_str::<Follow>(&text) {
+                    Ok(follow) => {
+                        subscription.filter.todo_ids = follow.todo_ids;
+                        Ok(())
+                    }
+                    Err(err) => {
+                        let problem = ApiError::BadRequest(err.to_string()).problem();
+                        let problem = serde_json::to_string(&problem).unwrap_or_default();
+                        session.text(problem).await
+                    }
+                },
+                Some(Ok(Message::Ping(bytes))) => session.pong(&bytes).await,
+                Some(Ok(Message::Close(reason))) => {
+                    let _ = session.close(reason).await;
+                    return;
+                }
+                Some(Ok(_)) => Ok(()),
+                Some(Err(_)) | None => break,
+            },
+            _ = keep_alive.tick() => session.ping(b"").await,
+        };
+        if sent.is_err() {
+            return;
+        }
+    }
+    let _ = session.close(None).await;
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        feed::{ChangeFeed, Filter, BACKLOG},
+        history::EventKind,
+        tag::TaggedTodo,
+        test::{fixture_todos, ALICE, BOB},
+    };
+
+    fn tagged(index: usize) -> TaggedTodo {
+        TaggedTodo {
+            todo: fixture_todos().swap_remove(index),
+            tags: vec![],
+        }
+    }
+
+    #[actix_web::test]
+    async fn filter_changes() {
+        let feed = ChangeFeed::new();
+        let mut all = feed.subscribe(Filter::new(ALICE, None), None);
+        let mut second = feed.subscribe(Filter::new(ALICE, Some(2)), None);
+        let mut bob = feed.subscribe(Filter::new(BOB, None), None);
+        feed.publish(EventKind::Create, tagged(0));
+        feed.publish(EventKind::Update, tagged(1));
+        drop(feed);
+
+        let mut ids = vec![];
+        while let Some(change) = all.next().await {
+            ids.push(change.id);
+        }
+        assert_eq!(ids, [1, 2]);
+        assert_eq!(second.next().await.map(|change| change.id), Some(2));
+        assert_eq!(second.next().await, None);
+        assert_eq!(bob.next().await, None);
+    }
+
+    #[actix_web::test]
+    async fn resume_from_backlog() {
+        let feed = ChangeFeed::new();
+        for _ in 0..BACKLOG + 2 {
+            feed.publish(EventKind::Update, tagged(0));
+        }
+        let last_id = BACKLOG as u64 + 2;
+
+        let mut subscription = feed.subscribe(Filter::new(ALICE, None), Some(last_id - 1));
+        feed.publish(EventKind::Delete, tagged(0));
+        assert_eq!(
+            subscription.next().await.map(|change| change.id),
+            Some(last_id)
+        );
+        assert_eq!(
+            subscription.next().await.map(|change| change.id),
+            Some(last_id + 1)
+        );
+
+        // Changes which left the backlog are lost
+        let mut subscription = feed.subscribe(Filter::new(ALICE, None), Some(0));
+        assert_eq!(subscription.next().await.map(|change| change.id), Some(4));
+    }
+
+    #[actix_web::test]
+    async fn disconnect_lagging() {
+        let feed = ChangeFeed::new();
+        let mut subscription = feed.subscribe(Filter::new(ALICE, None), None);
+        for _ in 0..BACKLOG + 1 {
+            feed.publish(EventKind::Update, tagged(0));
+        }
+        assert_eq!(subscription.next().await, None);
+    }
+}
@@ -0,0 +1,19 @@
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+pub const LIVE: &str = "ok";
+pub const READY: &str = "ready";
+
+/// Body of the liveness and readiness probes.
+#[derive(Debug, PartialEq, Eq, Deserialize, Serialize, ToSchema)]
+pub struct Health {
+    pub status: String,
+}
+
+impl Health {
+    pub fn new(status: &str) -> Self {
+        Self {
+            status: status.to_string(),
+        }
+    }
+}
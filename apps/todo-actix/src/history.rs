@@ -0,0 +1,45 @@
+use crate::todo::{utc, Todo};
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use sqlx::types::Json;
+use utoipa::ToSchema;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, sqlx::Type, ToSchema)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(rename_all = "lowercase")]
+pub enum EventKind {
+    Create,
+    Update,
+    Delete,
+    Restore,
+    Revert,
+    Purge,
+    /// A reminder of the todo fired. Reminders are sent to the change feed and
+    /// webhooks, but never recorded in the history.
+    Remind,
+}
+
+/// A change made to a todo, with snapshots of the todo before and after it.
+/// The `version` is the one of the todo once changed, or the last one for
+/// purges.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, ToSchema)]
+pub struct TodoEvent {
+    pub id: i64,
+    pub todo_id: i64,
+    /// The user who made the change, missing for changes made by the server.
+    pub user_id: Option<i64>,
+    pub kind: EventKind,
+    pub version: i64,
+    #[schema(value_type = Option<Todo>)]
+    pub before: Option<Json<Todo>>,
+    #[schema(value_type = Option<Todo>)]
+    pub after: Option<Json<Todo>>,
+    #[serde(with = "utc")]
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Debug, PartialEq, Eq, Deserialize, Serialize, ToSchema)]
+pub struct Revert {
+    /// Version of the todo to bring back.
+    pub version: i64,
+}
@@ -0,0 +1,383 @@
+use crate::{
+    error::FieldError,
+    todo::{CreateTodo, Todo},
+};
+use chrono::{NaiveDate, NaiveDateTime};
+
+const PRODUCT_ID: &str = "-//todo-actix//EN";
+
+/// Octets a content line may hold before being folded (RFC 5545, 3.1).
+const LINE_LENGTH: usize = 75;
+
+const DATE_TIME: &str = "%Y%m%dT%H%M%S";
+const DATE: &str = "%Y%m%d";
+
+/// Writes todos as the VTODO components of an iCalendar document.
+pub fn write(todos: &[Todo], now: NaiveDateTime) -> String {
+    let mut calendar = String::new();
+    push_line(&mut calendar, "BEGIN:VCALENDAR");
+    push_line(&mut calendar, "VERSION:2.0");
+    push_line(&mut calendar, &format!("PRODID:{PRODUCT_ID}"));
+    for todo in todos {
+        push_line(&mut calendar, "BEGIN:VTODO");
+        push_line(&mut calendar, &format!("UID:todo-{}@todo-actix", todo.id));
+        push_line(&mut calendar, &format!("DTSTAMP:{}", date_time(&now)));
+        push_line(
+            &mut calendar,
+            &format!("CREATED:{}", date_time(&todo.created_at)),
+        );
+        push_line(
+            &mut calendar,
+            &format!("LAST-MODIFIED:{}", date_time(&todo.updated_at)),
+        );
+        push_line(&mut calendar, &format!("SUMMARY:{}", escape(&todo.title)));
+        if !todo.description.is_empty() {
+            push_line(
+                &mut calendar,
+                &format!("DESCRIPTION:{}", escape(&todo.description)),
+            );
+        }
+        if let Some(due_at) = &todo.due_at {
+            push_line(&mut calendar, &format!("DUE:{}", date_time(due_at)));
+        }
+        if let Some(recurrence) = &todo.recurrence {
+            push_line(&mut calendar, &format!("RRULE:{recurrence}"));
+        }
+        let status = if todo.completed {
+            "COMPLETED"
+        } else {
+            "NEEDS-ACTION"
+        };
+        push_line(&mut calendar, &format!("STATUS:{status}"));
+        push_line(&mut calendar, "END:VTODO");
+    }
+    push_line(&mut calendar, "END:VCALENDAR");
+    calendar
+}
+
+fn date_time(value: &NaiveDateTime) -> String {
+    format!("{}Z", value.format(DATE_TIME))
+}
+
+/// Appends a content line, folded so that no line exceeds 75 octets.
+fn push_line(calendar: &mut String, line: &str) {
+    let mut length = 0;
+    for char in line.chars() {
+        if length + char.len_utf8() > LINE_LENGTH {
+            calendar.push_str("\r\n ");
+            // The leading space counts towards the length of the line
+            length = 1;
+        }
+        calendar.push(char);
+        length += char.len_utf8();
+    }
+    calendar.push_str("\r\n");
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for char in text.chars() {
+        match char {
+            '\\' | ';' | ',' => {
+                escaped.push('\\');
+                escaped.push(char);
+            }
+            '\n' => escaped.push_str("\\n"),
+            '\r' => {}
+            _ => escaped.push(char),
+        }
+    }
+    escaped
+}
+
+fn unescape(text: &str) -> String {
+    let mut unescaped = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(char) = chars.next() {
+        if char != '\\' {
+            unescaped.push(char);
+            continue;
+        }
+        match chars.next() {
+            Some('n' | 'N') => unescaped.push('\n'),
+            Some(char) => unescaped.push(char),
+            None => {}
+        }
+    }
+    unescaped
+}
+
+/// Unfolds the content lines of a document, dropping empty ones.
+fn unfold(text: &str) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+    for line in text.split('\n') {
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        match (line.strip_prefix([' ', '\t']), lines.last_mut()) {
+            (Some(continued), Some(last)) => last.push_str(continued),
+            _ if line.is_empty() => {}
+            _ => lines.push(line.to_string()),
+        }
+    }
+    lines
+}
+
+/// A content line split into its upper-cased name and its value, leaving out
+/// its parameters, as in `DUE;VALUE=DATE:20240610`.
+struct Property<'a> {
+    name: String,
+    value: &'a str,
+}
+
+impl<'a> Property<'a> {
+    fn parse(line: &'a str) -> Option<Self> {
+        // Parameter values may be quoted and hold colons
+        let mut quoted = false;
+        let colon = line.char_indices().find_map(|(index, char)| match char {
+            '"' => {
+                quoted = !quoted;
+                None
+            }
+            ':' if !quoted => Some(index),
+            _ => None,
+        })?;
+        let name = line[..colon].split(';').next()?.trim().to_ascii_uppercase();
+        Some(Self {
+            name,
+            value: &line[colon + 1..],
+        })
+    }
+}
+
+/// Reads a date or date-time. Times in a named time zone, or in none, are
+/// taken as UTC since time zone definitions are not resolved.
+fn parse_date_time(value: &str) -> Option<NaiveDateTime> {
+    let value = value.trim();
+    let value = value.strip_suffix(['Z', 'z']).unwrap_or(value);
+    NaiveDateTime::parse_from_str(value, DATE_TIME)
+        .ok()
+        .or_else(|| {
+            NaiveDate::parse_from_str(value, DATE)
+                .ok()?
+                .and_hms_opt(0, 0, 0)
+        })
+}
+
+/// The todo of a VTODO component being read, along with the errors of its
+/// properties.
+#[derive(Default)]
+struct Component {
+    title: String,
+    description: String,
+    completed: bool,
+    due_at: Option<NaiveDateTime>,
+    recurrence: Option<String>,
+    errors: Vec<FieldError>,
+}
+
+impl Component {
+    fn read(&mut self, property: &Property) {
+        match property.name.as_str() {
+            "SUMMARY" => self.title = unescape(property.value),
+            "DESCRIPTION" => self.description = unescape(property.value),
+            "STATUS" => self.completed = property.value.trim().eq_ignore_ascii_case("COMPLETED"),
+            "COMPLETED" => self.completed = true,
+            "RRULE" => self.recurrence = Some(property.value.to_string()),
+            "DUE" => match parse_date_time(property.value) {
+                Some(due_at) => self.due_at = Some(due_at),
+                None => self.errors.push(FieldError::new(
+                    "due_at",
+                    "must be an iCalendar date or date-time",
+                )),
+            },
+            _ => {}
+        }
+    }
+
+    fn finish(self) -> (CreateTodo, Vec<FieldError>) {
+        let todo = CreateTodo {
+            title: self.title,
+            description: self.description,
+            completed: self.completed,
+            due_at: self.due_at,
+            parent_id: None,
+            recurrence: self.recurrence,
+        };
+        (todo, self.errors)
+    }
+}
+
+/// Reads the VTODO components of an iCalendar document, in order, each with
+/// the errors of its properties. Other components are ignored.
+pub fn parse(text: &str) -> Result<Vec<(CreateTodo, Vec<FieldError>)>, String> {
+    let lines = unfold(text);
+    let is_calendar = lines
+        .first()
+        .is_some_and(|line| line.trim().eq_ignore_ascii_case("BEGIN:VCALENDAR"));
+    if !is_calendar {
+        return Err("Invalid iCalendar document, must start with BEGIN:VCALENDAR".to_string());
+    }
+
+    let mut todos = Vec::new();
+    let mut component: Option<Component> = None;
+    // Components nested in the current one, such as alarms
+    let mut depth = 0;
+    for (number, line) in lines.iter().enumerate() {
+        let property = Property::parse(line)
+            .ok_or_else(|| format!("Invalid iCalendar content line {}", number + 1))?;
+        let value = property.value.trim();
+        match (property.name.as_str(), &mut component) {
+            ("BEGIN", None) if value.eq_ignore_ascii_case("VTODO") => {
+                component = Some(Component::default());
+            }
+            ("BEGIN", Some(_)) => depth += 1,
+            ("END", Some(_)) if depth > 0 => depth -= 1,
+            ("END", Some(_)) => {
+                if let Some(component) = component.take() {
+                    todos.push(component.finish());
+                }
+            }
+            (_, Some(component)) if depth == 0 => component.read(&property),
+            _ => {}
+        }
+    }
+    if component.is_some() {
+        return Err("Invalid iCalendar document, VTODO is not ended".to_string());
+    }
+    Ok(todos)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        error::FieldError,
+        ical::{parse, write},
+        test::{fixture_todos, timestamp},
+        todo::CreateTodo,
+    };
+
+    #[test]
+    fn write_todos() {
+        let mut todos = fixture_todos();
+        todos[1].description = "first, second; \\third\nfourth".to_string();
+        todos[1].recurrence = Some("FREQ=WEEKLY;BYDAY=MO".to_string());
+        let calendar = write(&todos[..2], timestamp("2024-07-01 10:00:00"));
+        let expected = [
+            "BEGIN:VCALENDAR",
+            "VERSION:2.0",
+            "PRODID:-//todo-actix//EN",
+            "BEGIN:VTODO",
+            "UID:todo-1@todo-actix",
+            "DTSTAMP:20240701T100000Z",
+            "CREATED:20240601T100000Z",
+            "LAST-MODIFIED:20240601T100000Z",
+            "SUMMARY:todo1",
+            "DESCRIPTION:description1",
+            "STATUS:NEEDS-ACTION",
+            "END:VTODO",
+            "BEGIN:VTODO",
+            "UID:todo-2@todo-actix",
+            "DTSTAMP:20240701T100000Z",
+            "CREATED:20240602T100000Z",
+            "LAST-MODIFIED:20240603T100000Z",
+            "SUMMARY:todo2",
+            "DESCRIPTION:first\\, second\\; \\\\third\\nfourth",
+            "DUE:20240610T120000Z",
+            "RRULE:FREQ=WEEKLY;BYDAY=MO",
+            "STATUS:COMPLETED",
+            "END:VTODO",
+            "END:VCALENDAR",
+            "",
+        ];
+        assert_eq!(calendar, expected.join("\r\n"));
+    }
+
+    #[test]
+    fn fold_long_lines() {
+        let mut todos = fixture_todos();
+        todos[0].description = "é".repeat(100);
+        let calendar = write(&todos[..1], timestamp("2024-07-01 10:00:00"));
+        assert!(calendar.split("\r\n").all(|line| line.len() <= 75));
+
+        let parsed = parse(&calendar).unwrap();
+        assert_eq!(parsed[0].0.description, todos[0].description);
+    }
+
+    #[test]
+    fn parse_todos() {
+        let calendar = [
+            "BEGIN:VCALENDAR",
+            "BEGIN:VEVENT",
+            "SUMMARY:not a todo",
+            "END:VEVENT",
+            "BEGIN:VTODO",
+            "SUMMARY:first\\, todo",
+            "DESCRIPTION:long",
+            "  description",
+            "DUE;VALUE=DATE:20240610",
+            "STATUS:COMPLETED",
+            "BEGIN:VALARM",
+            "DESCRIPTION:alarm",
+            "END:VALARM",
+            "END:VTODO",
+            "BEGIN:VTODO",
+            "SUMMARY:second",
+            "DUE;TZID=\"Europe/Paris:Central\":20240620T120000",
+            "RRULE:FREQ=MONTHLY;BYDAY=-1FR",
+            "END:VTODO",
+            "BEGIN:VTODO",
+            "DUE:tomorrow",
+            "END:VTODO",
+            "END:VCALENDAR",
+        ]
+        .join("\n");
+        let parsed = parse(&calendar).unwrap();
+        assert_eq!(
+            parsed,
+            vec![
+                (
+                    CreateTodo {
+                        title: "first, todo".to_string(),
+                        description: "long description".to_string(),
+                        completed: true,
+                        due_at: Some(timestamp("2024-06-10 00:00:00")),
+                        parent_id: None,
+                        recurrence: None,
+                    },
+                    vec![]
+                ),
+                (
+                    CreateTodo {
+                        title: "second".to_string(),
+                        description: String::new(),
+                        completed: false,
+                        due_at: Some(timestamp("2024-06-20 12:00:00")),
+                        parent_id: None,
+                        recurrence: Some("FREQ=MONTHLY;BYDAY=-1FR".to_string()),
+                    },
+                    vec![]
+                ),
+                (
+                    CreateTodo {
+                        title: String::new(),
+                        description: String::new(),
+                        completed: false,
+                        due_at: None,
+                        parent_id: None,
+                        recurrence: None,
+                    },
+                    vec![FieldError::new(
+                        "due_at",
+                        "must be an iCalendar date or date-time"
+                    )]
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_invalid() {
+        assert!(parse("SUMMARY:todo").is_err());
+        assert!(parse("BEGIN:VCALENDAR\nBEGIN:VTODO\nSUMMARY:todo").is_err());
+        assert!(parse("BEGIN:VCALENDAR\nnot a property\nEND:VCALENDAR").is_err());
+    }
+}
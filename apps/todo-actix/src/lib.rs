@@ -1,12 +1,54 @@
+#![cfg_attr(test, allow(clippy::unwrap_used, clippy::expect_used))]
+
 mod app;
+mod auth;
+mod batch;
+mod cli;
+mod client;
 mod config;
+mod cors;
 mod db;
 mod error;
+mod etag;
+mod feed;
+mod health;
+mod history;
+mod ical;
+mod memory;
+mod metrics;
+mod openapi;
+mod patch;
+mod query;
+mod rate_limit;
+mod recurrence;
+mod reminder;
+mod repository;
 mod routes;
+mod search;
+mod subtask;
+mod tag;
+mod tls;
 mod todo;
+mod transfer;
+mod trash;
+mod user;
+mod validate;
+mod webhook;
 
 #[cfg(test)]
 mod test;
 
 pub use app::configure_app;
+pub use cli::{cli, run_cli};
+pub use client::TodoClient;
 pub use config::Config;
+pub use cors::cors;
+pub use feed::ChangeFeed;
+pub use memory::MemoryRepository;
+pub use metrics::{Metrics, RequestMetrics};
+pub use rate_limit::{RateLimit, RateLimiter};
+pub use reminder::remind_periodically;
+pub use repository::{SqliteRepository, TodoRepository};
+pub use tls::{configure_redirect, reload_certificates, server_config, CertResolver};
+pub use trash::purge_periodically;
+pub use webhook::deliver_periodically;
@@ -1,26 +1,160 @@
-use actix_web::{middleware::Logger, App, HttpServer};
-use log::info;
-use sqlx::SqlitePool;
-use todo_actix::{configure_app, Config};
+use actix_web::{
+    middleware::{Condition, Logger},
+    rt, App, HttpServer,
+};
+use futures_util::future::{join_all, try_join};
+use log::{info, warn};
+use sqlx::sqlite::SqlitePoolOptions;
+use std::{
+    future::{poll_fn, Future},
+    pin::pin,
+    sync::Arc,
+    task::Poll,
+};
+use todo_actix::{
+    configure_app, configure_redirect, cors, deliver_periodically, purge_periodically,
+    reload_certificates, remind_periodically, server_config, CertResolver, ChangeFeed, Config,
+    Metrics, RateLimit, RateLimiter, RequestMetrics, SqliteRepository, TodoRepository,
+};
 
 #[actix_web::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let config = Config::from_env()?;
+    let config = Config::from_args()?;
     env_logger::builder().filter_level(config.log_level).init();
+    if config.token_secret_generated {
+        warn!(
+            "TOKEN_SECRET is not set, so tokens are signed with a random secret and \
+             invalidated on restart. Set it in production, where it is required."
+        );
+    }
 
-    let db_pool = SqlitePool::connect(&config.db_url).await?;
+    let db_pool = SqlitePoolOptions::new()
+        .max_connections(config.pool_size)
+        .connect(&config.db_url)
+        .await?;
     sqlx::migrate!("./migrations").run(&db_pool).await?;
+    let repository: Arc<dyn TodoRepository> = Arc::new(SqliteRepository::new(db_pool.clone()));
+    if let Some(owner) = &config.orphan_owner {
+        let Some(claimed) = repository.claim_orphans(owner).await? else {
+            return Err(format!("No user named '{owner}' to give todos without an owner").into());
+        };
+        info!("Gave {claimed} todo(s) without an owner to '{owner}'");
+    }
+    let feed = Arc::new(ChangeFeed::new());
+    rt::spawn(purge_periodically(
+        repository.clone(),
+        config.trash_retention,
+    ));
+    rt::spawn(deliver_periodically(
+        repository.clone(),
+        config.webhook_policy.clone(),
+    ));
+    rt::spawn(remind_periodically(repository.clone(), feed.clone()));
 
+    let metrics = Arc::new(Metrics::new()?);
+    let rate_limiter = Arc::new(RateLimiter::new(&config));
+
+    let app_config = config.clone();
     let app_builder = move || {
         let logger = Logger::default();
+        let origins = &app_config.cors_origins;
         App::new()
+            .wrap(RateLimit::new(rate_limiter.clone()))
             .wrap(logger)
-            .configure(|c| configure_app(c, db_pool.clone()))
+            .wrap(RequestMetrics::new(metrics.clone()))
+            // Outermost, so that preflight requests are answered right away
+            .wrap(Condition::new(!origins.is_empty(), cors(origins)))
+            .configure(|c| {
+                configure_app(
+                    c,
+                    repository.clone(),
+                    app_config.clone(),
+                    metrics.clone(),
+                    feed.clone(),
+                )
+            })
+    };
+    // Signals are handled below, as the server would not drain requests on SIGINT
+    let mut server = HttpServer::new(app_builder)
+        .disable_signals()
+        .shutdown_timeout(config.drain_timeout);
+    if config.workers > 0 {
+        server = server.workers(config.workers);
+    }
+    let address = (config.host.clone(), config.port);
+    let (server, scheme) = match (&config.tls_cert, &config.tls_key) {
+        (Some(cert), Some(key)) => {
+            let resolver = Arc::new(CertResolver::new(cert, key)?);
+            rt::spawn(reload_certificates(resolver.clone()));
+            let server = server.bind_rustls_0_23(address, server_config(resolver)?)?;
+            (server, "https")
+        }
+        _ => (server.bind(address)?, "http"),
+    };
+    let server = server.run();
+
+    // Plain HTTP requests are redirected to HTTPS on a separate listener
+    let redirect = match config.redirect_port {
+        Some(port) => {
+            let https_port = config.port;
+            let redirect = HttpServer::new(move || {
+                App::new()
+                    .wrap(Logger::default())
+                    .configure(|c| configure_redirect(c, https_port))
+            })
+            .disable_signals()
+            .shutdown_timeout(config.drain_timeout)
+            .workers(1)
+            .bind((config.host.clone(), port))?
+            .run();
+            info!("Redirecting http://{}:{port} to HTTPS", config.host);
+            Some(redirect)
+        }
+        None => None,
     };
-    let server = HttpServer::new(app_builder).bind((config.host.clone(), config.port))?;
 
-    info!("Listening on http://{}:{}", config.host, config.port);
-    server.run().await?;
+    let handles = [Some(server.handle()), redirect.as_ref().map(|r| r.handle())];
+    rt::spawn(async move {
+        shutdown_signal().await;
+        info!(
+            "Shutting down, waiting up to {}s for requests in flight",
+            config.drain_timeout
+        );
+        join_all(
+            handles
+                .into_iter()
+                .flatten()
+                .map(|handle| handle.stop(true)),
+        )
+        .await;
+    });
+
+    info!("Listening on {scheme}://{}:{}", config.host, config.port);
+    match redirect {
+        Some(redirect) => {
+            try_join(server, redirect).await?;
+        }
+        None => server.await?,
+    }
+    db_pool.close().await;
+    info!("Shut down");
 
     Ok(())
 }
+
+/// Resolves on the first SIGINT, or SIGTERM on Unix.
+async fn shutdown_signal() {
+    let mut interrupt = pin!(rt::signal::ctrl_c());
+    #[cfg(unix)]
+    let mut terminate = rt::signal::unix::signal(rt::signal::unix::SignalKind::terminate()).ok();
+    poll_fn(|cx| {
+        #[cfg(unix)]
+        if let Some(terminate) = &mut terminate {
+            if terminate.poll_recv(cx).is_ready() {
+                return Poll::Ready(());
+            }
+        }
+        interrupt.as_mut().poll(cx).map(|_| ())
+    })
+    .await
+}
@@ -0,0 +1,1462 @@
+use crate::{
+    batch::{Batch, BatchMode, Operation, Outcome},
+    error::{ApiError, FieldError, InternalError},
+    etag::IfMatch,
+    history::{EventKind, TodoEvent},
+    query::{ListParams, Page, Position},
+    recurrence::{self, Updated},
+    reminder::{CreateReminder, FiredReminder, Reminder, ReminderEvent},
+    repository::{Patcher, TodoRepository},
+    search::{Highlights, SearchHit, SearchParams, HIGHLIGHT_END, HIGHLIGHT_START},
+    subtask::{self, DeletePolicy, Deleted},
+    tag::{self, ChangedTag, CreateTag, Tag, TaggedTodo, UpdateTag},
+    todo::{CreateTodo, Todo, UpdateTodo},
+    transfer::{self, Imported},
+    user::{self, User},
+    webhook::{CreateWebhook, Delivery, Payload, Webhook},
+};
+use async_trait::async_trait;
+use chrono::{Duration, NaiveDateTime, SubsecRound, Utc};
+use sqlx::types::Json;
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, BTreeSet, HashSet},
+    ops::Range,
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+};
+
+/// Number of words of a description kept around its matches in search hits.
+const SNIPPET_WORDS: usize = 16;
+/// How much more a match in the title counts than one in the description.
+const TITLE_WEIGHT: usize = 10;
+
+/// A repository keeping everything in memory, behaving like the SQLite one
+/// without needing a database. Search ranking approximates the FTS5 one.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryRepository {
+    state: Arc<Mutex<State>>,
+}
+
+#[derive(Debug, Clone, Default)]
+struct State {
+    users: BTreeMap<i64, UserRow>,
+    todos: BTreeMap<i64, Todo>,
+    tags: BTreeMap<i64, TagRow>,
+    /// Pairs of todo and tag ids.
+    todo_tags: BTreeSet<(i64, i64)>,
+    events: Vec<TodoEvent>,
+    webhooks: BTreeMap<i64, WebhookRow>,
+    deliveries: BTreeMap<i64, Delivery>,
+    reminders: BTreeMap<i64, Reminder>,
+    /// Last ids handed out, which like SQLite's are never reused.
+    last_ids: LastIds,
+}
+
+#[derive(Debug, Clone)]
+struct UserRow {
+    user: User,
+    password_hash: String,
+}
+
+#[derive(Debug, Clone)]
+struct TagRow {
+    user_id: i64,
+    tag: Tag,
+}
+
+#[derive(Debug, Clone)]
+struct WebhookRow {
+    user_id: i64,
+    webhook: Webhook,
+}
+
+#[derive(Debug, Clone, Default)]
+struct LastIds {
+    user: i64,
+    todo: i64,
+    tag: i64,
+    event: i64,
+    webhook: i64,
+    delivery: i64,
+    reminder: i64,
+}
+
+impl MemoryRepository {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        // The state is never left half-changed, so a panic elsewhere does not
+        // make it unusable
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// SQLite timestamps only have a precision of one second.
+fn now() -> NaiveDateTime {
+    Utc::now().naive_utc().trunc_subsecs(0)
+}
+
+fn next_id(last: &mut i64) -> i64 {
+    *last += 1;
+    *last
+}
+
+impl State {
+    fn todo(&self, user_id: i64, id: i64) -> Result<&Todo, InternalError> {
+        self.todos
+            .get(&id)
+            .filter(|todo| todo.user_id == Some(user_id) && todo.deleted_at.is_none())
+            .ok_or(InternalError::NotFound)
+    }
+
+    fn current_todo(
+        &self,
+        user_id: i64,
+        id: i64,
+        if_match: &IfMatch,
+    ) -> Result<Todo, InternalError> {
+        let todo = self.todo(user_id, id)?;
+        if !if_match.matches(todo.version) {
+            return Err(InternalError::PreconditionFailed);
+        }
+        Ok(todo.clone())
+    }
+
+    fn tag(&self, user_id: i64, id: i64) -> Result<&Tag, InternalError> {
+        self.tags
+            .get(&id)
+            .filter(|row| row.user_id == user_id)
+            .map(|row| &row.tag)
+            .ok_or(InternalError::NotFound)
+    }
+
+    fn webhook(&self, user_id: i64, id: i64) -> Result<&Webhook, InternalError> {
+        self.webhooks
+            .get(&id)
+            .filter(|row| row.user_id == user_id)
+            .map(|row| &row.webhook)
+            .ok_or(InternalError::NotFound)
+    }
+
+    fn insert_todo(&mut self, user_id: i64, todo: CreateTodo) -> Result<Todo, InternalError> {
+        self.check_parent(user_id, None, todo.parent_id)?;
+        let now = now();
+        let todo = Todo {
+            id: next_id(&mut self.last_ids.todo),
+            user_id: Some(user_id),
+            parent_id: todo.parent_id,
+            title: todo.title,
+            description: todo.description,
+            completed: todo.completed,
+            due_at: todo.due_at,
+            recurrence: todo.recurrence,
+            created_at: now,
+            updated_at: now,
+            version: 1,
+            deleted_at: None,
+        };
+        self.todos.insert(todo.id, todo.clone());
+        self.record_event(Some(user_id), EventKind::Create, None, Some(&todo));
+        Ok(todo)
+    }
+
+    /// Gives the next occurrence of a recurring todo the reminders of the
+    /// previous one.
+    fn copy_reminders(&mut self, from_id: i64, to_id: i64) {
+        let offsets: Vec<i64> = self
+            .reminders
+            .values()
+            .filter(|reminder| reminder.todo_id == from_id)
+            .map(|reminder| reminder.offset_seconds)
+            .collect();
+        for offset_seconds in offsets {
+            let reminder = Reminder {
+                id: next_id(&mut self.last_ids.reminder),
+                todo_id: to_id,
+                offset_seconds,
+                fired_at: None,
+                fired_for: None,
+                created_at: now(),
+            };
+            self.reminders.insert(reminder.id, reminder);
+        }
+    }
+
+    fn replace_todo(
+        &mut self,
+        user_id: i64,
+        id: i64,
+        mut todo: UpdateTodo,
+        if_match: &IfMatch,
+    ) -> Result<Updated, InternalError> {
+        let before = self.current_todo(user_id, id, if_match)?;
+        let next = recurrence::complete(&before, &mut todo);
+        let todo = self.update_todo_row(user_id, before.clone(), todo)?;
+        self.record_event(Some(user_id), EventKind::Update, Some(&before), Some(&todo));
+        let next = match next {
+            Some(next) => {
+                let next = self.insert_todo(user_id, next)?;
+                self.copy_reminders(id, next.id);
+                Some(next)
+            }
+            None => None,
+        };
+        Ok(Updated { todo, next })
+    }
+
+    fn update_todo_row(
+        &mut self,
+        user_id: i64,
+        before: Todo,
+        todo: UpdateTodo,
+    ) -> Result<Todo, InternalError> {
+        self.check_parent(user_id, Some(before.id), todo.parent_id)?;
+        let todo = Todo {
+            title: todo.title,
+            description: todo.description,
+            completed: todo.completed,
+            due_at: todo.due_at,
+            parent_id: todo.parent_id,
+            recurrence: todo.recurrence,
+            updated_at: now(),
+            version: before.version + 1,
+            ..before
+        };
+        self.todos.insert(todo.id, todo.clone());
+        Ok(todo)
+    }
+
+    /// Moves a todo to the trash, applying the delete policy to its subtasks.
+    fn trash_todo(
+        &mut self,
+        user_id: i64,
+        id: i64,
+        if_match: &IfMatch,
+        policy: DeletePolicy,
+    ) -> Result<Deleted, InternalError> {
+        let before = self.current_todo(user_id, id, if_match)?;
+        let children = self.children(id);
+        let mut subtasks = Vec::new();
+        match policy {
+            _ if children.is_empty() => {}
+            DeletePolicy::Reject => {
+                return Err(InternalError::Conflict(subtask::HAS_SUBTASKS.to_string()))
+            }
+            DeletePolicy::Orphan => {
+                for child in children {
+                    subtasks.push(self.detach_todo(user_id, child));
+                }
+            }
+            DeletePolicy::Cascade => {
+                let mut descendants = Vec::new();
+                let mut level = children;
+                while !level.is_empty() {
+                    let next = level
+                        .iter()
+                        .flat_map(|todo| self.children(todo.id))
+                        .collect();
+                    descendants.append(&mut level);
+                    level = next;
+                }
+                descendants.sort_by_key(|todo| todo.id);
+                for descendant in descendants {
+                    subtasks.push(self.trash_todo_row(user_id, descendant));
+                }
+            }
+        }
+        let todo = self.trash_todo_row(user_id, before);
+        Ok(Deleted { todo, subtasks })
+    }
+
+    fn trash_todo_row(&mut self, user_id: i64, before: Todo) -> Todo {
+        let todo = Todo {
+            deleted_at: Some(now()),
+            version: before.version + 1,
+            ..before.clone()
+        };
+        self.todos.insert(todo.id, todo.clone());
+        self.record_event(Some(user_id), EventKind::Delete, Some(&before), Some(&todo));
+        todo
+    }
+
+    /// Makes a subtask a top-level todo.
+    fn detach_todo(&mut self, user_id: i64, before: Todo) -> Todo {
+        let todo = Todo {
+            parent_id: None,
+            updated_at: now(),
+            version: before.version + 1,
+            ..before.clone()
+        };
+        self.todos.insert(todo.id, todo.clone());
+        self.record_event(Some(user_id), EventKind::Update, Some(&before), Some(&todo));
+        todo
+    }
+
+    /// Live subtasks of a todo, oldest first.
+    fn children(&self, id: i64) -> Vec<Todo> {
+        self.todos
+            .values()
+            .filter(|todo| todo.parent_id == Some(id) && todo.deleted_at.is_none())
+            .cloned()
+            .collect()
+    }
+
+    /// Checks that a todo can be placed under the given parent, which must be
+    /// another live todo of the user that is not one of its subtasks, without
+    /// nesting subtasks too deep. New todos have no id yet.
+    fn check_parent(
+        &self,
+        user_id: i64,
+        id: Option<i64>,
+        parent_id: Option<i64>,
+    ) -> Result<(), InternalError> {
+        let Some(parent_id) = parent_id else {
+            return Ok(());
+        };
+        self.todo(user_id, parent_id)
+            .map_err(|_| InternalError::Constraint(subtask::unknown_parent()))?;
+
+        // The parent along with its own ancestors
+        let mut ancestors = vec![parent_id];
+        let mut current = self.todos.get(&parent_id).and_then(|todo| todo.parent_id);
+        while let Some(ancestor) = current.filter(|ancestor| !ancestors.contains(ancestor)) {
+            ancestors.push(ancestor);
+            current = self.todos.get(&ancestor).and_then(|todo| todo.parent_id);
+        }
+        let Some(id) = id else {
+            return subtask::check_depth(ancestors.len() + 1);
+        };
+        if ancestors.contains(&id) {
+            return Err(InternalError::Constraint(subtask::cycle()));
+        }
+
+        // Levels of subtasks the todo brings along
+        let mut height = 0;
+        let mut level = vec![id];
+        while height < subtask::MAX_DEPTH {
+            level = level
+                .iter()
+                .flat_map(|id| self.children(*id))
+                .map(|todo| todo.id)
+                .collect();
+            if level.is_empty() {
+                break;
+            }
+            height += 1;
+        }
+        subtask::check_depth(ancestors.len() + 1 + height)
+    }
+
+    fn run_operation(
+        &mut self,
+        user_id: i64,
+        operation: Operation,
+        policy: DeletePolicy,
+    ) -> Result<Updated, InternalError> {
+        match operation {
+            Operation::Create { todo } => Ok(self.insert_todo(user_id, todo)?.into()),
+            Operation::Update { id, todo, version } => {
+                let if_match = Operation::if_match(version);
+                self.replace_todo(user_id, id, todo, &if_match)
+            }
+            Operation::Delete { id, version } => {
+                let if_match = Operation::if_match(version);
+                Ok(self.trash_todo(user_id, id, &if_match, policy)?.todo.into())
+            }
+        }
+    }
+
+    fn record_event(
+        &mut self,
+        user_id: Option<i64>,
+        kind: EventKind,
+        before: Option<&Todo>,
+        after: Option<&Todo>,
+    ) {
+        let Some(todo) = after.or(before) else {
+            return;
+        };
+        let event = TodoEvent {
+            id: next_id(&mut self.last_ids.event),
+            todo_id: todo.id,
+            user_id,
+            kind,
+            version: todo.version,
+            before: before.cloned().map(Json),
+            after: after.cloned().map(Json),
+            created_at: now(),
+        };
+        self.queue_deliveries(todo.user_id, &Payload::Event(event.clone()));
+        self.events.push(event);
+    }
+
+    /// Queues a payload for the webhooks of a user.
+    fn queue_deliveries(&mut self, user_id: Option<i64>, event: &Payload) {
+        let webhook_ids: Vec<i64> = self
+            .webhooks
+            .values()
+            .filter(|row| Some(row.user_id) == user_id)
+            .map(|row| row.webhook.id)
+            .collect();
+        for webhook_id in webhook_ids {
+            let delivery = Delivery {
+                id: next_id(&mut self.last_ids.delivery),
+                webhook_id,
+                event: event.clone(),
+                attempts: 0,
+                next_attempt_at: now(),
+                last_error: None,
+                failed_at: None,
+            };
+            self.deliveries.insert(delivery.id, delivery);
+        }
+    }
+
+    /// Bumps the version of a todo whose tags changed, as its representation
+    /// embeds them, and records the change. Like any other update, it is
+    /// queued for the webhooks of the owner.
+    fn touch_todo(&mut self, user_id: i64, id: i64) -> Option<Todo> {
+        let todo = self.todos.get_mut(&id)?;
+        let before = todo.clone();
+        todo.version += 1;
+        let after = todo.clone();
+        self.record_event(
+            Some(user_id),
+            EventKind::Update,
+            Some(&before),
+            Some(&after),
+        );
+        Some(after)
+    }
+
+    /// Touches the todos carrying a tag which is renamed or deleted, leaving
+    /// those in the trash alone.
+    fn touch_tagged_todos(&mut self, user_id: i64, tag_id: i64) -> Vec<Todo> {
+        let mut ids: Vec<i64> = self
+            .todo_tags
+            .iter()
+            .filter(|(_, tag)| *tag == tag_id)
+            .map(|(todo, _)| *todo)
+            .filter(|id| {
+                self.todos
+                    .get(id)
+                    .is_some_and(|todo| todo.user_id == Some(user_id) && todo.deleted_at.is_none())
+            })
+            .collect();
+        ids.sort_unstable();
+        ids.into_iter()
+            .filter_map(|id| self.touch_todo(user_id, id))
+            .collect()
+    }
+
+    fn tag_names(&self, todo_id: i64) -> HashSet<&str> {
+        self.todo_tags
+            .range((todo_id, i64::MIN)..=(todo_id, i64::MAX))
+            .filter_map(|(_, tag_id)| self.tags.get(tag_id))
+            .map(|row| row.tag.name.as_str())
+            .collect()
+    }
+
+    fn tag_todo(&self, todo: Todo) -> TaggedTodo {
+        let mut tags: Vec<Tag> = self
+            .todo_tags
+            .range((todo.id, i64::MIN)..=(todo.id, i64::MAX))
+            .filter_map(|(_, tag_id)| self.tags.get(tag_id))
+            .map(|row| row.tag.clone())
+            .collect();
+        tags.sort_by(|a, b| a.name.cmp(&b.name));
+        TaggedTodo { todo, tags }
+    }
+
+    fn check_tag_name(
+        &self,
+        user_id: i64,
+        id: Option<i64>,
+        name: &str,
+    ) -> Result<(), InternalError> {
+        let taken = self
+            .tags
+            .values()
+            .any(|row| row.user_id == user_id && row.tag.name == name && Some(row.tag.id) != id);
+        if taken {
+            return Err(InternalError::Conflict(tag::NAME_TAKEN.to_string()));
+        }
+        Ok(())
+    }
+}
+
+/// Like SQLite's `LIKE`, only ASCII letters are compared case-insensitively.
+fn contains(text: &str, value: &str) -> bool {
+    text.to_ascii_lowercase()
+        .contains(&value.to_ascii_lowercase())
+}
+
+#[async_trait]
+impl TodoRepository for MemoryRepository {
+    async fn check_ready(&self) -> Result<(), InternalError> {
+        Ok(())
+    }
+
+    async fn list_todos(&self, user_id: i64, params: &ListParams) -> Result<Page, InternalError> {
+        let state = self.state();
+        let tags: HashSet<&str> = params.tags.iter().map(String::as_str).collect();
+        let mut rows: Vec<_> = state
+            .todos
+            .values()
+            .filter(|todo| todo.user_id == Some(user_id) && todo.deleted_at.is_none())
+            .filter(|todo| {
+                params
+                    .title
+                    .as_ref()
+                    .is_none_or(|title| contains(&todo.title, title))
+            })
+            .filter(|todo| {
+                params
+                    .description
+                    .as_ref()
+                    .is_none_or(|description| contains(&todo.description, description))
+            })
+            .filter(|todo| {
+                params
+                    .completed
+                    .is_none_or(|completed| todo.completed == completed)
+            })
+            .filter(|todo| {
+                params
+                    .due_before
+                    .is_none_or(|before| todo.due_at.is_some_and(|due_at| due_at < before))
+            })
+            .filter(|todo| tags.is_subset(&state.tag_names(todo.id)))
+            .map(|todo| (params.sort.values(todo), todo))
+            .collect();
+        if let Position::After(cursor) = &params.position {
+            rows.retain(|(values, _)| params.sort.compare(values, cursor.values()).is_gt());
+        }
+        rows.sort_by(|(a, _), (b, _)| params.sort.compare(a, b));
+
+        let offset = match params.position {
+            Position::Offset(offset) => offset as usize,
+            Position::Start | Position::After(_) => 0,
+        };
+        // Take one extra todo to know whether there is a next page
+        let todos = rows
+            .into_iter()
+            .skip(offset)
+            .take(params.limit as usize + 1)
+            .map(|(_, todo)| todo.clone())
+            .collect();
+        Ok(Page::new(params, todos))
+    }
+
+    async fn get_todo(&self, user_id: i64, id: i64) -> Result<Todo, InternalError> {
+        self.state().todo(user_id, id).cloned()
+    }
+
+    async fn create_todo(&self, user_id: i64, todo: CreateTodo) -> Result<Todo, InternalError> {
+        self.state().insert_todo(user_id, todo)
+    }
+
+    async fn update_todo(
+        &self,
+        user_id: i64,
+        id: i64,
+        todo: UpdateTodo,
+        if_match: &IfMatch,
+    ) -> Result<Updated, InternalError> {
+        self.state().replace_todo(user_id, id, todo, if_match)
+    }
+
+    async fn patch_todo(
+        &self,
+        user_id: i64,
+        id: i64,
+        if_match: &IfMatch,
+        patch: &Patcher<'_>,
+    ) -> Result<Result<Updated, ApiError>, InternalError> {
+        let mut state = self.state();
+        let before = state.current_todo(user_id, id, if_match)?;
+        let todo = match patch(before) {
+            Ok(todo) => todo,
+            Err(err) => return Ok(Err(err)),
+        };
+        state.replace_todo(user_id, id, todo, &IfMatch::Any).map(Ok)
+    }
+
+    async fn delete_todo(
+        &self,
+        user_id: i64,
+        id: i64,
+        if_match: &IfMatch,
+        policy: DeletePolicy,
+    ) -> Result<Deleted, InternalError> {
+        self.state().trash_todo(user_id, id, if_match, policy)
+    }
+
+    /// Runs the operations of a batch on a copy of the state for each, so that
+    /// a failing operation can be undone on its own.
+    async fn run_batch(
+        &self,
+        user_id: i64,
+        batch: Batch,
+        policy: DeletePolicy,
+    ) -> Result<Outcome, InternalError> {
+        let mut state = self.state();
+        let initial = state.clone();
+        let mut results = Vec::with_capacity(batch.operations.len());
+        let mut committed = true;
+        for operation in batch.operations {
+            let savepoint = state.clone();
+            let result = state.run_operation(user_id, operation, policy);
+            let failed = result.is_err();
+            if failed {
+                *state = savepoint;
+            }
+            results.push(result);
+            if failed && batch.mode == BatchMode::Atomic {
+                committed = false;
+                *state = initial;
+                break;
+            }
+        }
+        Ok(Outcome { committed, results })
+    }
+
+    async fn list_children(&self, user_id: i64, id: i64) -> Result<Vec<Todo>, InternalError> {
+        let state = self.state();
+        state.todo(user_id, id)?;
+        Ok(state.children(id))
+    }
+
+    async fn todo_tree(&self, user_id: i64) -> Result<Vec<Todo>, InternalError> {
+        let state = self.state();
+        let mut todos = Vec::new();
+        let mut level: Vec<Todo> = state
+            .todos
+            .values()
+            .filter(|todo| todo.user_id == Some(user_id) && todo.deleted_at.is_none())
+            .filter(|todo| todo.parent_id.is_none())
+            .cloned()
+            .collect();
+        for _ in 0..=subtask::MAX_DEPTH {
+            if level.is_empty() {
+                break;
+            }
+            let mut next: Vec<Todo> = level
+                .iter()
+                .flat_map(|todo| state.children(todo.id))
+                .collect();
+            next.sort_by_key(|todo| todo.id);
+            todos.append(&mut level);
+            level = next;
+        }
+        Ok(todos)
+    }
+
+    async fn export_todos(&self, user_id: i64) -> Result<Vec<Todo>, InternalError> {
+        let state = self.state();
+        let todos = state
+            .todos
+            .values()
+            .filter(|todo| todo.user_id == Some(user_id) && todo.deleted_at.is_none())
+            .cloned()
+            .collect();
+        Ok(todos)
+    }
+
+    async fn import_todos(
+        &self,
+        user_id: i64,
+        todos: Vec<CreateTodo>,
+    ) -> Result<Vec<Imported>, InternalError> {
+        let mut state = self.state();
+        let mut imported = Vec::with_capacity(todos.len());
+        for todo in todos {
+            let existing = state
+                .todos
+                .values()
+                .filter(|existing| existing.user_id == Some(user_id))
+                .filter(|existing| existing.deleted_at.is_none())
+                .find(|existing| transfer::is_duplicate(&todo, existing))
+                .cloned();
+            imported.push(match existing {
+                Some(existing) => Imported::Duplicate(existing),
+                None => Imported::Created(state.insert_todo(user_id, todo)?),
+            });
+        }
+        Ok(imported)
+    }
+
+    async fn list_trash(&self, user_id: i64) -> Result<Vec<Todo>, InternalError> {
+        let state = self.state();
+        let mut todos: Vec<Todo> = state
+            .todos
+            .values()
+            .filter(|todo| todo.user_id == Some(user_id) && todo.deleted_at.is_some())
+            .cloned()
+            .collect();
+        todos.sort_by_key(|todo| Reverse((todo.deleted_at, todo.id)));
+        Ok(todos)
+    }
+
+    async fn restore_todo(&self, user_id: i64, id: i64) -> Result<Todo, InternalError> {
+        let mut state = self.state();
+        let before = state
+            .todos
+            .get(&id)
+            .filter(|todo| todo.user_id == Some(user_id) && todo.deleted_at.is_some())
+            .cloned()
+            .ok_or(InternalError::NotFound)?;
+        // Subtasks restored without their parent become top-level todos
+        let parent_id = before
+            .parent_id
+            .filter(|parent_id| state.todo(user_id, *parent_id).is_ok());
+        let todo = Todo {
+            deleted_at: None,
+            version: before.version + 1,
+            parent_id,
+            ..before.clone()
+        };
+        state.todos.insert(todo.id, todo.clone());
+        state.record_event(
+            Some(user_id),
+            EventKind::Restore,
+            Some(&before),
+            Some(&todo),
+        );
+        Ok(todo)
+    }
+
+    async fn purge_trash(&self, before: NaiveDateTime) -> Result<u64, InternalError> {
+        let mut state = self.state();
+        let todos: Vec<Todo> = state
+            .todos
+            .values()
+            .filter(|todo| {
+                todo.deleted_at
+                    .is_some_and(|deleted_at| deleted_at < before)
+            })
+            .cloned()
+            .collect();
+        for todo in &todos {
+            state.record_event(None, EventKind::Purge, Some(todo), None);
+            state.todos.remove(&todo.id);
+            state.todo_tags.retain(|(todo_id, _)| *todo_id != todo.id);
+            state
+                .reminders
+                .retain(|_, reminder| reminder.todo_id != todo.id);
+            for child in state.todos.values_mut() {
+                if child.parent_id == Some(todo.id) {
+                    child.parent_id = None;
+                }
+            }
+        }
+        Ok(todos.len() as u64)
+    }
+
+    async fn list_history(
+        &self,
+        user_id: i64,
+        todo_id: i64,
+    ) -> Result<Vec<TodoEvent>, InternalError> {
+        let state = self.state();
+        state
+            .todos
+            .get(&todo_id)
+            .filter(|todo| todo.user_id == Some(user_id))
+            .ok_or(InternalError::NotFound)?;
+        let events = state
+            .events
+            .iter()
+            .filter(|event| event.todo_id == todo_id)
+            .cloned()
+            .collect();
+        Ok(events)
+    }
+
+    async fn revert_todo(
+        &self,
+        user_id: i64,
+        id: i64,
+        version: i64,
+        if_match: &IfMatch,
+    ) -> Result<Todo, InternalError> {
+        let mut state = self.state();
+        let before = state.current_todo(user_id, id, if_match)?;
+        let snapshot = state
+            .events
+            .iter()
+            .filter(|event| event.todo_id == id && event.version == version)
+            .find_map(|event| event.after.clone())
+            .ok_or_else(|| {
+                InternalError::Constraint(FieldError::new("version", "Unknown version"))
+            })?;
+
+        let todo = state.update_todo_row(user_id, before.clone(), UpdateTodo::from(snapshot.0))?;
+        state.record_event(Some(user_id), EventKind::Revert, Some(&before), Some(&todo));
+        Ok(todo)
+    }
+
+    async fn create_user(
+        &self,
+        username: &str,
+        password_hash: &str,
+    ) -> Result<User, InternalError> {
+        let mut state = self.state();
+        if state
+            .users
+            .values()
+            .any(|row| row.user.username == username)
+        {
+            return Err(InternalError::Conflict(user::USERNAME_TAKEN.to_string()));
+        }
+        let user = User {
+            id: next_id(&mut state.last_ids.user),
+            username: username.to_string(),
+            created_at: now(),
+        };
+        let row = UserRow {
+            user: user.clone(),
+            password_hash: password_hash.to_string(),
+        };
+        state.users.insert(user.id, row);
+        Ok(user)
+    }
+
+    async fn get_user_password_hash(
+        &self,
+        username: &str,
+    ) -> Result<Option<(i64, String)>, InternalError> {
+        let state = self.state();
+        let user = state
+            .users
+            .values()
+            .find(|row| row.user.username == username)
+            .map(|row| (row.user.id, row.password_hash.clone()));
+        Ok(user)
+    }
+
+    async fn claim_orphans(&self, username: &str) -> Result<Option<u64>, InternalError> {
+        let mut state = self.state();
+        let Some(user_id) = state
+            .users
+            .values()
+            .find(|row| row.user.username == username)
+            .map(|row| row.user.id)
+        else {
+            return Ok(None);
+        };
+        let mut claimed = 0;
+        for todo in state.todos.values_mut() {
+            if todo.user_id.is_none() {
+                todo.user_id = Some(user_id);
+                claimed += 1;
+            }
+        }
+        Ok(Some(claimed))
+    }
+
+    async fn search_todos(
+        &self,
+        user_id: i64,
+        params: &SearchParams,
+    ) -> Result<Vec<SearchHit>, InternalError> {
+        let state = self.state();
+        let mut hits: Vec<(usize, SearchHit)> = state
+            .todos
+            .values()
+            .filter(|todo| todo.user_id == Some(user_id) && todo.deleted_at.is_none())
+            .filter_map(|todo| {
+                let (score, highlights) = search(todo, params)?;
+                let todo = state.tag_todo(todo.clone());
+                Some((score, SearchHit { todo, highlights }))
+            })
+            .collect();
+        hits.sort_by_key(|(score, hit)| (Reverse(*score), hit.todo.todo.id));
+        let hits = hits
+            .into_iter()
+            .take(params.limit as usize)
+            .map(|(_, hit)| hit)
+            .collect();
+        Ok(hits)
+    }
+
+    async fn list_tags(&self, user_id: i64) -> Result<Vec<Tag>, InternalError> {
+        let state = self.state();
+        let mut tags: Vec<Tag> = state
+            .tags
+            .values()
+            .filter(|row| row.user_id == user_id)
+            .map(|row| row.tag.clone())
+            .collect();
+        tags.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(tags)
+    }
+
+    async fn get_tag(&self, user_id: i64, id: i64) -> Result<Tag, InternalError> {
+        self.state().tag(user_id, id).cloned()
+    }
+
+    async fn create_tag(&self, user_id: i64, tag: CreateTag) -> Result<Tag, InternalError> {
+        let mut state = self.state();
+        state.check_tag_name(user_id, None, &tag.name)?;
+        let tag = Tag {
+            id: next_id(&mut state.last_ids.tag),
+            name: tag.name,
+        };
+        let row = TagRow {
+            user_id,
+            tag: tag.clone(),
+        };
+        state.tags.insert(tag.id, row);
+        Ok(tag)
+    }
+
+    async fn update_tag(
+        &self,
+        user_id: i64,
+        id: i64,
+        tag: UpdateTag,
+    ) -> Result<ChangedTag, InternalError> {
+        let mut state = self.state();
+        state.tag(user_id, id)?;
+        state.check_tag_name(user_id, Some(id), &tag.name)?;
+        let tag = Tag { id, name: tag.name };
+        let row = TagRow {
+            user_id,
+            tag: tag.clone(),
+        };
+        state.tags.insert(id, row);
+        let todos = state.touch_tagged_todos(user_id, id);
+        Ok(ChangedTag { tag, todos })
+    }
+
+    async fn delete_tag(&self, user_id: i64, id: i64) -> Result<ChangedTag, InternalError> {
+        let mut state = self.state();
+        let tag = state.tag(user_id, id)?.clone();
+        let todos = state.touch_tagged_todos(user_id, id);
+        state.tags.remove(&id);
+        state.todo_tags.retain(|(_, tag_id)| *tag_id != id);
+        Ok(ChangedTag { tag, todos })
+    }
+
+    async fn list_todo_tags(&self, user_id: i64, todo_id: i64) -> Result<Vec<Tag>, InternalError> {
+        let state = self.state();
+        let todo = state.todo(user_id, todo_id)?.clone();
+        Ok(state.tag_todo(todo).tags)
+    }
+
+    async fn attach_tag(
+        &self,
+        user_id: i64,
+        todo_id: i64,
+        tag_id: i64,
+    ) -> Result<TaggedTodo, InternalError> {
+        let mut state = self.state();
+        state.todo(user_id, todo_id)?;
+        state.tag(user_id, tag_id)?;
+        if state.todo_tags.insert((todo_id, tag_id)) {
+            state.touch_todo(user_id, todo_id);
+        }
+        let todo = state.todo(user_id, todo_id)?.clone();
+        Ok(state.tag_todo(todo))
+    }
+
+    async fn detach_tag(
+        &self,
+        user_id: i64,
+        todo_id: i64,
+        tag_id: i64,
+    ) -> Result<TaggedTodo, InternalError> {
+        let mut state = self.state();
+        state.todo(user_id, todo_id)?;
+        if !state.todo_tags.remove(&(todo_id, tag_id)) {
+            return Err(InternalError::NotFound);
+        }
+        state.touch_todo(user_id, todo_id);
+        let todo = state.todo(user_id, todo_id)?.clone();
+        Ok(state.tag_todo(todo))
+    }
+
+    async fn tag_todos(&self, todos: Vec<Todo>) -> Result<Vec<TaggedTodo>, InternalError> {
+        let state = self.state();
+        Ok(todos.into_iter().map(|todo| state.tag_todo(todo)).collect())
+    }
+
+    async fn list_webhooks(&self, user_id: i64) -> Result<Vec<Webhook>, InternalError> {
+        let state = self.state();
+        let webhooks = state
+            .webhooks
+            .values()
+            .filter(|row| row.user_id == user_id)
+            .map(|row| row.webhook.clone())
+            .collect();
+        Ok(webhooks)
+    }
+
+    async fn get_webhook(&self, user_id: i64, id: i64) -> Result<Webhook, InternalError> {
+        self.state().webhook(user_id, id).cloned()
+    }
+
+    async fn create_webhook(
+        &self,
+        user_id: i64,
+        webhook: CreateWebhook,
+        secret: &str,
+    ) -> Result<Webhook, InternalError> {
+        let mut state = self.state();
+        let webhook = Webhook {
+            id: next_id(&mut state.last_ids.webhook),
+            url: webhook.url,
+            secret: secret.to_string(),
+            created_at: now(),
+        };
+        let row = WebhookRow {
+            user_id,
+            webhook: webhook.clone(),
+        };
+        state.webhooks.insert(webhook.id, row);
+        Ok(webhook)
+    }
+
+    async fn delete_webhook(&self, user_id: i64, id: i64) -> Result<Webhook, InternalError> {
+        let mut state = self.state();
+        let webhook = state.webhook(user_id, id)?.clone();
+        state.webhooks.remove(&id);
+        state
+            .deliveries
+            .retain(|_, delivery| delivery.webhook_id != id);
+        Ok(webhook)
+    }
+
+    async fn list_dead_letters(
+        &self,
+        user_id: i64,
+        webhook_id: i64,
+    ) -> Result<Vec<Delivery>, InternalError> {
+        let state = self.state();
+        state.webhook(user_id, webhook_id)?;
+        let deliveries = state
+            .deliveries
+            .values()
+            .filter(|delivery| delivery.webhook_id == webhook_id && delivery.failed_at.is_some())
+            .cloned()
+            .collect();
+        Ok(deliveries)
+    }
+
+    async fn retry_dead_letter(
+        &self,
+        user_id: i64,
+        webhook_id: i64,
+        id: i64,
+    ) -> Result<Delivery, InternalError> {
+        let mut state = self.state();
+        state.webhook(user_id, webhook_id)?;
+        let delivery = state
+            .deliveries
+            .get_mut(&id)
+            .filter(|delivery| delivery.webhook_id == webhook_id && delivery.failed_at.is_some())
+            .ok_or(InternalError::NotFound)?;
+        delivery.attempts = 0;
+        delivery.next_attempt_at = now();
+        delivery.last_error = None;
+        delivery.failed_at = None;
+        Ok(delivery.clone())
+    }
+
+    async fn due_deliveries(
+        &self,
+        now: NaiveDateTime,
+        limit: u32,
+    ) -> Result<Vec<(Webhook, Delivery)>, InternalError> {
+        let state = self.state();
+        let deliveries = state
+            .deliveries
+            .values()
+            .filter(|delivery| delivery.failed_at.is_none() && delivery.next_attempt_at <= now)
+            .take(limit as usize)
+            .map(|delivery| {
+                let webhook = state.webhooks[&delivery.webhook_id].webhook.clone();
+                (webhook, delivery.clone())
+            })
+            .collect();
+        Ok(deliveries)
+    }
+
+    async fn complete_delivery(&self, id: i64) -> Result<(), InternalError> {
+        self.state().deliveries.remove(&id);
+        Ok(())
+    }
+
+    async fn fail_delivery(
+        &self,
+        id: i64,
+        error: &str,
+        retry_at: Option<NaiveDateTime>,
+    ) -> Result<(), InternalError> {
+        let mut state = self.state();
+        if let Some(delivery) = state.deliveries.get_mut(&id) {
+            delivery.attempts += 1;
+            delivery.last_error = Some(error.to_string());
+            match retry_at {
+                Some(retry_at) => delivery.next_attempt_at = retry_at,
+                None => delivery.failed_at = Some(now()),
+            }
+        }
+        Ok(())
+    }
+
+    async fn list_reminders(
+        &self,
+        user_id: i64,
+        todo_id: i64,
+    ) -> Result<Vec<Reminder>, InternalError> {
+        let state = self.state();
+        state.todo(user_id, todo_id)?;
+        let reminders = state
+            .reminders
+            .values()
+            .filter(|reminder| reminder.todo_id == todo_id)
+            .cloned()
+            .collect();
+        Ok(reminders)
+    }
+
+    async fn create_reminder(
+        &self,
+        user_id: i64,
+        todo_id: i64,
+        reminder: CreateReminder,
+    ) -> Result<Reminder, InternalError> {
+        let mut state = self.state();
+        state.todo(user_id, todo_id)?;
+        let reminder = Reminder {
+            id: next_id(&mut state.last_ids.reminder),
+            todo_id,
+            offset_seconds: reminder.offset_seconds,
+            fired_at: None,
+            fired_for: None,
+            created_at: now(),
+        };
+        state.reminders.insert(reminder.id, reminder.clone());
+        Ok(reminder)
+    }
+
+    async fn delete_reminder(
+        &self,
+        user_id: i64,
+        todo_id: i64,
+        id: i64,
+    ) -> Result<Reminder, InternalError> {
+        let mut state = self.state();
+        state.todo(user_id, todo_id)?;
+        state
+            .reminders
+            .get(&id)
+            .filter(|reminder| reminder.todo_id == todo_id)
+            .ok_or(InternalError::NotFound)?;
+        state.reminders.remove(&id).ok_or(InternalError::NotFound)
+    }
+
+    async fn fire_due_reminders(
+        &self,
+        now: NaiveDateTime,
+        limit: u32,
+    ) -> Result<Vec<FiredReminder>, InternalError> {
+        let mut state = self.state();
+        let State {
+            todos, reminders, ..
+        } = &mut *state;
+        let mut fired = Vec::new();
+        for reminder in reminders.values_mut() {
+            if fired.len() == limit as usize {
+                break;
+            }
+            let Some(todo) = todos.get(&reminder.todo_id) else {
+                continue;
+            };
+            let Some(due_at) = todo.due_at else {
+                continue;
+            };
+            let due = due_at - Duration::seconds(reminder.offset_seconds) <= now;
+            if due
+                && !todo.completed
+                && todo.deleted_at.is_none()
+                && reminder.fired_for != Some(due_at)
+            {
+                reminder.fired_at = Some(now);
+                reminder.fired_for = Some(due_at);
+                fired.push(FiredReminder {
+                    reminder: reminder.clone(),
+                    todo: todo.clone(),
+                });
+            }
+        }
+        for fired in &fired {
+            let event = Payload::Reminder(ReminderEvent::from(fired.clone()));
+            state.queue_deliveries(fired.todo.user_id, &event);
+        }
+        Ok(fired)
+    }
+}
+
+/// A word of a text, lowercased, along with where it is in the text.
+struct Token {
+    range: Range<usize>,
+    word: String,
+}
+
+/// Splits a text into words of letters and digits, like the FTS5 `unicode61`
+/// tokenizer does.
+fn tokenize(text: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut start = None;
+    for (i, c) in text.char_indices().chain([(text.len(), ' ')]) {
+        match (c.is_alphanumeric(), start) {
+            (true, None) => start = Some(i),
+            (false, Some(begin)) => {
+                tokens.push(Token {
+                    range: begin..i,
+                    word: text[begin..i].to_lowercase(),
+                });
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    tokens
+}
+
+/// Indexes of the first and last tokens of every occurrence of a phrase.
+fn find_phrase(tokens: &[Token], phrase: &[Token], prefix: bool) -> Vec<Range<usize>> {
+    if phrase.is_empty() || tokens.len() < phrase.len() {
+        return Vec::new();
+    }
+    (0..=tokens.len() - phrase.len())
+        .filter(|&start| {
+            phrase.iter().enumerate().all(|(i, word)| {
+                let token = &tokens[start + i].word;
+                if prefix && i == phrase.len() - 1 {
+                    token.starts_with(&word.word)
+                } else {
+                    *token == word.word
+                }
+            })
+        })
+        .map(|start| start..start + phrase.len() - 1)
+        .collect()
+}
+
+/// Scores a todo against the search terms, returning nothing unless every
+/// term is found in its title or description.
+fn search(todo: &Todo, params: &SearchParams) -> Option<(usize, Highlights)> {
+    let title = tokenize(&todo.title);
+    let description = tokenize(&todo.description);
+    let mut title_matches = Vec::new();
+    let mut description_matches = Vec::new();
+    let mut score = 0;
+    for term in &params.terms {
+        let phrase = tokenize(&term.text);
+        let in_title = find_phrase(&title, &phrase, term.prefix);
+        let in_description = find_phrase(&description, &phrase, term.prefix);
+        if in_title.is_empty() && in_description.is_empty() {
+            return None;
+        }
+        score += TITLE_WEIGHT * in_title.len() + in_description.len();
+        title_matches.extend(in_title);
+        description_matches.extend(in_description);
+    }
+
+    let highlights = Highlights {
+        title: highlight(&todo.title, &title, &title_matches, 0..title.len()),
+        description: snippet(&todo.description, &description, &description_matches),
+    };
+    Some((score, highlights))
+}
+
+/// An excerpt of a text of at most `SNIPPET_WORDS` words, starting at its
+/// first match, with `…` marking where words were left out.
+fn snippet(text: &str, tokens: &[Token], matches: &[Range<usize>]) -> String {
+    if tokens.len() <= SNIPPET_WORDS {
+        return highlight(text, tokens, matches, 0..tokens.len());
+    }
+    let first = matches.iter().map(|m| m.start).min().unwrap_or(0);
+    let start = first.min(tokens.len() - SNIPPET_WORDS);
+    let end = start + SNIPPET_WORDS;
+    let mut snippet = highlight(text, tokens, matches, start..end);
+    if start > 0 {
+        snippet.insert(0, '…');
+    }
+    if end < tokens.len() {
+        snippet.push('…');
+    }
+    snippet
+}
+
+/// The part of a text spanning the given tokens, with matches wrapped in
+/// highlight tags. The whole text is kept when spanning every token.
+fn highlight(text: &str, tokens: &[Token], matches: &[Range<usize>], span: Range<usize>) -> String {
+    let from = match span.start {
+        0 => 0,
+        start => tokens[start].range.start,
+    };
+    let to = match span.end {
+        end if end == tokens.len() => text.len(),
+        end => tokens[end - 1].range.end,
+    };
+
+    let mut ranges: Vec<Range<usize>> = matches
+        .iter()
+        .filter(|m| span.contains(&m.start) && span.contains(&m.end))
+        .map(|m| tokens[m.start].range.start..tokens[m.end].range.end)
+        .collect();
+    ranges.sort_by_key(|range| range.start);
+
+    let mut highlighted = String::new();
+    let mut position = from;
+    for range in ranges {
+        // Overlapping matches are merged into the previous one
+        if range.start < position {
+            if range.end > position {
+                let end = highlighted.len() - HIGHLIGHT_END.len();
+                highlighted.insert_str(end, &text[position..range.end]);
+                position = range.end;
+            }
+            continue;
+        }
+        highlighted.push_str(&text[position..range.start]);
+        highlighted.push_str(HIGHLIGHT_START);
+        highlighted.push_str(&text[range.clone()]);
+        highlighted.push_str(HIGHLIGHT_END);
+        position = range.end;
+    }
+    highlighted.push_str(&text[position..to]);
+    highlighted
+}
+
+#[cfg(test)]
+impl MemoryRepository {
+    /// A repository holding a copy of the users, todos and tags stored in the
+    /// database, along with their history. Webhooks are left out, as tests
+    /// register them through the API.
+    pub async fn copy(pool: &sqlx::SqlitePool) -> Result<Self, InternalError> {
+        let mut state = State::default();
+
+        let users: Vec<(i64, String, NaiveDateTime, String)> =
+            sqlx::query_as("SELECT id, username, created_at, password_hash FROM users")
+                .fetch_all(pool)
+                .await?;
+        for (id, username, created_at, password_hash) in users {
+            let user = User {
+                id,
+                username,
+                created_at,
+            };
+            state.users.insert(
+                id,
+                UserRow {
+                    user,
+                    password_hash,
+                },
+            );
+        }
+
+        let todos: Vec<Todo> = sqlx::query_as("SELECT * FROM todos")
+            .fetch_all(pool)
+            .await?;
+        state.todos = todos.into_iter().map(|todo| (todo.id, todo)).collect();
+
+        let tags: Vec<(i64, i64, String)> = sqlx::query_as("SELECT id, user_id, name FROM tags")
+            .fetch_all(pool)
+            .await?;
+        for (id, user_id, name) in tags {
+            let tag = Tag { id, name };
+            state.tags.insert(id, TagRow { user_id, tag });
+        }
+
+        let todo_tags: Vec<(i64, i64)> = sqlx::query_as("SELECT todo_id, tag_id FROM todo_tags")
+            .fetch_all(pool)
+            .await?;
+        state.todo_tags = todo_tags.into_iter().collect();
+
+        type EventRow = (
+            i64,
+            i64,
+            Option<i64>,
+            EventKind,
+            i64,
+            Option<Json<Todo>>,
+            Option<Json<Todo>>,
+            NaiveDateTime,
+        );
+        let events: Vec<EventRow> = sqlx::query_as(
+            "SELECT id, todo_id, user_id, kind, version, before, after, created_at \
+             FROM todo_events ORDER BY id",
+        )
+        .fetch_all(pool)
+        .await?;
+        for (id, todo_id, user_id, kind, version, before, after, created_at) in events {
+            state.events.push(TodoEvent {
+                id,
+                todo_id,
+                user_id,
+                kind,
+                version,
+                before,
+                after,
+                created_at,
+            });
+        }
+
+        state.last_ids = LastIds {
+            user: state.users.keys().max().copied().unwrap_or_default(),
+            todo: state.todos.keys().max().copied().unwrap_or_default(),
+            tag: state.tags.keys().max().copied().unwrap_or_default(),
+            event: state
+                .events
+                .iter()
+                .map(|event| event.id)
+                .max()
+                .unwrap_or_default(),
+            ..Default::default()
+        };
+        Ok(Self {
+            state: Arc::new(Mutex::new(state)),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::memory::{highlight, snippet, tokenize};
+    use std::ops::Range;
+
+    #[test]
+    fn tokenize_words() {
+        let words: Vec<String> = tokenize("Buy  MILK, then-eggs!")
+            .into_iter()
+            .map(|token| token.word)
+            .collect();
+        assert_eq!(words, ["buy", "milk", "then", "eggs"]);
+    }
+
+    #[test]
+    fn highlight_matches() {
+        let text = "Buy milk, then eggs";
+        let tokens = tokenize(text);
+        let matches = [1..1, 3..3];
+        assert_eq!(
+            highlight(text, &tokens, &matches, 0..tokens.len()),
+            "Buy <mark>milk</mark>, then <mark>eggs</mark>"
+        );
+
+        let text = "a b c d e f g h i j k l m n o p q r s t";
+        let tokens = tokenize(text);
+        assert_eq!(
+            snippet(text, &tokens, &[Range { start: 2, end: 2 }]),
+            "…<mark>c</mark> d e f g h i j k l m n o p q r…"
+        );
+    }
+}
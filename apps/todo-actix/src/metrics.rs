@@ -0,0 +1,158 @@
+use crate::{error::InternalError, repository::PoolStatus};
+use actix_web::{
+    dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
+    http::StatusCode,
+    Error,
+};
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, Opts, Registry, TextEncoder,
+};
+use std::{
+    future::{ready, Future, Ready},
+    pin::Pin,
+    rc::Rc,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+/// Route label of requests that matched no route, so that unknown paths do not
+/// each get their own series.
+const UNMATCHED: &str = "unmatched";
+
+/// Metrics of the server, shared by every worker.
+pub struct Metrics {
+    registry: Registry,
+    requests: IntCounterVec,
+    durations: HistogramVec,
+    pool_connections: IntGauge,
+    pool_idle_connections: IntGauge,
+    pool_max_connections: IntGauge,
+}
+
+impl Metrics {
+    pub fn new() -> Result<Self, InternalError> {
+        let requests = IntCounterVec::new(
+            Opts::new("http_requests_total", "Number of HTTP requests handled."),
+            &["method", "route", "status"],
+        )?;
+        let durations = HistogramVec::new(
+            HistogramOpts::new(
+                "http_request_duration_seconds",
+                "Time taken to handle HTTP requests.",
+            ),
+            &["method", "route"],
+        )?;
+        let pool_connections = IntGauge::new(
+            "db_pool_connections",
+            "Number of open database connections.",
+        )?;
+        let pool_idle_connections = IntGauge::new(
+            "db_pool_idle_connections",
+            "Number of idle database connections.",
+        )?;
+        let pool_max_connections = IntGauge::new(
+            "db_pool_max_connections",
+            "Maximum number of database connections.",
+        )?;
+
+        let registry = Registry::new();
+        registry.register(Box::new(requests.clone()))?;
+        registry.register(Box::new(durations.clone()))?;
+        registry.register(Box::new(pool_connections.clone()))?;
+        registry.register(Box::new(pool_idle_connections.clone()))?;
+        registry.register(Box::new(pool_max_connections.clone()))?;
+        Ok(Self {
+            registry,
+            requests,
+            durations,
+            pool_connections,
+            pool_idle_connections,
+            pool_max_connections,
+        })
+    }
+
+    pub fn observe(&self, method: &str, route: &str, status: StatusCode, elapsed: Duration) {
+        let class = format!("{}xx", status.as_u16() / 100);
+        self.requests
+            .with_label_values(&[method, route, &class])
+            .inc();
+        self.durations
+            .with_label_values(&[method, route])
+            .observe(elapsed.as_secs_f64());
+    }
+
+    /// Renders every metric in the Prometheus text format, along with the
+    /// current state of the connection pool if there is one.
+    pub fn render(&self, pool: Option<PoolStatus>) -> Result<String, InternalError> {
+        if let Some(pool) = pool {
+            self.pool_connections.set(pool.size.into());
+            self.pool_idle_connections.set(pool.idle as i64);
+            self.pool_max_connections.set(pool.max.into());
+        }
+        let mut buffer = Vec::new();
+        TextEncoder::new().encode(&self.registry.gather(), &mut buffer)?;
+        Ok(String::from_utf8_lossy(&buffer).into_owned())
+    }
+}
+
+/// Middleware recording the count and duration of requests per route.
+pub struct RequestMetrics {
+    metrics: Arc<Metrics>,
+}
+
+impl RequestMetrics {
+    pub fn new(metrics: Arc<Metrics>) -> Self {
+        Self { metrics }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for RequestMetrics
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = RequestMetricsMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RequestMetricsMiddleware {
+            service: Rc::new(service),
+            metrics: self.metrics.clone(),
+        }))
+    }
+}
+
+pub struct RequestMetricsMiddleware<S> {
+    service: Rc<S>,
+    metrics: Arc<Metrics>,
+}
+
+impl<S, B> Service<ServiceRequest> for RequestMetricsMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    forward_ready!(service);
+
+    fn call(&self, request: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        let metrics = self.metrics.clone();
+        Box::pin(async move {
+            let start = Instant::now();
+            let method = request.method().to_string();
+            let response = service.call(request).await?;
+
+            let route = response.request().match_pattern();
+            let route = route.as_deref().unwrap_or(UNMATCHED);
+            metrics.observe(&method, route, response.status(), start.elapsed());
+            Ok(response)
+        })
+    }
+}
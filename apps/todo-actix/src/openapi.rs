@@ -0,0 +1,186 @@
+use crate::{
+    batch::{Batch, BatchMode, BatchResult, Operation, OperationResult},
+    error::{ApiError, FieldError, Problem, PROBLEM_JSON},
+    feed::{Change, Follow},
+    health::Health,
+    history::{EventKind, Revert, TodoEvent},
+    rate_limit,
+    recurrence::{Occurrence, UpdatedTodo},
+    reminder::{CreateReminder, Reminder, ReminderEvent},
+    routes,
+    search::{Highlights, SearchHit},
+    subtask::TodoNode,
+    tag::{AttachTag, CreateTag, Tag, TaggedTodo, UpdateTag},
+    todo::{CreateTodo, Todo, UpdateTodo},
+    transfer::{Format, ImportResult, RowResult, RowStatus},
+    user::{Credentials, Token, User},
+    webhook::{CreateWebhook, CreatedWebhook, Delivery, Payload, Webhook},
+};
+use actix_web::ResponseError;
+use utoipa::{
+    openapi::{
+        path::Operation as PathOperation,
+        security::{Http, HttpAuthScheme, SecurityScheme},
+        Content, OpenApi as OpenApiDocument, Ref, RefOr, Response,
+    },
+    Modify, OpenApi,
+};
+
+/// Name of the security scheme of bearer tokens, as required by operations.
+pub const BEARER: &str = "bearer";
+
+/// OpenAPI document of the API, generated from the route definitions.
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "TODO API"),
+    paths(
+        routes::healthz,
+        routes::readyz,
+        routes::metrics,
+        routes::register,
+        routes::login,
+        routes::list_todos,
+        routes::search_todos,
+        routes::todo_events,
+        routes::todo_events_ws,
+        routes::batch_todos,
+        routes::export_todos,
+        routes::import_todos,
+        routes::get_todo,
+        routes::create_todo,
+        routes::update_todo,
+        routes::patch_todo,
+        routes::delete_todo,
+        routes::list_trash,
+        routes::restore_todo,
+        routes::list_history,
+        routes::revert_todo,
+        routes::list_children,
+        routes::list_occurrences,
+        routes::list_reminders,
+        routes::create_reminder,
+        routes::delete_reminder,
+        routes::list_todo_tags,
+        routes::attach_tag,
+        routes::detach_tag,
+        routes::list_tags,
+        routes::get_tag,
+        routes::create_tag,
+        routes::update_tag,
+        routes::delete_tag,
+        routes::list_webhooks,
+        routes::get_webhook,
+        routes::create_webhook,
+        routes::delete_webhook,
+        routes::list_dead_letters,
+        routes::retry_dead_letter,
+    ),
+    components(schemas(
+        AttachTag,
+        Batch,
+        BatchMode,
+        BatchResult,
+        Change,
+        CreateReminder,
+        CreateTag,
+        CreateTodo,
+        CreateWebhook,
+        CreatedWebhook,
+        Credentials,
+        Delivery,
+        EventKind,
+        FieldError,
+        Follow,
+        Format,
+        Health,
+        Highlights,
+        ImportResult,
+        Occurrence,
+        Operation,
+        OperationResult,
+        Payload,
+        Problem,
+        Reminder,
+        ReminderEvent,
+        Revert,
+        RowResult,
+        RowStatus,
+        SearchHit,
+        Tag,
+        TaggedTodo,
+        Todo,
+        TodoEvent,
+        TodoNode,
+        Token,
+        UpdateTag,
+        UpdateTodo,
+        UpdatedTodo,
+        User,
+        Webhook,
+    )),
+    modifiers(&Problems),
+    security(("bearer" = [])),
+)]
+pub struct ApiDoc;
+
+/// Declares the bearer token authentication, and documents every error
+/// response as a problem details document. Errors any operation may fail with,
+/// including rate limiting where it applies and too large bodies for those
+/// taking one, are added to all of them.
+struct Problems;
+
+impl Modify for Problems {
+    fn modify(&self, openapi: &mut OpenApiDocument) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            BEARER,
+            SecurityScheme::Http(Http::new(HttpAuthScheme::Bearer)),
+        );
+
+        for (path, item) in openapi.paths.paths.iter_mut() {
+            let operations = [
+                &mut item.get,
+                &mut item.put,
+                &mut item.post,
+                &mut item.delete,
+                &mut item.patch,
+            ];
+            for operation in operations.into_iter().flatten() {
+                // Operations without their own requirements require a token
+                if operation.security.is_none() {
+                    add_error(operation, &ApiError::Unauthorized);
+                }
+                if operation.request_body.is_some() {
+                    add_error(operation, &ApiError::PayloadTooLarge(0));
+                }
+                if !rate_limit::EXEMPT_PATHS.contains(&path.as_str()) {
+                    add_error(operation, &ApiError::TooManyRequests(0));
+                }
+                add_error(operation, &ApiError::Internal);
+                for (status, response) in operation.responses.responses.iter_mut() {
+                    if let RefOr::T(response) = response {
+                        if status.starts_with(['4', '5']) && response.content.is_empty() {
+                            response
+                                .content
+                                .insert(PROBLEM_JSON.to_string(), problem_content());
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn add_error(operation: &mut PathOperation, err: &ApiError) {
+    let status = err.status_code();
+    let description = status.canonical_reason().unwrap_or_default();
+    operation
+        .responses
+        .responses
+        .entry(status.as_str().to_string())
+        .or_insert_with(|| RefOr::T(Response::new(description)));
+}
+
+fn problem_content() -> Content {
+    Content::new(Some(Ref::from_schema_name("Problem")))
+}
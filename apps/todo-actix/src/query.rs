@@ -0,0 +1,478 @@
+use crate::{
+    error::ApiError,
+    todo::{utc, Todo},
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use std::{cmp::Ordering, fmt, str::FromStr};
+use utoipa::IntoParams;
+
+pub const DEFAULT_LIMIT: i64 = 100;
+pub const MAX_LIMIT: i64 = 1000;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ListQuery {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub limit: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub offset: Option<i64>,
+    /// Cursor of the next page, as returned in the `X-Next-Cursor` header.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cursor: Option<String>,
+    /// Comma-separated fields to sort by, prefixed with `-` for descending.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sort: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub completed: Option<bool>,
+    #[serde(default, with = "utc::option", skip_serializing_if = "Option::is_none")]
+    pub due_before: Option<NaiveDateTime>,
+    /// Returns every todo nested under its parent, which cannot be combined
+    /// with other parameters.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tree: Option<bool>,
+    /// Repeated `tag` parameters, which `serde_urlencoded` cannot represent.
+    #[serde(skip)]
+    pub tags: Vec<String>,
+}
+
+impl ListQuery {
+    pub fn parse(query: &str) -> Result<Self, ApiError> {
+        let mut parsed: Self = serde_urlencoded::from_str(query)
+            .map_err(|err| ApiError::BadRequest(format!("Query deserialize error: {err}")))?;
+        parsed.tags = form_urlencoded::parse(query.as_bytes())
+            .filter(|(key, _)| key == "tag")
+            .map(|(_, value)| value.into_owned())
+            .collect();
+        Ok(parsed)
+    }
+
+    pub fn encode(&self) -> String {
+        let query = serde_urlencoded::to_string(self).unwrap_or_default();
+        let mut tags = form_urlencoded::Serializer::new(String::new());
+        for tag in &self.tags {
+            tags.append_pair("tag", tag);
+        }
+        [query, tags.finish()]
+            .into_iter()
+            .filter(|part| !part.is_empty())
+            .collect::<Vec<_>>()
+            .join("&")
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortField {
+    Id,
+    Title,
+    Description,
+    Completed,
+    DueAt,
+    CreatedAt,
+    UpdatedAt,
+}
+
+impl SortField {
+    pub fn column(&self) -> &'static str {
+        match self {
+            Self::Id => "id",
+            Self::Title => "title",
+            Self::Description => "description",
+            Self::Completed => "completed",
+            Self::DueAt => "due_at",
+            Self::CreatedAt => "created_at",
+            Self::UpdatedAt => "updated_at",
+        }
+    }
+
+    fn value(&self, todo: &Todo) -> CursorValue {
+        match self {
+            Self::Id => CursorValue::Integer(todo.id),
+            Self::Title => CursorValue::Text(todo.title.clone()),
+            Self::Description => CursorValue::Text(todo.description.clone()),
+            Self::Completed => CursorValue::Integer(todo.completed.into()),
+            Self::DueAt => todo
+                .due_at
+                .map_or(CursorValue::Null, |due_at| CursorValue::timestamp(&due_at)),
+            Self::CreatedAt => CursorValue::timestamp(&todo.created_at),
+            Self::UpdatedAt => CursorValue::timestamp(&todo.updated_at),
+        }
+    }
+}
+
+impl FromStr for SortField {
+    type Err = ApiError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "id" => Ok(Self::Id),
+            "title" => Ok(Self::Title),
+            "description" => Ok(Self::Description),
+            "completed" => Ok(Self::Completed),
+            "due_at" => Ok(Self::DueAt),
+            "created_at" => Ok(Self::CreatedAt),
+            "updated_at" => Ok(Self::UpdatedAt),
+            _ => Err(ApiError::BadRequest(format!("Invalid sort field '{s}'"))),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SortKey {
+    pub field: SortField,
+    pub descending: bool,
+}
+
+/// Ordering of a listing, always ending with `id` so that rows are totally
+/// ordered and cursors can point at an exact position.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sort(Vec<SortKey>);
+
+impl Sort {
+    pub fn keys(&self) -> &[SortKey] {
+        &self.0
+    }
+
+    /// The values of the sort keys of a todo.
+    pub fn values(&self, todo: &Todo) -> Vec<CursorValue> {
+        self.0.iter().map(|key| key.field.value(todo)).collect()
+    }
+
+    /// Orders sort key values the way SQLite orders the matching rows.
+    pub fn compare(&self, a: &[CursorValue], b: &[CursorValue]) -> Ordering {
+        self.0
+            .iter()
+            .zip(a.iter().zip(b))
+            .map(
+                |(key, (a, b))| {
+                    if key.descending {
+                        b.cmp(a)
+                    } else {
+                        a.cmp(b)
+                    }
+                },
+            )
+            .find(|ordering| ordering.is_ne())
+            .unwrap_or(Ordering::Equal)
+    }
+}
+
+impl Default for Sort {
+    fn default() -> Self {
+        Self(vec![SortKey {
+            field: SortField::Id,
+            descending: false,
+        }])
+    }
+}
+
+impl FromStr for Sort {
+    type Err = ApiError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut keys: Vec<SortKey> = Vec::new();
+        for part in s.split(',') {
+            let (name, descending) = match part.strip_prefix('-') {
+                Some(name) => (name, true),
+                None => (part, false),
+            };
+            let field = name.parse()?;
+            if keys.iter().any(|key| key.field == field) {
+                return Err(ApiError::BadRequest(format!(
+                    "Duplicate sort field '{name}'"
+                )));
+            }
+            keys.push(SortKey { field, descending });
+        }
+        if !keys.iter().any(|key| key.field == SortField::Id) {
+            keys.push(SortKey {
+                field: SortField::Id,
+                descending: false,
+            });
+        }
+        Ok(Self(keys))
+    }
+}
+
+impl fmt::Display for Sort {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let keys: Vec<String> = self
+            .0
+            .iter()
+            .map(|key| {
+                let sign = if key.descending { "-" } else { "" };
+                format!("{sign}{}", key.field.column())
+            })
+            .collect();
+        write!(f, "{}", keys.join(","))
+    }
+}
+
+/// A sort key value. Values are ordered like SQLite orders them, with `NULL`
+/// before integers and integers before text.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum CursorValue {
+    Null,
+    Integer(i64),
+    Text(String),
+}
+
+impl CursorValue {
+    /// Timestamps are kept in the same textual format SQLite stores them in,
+    /// so that they compare correctly against the columns.
+    fn timestamp(value: &NaiveDateTime) -> Self {
+        Self::Text(value.format("%F %T%.f").to_string())
+    }
+}
+
+/// Opaque keyset cursor holding the sort key values of the last row of a page.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Cursor {
+    sort: String,
+    values: Vec<CursorValue>,
+}
+
+impl Cursor {
+    fn after(sort: &Sort, todo: &Todo) -> Self {
+        Self {
+            sort: sort.to_string(),
+            values: sort.values(todo),
+        }
+    }
+
+    pub fn encode(&self) -> String {
+        let json = serde_json::to_vec(self).unwrap_or_default();
+        URL_SAFE_NO_PAD.encode(json)
+    }
+
+    pub fn decode(cursor: &str, sort: &Sort) -> Result<Self, ApiError> {
+        let invalid = || ApiError::BadRequest("Invalid cursor".to_string());
+        let json = URL_SAFE_NO_PAD.decode(cursor).map_err(|_| invalid())?;
+        let cursor: Self = serde_json::from_slice(&json).map_err(|_| invalid())?;
+        if cursor.sort != sort.to_string() || cursor.values.len() != sort.keys().len() {
+            return Err(ApiError::BadRequest(
+                "Cursor does not match the requested sort".to_string(),
+            ));
+        }
+        Ok(cursor)
+    }
+
+    pub fn values(&self) -> &[CursorValue] {
+        &self.values
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Position {
+    Start,
+    Offset(i64),
+    After(Cursor),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ListParams {
+    pub limit: i64,
+    pub position: Position,
+    pub sort: Sort,
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub completed: Option<bool>,
+    pub due_before: Option<NaiveDateTime>,
+    pub tags: Vec<String>,
+}
+
+impl Default for ListParams {
+    fn default() -> Self {
+        Self {
+            limit: DEFAULT_LIMIT,
+            position: Position::Start,
+            sort: Sort::default(),
+            title: None,
+            description: None,
+            completed: None,
+            due_before: None,
+            tags: Vec::new(),
+        }
+    }
+}
+
+impl TryFrom<ListQuery> for ListParams {
+    type Error = ApiError;
+
+    fn try_from(query: ListQuery) -> Result<Self, Self::Error> {
+        let limit = query.limit.unwrap_or(DEFAULT_LIMIT);
+        if !(1..=MAX_LIMIT).contains(&limit) {
+            return Err(ApiError::BadRequest(format!(
+                "Invalid 'limit' value '{limit}', must be between 1 and {MAX_LIMIT}"
+            )));
+        }
+
+        let sort = match query.sort {
+            Some(sort) => sort.parse()?,
+            None => Sort::default(),
+        };
+
+        let position = match (query.offset, query.cursor) {
+            (Some(_), Some(_)) => {
+                return Err(ApiError::BadRequest(
+                    "'offset' and 'cursor' are mutually exclusive".to_string(),
+                ))
+            }
+            (Some(offset), None) if offset < 0 => {
+                return Err(ApiError::BadRequest(format!(
+                    "Invalid 'offset' value '{offset}', must not be negative"
+                )))
+            }
+            (Some(offset), None) => Position::Offset(offset),
+            (None, Some(cursor)) => Position::After(Cursor::decode(&cursor, &sort)?),
+            (None, None) => Position::Start,
+        };
+
+        Ok(Self {
+            limit,
+            position,
+            sort,
+            title: query.title,
+            description: query.description,
+            completed: query.completed,
+            due_before: query.due_before,
+            tags: query.tags,
+        })
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Page {
+    pub todos: Vec<Todo>,
+    pub next: Option<Position>,
+}
+
+impl Page {
+    /// Builds a page out of at most `limit + 1` rows, the extra row only
+    /// signaling that a following page exists.
+    pub fn new(params: &ListParams, mut todos: Vec<Todo>) -> Self {
+        let limit = params.limit as usize;
+        if todos.len() <= limit {
+            return Self { todos, next: None };
+        }
+
+        todos.truncate(limit);
+        let next = match &params.position {
+            Position::Offset(offset) => Some(Position::Offset(offset + params.limit)),
+            Position::Start | Position::After(_) => todos
+                .last()
+                .map(|last| Position::After(Cursor::after(&params.sort, last))),
+        };
+        Self { todos, next }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        error::ApiError,
+        query::{Cursor, CursorValue, ListParams, ListQuery, Position, Sort},
+    };
+    use assert_matches::assert_matches;
+
+    #[test]
+    fn sort_appends_id() {
+        let sort: Sort = "-title".parse().unwrap();
+        assert_eq!(sort.to_string(), "-title,id");
+
+        let sort: Sort = "-id,title".parse().unwrap();
+        assert_eq!(sort.to_string(), "-id,title");
+    }
+
+    #[test]
+    fn sort_invalid() {
+        assert_matches!("name".parse::<Sort>(), Err(ApiError::BadRequest(_)));
+        assert_matches!("title,-title".parse::<Sort>(), Err(ApiError::BadRequest(_)));
+        assert_matches!("".parse::<Sort>(), Err(ApiError::BadRequest(_)));
+    }
+
+    #[test]
+    fn cursor_roundtrip() {
+        let sort: Sort = "title".parse().unwrap();
+        let cursor = Cursor {
+            sort: sort.to_string(),
+            values: vec![
+                CursorValue::Text("todo".to_string()),
+                CursorValue::Integer(2),
+            ],
+        };
+        let decoded = Cursor::decode(&cursor.encode(), &sort).unwrap();
+        assert_eq!(decoded, cursor);
+    }
+
+    #[test]
+    fn cursor_sort_mismatch() {
+        let sort: Sort = "title".parse().unwrap();
+        let cursor = Cursor {
+            sort: sort.to_string(),
+            values: vec![
+                CursorValue::Text("todo".to_string()),
+                CursorValue::Integer(2),
+            ],
+        };
+        let err = Cursor::decode(&cursor.encode(), &Sort::default());
+        assert_matches!(err, Err(ApiError::BadRequest(_)));
+    }
+
+    #[test]
+    fn params_invalid() {
+        let query = ListQuery {
+            limit: Some(0),
+            ..Default::default()
+        };
+        assert_matches!(ListParams::try_from(query), Err(ApiError::BadRequest(_)));
+
+        let query = ListQuery {
+            offset: Some(-1),
+            ..Default::default()
+        };
+        assert_matches!(ListParams::try_from(query), Err(ApiError::BadRequest(_)));
+
+        let query = ListQuery {
+            offset: Some(1),
+            cursor: Some(Cursor::after_id(1).encode()),
+            ..Default::default()
+        };
+        assert_matches!(ListParams::try_from(query), Err(ApiError::BadRequest(_)));
+    }
+
+    #[test]
+    fn query_tags_roundtrip() {
+        let query = ListQuery::parse("limit=2&tag=home&tag=a%26b").unwrap();
+        assert_eq!(query.limit, Some(2));
+        assert_eq!(query.tags, vec!["home", "a&b"]);
+        assert_eq!(query.encode(), "limit=2&tag=home&tag=a%26b");
+
+        assert_matches!(
+            ListQuery::parse("limit=2&limit=3"),
+            Err(ApiError::BadRequest(_))
+        );
+    }
+
+    #[test]
+    fn params_default() {
+        let params = ListParams::try_from(ListQuery::default()).unwrap();
+        assert_eq!(params, ListParams::default());
+        assert_eq!(params.position, Position::Start);
+    }
+
+    impl Cursor {
+        fn after_id(id: i64) -> Self {
+            Self {
+                sort: Sort::default().to_string(),
+                values: vec![CursorValue::Integer(id)],
+            }
+        }
+    }
+}
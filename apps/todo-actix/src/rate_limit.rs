@@ -0,0 +1,302 @@
+use crate::{auth, config::Config, error::ApiError};
+use actix_web::{
+    body::EitherBody,
+    dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
+    http::header::{HeaderMap, HeaderName, HeaderValue, AUTHORIZATION},
+    Error, ResponseError,
+};
+use std::{
+    collections::HashMap,
+    future::{ready, Future, Ready},
+    pin::Pin,
+    rc::Rc,
+    sync::{Arc, Mutex, PoisonError},
+    time::Instant,
+};
+
+pub const RATELIMIT_LIMIT: HeaderName = HeaderName::from_static("ratelimit-limit");
+pub const RATELIMIT_REMAINING: HeaderName = HeaderName::from_static("ratelimit-remaining");
+pub const RATELIMIT_RESET: HeaderName = HeaderName::from_static("ratelimit-reset");
+
+/// Paths polled by infrastructure, which are never limited.
+pub const EXEMPT_PATHS: [&str; 3] = ["/healthz", "/readyz", "/metrics"];
+
+/// Number of buckets kept at most. Once reached, the full ones are dropped, as
+/// they hold no more information than a new bucket would, and then the least
+/// recently used ones until `SWEPT_BUCKETS` are left, so that sweeps are rare.
+const MAX_BUCKETS: usize = 10_000;
+const SWEPT_BUCKETS: usize = MAX_BUCKETS * 3 / 4;
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    updated_at: Instant,
+}
+
+/// Outcome of a request against the bucket of its client.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Decision {
+    pub allowed: bool,
+    pub limit: u32,
+    pub remaining: u32,
+    /// Seconds until the bucket is full again.
+    pub reset: u64,
+    /// Seconds until the next request would be allowed.
+    pub retry_after: u64,
+}
+
+impl Decision {
+    fn insert_headers(&self, headers: &mut HeaderMap) {
+        headers.insert(RATELIMIT_LIMIT, HeaderValue::from(self.limit));
+        headers.insert(RATELIMIT_REMAINING, HeaderValue::from(self.remaining));
+        headers.insert(RATELIMIT_RESET, HeaderValue::from(self.reset));
+    }
+}
+
+/// Token buckets of every client, each holding up to `burst` requests and
+/// refilled by `per_second` requests every second.
+pub struct RateLimiter {
+    burst: u32,
+    per_second: f64,
+    token_secret: String,
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+impl RateLimiter {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            burst: config.rate_limit_burst,
+            per_second: config.rate_limit_per_second,
+            token_secret: config.token_secret.clone(),
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn enabled(&self) -> bool {
+        self.burst > 0 && self.per_second > 0.0
+    }
+
+    /// Takes a token from the bucket of the client, if there is one left.
+    pub fn check(&self, key: &str, now: Instant) -> Decision {
+        let burst = f64::from(self.burst);
+        let mut buckets = self.buckets.lock().unwrap_or_else(PoisonError::into_inner);
+        if buckets.len() >= MAX_BUCKETS {
+            self.sweep(&mut buckets, now);
+        }
+        let bucket = buckets.entry(key.to_string()).or_insert(Bucket {
+            tokens: burst,
+            updated_at: now,
+        });
+        bucket.tokens = self.refilled(bucket, now);
+        bucket.updated_at = now;
+
+        let allowed = bucket.tokens >= 1.0;
+        if allowed {
+            bucket.tokens -= 1.0;
+        }
+        Decision {
+            allowed,
+            limit: self.burst,
+            remaining: bucket.tokens as u32,
+            reset: self.seconds_until(burst - bucket.tokens),
+            retry_after: if allowed {
+                0
+            } else {
+                self.seconds_until(1.0 - bucket.tokens)
+            },
+        }
+    }
+
+    fn sweep(&self, buckets: &mut HashMap<String, Bucket>, now: Instant) {
+        let burst = f64::from(self.burst);
+        buckets.retain(|_, bucket| self.refilled(bucket, now) < burst);
+        if buckets.len() <= SWEPT_BUCKETS {
+            return;
+        }
+        let mut updated_at: Vec<Instant> =
+            buckets.values().map(|bucket| bucket.updated_at).collect();
+        // Buckets as old as the newest one dropped go too, so none is left over
+        let (_, newest_dropped, _) = updated_at.select_nth_unstable(buckets.len() - SWEPT_BUCKETS);
+        let newest_dropped = *newest_dropped;
+        buckets.retain(|_, bucket| bucket.updated_at > newest_dropped);
+    }
+
+    fn refilled(&self, bucket: &Bucket, now: Instant) -> f64 {
+        let elapsed = now.saturating_duration_since(bucket.updated_at);
+        (bucket.tokens + elapsed.as_secs_f64() * self.per_second).min(f64::from(self.burst))
+    }
+
+    fn seconds_until(&self, tokens: f64) -> u64 {
+        (tokens / self.per_second).ceil() as u64
+    }
+
+    /// Identifies the client by the user of its token if it is valid, or else
+    /// by its address.
+    fn key(&self, request: &ServiceRequest) -> String {
+        let user = request
+            .headers()
+            .get(AUTHORIZATION)
+            .and_then(|header| header.to_str().ok()?.strip_prefix("Bearer "))
+            .and_then(|token| auth::verify_token(&self.token_secret, token.trim()));
+        match (user, request.peer_addr()) {
+            (Some(user_id), _) => format!("user:{user_id}"),
+            (None, Some(addr)) => format!("ip:{}", addr.ip()),
+            (None, None) => "ip:unknown".to_string(),
+        }
+    }
+}
+
+/// Middleware answering `429 Too Many Requests` to clients that emptied their
+/// bucket, and telling every client how many requests it has left.
+pub struct RateLimit {
+    limiter: Arc<RateLimiter>,
+}
+
+impl RateLimit {
+    pub fn new(limiter: Arc<RateLimiter>) -> Self {
+        Self { limiter }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for RateLimit
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Transform = RateLimitMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RateLimitMiddleware {
+            service: Rc::new(service),
+            limiter: self.limiter.clone(),
+        }))
+    }
+}
+
+pub struct RateLimitMiddleware<S> {
+    service: Rc<S>,
+    limiter: Arc<RateLimiter>,
+}
+
+impl<S, B> Service<ServiceRequest> for RateLimitMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    forward_ready!(service);
+
+    fn call(&self, request: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        let limiter = self.limiter.clone();
+        Box::pin(async move {
+            if !limiter.enabled() || EXEMPT_PATHS.contains(&request.path()) {
+                return Ok(service.call(request).await?.map_into_left_body());
+            }
+
+            let decision = limiter.check(&limiter.key(&request), Instant::now());
+            let mut response = if decision.allowed {
+                service.call(request).await?.map_into_left_body()
+            } else {
+                let err = ApiError::TooManyRequests(decision.retry_after);
+                request
+                    .into_response(err.error_response())
+                    .map_into_right_body()
+            };
+            decision.insert_headers(response.headers_mut());
+            Ok(response)
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        config::Config,
+        rate_limit::{Decision, RateLimiter, MAX_BUCKETS},
+    };
+    use std::time::{Duration, Instant};
+
+    fn limiter(burst: u32, per_second: f64) -> RateLimiter {
+        RateLimiter::new(&Config {
+            rate_limit_burst: burst,
+            rate_limit_per_second: per_second,
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn burst() {
+        let limiter = limiter(3, 1.0);
+        let now = Instant::now();
+        let remaining: Vec<_> = (0..3)
+            .map(|_| limiter.check("alice", now))
+            .map(|decision| (decision.allowed, decision.remaining))
+            .collect();
+        assert_eq!(remaining, [(true, 2), (true, 1), (true, 0)]);
+
+        assert_eq!(
+            limiter.check("alice", now),
+            Decision {
+                allowed: false,
+                limit: 3,
+                remaining: 0,
+                reset: 3,
+                retry_after: 1,
+            }
+        );
+        // Clients have their own bucket
+        assert!(limiter.check("bob", now).allowed);
+    }
+
+    #[test]
+    fn refill() {
+        let limiter = limiter(2, 0.5);
+        let start = Instant::now();
+        assert!(limiter.check("alice", start).allowed);
+        assert!(limiter.check("alice", start).allowed);
+        let decision = limiter.check("alice", start);
+        assert!(!decision.allowed);
+        assert_eq!(decision.retry_after, 2);
+
+        let later = start + Duration::from_secs(1);
+        let decision = limiter.check("alice", later);
+        assert!(!decision.allowed);
+        assert_eq!(decision.retry_after, 1);
+
+        let later = start + Duration::from_secs(2);
+        let decision = limiter.check("alice", later);
+        assert!(decision.allowed);
+        assert_eq!(decision.reset, 4);
+        assert!(!limiter.check("alice", later).allowed);
+
+        // Buckets hold no more than the burst however long clients wait
+        let later = start + Duration::from_secs(60);
+        assert!(limiter.check("alice", later).allowed);
+        assert!(limiter.check("alice", later).allowed);
+        assert!(!limiter.check("alice", later).allowed);
+    }
+
+    #[test]
+    fn bounded_buckets() {
+        let limiter = limiter(2, 1.0);
+        let start = Instant::now();
+        for i in 0..3 * MAX_BUCKETS {
+            let now = start + Duration::from_millis(i as u64);
+            limiter.check(&format!("client{i}"), now);
+            if i % 100 == 0 {
+                limiter.check("alice", now);
+            }
+            assert!(limiter.buckets.lock().unwrap().len() <= MAX_BUCKETS);
+        }
+        // Clients seen lately keep their bucket
+        assert!(limiter.buckets.lock().unwrap().contains_key("alice"));
+    }
+}
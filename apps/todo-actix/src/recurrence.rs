@@ -0,0 +1,651 @@
+use crate::{
+    error::FieldError,
+    tag::TaggedTodo,
+    todo::{utc, CreateTodo, Todo, UpdateTodo},
+    validate,
+};
+use chrono::{Datelike, Days, Months, NaiveDate, NaiveDateTime, NaiveTime, Weekday};
+use serde::{Deserialize, Serialize};
+use std::{collections::VecDeque, fmt, iter, str::FromStr};
+use utoipa::{IntoParams, ToSchema};
+
+pub const RECURRENCE_MAX_LENGTH: usize = 200;
+
+/// Occurrences returned at most when previewing a schedule.
+pub const MAX_OCCURRENCES: usize = 1000;
+
+/// Periods scanned in a row without finding an occurrence before a schedule is
+/// taken to have none left, as with the 31st of every other February.
+const MAX_EMPTY_PERIODS: u32 = 1000;
+
+const DATE_TIME: &str = "%Y%m%dT%H%M%S";
+const DATE: &str = "%Y%m%d";
+const WEEKDAYS: [(&str, Weekday); 7] = [
+    ("MO", Weekday::Mon),
+    ("TU", Weekday::Tue),
+    ("WE", Weekday::Wed),
+    ("TH", Weekday::Thu),
+    ("FR", Weekday::Fri),
+    ("SA", Weekday::Sat),
+    ("SU", Weekday::Sun),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Frequency {
+    Daily,
+    Weekly,
+    Monthly,
+}
+
+/// A day of the week, which monthly rules may narrow down to its nth
+/// occurrence in the month, counted from the end when negative.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WeekdayNum {
+    pub nth: Option<i8>,
+    pub weekday: Weekday,
+}
+
+/// An iCalendar recurrence rule (RFC 5545, 3.3.10), restricted to daily, weekly
+/// and monthly schedules.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rule {
+    pub frequency: Frequency,
+    pub interval: u32,
+    pub by_day: Vec<WeekdayNum>,
+    pub by_month_day: Vec<i8>,
+    /// Occurrences in the schedule, including the first one.
+    pub count: Option<u32>,
+    /// Last date an occurrence may fall on.
+    pub until: Option<NaiveDateTime>,
+}
+
+impl FromStr for Rule {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let value = value.trim();
+        let value = match value.get(..6) {
+            Some(prefix) if prefix.eq_ignore_ascii_case("RRULE:") => &value[6..],
+            _ => value,
+        };
+        let mut frequency = None;
+        let mut interval = 1;
+        let mut by_day = Vec::new();
+        let mut by_month_day = Vec::new();
+        let mut count = None;
+        let mut until = None;
+        let mut names = Vec::new();
+        for part in value.split(';') {
+            let (name, value) = part
+                .split_once('=')
+                .ok_or_else(|| format!("Invalid rule part '{part}'"))?;
+            let name = name.trim().to_ascii_uppercase();
+            let value = value.trim().to_ascii_uppercase();
+            if names.contains(&name) {
+                return Err(format!("Repeated rule part '{name}'"));
+            }
+            match name.as_str() {
+                "FREQ" => {
+                    frequency = Some(match value.as_str() {
+                        "DAILY" => Frequency::Daily,
+                        "WEEKLY" => Frequency::Weekly,
+                        "MONTHLY" => Frequency::Monthly,
+                        _ => {
+                            return Err(format!(
+                                "Unsupported FREQ '{value}', must be DAILY, WEEKLY or MONTHLY"
+                            ))
+                        }
+                    })
+                }
+                "INTERVAL" => {
+                    interval = value
+                        .parse()
+                        .ok()
+                        .filter(|interval| *interval > 0)
+                        .ok_or_else(|| format!("Invalid INTERVAL '{value}'"))?
+                }
+                "COUNT" => {
+                    count = Some(
+                        value
+                            .parse()
+                            .ok()
+                            .filter(|count| *count > 0)
+                            .ok_or_else(|| format!("Invalid COUNT '{value}'"))?,
+                    )
+                }
+                "UNTIL" => until = Some(parse_until(&value)?),
+                "BYDAY" => {
+                    by_day = value
+                        .split(',')
+                        .map(parse_weekday_num)
+                        .collect::<Result<_, _>>()?
+                }
+                "BYMONTHDAY" => {
+                    by_month_day = value
+                        .split(',')
+                        .map(parse_month_day)
+                        .collect::<Result<_, _>>()?
+                }
+                // Weeks start on Monday, as they do by default
+                "WKST" if value == "MO" => {}
+                _ => return Err(format!("Unsupported rule part '{name}'")),
+            }
+            names.push(name);
+        }
+
+        let frequency = frequency.ok_or("Missing rule part 'FREQ'")?;
+        if count.is_some() && until.is_some() {
+            return Err("COUNT and UNTIL cannot be combined".to_string());
+        }
+        if frequency != Frequency::Monthly && !by_month_day.is_empty() {
+            return Err("BYMONTHDAY is only supported with FREQ=MONTHLY".to_string());
+        }
+        if frequency != Frequency::Monthly && by_day.iter().any(|day| day.nth.is_some()) {
+            return Err("Numbered BYDAY is only supported with FREQ=MONTHLY".to_string());
+        }
+        if !by_day.is_empty() && !by_month_day.is_empty() {
+            return Err("BYDAY and BYMONTHDAY cannot be combined".to_string());
+        }
+        Ok(Self {
+            frequency,
+            interval,
+            by_day,
+            by_month_day,
+            count,
+            until,
+        })
+    }
+}
+
+/// Writes the rule in a canonical form, as stored along with todos.
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let frequency = match self.frequency {
+            Frequency::Daily => "DAILY",
+            Frequency::Weekly => "WEEKLY",
+            Frequency::Monthly => "MONTHLY",
+        };
+        write!(f, "FREQ={frequency}")?;
+        if self.interval != 1 {
+            write!(f, ";INTERVAL={}", self.interval)?;
+        }
+        if !self.by_day.is_empty() {
+            let days: Vec<String> = self
+                .by_day
+                .iter()
+                .map(|day| {
+                    let nth = day.nth.map(|nth| nth.to_string()).unwrap_or_default();
+                    let (name, _) = WEEKDAYS[day.weekday.num_days_from_monday() as usize];
+                    format!("{nth}{name}")
+                })
+                .collect();
+            write!(f, ";BYDAY={}", days.join(","))?;
+        }
+        if !self.by_month_day.is_empty() {
+            let days: Vec<String> = self.by_month_day.iter().map(i8::to_string).collect();
+            write!(f, ";BYMONTHDAY={}", days.join(","))?;
+        }
+        if let Some(count) = self.count {
+            write!(f, ";COUNT={count}")?;
+        }
+        if let Some(until) = self.until {
+            write!(f, ";UNTIL={}Z", until.format(DATE_TIME))?;
+        }
+        Ok(())
+    }
+}
+
+/// Reads the end of a schedule. Dates without a time include the whole day.
+fn parse_until(value: &str) -> Result<NaiveDateTime, String> {
+    let until = value.strip_suffix('Z').unwrap_or(value);
+    NaiveDateTime::parse_from_str(until, DATE_TIME)
+        .ok()
+        .or_else(|| {
+            let date = NaiveDate::parse_from_str(until, DATE).ok()?;
+            Some(date.and_time(NaiveTime::from_hms_opt(23, 59, 59)?))
+        })
+        .ok_or_else(|| format!("Invalid UNTIL '{value}'"))
+}
+
+fn parse_weekday_num(value: &str) -> Result<WeekdayNum, String> {
+    let invalid = || format!("Invalid BYDAY '{value}'");
+    let value = value.trim();
+    let split = value.len().checked_sub(2).ok_or_else(invalid)?;
+    let (nth, name) = value.split_at_checked(split).ok_or_else(invalid)?;
+    let (_, weekday) = WEEKDAYS
+        .iter()
+        .find(|(day, _)| *day == name)
+        .ok_or_else(invalid)?;
+    let nth = match nth {
+        "" => None,
+        nth => Some(
+            nth.parse::<i8>()
+                .ok()
+                .filter(|nth| (1..=5).contains(&nth.abs()))
+                .ok_or_else(invalid)?,
+        ),
+    };
+    Ok(WeekdayNum {
+        nth,
+        weekday: *weekday,
+    })
+}
+
+fn parse_month_day(value: &str) -> Result<i8, String> {
+    value
+        .trim()
+        .parse::<i8>()
+        .ok()
+        .filter(|day| (1..=31).contains(&day.abs()))
+        .ok_or_else(|| format!("Invalid BYMONTHDAY '{value}'"))
+}
+
+impl Rule {
+    /// Dates of a schedule starting at the given one, which is its first
+    /// occurrence whether or not it matches the rule. Every occurrence keeps
+    /// its time of day.
+    pub fn occurrences(self, start: NaiveDateTime) -> Occurrences {
+        Occurrences {
+            rule: self,
+            start,
+            period: 0,
+            pending: VecDeque::new(),
+            emitted: 0,
+        }
+    }
+
+    /// The occurrence following the given one, along with the rule scheduling
+    /// those after it.
+    pub fn next(&self, due_at: NaiveDateTime) -> Option<(NaiveDateTime, Rule)> {
+        let next = self.clone().occurrences(due_at).nth(1)?;
+        let rule = Self {
+            count: self.count.map(|count| count - 1),
+            ..self.clone()
+        };
+        Some((next, rule))
+    }
+
+    /// Dates matching the rule in the given period after the start, in order.
+    fn period_dates(&self, start: NaiveDate, period: u32) -> Option<Vec<NaiveDate>> {
+        let step = period.checked_mul(self.interval)?;
+        let mut dates = match self.frequency {
+            Frequency::Daily => {
+                let date = start.checked_add_days(Days::new(step.into()))?;
+                let matches = self.by_day.is_empty()
+                    || self.by_day.iter().any(|day| day.weekday == date.weekday());
+                matches.then_some(date).into_iter().collect()
+            }
+            Frequency::Weekly => {
+                let monday = start
+                    .checked_sub_days(Days::new(start.weekday().num_days_from_monday().into()))?
+                    .checked_add_days(Days::new(u64::from(step) * 7))?;
+                let weekdays = match self.by_day.as_slice() {
+                    [] => vec![start.weekday()],
+                    by_day => by_day.iter().map(|day| day.weekday).collect(),
+                };
+                weekdays
+                    .into_iter()
+                    .filter_map(|weekday| {
+                        monday.checked_add_days(Days::new(weekday.num_days_from_monday().into()))
+                    })
+                    .collect()
+            }
+            Frequency::Monthly => {
+                let first = start.with_day(1)?.checked_add_months(Months::new(step))?;
+                self.month_dates(first, start.day())
+            }
+        };
+        dates.sort();
+        dates.dedup();
+        Some(dates)
+    }
+
+    fn month_dates(&self, first: NaiveDate, start_day: u32) -> Vec<NaiveDate> {
+        let length = first
+            .checked_add_months(Months::new(1))
+            .map_or(31, |next| (next - first).num_days() as i32);
+        let days = (1..=length).filter_map(|day| Some((day, first.with_day(day as u32)?)));
+        if !self.by_day.is_empty() {
+            return days
+                .filter(|(day, date)| {
+                    let from_start = ((day - 1) / 7 + 1) as i8;
+                    let from_end = -(((length - day) / 7 + 1) as i8);
+                    self.by_day.iter().any(|by_day| {
+                        by_day.weekday == date.weekday()
+                            && by_day
+                                .nth
+                                .is_none_or(|nth| nth == from_start || nth == from_end)
+                    })
+                })
+                .map(|(_, date)| date)
+                .collect();
+        }
+        // Months without the day are skipped
+        let month_days = match self.by_month_day.as_slice() {
+            [] => vec![start_day as i32],
+            by_month_day => by_month_day.iter().map(|day| i32::from(*day)).collect(),
+        };
+        days.filter(|(day, _)| {
+            month_days
+                .iter()
+                .any(|month_day| *month_day == *day || length + 1 + *month_day == *day)
+        })
+        .map(|(_, date)| date)
+        .collect()
+    }
+}
+
+pub struct Occurrences {
+    rule: Rule,
+    start: NaiveDateTime,
+    period: u32,
+    pending: VecDeque<NaiveDateTime>,
+    emitted: u32,
+}
+
+impl Occurrences {
+    fn next_date(&mut self) -> Option<NaiveDateTime> {
+        let mut empty_periods = 0;
+        while self.pending.is_empty() {
+            if empty_periods == MAX_EMPTY_PERIODS {
+                return None;
+            }
+            let dates = self.rule.period_dates(self.start.date(), self.period)?;
+            self.period += 1;
+            self.pending = dates
+                .into_iter()
+                .map(|date| date.and_time(self.start.time()))
+                .filter(|date| *date > self.start)
+                .collect();
+            empty_periods += 1;
+        }
+        self.pending.pop_front()
+    }
+}
+
+impl Iterator for Occurrences {
+    type Item = NaiveDateTime;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.rule.count.is_some_and(|count| self.emitted >= count) {
+            return None;
+        }
+        let next = match self.emitted {
+            0 => self.start,
+            _ => self
+                .next_date()
+                .filter(|next| self.rule.until.is_none_or(|until| *next <= until))?,
+        };
+        self.emitted += 1;
+        Some(next)
+    }
+}
+
+/// Normalizes the schedule of a todo, which needs a due date to start from.
+pub fn validate(
+    errors: &mut Vec<FieldError>,
+    recurrence: Option<String>,
+    due_at: Option<&NaiveDateTime>,
+) -> Option<String> {
+    let recurrence = validate::text(
+        errors,
+        "recurrence",
+        recurrence?,
+        false,
+        RECURRENCE_MAX_LENGTH,
+    );
+    if recurrence.is_empty() {
+        return None;
+    }
+    if due_at.is_none() {
+        errors.push(FieldError::new("recurrence", "requires a due date"));
+    }
+    match recurrence.parse::<Rule>() {
+        Ok(rule) => Some(rule.to_string()),
+        Err(err) => {
+            errors.push(FieldError::new(
+                "recurrence",
+                format!("must be a supported RRULE: {err}"),
+            ));
+            Some(recurrence)
+        }
+    }
+}
+
+/// Hands the schedule of a recurring todo over to its next occurrence when a
+/// change completes it, returning the todo of that occurrence unless the
+/// schedule is over.
+pub fn complete(before: &Todo, todo: &mut UpdateTodo) -> Option<CreateTodo> {
+    if before.completed || !todo.completed {
+        return None;
+    }
+    let due_at = todo.due_at?;
+    let recurrence = todo.recurrence.take()?;
+    let (due_at, rule) = recurrence.parse::<Rule>().ok()?.next(due_at)?;
+    Some(CreateTodo {
+        title: todo.title.clone(),
+        description: todo.description.clone(),
+        completed: false,
+        due_at: Some(due_at),
+        parent_id: todo.parent_id,
+        recurrence: Some(rule.to_string()),
+    })
+}
+
+/// A todo once changed, along with the todo of its next occurrence when the
+/// change completed a recurring todo.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Updated {
+    pub todo: Todo,
+    pub next: Option<Todo>,
+}
+
+impl From<Todo> for Updated {
+    fn from(todo: Todo) -> Self {
+        Self { todo, next: None }
+    }
+}
+
+/// A changed todo as returned by the API, along with the todo of its next
+/// occurrence when the change completed a recurring todo.
+#[derive(Debug, PartialEq, Eq, Deserialize, Serialize, ToSchema)]
+pub struct UpdatedTodo {
+    #[serde(flatten)]
+    pub todo: TaggedTodo,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next_occurrence: Option<TaggedTodo>,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct OccurrenceQuery {
+    /// Earliest occurrence to return, the due date of the todo by default.
+    #[serde(default, with = "utc::option")]
+    pub from: Option<NaiveDateTime>,
+    /// Latest occurrence to return.
+    #[serde(default, with = "utc::option")]
+    pub to: Option<NaiveDateTime>,
+}
+
+#[derive(Debug, PartialEq, Eq, Deserialize, Serialize, ToSchema)]
+pub struct Occurrence {
+    #[serde(with = "utc")]
+    pub due_at: NaiveDateTime,
+}
+
+/// Occurrences of a todo in a range of dates, both included, at most
+/// `MAX_OCCURRENCES` of them. Todos without a schedule occur once, when due.
+pub fn preview(
+    todo: &Todo,
+    from: Option<NaiveDateTime>,
+    to: Option<NaiveDateTime>,
+) -> Vec<Occurrence> {
+    let Some(due_at) = todo.due_at else {
+        return vec![];
+    };
+    let rule = todo
+        .recurrence
+        .as_deref()
+        .and_then(|recurrence| recurrence.parse::<Rule>().ok());
+    let dates: Box<dyn Iterator<Item = NaiveDateTime>> = match rule {
+        Some(rule) => Box::new(rule.occurrences(due_at)),
+        None => Box::new(iter::once(due_at)),
+    };
+    dates
+        .skip_while(|date| from.is_some_and(|from| *date < from))
+        .take_while(|date| to.is_none_or(|to| *date <= to))
+        .take(MAX_OCCURRENCES)
+        .map(|due_at| Occurrence { due_at })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        recurrence::{complete, validate, Rule},
+        test::{fixture_todos, timestamp},
+        todo::{CreateTodo, UpdateTodo},
+    };
+
+    fn occurrences(rule: &str, start: &str, count: usize) -> Vec<String> {
+        let rule: Rule = rule.parse().unwrap();
+        rule.occurrences(timestamp(start))
+            .take(count)
+            .map(|date| date.format("%F %a").to_string())
+            .collect()
+    }
+
+    #[test]
+    fn parse_rule() {
+        let rule: Rule = "rrule:freq=weekly;byday=mo,we;interval=1;count=4"
+            .parse()
+            .unwrap();
+        assert_eq!(rule.to_string(), "FREQ=WEEKLY;BYDAY=MO,WE;COUNT=4");
+        let rule: Rule = "FREQ=MONTHLY;BYDAY=-1FR;UNTIL=20241231".parse().unwrap();
+        assert_eq!(
+            rule.to_string(),
+            "FREQ=MONTHLY;BYDAY=-1FR;UNTIL=20241231T235959Z"
+        );
+
+        for invalid in [
+            "",
+            "FREQ=YEARLY",
+            "INTERVAL=2",
+            "FREQ=DAILY;INTERVAL=0",
+            "FREQ=DAILY;COUNT=2;UNTIL=20240101",
+            "FREQ=WEEKLY;BYDAY=1MO",
+            "FREQ=MONTHLY;BYDAY=XX",
+            "FREQ=MONTHLY;BYMONTHDAY=32",
+            "FREQ=DAILY;FREQ=WEEKLY",
+            "FREQ=DAILY;BYHOUR=9",
+        ] {
+            assert!(invalid.parse::<Rule>().is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn daily() {
+        assert_eq!(
+            occurrences("FREQ=DAILY;INTERVAL=2;COUNT=3", "2024-06-28 09:00:00", 10),
+            ["2024-06-28 Fri", "2024-06-30 Sun", "2024-07-02 Tue"]
+        );
+    }
+
+    #[test]
+    fn weekly_on_weekdays() {
+        assert_eq!(
+            occurrences("FREQ=WEEKLY;BYDAY=MO,TU,WE,TH,FR", "2024-06-06 09:00:00", 4),
+            [
+                "2024-06-06 Thu",
+                "2024-06-07 Fri",
+                "2024-06-10 Mon",
+                "2024-06-11 Tue"
+            ]
+        );
+        assert_eq!(
+            occurrences(
+                "FREQ=WEEKLY;INTERVAL=2;BYDAY=TU;UNTIL=20240630",
+                "2024-06-04 09:00:00",
+                10
+            ),
+            ["2024-06-04 Tue", "2024-06-18 Tue"]
+        );
+    }
+
+    #[test]
+    fn monthly_by_day() {
+        assert_eq!(
+            occurrences("FREQ=MONTHLY;BYDAY=-1FR", "2024-06-28 09:00:00", 3),
+            ["2024-06-28 Fri", "2024-07-26 Fri", "2024-08-30 Fri"]
+        );
+        assert_eq!(
+            occurrences("FREQ=MONTHLY;BYDAY=2TU", "2024-06-11 09:00:00", 2),
+            ["2024-06-11 Tue", "2024-07-09 Tue"]
+        );
+        // Months without a 31st are skipped
+        assert_eq!(
+            occurrences("FREQ=MONTHLY", "2024-05-31 09:00:00", 3),
+            ["2024-05-31 Fri", "2024-07-31 Wed", "2024-08-31 Sat"]
+        );
+        assert_eq!(
+            occurrences("FREQ=MONTHLY;BYMONTHDAY=1,-1", "2024-06-01 09:00:00", 3),
+            ["2024-06-01 Sat", "2024-06-30 Sun", "2024-07-01 Mon"]
+        );
+        // Never matched again
+        assert_eq!(
+            occurrences(
+                "FREQ=MONTHLY;INTERVAL=12;BYMONTHDAY=30",
+                "2024-02-01 09:00:00",
+                3
+            ),
+            ["2024-02-01 Thu"]
+        );
+    }
+
+    #[test]
+    fn validate_recurrence() {
+        let mut errors = Vec::new();
+        let due_at = timestamp("2024-06-10 12:00:00");
+        let recurrence = validate(&mut errors, Some(" FREQ=daily ".to_string()), Some(&due_at));
+        assert_eq!(recurrence.as_deref(), Some("FREQ=DAILY"));
+        assert_eq!(validate(&mut errors, Some(String::new()), None), None);
+        assert_eq!(errors, vec![]);
+
+        validate(&mut errors, Some("FREQ=DAILY".to_string()), None);
+        validate(&mut errors, Some("FREQ=HOURLY".to_string()), Some(&due_at));
+        let fields: Vec<_> = errors.iter().map(|error| error.field.as_str()).collect();
+        assert_eq!(fields, ["recurrence", "recurrence"]);
+    }
+
+    #[test]
+    fn complete_occurrence() {
+        let before = fixture_todos()[2].clone();
+        let mut todo = UpdateTodo {
+            completed: true,
+            recurrence: Some("FREQ=WEEKLY;COUNT=2".to_string()),
+            ..UpdateTodo::from(before.clone())
+        };
+        let next = complete(&before, &mut todo);
+        assert_eq!(todo.recurrence, None);
+        assert_eq!(
+            next,
+            Some(CreateTodo {
+                title: before.title.clone(),
+                description: before.description.clone(),
+                completed: false,
+                due_at: Some(timestamp("2024-06-27 12:00:00")),
+                parent_id: None,
+                recurrence: Some("FREQ=WEEKLY;COUNT=1".to_string()),
+            })
+        );
+
+        // The last occurrence ends the schedule
+        let mut todo = UpdateTodo {
+            completed: true,
+            recurrence: Some("FREQ=WEEKLY;COUNT=1".to_string()),
+            ..UpdateTodo::from(before.clone())
+        };
+        assert_eq!(complete(&before, &mut todo), None);
+        assert_eq!(todo.recurrence, None);
+    }
+}
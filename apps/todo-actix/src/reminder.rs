@@ -0,0 +1,485 @@
+use crate::{
+    error::{FieldError, InternalError},
+    feed::ChangeFeed,
+    history::EventKind,
+    repository::TodoRepository,
+    todo::{utc, Todo},
+    validate::Validate,
+};
+use actix_web::rt::time;
+use async_trait::async_trait;
+use chrono::{NaiveDateTime, SubsecRound, Utc};
+use futures_util::future::join_all;
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use std::sync::Arc;
+use utoipa::ToSchema;
+
+/// Longest time before its todo falls due a reminder can fire, 30 days.
+pub const MAX_OFFSET: i64 = 30 * 24 * 60 * 60;
+/// Reminders fired at once by the scheduler.
+const BATCH_SIZE: u32 = 50;
+const POLL_INTERVAL: std::time::Duration = std::time::Duration::from_secs(30);
+
+/// A reminder firing some time before its todo falls due. It fires once per
+/// due date, and again after the todo is rescheduled.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, FromRow, ToSchema)]
+pub struct Reminder {
+    pub id: i64,
+    pub todo_id: i64,
+    /// How long before the todo falls due the reminder fires, in seconds.
+    pub offset_seconds: i64,
+    /// When the reminder last fired.
+    #[serde(with = "utc::option")]
+    pub fired_at: Option<NaiveDateTime>,
+    /// The due date the reminder last fired for.
+    #[serde(with = "utc::option")]
+    pub fired_for: Option<NaiveDateTime>,
+    #[serde(with = "utc")]
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Debug, PartialEq, Eq, Deserialize, Serialize, ToSchema)]
+pub struct CreateReminder {
+    /// How long before the todo falls due the reminder fires, in seconds, at
+    /// most 30 days.
+    pub offset_seconds: i64,
+}
+
+/// A reminder which fired, along with its todo.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FiredReminder {
+    pub reminder: Reminder,
+    pub todo: Todo,
+}
+
+/// A fired reminder as posted to webhooks, among the events of its todo.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, ToSchema)]
+pub struct ReminderEvent {
+    /// Always `remind`.
+    pub kind: EventKind,
+    pub reminder: Reminder,
+    pub todo: Todo,
+}
+
+impl From<FiredReminder> for ReminderEvent {
+    fn from(fired: FiredReminder) -> Self {
+        Self {
+            kind: EventKind::Remind,
+            reminder: fired.reminder,
+            todo: fired.todo,
+        }
+    }
+}
+
+impl Validate for CreateReminder {
+    fn validate(self) -> Result<Self, Vec<FieldError>> {
+        if !(0..=MAX_OFFSET).contains(&self.offset_seconds) {
+            return Err(vec![FieldError::new(
+                "offset_seconds",
+                format!("must be between 0 and {MAX_OFFSET}"),
+            )]);
+        }
+        Ok(self)
+    }
+}
+
+/// Source of the current time, so that tests can control it.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> NaiveDateTime;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    /// The current time, to the second like SQLite timestamps.
+    fn now(&self) -> NaiveDateTime {
+        Utc::now().naive_utc().trunc_subsecs(0)
+    }
+}
+
+/// Where fired reminders are dispatched to.
+#[async_trait(?Send)]
+pub trait ReminderSink {
+    /// Name of the sink in logs.
+    fn name(&self) -> &'static str;
+
+    async fn send(&self, fired: &FiredReminder) -> Result<(), String>;
+}
+
+/// Logs fired reminders.
+pub struct LogSink;
+
+#[async_trait(?Send)]
+impl ReminderSink for LogSink {
+    fn name(&self) -> &'static str {
+        "log"
+    }
+
+    async fn send(&self, fired: &FiredReminder) -> Result<(), String> {
+        let FiredReminder { reminder, todo } = fired;
+        let due_at = todo.due_at.as_ref().map(utc::format).unwrap_or_default();
+        info!(
+            "Reminder {} fired for todo {} due at {due_at}",
+            reminder.id, todo.id
+        );
+        Ok(())
+    }
+}
+
+/// Publishes fired reminders to the change feed, as `remind` changes.
+pub struct FeedSink {
+    feed: Arc<ChangeFeed>,
+    repository: Arc<dyn TodoRepository>,
+}
+
+impl FeedSink {
+    pub fn new(feed: Arc<ChangeFeed>, repository: Arc<dyn TodoRepository>) -> Self {
+        Self { feed, repository }
+    }
+}
+
+#[async_trait(?Send)]
+impl ReminderSink for FeedSink {
+    fn name(&self) -> &'static str {
+        "feed"
+    }
+
+    async fn send(&self, fired: &FiredReminder) -> Result<(), String> {
+        let todo = self
+            .repository
+            .tag_todo(fired.todo.clone())
+            .await
+            .map_err(|err| err.to_string())?;
+        self.feed.publish(EventKind::Remind, todo);
+        Ok(())
+    }
+}
+
+/// Polls the reminders falling due and dispatches them to every sink.
+pub struct ReminderScheduler {
+    repository: Arc<dyn TodoRepository>,
+    clock: Arc<dyn Clock>,
+    sinks: Vec<Box<dyn ReminderSink>>,
+}
+
+impl ReminderScheduler {
+    pub fn new(repository: Arc<dyn TodoRepository>, clock: Arc<dyn Clock>) -> Self {
+        Self {
+            repository,
+            clock,
+            sinks: Vec::new(),
+        }
+    }
+
+    pub fn with_sink(mut self, sink: impl ReminderSink + 'static) -> Self {
+        self.sinks.push(Box::new(sink));
+        self
+    }
+
+    /// Fires every reminder due by now, returning how many fired. Reminders
+    /// are marked as fired in the storage before being dispatched, so that
+    /// each fires once even across restarts or with several schedulers, while
+    /// sinks failing to send them are only logged. The storage queues them for
+    /// webhooks along the way, to be delivered and retried like events.
+    pub async fn fire_due(&self) -> Result<usize, InternalError> {
+        let now = self.clock.now();
+        let mut count = 0;
+        loop {
+            let fired = self.repository.fire_due_reminders(now, BATCH_SIZE).await?;
+            join_all(fired.iter().map(|fired| self.dispatch(fired))).await;
+            count += fired.len();
+            if fired.len() < BATCH_SIZE as usize {
+                return Ok(count);
+            }
+        }
+    }
+
+    async fn dispatch(&self, fired: &FiredReminder) {
+        for sink in &self.sinks {
+            if let Err(err) = sink.send(fired).await {
+                warn!(
+                    "Failed to send reminder {} to the {} sink: {err}",
+                    fired.reminder.id,
+                    sink.name()
+                );
+            }
+        }
+    }
+
+    /// Fires the due reminders every 30 seconds. Those which fell due while
+    /// the server was down fire on the first poll.
+    pub async fn run(self) {
+        let mut interval = time::interval(POLL_INTERVAL);
+        loop {
+            interval.tick().await;
+            match self.fire_due().await {
+                Ok(0) => {}
+                Ok(fired) => info!("Fired {fired} reminders"),
+                Err(err) => error!("Failed to fire reminders: {err}"),
+            }
+        }
+    }
+}
+
+/// Fires reminders to the log and the change feed, while webhooks are sent
+/// them by the delivery worker.
+pub async fn remind_periodically(repository: Arc<dyn TodoRepository>, feed: Arc<ChangeFeed>) {
+    let clock: Arc<dyn Clock> = Arc::new(SystemClock);
+    ReminderScheduler::new(repository.clone(), clock)
+        .with_sink(LogSink)
+        .with_sink(FeedSink::new(feed, repository))
+        .run()
+        .await
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        error::FieldError,
+        etag::IfMatch,
+        feed::{ChangeFeed, Filter},
+        history::EventKind,
+        reminder::{CreateReminder, FeedSink, FiredReminder, ReminderScheduler, ReminderSink},
+        subtask::DeletePolicy,
+        test::{
+            patch, receiver, repositories, test_config, timestamp, ManualClock, Received, ALICE,
+        },
+        validate::Validate,
+        webhook::{client, deliver_due, sign, CreateWebhook},
+    };
+    use actix_web::http::StatusCode;
+    use async_trait::async_trait;
+    use chrono::Duration;
+    use serde_json::{json, Value};
+    use sqlx::SqlitePool;
+    use std::sync::{Arc, Mutex};
+    use tokio::task::LocalSet;
+
+    /// Sink recording the ids of the reminders sent to it.
+    #[derive(Clone, Default)]
+    struct Recorder(Arc<Mutex<Vec<i64>>>);
+
+    #[async_trait(?Send)]
+    impl ReminderSink for Recorder {
+        fn name(&self) -> &'static str {
+            "recorder"
+        }
+
+        async fn send(&self, fired: &FiredReminder) -> Result<(), String> {
+            self.0.lock().unwrap().push(fired.reminder.id);
+            Ok(())
+        }
+    }
+
+    /// Sink failing to send every reminder.
+    struct Failing;
+
+    #[async_trait(?Send)]
+    impl ReminderSink for Failing {
+        fn name(&self) -> &'static str {
+            "failing"
+        }
+
+        async fn send(&self, _: &FiredReminder) -> Result<(), String> {
+            Err("unreachable".to_string())
+        }
+    }
+
+    fn reminder(offset_seconds: i64) -> CreateReminder {
+        CreateReminder { offset_seconds }
+    }
+
+    #[test]
+    fn offset_range() {
+        assert_eq!(reminder(0).validate(), Ok(reminder(0)));
+        assert_eq!(reminder(2_592_000).validate(), Ok(reminder(2_592_000)));
+        for offset_seconds in [-1, 2_592_001] {
+            assert_eq!(
+                reminder(offset_seconds).validate(),
+                Err(vec![FieldError::new(
+                    "offset_seconds",
+                    "must be between 0 and 2592000"
+                )])
+            );
+        }
+    }
+
+    #[sqlx::test(fixtures("test/fixtures/users.sql", "test/fixtures/todos.sql"))]
+    async fn fire_once(pool: SqlitePool) {
+        for repository in repositories(pool).await {
+            let clock = Arc::new(ManualClock::new("2024-06-20 10:59:59"));
+            let recorder = Recorder::default();
+            let scheduler = ReminderScheduler::new(repository.clone(), clock.clone())
+                .with_sink(Failing)
+                .with_sink(recorder.clone());
+            let created = repository
+                .create_reminder(ALICE, 3, reminder(3600))
+                .await
+                .unwrap();
+            // Completed todos are not reminded of
+            repository
+                .create_reminder(ALICE, 2, reminder(0))
+                .await
+                .unwrap();
+
+            assert_eq!(scheduler.fire_due().await.unwrap(), 0);
+            clock.advance(Duration::seconds(1));
+            assert_eq!(scheduler.fire_due().await.unwrap(), 1);
+            // A failing sink does not keep the others from being sent to
+            assert_eq!(*recorder.0.lock().unwrap(), [created.id]);
+
+            // Not fired again, even by a scheduler started afresh
+            clock.advance(Duration::hours(1));
+            assert_eq!(scheduler.fire_due().await.unwrap(), 0);
+            let restarted = ReminderScheduler::new(repository.clone(), clock.clone());
+            assert_eq!(restarted.fire_due().await.unwrap(), 0);
+            let reminders = repository.list_reminders(ALICE, 3).await.unwrap();
+            assert_eq!(
+                reminders[0].fired_at,
+                Some(timestamp("2024-06-20 11:00:00"))
+            );
+            assert_eq!(
+                reminders[0].fired_for,
+                Some(timestamp("2024-06-20 12:00:00"))
+            );
+
+            // Rescheduling the todo arms the reminder again
+            patch(
+                repository.as_ref(),
+                ALICE,
+                3,
+                &json!({"due_at": "2024-06-20T13:00:00Z"}),
+            )
+            .await;
+            assert_eq!(scheduler.fire_due().await.unwrap(), 1);
+            assert_eq!(*recorder.0.lock().unwrap(), [created.id, created.id]);
+
+            patch(
+                repository.as_ref(),
+                ALICE,
+                3,
+                &json!({"due_at": "2024-06-20T14:00:00Z", "completed": true}),
+            )
+            .await;
+            clock.advance(Duration::days(1));
+            assert_eq!(scheduler.fire_due().await.unwrap(), 0);
+        }
+    }
+
+    #[sqlx::test(fixtures("test/fixtures/users.sql", "test/fixtures/todos.sql"))]
+    async fn trashed_todo(pool: SqlitePool) {
+        for repository in repositories(pool).await {
+            let clock = Arc::new(ManualClock::new("2024-06-21 00:00:00"));
+            let scheduler = ReminderScheduler::new(repository.clone(), clock.clone());
+            repository
+                .create_reminder(ALICE, 3, reminder(0))
+                .await
+                .unwrap();
+            repository
+                .delete_todo(ALICE, 3, &IfMatch::Any, DeletePolicy::Reject)
+                .await
+                .unwrap();
+            assert_eq!(scheduler.fire_due().await.unwrap(), 0);
+
+            // Reminders missed while in the trash fire once restored
+            repository.restore_todo(ALICE, 3).await.unwrap();
+            assert_eq!(scheduler.fire_due().await.unwrap(), 1);
+        }
+    }
+
+    #[sqlx::test(fixtures("test/fixtures/users.sql", "test/fixtures/todos.sql"))]
+    async fn recurring_todo(pool: SqlitePool) {
+        for repository in repositories(pool).await {
+            patch(
+                repository.as_ref(),
+                ALICE,
+                3,
+                &json!({"recurrence": "FREQ=DAILY"}),
+            )
+            .await;
+            repository
+                .create_reminder(ALICE, 3, reminder(600))
+                .await
+                .unwrap();
+            let changes = json!({"completed": true});
+            patch(repository.as_ref(), ALICE, 3, &changes).await;
+
+            // The next occurrence is reminded of like the previous one
+            let reminders = repository.list_reminders(ALICE, 4).await.unwrap();
+            assert_eq!(reminders.len(), 1);
+            assert_eq!(reminders[0].offset_seconds, 600);
+            assert_eq!(reminders[0].fired_for, None);
+        }
+    }
+
+    #[sqlx::test(fixtures("test/fixtures/users.sql", "test/fixtures/todos.sql"))]
+    async fn feed_sink(pool: SqlitePool) {
+        for repository in repositories(pool).await {
+            let feed = Arc::new(ChangeFeed::new());
+            let mut subscription = feed.subscribe(Filter::new(ALICE, None), None);
+            let clock = Arc::new(ManualClock::new("2024-06-20 12:00:00"));
+            let scheduler = ReminderScheduler::new(repository.clone(), clock)
+                .with_sink(FeedSink::new(feed, repository.clone()));
+            repository
+                .create_reminder(ALICE, 3, reminder(0))
+                .await
+                .unwrap();
+            assert_eq!(scheduler.fire_due().await.unwrap(), 1);
+
+            let change = subscription.next().await.unwrap();
+            assert_eq!(change.kind, EventKind::Remind);
+            assert_eq!(change.todo.todo.id, 3);
+        }
+    }
+
+    #[sqlx::test(fixtures("test/fixtures/users.sql", "test/fixtures/todos.sql"))]
+    async fn webhook_delivery(pool: SqlitePool) {
+        LocalSet::new()
+            .run_until(async move {
+                for repository in repositories(pool).await {
+                    let received = Received::default();
+                    let server = receiver(StatusCode::NO_CONTENT, received.clone());
+                    let webhook = repository
+                        .create_webhook(
+                            ALICE,
+                            CreateWebhook {
+                                url: server.url("/hook"),
+                            },
+                            "secret",
+                        )
+                        .await
+                        .unwrap();
+                    let clock = Arc::new(ManualClock::new("2024-06-20 12:00:00"));
+                    let scheduler = ReminderScheduler::new(repository.clone(), clock);
+                    let created = repository
+                        .create_reminder(ALICE, 3, reminder(0))
+                        .await
+                        .unwrap();
+                    assert_eq!(scheduler.fire_due().await.unwrap(), 1);
+                    assert!(received.lock().unwrap().is_empty());
+
+                    let now = timestamp("2100-01-01 00:00:00");
+                    let delivered = deliver_due(
+                        repository.as_ref(),
+                        &client(&test_config().webhook_policy),
+                        now,
+                    )
+                    .await;
+                    assert_eq!(delivered.unwrap(), 1);
+                    assert_eq!(repository.due_deliveries(now, 10).await.unwrap(), vec![]);
+
+                    let (signature, timestamp, body) = received.lock().unwrap().pop().unwrap();
+                    let expected =
+                        sign(&webhook.secret, timestamp.parse().unwrap(), body.as_bytes());
+                    assert_eq!(signature, expected);
+                    let body: Value = serde_json::from_str(&body).unwrap();
+                    assert_eq!(body["kind"], "remind");
+                    assert_eq!(body["reminder"]["id"], created.id);
+                    assert_eq!(body["todo"]["id"], 3);
+                }
+            })
+            .await;
+    }
+}
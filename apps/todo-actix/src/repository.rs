@@ -0,0 +1,570 @@
+use crate::{
+    batch::{Batch, Outcome},
+    db,
+    error::{ApiError, InternalError},
+    etag::IfMatch,
+    history::TodoEvent,
+    query::{ListParams, Page},
+    recurrence::Updated,
+    reminder::{CreateReminder, FiredReminder, Reminder},
+    search::{SearchHit, SearchParams},
+    subtask::{DeletePolicy, Deleted},
+    tag::{ChangedTag, CreateTag, Tag, TaggedTodo, UpdateTag},
+    todo::{CreateTodo, Todo, UpdateTodo},
+    transfer::Imported,
+    user::User,
+    webhook::{CreateWebhook, Delivery, Webhook},
+};
+use async_trait::async_trait;
+use chrono::NaiveDateTime;
+use sqlx::SqlitePool;
+
+/// Turns a todo into the fields replacing them, or rejects it.
+pub type Patcher<'a> = dyn Fn(Todo) -> Result<UpdateTodo, ApiError> + Send + Sync + 'a;
+
+/// Storage of users, todos, tags, reminders and webhooks. Every operation on them is
+/// scoped to the user owning them, so that other users' rows are reported as
+/// not found.
+#[async_trait]
+pub trait TodoRepository: Send + Sync {
+    /// Checks that the storage is reachable and up to date.
+    async fn check_ready(&self) -> Result<(), InternalError>;
+
+    /// Reports the state of the connection pool, for storages that have one.
+    fn pool_status(&self) -> Option<PoolStatus> {
+        None
+    }
+
+    async fn list_todos(&self, user_id: i64, params: &ListParams) -> Result<Page, InternalError>;
+
+    async fn get_todo(&self, user_id: i64, id: i64) -> Result<Todo, InternalError>;
+
+    async fn create_todo(&self, user_id: i64, todo: CreateTodo) -> Result<Todo, InternalError>;
+
+    async fn update_todo(
+        &self,
+        user_id: i64,
+        id: i64,
+        todo: UpdateTodo,
+        if_match: &IfMatch,
+    ) -> Result<Updated, InternalError>;
+
+    /// Replaces a todo with what `patch` makes of it, reading and writing it in
+    /// the same transaction so that no other change lands in between. A todo
+    /// rejected by the patch is left untouched, and the rejection handed back.
+    async fn patch_todo(
+        &self,
+        user_id: i64,
+        id: i64,
+        if_match: &IfMatch,
+        patch: &Patcher<'_>,
+    ) -> Result<Result<Updated, ApiError>, InternalError>;
+
+    /// Moves a todo to the trash, applying the delete policy to its subtasks.
+    async fn delete_todo(
+        &self,
+        user_id: i64,
+        id: i64,
+        if_match: &IfMatch,
+        policy: DeletePolicy,
+    ) -> Result<Deleted, InternalError>;
+
+    async fn run_batch(
+        &self,
+        user_id: i64,
+        batch: Batch,
+        policy: DeletePolicy,
+    ) -> Result<Outcome, InternalError>;
+
+    /// Lists the subtasks of a todo, oldest first.
+    async fn list_children(&self, user_id: i64, id: i64) -> Result<Vec<Todo>, InternalError>;
+
+    /// Lists every todo of a user, each level of subtasks after the one above
+    /// it, and oldest first within a level.
+    async fn todo_tree(&self, user_id: i64) -> Result<Vec<Todo>, InternalError>;
+
+    /// Lists every todo of a user, oldest first.
+    async fn export_todos(&self, user_id: i64) -> Result<Vec<Todo>, InternalError>;
+
+    /// Creates the given todos at once, skipping those which duplicate an
+    /// existing todo or one created before them.
+    async fn import_todos(
+        &self,
+        user_id: i64,
+        todos: Vec<CreateTodo>,
+    ) -> Result<Vec<Imported>, InternalError>;
+
+    /// Lists trashed todos, most recently deleted first.
+    async fn list_trash(&self, user_id: i64) -> Result<Vec<Todo>, InternalError>;
+
+    async fn restore_todo(&self, user_id: i64, id: i64) -> Result<Todo, InternalError>;
+
+    /// Permanently removes every todo trashed before the given time, returning
+    /// how many were removed.
+    async fn purge_trash(&self, before: NaiveDateTime) -> Result<u64, InternalError>;
+
+    /// Lists the changes made to a todo, oldest first.
+    async fn list_history(
+        &self,
+        user_id: i64,
+        todo_id: i64,
+    ) -> Result<Vec<TodoEvent>, InternalError>;
+
+    /// Brings the fields of a todo back to how they were at a previous version.
+    async fn revert_todo(
+        &self,
+        user_id: i64,
+        id: i64,
+        version: i64,
+        if_match: &IfMatch,
+    ) -> Result<Todo, InternalError>;
+
+    async fn create_user(&self, username: &str, password_hash: &str)
+        -> Result<User, InternalError>;
+
+    /// Returns the id and password hash of the user with the given username.
+    async fn get_user_password_hash(
+        &self,
+        username: &str,
+    ) -> Result<Option<(i64, String)>, InternalError>;
+
+    /// Gives the todos created before accounts existed, which have no owner, to
+    /// a user. Returns how many there were, or none when there is no such user.
+    async fn claim_orphans(&self, username: &str) -> Result<Option<u64>, InternalError>;
+
+    /// Searches the todos text, best matches first.
+    async fn search_todos(
+        &self,
+        user_id: i64,
+        params: &SearchParams,
+    ) -> Result<Vec<SearchHit>, InternalError>;
+
+    async fn list_tags(&self, user_id: i64) -> Result<Vec<Tag>, InternalError>;
+
+    async fn get_tag(&self, user_id: i64, id: i64) -> Result<Tag, InternalError>;
+
+    async fn create_tag(&self, user_id: i64, tag: CreateTag) -> Result<Tag, InternalError>;
+
+    /// Renames a tag, bumping the version of the todos carrying it.
+    async fn update_tag(
+        &self,
+        user_id: i64,
+        id: i64,
+        tag: UpdateTag,
+    ) -> Result<ChangedTag, InternalError>;
+
+    /// Deletes a tag, bumping the version of the todos carrying it.
+    async fn delete_tag(&self, user_id: i64, id: i64) -> Result<ChangedTag, InternalError>;
+
+    async fn list_todo_tags(&self, user_id: i64, todo_id: i64) -> Result<Vec<Tag>, InternalError>;
+
+    async fn attach_tag(
+        &self,
+        user_id: i64,
+        todo_id: i64,
+        tag_id: i64,
+    ) -> Result<TaggedTodo, InternalError>;
+
+    async fn detach_tag(
+        &self,
+        user_id: i64,
+        todo_id: i64,
+        tag_id: i64,
+    ) -> Result<TaggedTodo, InternalError>;
+
+    /// Embeds the tags of every given todo.
+    async fn tag_todos(&self, todos: Vec<Todo>) -> Result<Vec<TaggedTodo>, InternalError>;
+
+    async fn tag_todo(&self, todo: Todo) -> Result<TaggedTodo, InternalError> {
+        let mut todos = self.tag_todos(vec![todo]).await?;
+        todos.pop().ok_or(InternalError::NotFound)
+    }
+
+    async fn list_webhooks(&self, user_id: i64) -> Result<Vec<Webhook>, InternalError>;
+
+    async fn get_webhook(&self, user_id: i64, id: i64) -> Result<Webhook, InternalError>;
+
+    async fn create_webhook(
+        &self,
+        user_id: i64,
+        webhook: CreateWebhook,
+        secret: &str,
+    ) -> Result<Webhook, InternalError>;
+
+    async fn delete_webhook(&self, user_id: i64, id: i64) -> Result<Webhook, InternalError>;
+
+    /// Lists the deliveries to a webhook which were given up, oldest first.
+    async fn list_dead_letters(
+        &self,
+        user_id: i64,
+        webhook_id: i64,
+    ) -> Result<Vec<Delivery>, InternalError>;
+
+    /// Queues a dead letter again, to be delivered right away.
+    async fn retry_dead_letter(
+        &self,
+        user_id: i64,
+        webhook_id: i64,
+        id: i64,
+    ) -> Result<Delivery, InternalError>;
+
+    /// Lists the deliveries due at the given time, oldest first, along with
+    /// their webhook. Unlike other operations, this one spans every user.
+    async fn due_deliveries(
+        &self,
+        now: NaiveDateTime,
+        limit: u32,
+    ) -> Result<Vec<(Webhook, Delivery)>, InternalError>;
+
+    async fn complete_delivery(&self, id: i64) -> Result<(), InternalError>;
+
+    /// Records a failed attempt at a delivery, which is retried at the given
+    /// time, or else given up.
+    async fn fail_delivery(
+        &self,
+        id: i64,
+        error: &str,
+        retry_at: Option<NaiveDateTime>,
+    ) -> Result<(), InternalError>;
+
+    /// Lists the reminders of a todo, oldest first.
+    async fn list_reminders(
+        &self,
+        user_id: i64,
+        todo_id: i64,
+    ) -> Result<Vec<Reminder>, InternalError>;
+
+    async fn create_reminder(
+        &self,
+        user_id: i64,
+        todo_id: i64,
+        reminder: CreateReminder,
+    ) -> Result<Reminder, InternalError>;
+
+    async fn delete_reminder(
+        &self,
+        user_id: i64,
+        todo_id: i64,
+        id: i64,
+    ) -> Result<Reminder, InternalError>;
+
+    /// Marks the reminders due at the given time as fired and returns them,
+    /// oldest first. Reminders of completed or trashed todos are not due.
+    /// Unlike other operations, this one spans every user.
+    async fn fire_due_reminders(
+        &self,
+        now: NaiveDateTime,
+        limit: u32,
+    ) -> Result<Vec<FiredReminder>, InternalError>;
+}
+
+/// Number of connections of a pool, as exposed in the metrics.
+#[derive(Debug, Clone, Copy)]
+pub struct PoolStatus {
+    pub size: u32,
+    pub idle: usize,
+    pub max: u32,
+}
+
+/// The repository used in production, storing everything in SQLite.
+#[derive(Debug, Clone)]
+pub struct SqliteRepository {
+    pool: SqlitePool,
+}
+
+impl SqliteRepository {
+    pub fn new(pool: SqlitePool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait]
+impl TodoRepository for SqliteRepository {
+    async fn check_ready(&self) -> Result<(), InternalError> {
+        db::check_ready(&self.pool).await
+    }
+
+    fn pool_status(&self) -> Option<PoolStatus> {
+        Some(PoolStatus {
+            size: self.pool.size(),
+            idle: self.pool.num_idle(),
+            max: self.pool.options().get_max_connections(),
+        })
+    }
+
+    async fn list_todos(&self, user_id: i64, params: &ListParams) -> Result<Page, InternalError> {
+        db::list_todos(&self.pool, user_id, params).await
+    }
+
+    async fn get_todo(&self, user_id: i64, id: i64) -> Result<Todo, InternalError> {
+        db::get_todo(&self.pool, user_id, id).await
+    }
+
+    async fn create_todo(&self, user_id: i64, todo: CreateTodo) -> Result<Todo, InternalError> {
+        db::create_todo(&self.pool, user_id, todo).await
+    }
+
+    async fn update_todo(
+        &self,
+        user_id: i64,
+        id: i64,
+        todo: UpdateTodo,
+        if_match: &IfMatch,
+    ) -> Result<Updated, InternalError> {
+        db::update_todo(&self.pool, user_id, id, todo, if_match).await
+    }
+
+    async fn patch_todo(
+        &self,
+        user_id: i64,
+        id: i64,
+        if_match: &IfMatch,
+        patch: &Patcher<'_>,
+    ) -> Result<Result<Updated, ApiError>, InternalError> {
+        db::patch_todo(&self.pool, user_id, id, if_match, patch).await
+    }
+
+    async fn delete_todo(
+        &self,
+        user_id: i64,
+        id: i64,
+        if_match: &IfMatch,
+        policy: DeletePolicy,
+    ) -> Result<Deleted, InternalError> {
+        db::delete_todo(&self.pool, user_id, id, if_match, policy).await
+    }
+
+    async fn run_batch(
+        &self,
+        user_id: i64,
+        batch: Batch,
+        policy: DeletePolicy,
+    ) -> Result<Outcome, InternalError> {
+        db::run_batch(&self.pool, user_id, batch, policy).await
+    }
+
+    async fn list_children(&self, user_id: i64, id: i64) -> Result<Vec<Todo>, InternalError> {
+        db::list_children(&self.pool, user_id, id).await
+    }
+
+    async fn todo_tree(&self, user_id: i64) -> Result<Vec<Todo>, InternalError> {
+        db::todo_tree(&self.pool, user_id).await
+    }
+
+    async fn export_todos(&self, user_id: i64) -> Result<Vec<Todo>, InternalError> {
+        db::export_todos(&self.pool, user_id).await
+    }
+
+    async fn import_todos(
+        &self,
+        user_id: i64,
+        todos: Vec<CreateTodo>,
+    ) -> Result<Vec<Imported>, InternalError> {
+        db::import_todos(&self.pool, user_id, todos).await
+    }
+
+    async fn list_trash(&self, user_id: i64) -> Result<Vec<Todo>, InternalError> {
+        db::list_trash(&self.pool, user_id).await
+    }
+
+    async fn restore_todo(&self, user_id: i64, id: i64) -> Result<Todo, InternalError> {
+        db::restore_todo(&self.pool, user_id, id).await
+    }
+
+    async fn purge_trash(&self, before: NaiveDateTime) -> Result<u64, InternalError> {
+        db::purge_trash(&self.pool, before).await
+    }
+
+    async fn list_history(
+        &self,
+        user_id: i64,
+        todo_id: i64,
+    ) -> Result<Vec<TodoEvent>, InternalError> {
+        db::list_history(&self.pool, user_id, todo_id).await
+    }
+
+    async fn revert_todo(
+        &self,
+        user_id: i64,
+        id: i64,
+        version: i64,
+        if_match: &IfMatch,
+    ) -> Result<Todo, InternalError> {
+        db::revert_todo(&self.pool, user_id, id, version, if_match).await
+    }
+
+    async fn create_user(
+        &self,
+        username: &str,
+        password_hash: &str,
+    ) -> Result<User, InternalError> {
+        db::create_user(&self.pool, username, password_hash).await
+    }
+
+    async fn get_user_password_hash(
+        &self,
+        username: &str,
+    ) -> Result<Option<(i64, String)>, InternalError> {
+        db::get_user_password_hash(&self.pool, username).await
+    }
+
+    async fn claim_orphans(&self, username: &str) -> Result<Option<u64>, InternalError> {
+        db::claim_orphans(&self.pool, username).await
+    }
+
+    async fn search_todos(
+        &self,
+        user_id: i64,
+        params: &SearchParams,
+    ) -> Result<Vec<SearchHit>, InternalError> {
+        db::search_todos(&self.pool, user_id, params).await
+    }
+
+    async fn list_tags(&self, user_id: i64) -> Result<Vec<Tag>, InternalError> {
+        db::list_tags(&self.pool, user_id).await
+    }
+
+    async fn get_tag(&self, user_id: i64, id: i64) -> Result<Tag, InternalError> {
+        db::get_tag(&self.pool, user_id, id).await
+    }
+
+    async fn create_tag(&self, user_id: i64, tag: CreateTag) -> Result<Tag, InternalError> {
+        db::create_tag(&self.pool, user_id, tag).await
+    }
+
+    async fn update_tag(
+        &self,
+        user_id: i64,
+        id: i64,
+        tag: UpdateTag,
+    ) -> Result<ChangedTag, InternalError> {
+        db::update_tag(&self.pool, user_id, id, tag).await
+    }
+
+    async fn delete_tag(&self, user_id: i64, id: i64) -> Result<ChangedTag, InternalError> {
+        db::delete_tag(&self.pool, user_id, id).await
+    }
+
+    async fn list_todo_tags(&self, user_id: i64, todo_id: i64) -> Result<Vec<Tag>, InternalError> {
+        db::list_todo_tags(&self.pool, user_id, todo_id).await
+    }
+
+    async fn attach_tag(
+        &self,
+        user_id: i64,
+        todo_id: i64,
+        tag_id: i64,
+    ) -> Result<TaggedTodo, InternalError> {
+        db::attach_tag(&self.pool, user_id, todo_id, tag_id).await
+    }
+
+    async fn detach_tag(
+        &self,
+        user_id: i64,
+        todo_id: i64,
+        tag_id: i64,
+    ) -> Result<TaggedTodo, InternalError> {
+        db::detach_tag(&self.pool, user_id, todo_id, tag_id).await
+    }
+
+    async fn tag_todos(&self, todos: Vec<Todo>) -> Result<Vec<TaggedTodo>, InternalError> {
+        db::tag_todos(&self.pool, todos).await
+    }
+
+    async fn tag_todo(&self, todo: Todo) -> Result<TaggedTodo, InternalError> {
+        db::tag_todo(&self.pool, todo).await
+    }
+
+    async fn list_webhooks(&self, user_id: i64) -> Result<Vec<Webhook>, InternalError> {
+        db::list_webhooks(&self.pool, user_id).await
+    }
+
+    async fn get_webhook(&self, user_id: i64, id: i64) -> Result<Webhook, InternalError> {
+        db::get_webhook(&self.pool, user_id, id).await
+    }
+
+    async fn create_webhook(
+        &self,
+        user_id: i64,
+        webhook: CreateWebhook,
+        secret: &str,
+    ) -> Result<Webhook, InternalError> {
+        db::create_webhook(&self.pool, user_id, webhook, secret).await
+    }
+
+    async fn delete_webhook(&self, user_id: i64, id: i64) -> Result<Webhook, InternalError> {
+        db::delete_webhook(&self.pool, user_id, id).await
+    }
+
+    async fn list_dead_letters(
+        &self,
+        user_id: i64,
+        webhook_id: i64,
+    ) -> Result<Vec<Delivery>, InternalError> {
+        db::list_dead_letters(&self.pool, user_id, webhook_id).await
+    }
+
+    async fn retry_dead_letter(
+        &self,
+        user_id: i64,
+        webhook_id: i64,
+        id: i64,
+    ) -> Result<Delivery, InternalError> {
+        db::retry_dead_letter(&self.pool, user_id, webhook_id, id).await
+    }
+
+    async fn due_deliveries(
+        &self,
+        now: NaiveDateTime,
+        limit: u32,
+    ) -> Result<Vec<(Webhook, Delivery)>, InternalError> {
+        db::due_deliveries(&self.pool, now, limit).await
+    }
+
+    async fn complete_delivery(&self, id: i64) -> Result<(), InternalError> {
+        db::complete_delivery(&self.pool, id).await
+    }
+
+    async fn fail_delivery(
+        &self,
+        id: i64,
+        error: &str,
+        retry_at: Option<NaiveDateTime>,
+    ) -> Result<(), InternalError> {
+        db::fail_delivery(&self.pool, id, error, retry_at).await
+    }
+
+    async fn list_reminders(
+        &self,
+        user_id: i64,
+        todo_id: i64,
+    ) -> Result<Vec<Reminder>, InternalError> {
+        db::list_reminders(&self.pool, user_id, todo_id).await
+    }
+
+    async fn create_reminder(
+        &self,
+        user_id: i64,
+        todo_id: i64,
+        reminder: CreateReminder,
+    ) -> Result<Reminder, InternalError> {
+        db::create_reminder(&self.pool, user_id, todo_id, reminder).await
+    }
+
+    async fn delete_reminder(
+        &self,
+        user_id: i64,
+        todo_id: i64,
+        id: i64,
+    ) -> Result<Reminder, InternalError> {
+        db::delete_reminder(&self.pool, user_id, todo_id, id).await
+    }
+
+    async fn fire_due_reminders(
+        &self,
+        now: NaiveDateTime,
+        limit: u32,
+    ) -> Result<Vec<FiredReminder>, InternalError> {
+        db::fire_due_reminders(&self.pool, now, limit).await
+    }
+}
@@ -1,224 +1,4080 @@
 use crate::{
     app::AppData,
-    db,
-    error::ApiError,
-    todo::{CreateTodo, UpdateTodo},
+    auth::{self, AuthUser},
+    batch::{Batch, BatchMode, BatchResult, Operation, OperationResult, Outcome, MAX_OPERATIONS},
+    error::{ApiError, FieldError, InternalError},
+    etag::{self, IfMatch},
+    feed::{self, Change, FeedQuery, Filter},
+    health::{self, Health},
+    history::{EventKind, Revert, TodoEvent},
+    openapi::ApiDoc,
+    query::{ListParams, ListQuery, Position},
+    recurrence::{self, Occurrence, OccurrenceQuery, Updated, UpdatedTodo},
+    reminder::{CreateReminder, Reminder},
+    search::{SearchHit, SearchParams, SearchQuery},
+    subtask::{self, Deleted},
+    tag::{AttachTag, CreateTag, Tag, TaggedTodo, UpdateTag},
+    todo::{CreateTodo, Todo, UpdateTodo},
+    transfer::{self, ExportQuery, Format, ImportQuery, ImportResult, RowStatus, MAX_ROWS},
+    user::{Credentials, Token, User},
+    validate::Validate,
+    webhook::{CreateWebhook, CreatedWebhook, Delivery, Webhook},
 };
 use actix_web::{
-    delete, get, post, put,
-    web::{Data, Json, Path},
-    HttpResponse,
+    delete, get,
+    http::{
+        header::{
+            CacheControl, CacheDirective, ContentDisposition, ContentType, DispositionParam,
+            DispositionType, ETag, CONTENT_TYPE, LINK,
+        },
+        StatusCode,
+    },
+    patch, post, put, rt,
+    web::{self, Data, Json, Path, Payload, Query},
+    HttpRequest, HttpResponse, HttpResponseBuilder,
 };
+use chrono::{SubsecRound, Utc};
+use serde::Serialize;
+use serde_json::Value;
+use utoipa::OpenApi;
 
+pub const NEXT_CURSOR: &str = "X-Next-Cursor";
+pub const LAST_EVENT_ID: &str = "Last-Event-ID";
+const EVENT_STREAM: &str = "text/event-stream";
+
+/// Liveness probe, answering as long as the server is running.
+#[utoipa::path(
+    tag = "health",
+    responses((status = 200, description = "Server running", body = Health)),
+    security(()),
+)]
+#[get("/healthz")]
+pub async fn healthz() -> HttpResponse {
+    HttpResponse::Ok().json(Health::new(health::LIVE))
+}
+
+/// Readiness probe, answering once the storage can serve requests.
+#[utoipa::path(
+    tag = "health",
+    responses(
+        (status = 200, description = "Server ready", body = Health),
+        (status = 503, description = "Storage unreachable or not migrated"),
+    ),
+    security(()),
+)]
+#[get("/readyz")]
+pub async fn readyz(app_data: Data<AppData>) -> Result<HttpResponse, ApiError> {
+    app_data
+        .repository
+        .check_ready()
+        .await
+        .map_err(|err| ApiError::ServiceUnavailable(err.to_string()))?;
+    Ok(HttpResponse::Ok().json(Health::new(health::READY)))
+}
+
+/// Request and connection pool metrics, in the Prometheus text format.
+#[utoipa::path(
+    tag = "health",
+    responses((status = 200, description = "Metrics", body = String, content_type = "text/plain")),
+    security(()),
+)]
+#[get("/metrics")]
+pub async fn metrics(app_data: Data<AppData>) -> Result<HttpResponse, ApiError> {
+    let pool = app_data.repository.pool_status();
+    let body = app_data.metrics.render(pool)?;
+    let response = HttpResponse::Ok()
+        .content_type(prometheus::TEXT_FORMAT)
+        .body(body);
+    Ok(response)
+}
+
+#[utoipa::path(
+    tag = "users",
+    request_body = Credentials,
+    responses(
+        (status = 200, description = "Registered user", body = User),
+        (status = 400, description = "Malformed credentials"),
+        (status = 409, description = "Username already taken"),
+        (status = 422, description = "Invalid credentials"),
+    ),
+    security(()),
+)]
+#[post("/users")]
+pub async fn register(
+    app_data: Data<AppData>,
+    credentials: Json<Credentials>,
+) -> Result<HttpResponse, ApiError> {
+    let credentials = credentials
+        .into_inner()
+        .validate()
+        .map_err(ApiError::UnprocessableEntity)?;
+    let password_hash = web::block(move || auth::hash_password(&credentials.password))
+        .await
+        .map_err(InternalError::from)?
+        .map_err(InternalError::from)?;
+    let user = app_data
+        .repository
+        .create_user(&credentials.username, &password_hash)
+        .await?;
+    let response = HttpResponse::Ok().json(user);
+    Ok(response)
+}
+
+#[utoipa::path(
+    tag = "users",
+    request_body = Credentials,
+    responses(
+        (status = 200, description = "Bearer token", body = Token),
+        (status = 400, description = "Malformed credentials"),
+        (status = 401, description = "Unknown user or wrong password"),
+    ),
+    security(()),
+)]
+#[post("/login")]
+pub async fn login(
+    app_data: Data<AppData>,
+    credentials: Json<Credentials>,
+) -> Result<HttpResponse, ApiError> {
+    let Credentials { username, password } = credentials.into_inner();
+    let user = app_data
+        .repository
+        .get_user_password_hash(username.trim())
+        .await?;
+    let (user_id, password_hash) = user.unzip();
+    let verified = web::block(move || auth::verify_password(&password, password_hash.as_deref()))
+        .await
+        .map_err(InternalError::from)?;
+    let user_id = user_id.filter(|_| verified).ok_or(ApiError::Unauthorized)?;
+
+    let config = &app_data.config;
+    let token = Token {
+        access_token: auth::sign_token(&config.token_secret, user_id, config.token_ttl),
+        token_type: "Bearer".to_string(),
+        expires_in: config.token_ttl,
+    };
+    let response = HttpResponse::Ok().json(token);
+    Ok(response)
+}
+
+#[utoipa::path(
+    tag = "todos",
+    params(
+        ListQuery,
+        ("tag" = Option<Vec<String>>, Query, explode, description = "Tags todos must all carry"),
+        ("If-None-Match" = Option<String>, Header, description = "Entity tags of a representation already known"),
+    ),
+    responses(
+        (status = 200, description = "Page of todos, or every todo nested under its parent when `tree` is set", body = Vec<TaggedTodo>, headers(
+            ("ETag" = String, description = "Digest of the page"),
+            ("Link" = String, description = "Link to the next page, if any"),
+            ("X-Next-Cursor" = String, description = "Cursor of the next page, if any"),
+        )),
+        (status = 304, description = "Page not modified"),
+        (status = 400, description = "Invalid query"),
+    ),
+)]
 #[get("/todos")]
-pub async fn list_todos(app_data: Data<AppData>) -> Result<HttpResponse, ApiError> {
-    let todos = db::list_todos(&app_data.db_pool).await?;
-    let response = HttpResponse::Ok().json(todos);
+pub async fn list_todos(
+    app_data: Data<AppData>,
+    user: AuthUser,
+    request: HttpRequest,
+) -> Result<HttpResponse, ApiError> {
+    // Parsed by hand since repeated `tag` parameters are not supported by `Query`
+    let query = ListQuery::parse(request.query_string())?;
+    if query.tree == Some(true) {
+        return todo_tree(app_data, user, request, query).await;
+    }
+    let params = ListParams::try_from(query.clone())?;
+    let page = app_data.repository.list_todos(user.id, &params).await?;
+    let todos = app_data.repository.tag_todos(page.todos).await?;
+
+    let mut response = HttpResponse::Ok();
+    if let Some(next) = page.next {
+        let next_query = match next {
+            Position::Start => query,
+            Position::Offset(offset) => ListQuery {
+                offset: Some(offset),
+                ..query
+            },
+            Position::After(cursor) => {
+                let cursor = cursor.encode();
+                response.insert_header((NEXT_CURSOR, cursor.clone()));
+                ListQuery {
+                    cursor: Some(cursor),
+                    ..query
+                }
+            }
+        };
+        let link = format!("<{}?{}>; rel=\"next\"", request.path(), next_query.encode());
+        response.insert_header((LINK, link));
+    }
+    json_with_etag(&request, response, &todos)
+}
+
+async fn todo_tree(
+    app_data: Data<AppData>,
+    user: AuthUser,
+    request: HttpRequest,
+    query: ListQuery,
+) -> Result<HttpResponse, ApiError> {
+    let tree_only = ListQuery {
+        tree: Some(true),
+        ..ListQuery::default()
+    };
+    if query != tree_only {
+        return Err(ApiError::BadRequest(
+            "Invalid query, tree cannot be combined with other parameters".to_string(),
+        ));
+    }
+    let todos = app_data.repository.todo_tree(user.id).await?;
+    let todos = app_data.repository.tag_todos(todos).await?;
+    json_with_etag(&request, HttpResponse::Ok(), &subtask::tree(todos))
+}
+
+/// Answers with a JSON body tagged with its digest, or with `304 Not Modified`
+/// when the client already holds it.
+fn json_with_etag(
+    request: &HttpRequest,
+    mut response: HttpResponseBuilder,
+    value: &impl Serialize,
+) -> Result<HttpResponse, ApiError> {
+    let body = serde_json::to_vec(value).map_err(InternalError::from)?;
+    let etag = etag::body(&body);
+    if etag::not_modified(request, &etag) {
+        return Ok(response
+            .status(StatusCode::NOT_MODIFIED)
+            .insert_header(ETag(etag))
+            .finish());
+    }
+    let response = response
+        .insert_header(ETag(etag))
+        .content_type(ContentType::json())
+        .body(body);
+    Ok(response)
+}
+
+#[utoipa::path(
+    tag = "todos",
+    request_body = Batch,
+    responses(
+        (status = 200, description = "Result of every operation", body = BatchResult),
+        (status = 400, description = "Malformed or too large batch"),
+    ),
+)]
+#[post("/todos/batch")]
+pub async fn batch_todos(
+    app_data: Data<AppData>,
+    user: AuthUser,
+    batch: Json<Batch>,
+) -> Result<HttpResponse, ApiError> {
+    let Batch { mode, operations } = batch.into_inner();
+    let count = operations.len();
+    if count > MAX_OPERATIONS {
+        return Err(ApiError::BadRequest(format!(
+            "Invalid batch of {count} operations, must hold at most {MAX_OPERATIONS}"
+        )));
+    }
+    let kinds: Vec<_> = operations.iter().map(Operation::kind).collect();
+    let mut invalid = Vec::new();
+    let mut valid = Vec::new();
+    for (index, operation) in operations.into_iter().enumerate() {
+        match operation.validate() {
+            Ok(operation) => valid.push(operation),
+            Err(errors) => invalid.push((index, ApiError::UnprocessableEntity(errors))),
+        }
+    }
+    // An invalid operation fails an atomic batch before any is run
+    if let (BatchMode::Atomic, Some((failed, err))) = (mode, invalid.first()) {
+        let rolled_back = rolled_back(*failed);
+        let results = (0..count)
+            .map(|index| match index == *failed {
+                true => OperationResult::error(err),
+                false => OperationResult::error(&rolled_back),
+            })
+            .collect();
+        let response = HttpResponse::Ok().json(BatchResult {
+            committed: false,
+            results,
+        });
+        return Ok(response);
+    }
+
+    let policy = app_data.config.subtask_delete_policy;
+    let batch = Batch {
+        mode,
+        operations: valid,
+    };
+    let Outcome { committed, results } = app_data
+        .repository
+        .run_batch(user.id, batch, policy)
+        .await?;
+    let mut results: Vec<Result<Updated, ApiError>> = results
+        .into_iter()
+        .map(|result| result.map_err(ApiError::from))
+        .collect();
+    // Invalid operations of best effort batches take back their place
+    for (index, err) in invalid {
+        results.insert(index, Err(err));
+    }
+
+    // Tags of every changed todo and created next occurrence are fetched at once
+    let changed = results
+        .iter()
+        .filter(|_| committed)
+        .filter_map(|result| result.as_ref().ok())
+        .flat_map(|updated| [Some(&updated.todo), updated.next.as_ref()])
+        .flatten()
+        .cloned()
+        .collect();
+    let mut changed = app_data.repository.tag_todos(changed).await?.into_iter();
+    let rolled_back = rolled_back(results.len().saturating_sub(1));
+    let mut results: Vec<OperationResult> = results
+        .into_iter()
+        .zip(kinds)
+        .map(|(result, kind)| match result {
+            Ok(_) if !committed => OperationResult::error(&rolled_back),
+            Ok(updated) => {
+                let todo = changed.next();
+                if let Some(todo) = &todo {
+                    app_data.feed.publish(kind, todo.clone());
+                }
+                let next_occurrence = updated.next.and_then(|_| changed.next());
+                if let Some(next) = &next_occurrence {
+                    app_data.feed.publish(EventKind::Create, next.clone());
+                }
+                OperationResult::ok(todo, next_occurrence)
+            }
+            Err(err) => OperationResult::error(&err),
+        })
+        .collect();
+    results.resize_with(count, || OperationResult::error(&rolled_back));
+
+    let response = HttpResponse::Ok().json(BatchResult { committed, results });
+    Ok(response)
+}
+
+/// The error of the operations of an atomic batch left undone by another.
+fn rolled_back(failed: usize) -> ApiError {
+    ApiError::FailedDependency(format!(
+        "Operation {failed} failed, so the batch was rolled back"
+    ))
+}
+
+/// Downloads every todo of the user as a single file.
+#[utoipa::path(
+    tag = "todos",
+    params(ExportQuery),
+    responses(
+        (status = 200, description = "Every todo, oldest first", content(
+            (Vec<Todo> = "application/json"),
+            (String = "text/csv"),
+            (String = "text/calendar"),
+        )),
+        (status = 400, description = "Unknown format"),
+    ),
+)]
+#[get("/todos/export")]
+pub async fn export_todos(
+    app_data: Data<AppData>,
+    user: AuthUser,
+    query: Query<ExportQuery>,
+) -> Result<HttpResponse, ApiError> {
+    let format = query.format;
+    let todos = app_data.repository.export_todos(user.id).await?;
+    let now = Utc::now().naive_utc().trunc_subsecs(0);
+    let body = transfer::export(format, &todos, now)?;
+    let disposition = ContentDisposition {
+        disposition: DispositionType::Attachment,
+        parameters: vec![DispositionParam::Filename(format!(
+            "todos.{}",
+            format.extension()
+        ))],
+    };
+    let response = HttpResponse::Ok()
+        .insert_header(disposition)
+        .content_type(format.content_type())
+        .body(body);
+    Ok(response)
+}
+
+/// Creates todos from a file, in the same formats as exports. Rows matching an
+/// existing todo are skipped, and either every row is imported or, when some
+/// are invalid, none is.
+#[utoipa::path(
+    tag = "todos",
+    params(ImportQuery),
+    request_body(content(
+        (Vec<CreateTodo> = "application/json"),
+        (String = "text/csv"),
+        (String = "text/calendar"),
+    )),
+    responses(
+        (status = 200, description = "Result of every row", body = ImportResult),
+        (status = 400, description = "Unreadable or too large file"),
+    ),
+)]
+#[post("/todos/import")]
+pub async fn import_todos(
+    app_data: Data<AppData>,
+    user: AuthUser,
+    request: HttpRequest,
+    query: Query<ImportQuery>,
+    payload: Payload,
+) -> Result<HttpResponse, ApiError> {
+    let limit = app_data.config.body_limit;
+    let body = payload
+        .to_bytes_limited(limit)
+        .await
+        .map_err(|_| ApiError::PayloadTooLarge(limit))?
+        .map_err(|err| ApiError::BadRequest(err.to_string()))?;
+    let content_type = request
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok());
+    let format = query
+        .format
+        .or_else(|| content_type.and_then(Format::from_content_type))
+        .ok_or_else(|| {
+            ApiError::BadRequest(
+                "Unknown import format, set the format parameter or Content-Type".to_string(),
+            )
+        })?;
+    let rows = transfer::parse(format, &body)?;
+    let count = rows.len();
+    if count > MAX_ROWS {
+        return Err(ApiError::BadRequest(format!(
+            "Invalid import of {count} rows, must hold at most {MAX_ROWS}"
+        )));
+    }
+    if rows.iter().any(Result::is_err) {
+        let response = HttpResponse::Ok().json(ImportResult::rejected(rows));
+        return Ok(response);
+    }
+
+    let todos = rows.into_iter().flatten().collect();
+    let imported = app_data.repository.import_todos(user.id, todos).await?;
+    let result = ImportResult::committed(imported);
+    let created = result
+        .rows
+        .iter()
+        .filter(|row| row.status == RowStatus::Created)
+        .filter_map(|row| row.todo.clone())
+        .collect();
+    for todo in app_data.repository.tag_todos(created).await? {
+        app_data.feed.publish(EventKind::Create, todo);
+    }
+    let response = HttpResponse::Ok().json(result);
+    Ok(response)
+}
+
+#[utoipa::path(
+    tag = "todos",
+    params(SearchQuery),
+    responses(
+        (status = 200, description = "Matching todos, best first", body = Vec<SearchHit>),
+        (status = 400, description = "Invalid query"),
+    ),
+)]
+#[get("/todos/search")]
+pub async fn search_todos(
+    app_data: Data<AppData>,
+    user: AuthUser,
+    query: Query<SearchQuery>,
+) -> Result<HttpResponse, ApiError> {
+    let params = SearchParams::try_from(query.into_inner())?;
+    let hits = app_data.repository.search_todos(user.id, &params).await?;
+    let response = HttpResponse::Ok().json(hits);
+    Ok(response)
+}
+
+/// Resumes after the change given by the `Last-Event-ID` header, or else by the
+/// `last_event_id` parameter.
+fn last_event_id(request: &HttpRequest, query: &FeedQuery) -> Result<Option<u64>, ApiError> {
+    let Some(header) = request.headers().get(LAST_EVENT_ID) else {
+        return Ok(query.last_event_id);
+    };
+    let id = header.to_str().ok().and_then(|id| id.trim().parse().ok());
+    id.map(Some)
+        .ok_or_else(|| ApiError::BadRequest("Invalid Last-Event-ID header".to_string()))
+}
+
+/// Changes made to the todos of the user, as Server-Sent Events named after
+/// their kind.
+#[utoipa::path(
+    tag = "todos",
+    params(FeedQuery, ("Last-Event-ID" = Option<u64>, Header, description = "Id of the last change received, to resume after")),
+    responses(
+        (status = 200, description = "Stream of changes", body = Change, content_type = "text/event-stream"),
+        (status = 400, description = "Invalid query"),
+    ),
+)]
+#[get("/todos/events")]
+pub async fn todo_events(
+    app_data: Data<AppData>,
+    user: AuthUser,
+    request: HttpRequest,
+    query: Query<FeedQuery>,
+) -> Result<HttpResponse, ApiError> {
+    let last_event_id = last_event_id(&request, &query)?;
+    let filter = Filter::new(user.id, query.todo_id);
+    let subscription = app_data.feed.subscribe(filter, last_event_id);
+    let response = HttpResponse::Ok()
+        .content_type(EVENT_STREAM)
+        .insert_header(CacheControl(vec![CacheDirective::NoCache]))
+        .streaming(feed::events(subscription));
+    Ok(response)
+}
+
+/// Changes made to the todos of the user, as JSON text messages over a
+/// WebSocket. Clients can send a `Follow` message to change which todos they
+/// follow.
+#[utoipa::path(
+    tag = "todos",
+    params(FeedQuery, ("Last-Event-ID" = Option<u64>, Header, description = "Id of the last change received, to resume after")),
+    responses(
+        (status = 101, description = "Switched to a WebSocket sending changes", body = Change),
+        (status = 400, description = "Invalid query or handshake"),
+    ),
+)]
+#[get("/todos/events/ws")]
+pub async fn todo_events_ws(
+    app_data: Data<AppData>,
+    user: AuthUser,
+    request: HttpRequest,
+    query: Query<FeedQuery>,
+    body: web::Payload,
+) -> Result<HttpResponse, ApiError> {
+    let last_event_id = last_event_id(&request, &query)?;
+    let (response, session, messages) =
+        actix_ws::handle(&request, body).map_err(|err| ApiError::BadRequest(err.to_string()))?;
+    let filter = Filter::new(user.id, query.todo_id);
+    let subscription = app_data.feed.subscribe(filter, last_event_id);
+    rt::spawn(feed::forward(subscription, session, messages));
     Ok(response)
 }
 
+#[utoipa::path(
+    tag = "todos",
+    params(("id" = i64, Path, description = "Todo id"), ("If-None-Match" = Option<String>, Header, description = "Entity tags of a representation already known")),
+    responses(
+        (status = 200, description = "Todo", body = TaggedTodo, headers(("ETag" = String, description = "Version of the todo"))),
+        (status = 304, description = "Todo not modified"),
+        (status = 404, description = "Todo not found"),
+    ),
+)]
 #[get("/todos/{id}")]
-pub async fn get_todo(app_data: Data<AppData>, id: Path<i64>) -> Result<HttpResponse, ApiError> {
-    let todo = db::get_todo(&app_data.db_pool, *id).await?;
-    let response = HttpResponse::Ok().json(todo);
+pub async fn get_todo(
+    app_data: Data<AppData>,
+    user: AuthUser,
+    request: HttpRequest,
+    id: Path<i64>,
+) -> Result<HttpResponse, ApiError> {
+    let todo = app_data.repository.get_todo(user.id, *id).await?;
+    let etag = etag::todo(&todo);
+    if etag::not_modified(&request, &etag) {
+        return Ok(HttpResponse::NotModified()
+            .insert_header(ETag(etag))
+            .finish());
+    }
+    let todo = app_data.repository.tag_todo(todo).await?;
+    let response = HttpResponse::Ok().insert_header(ETag(etag)).json(todo);
     Ok(response)
 }
 
+#[utoipa::path(
+    tag = "todos",
+    request_body = CreateTodo,
+    responses(
+        (status = 200, description = "Created todo", body = TaggedTodo, headers(("ETag" = String, description = "Version of the todo"))),
+        (status = 400, description = "Malformed todo"),
+        (status = 422, description = "Invalid todo"),
+    ),
+)]
 #[post("/todos")]
 pub async fn create_todo(
     app_data: Data<AppData>,
+    user: AuthUser,
     todo: Json<CreateTodo>,
 ) -> Result<HttpResponse, ApiError> {
-    let created = db::create_todo(&app_data.db_pool, todo.into_inner()).await?;
-    let response = HttpResponse::Ok().json(created);
+    let todo = todo
+        .into_inner()
+        .validate()
+        .map_err(ApiError::UnprocessableEntity)?;
+    let created = app_data.repository.create_todo(user.id, todo).await?;
+    let etag = etag::todo(&created);
+    let created = app_data.repository.tag_todo(created).await?;
+    app_data.feed.publish(EventKind::Create, created.clone());
+    let response = HttpResponse::Ok().insert_header(ETag(etag)).json(created);
     Ok(response)
 }
 
+#[utoipa::path(
+    tag = "todos",
+    params(("id" = i64, Path, description = "Todo id"), ("If-Match" = Option<String>, Header, description = "Versions of the todo the change is allowed on")),
+    request_body = UpdateTodo,
+    responses(
+        (status = 200, description = "Updated todo, along with the next occurrence it created", body = UpdatedTodo, headers(("ETag" = String, description = "Version of the todo"))),
+        (status = 400, description = "Malformed todo"),
+        (status = 404, description = "Todo not found"),
+        (status = 412, description = "Todo version not matched"),
+        (status = 422, description = "Invalid todo"),
+    ),
+)]
 #[put("/todos/{id}")]
 pub async fn update_todo(
     app_data: Data<AppData>,
+    user: AuthUser,
     id: Path<i64>,
+    if_match: IfMatch,
     todo: Json<UpdateTodo>,
 ) -> Result<HttpResponse, ApiError> {
-    let updated = db::update_todo(&app_data.db_pool, *id, todo.into_inner()).await?;
-    let response = HttpResponse::Ok().json(updated);
+    let todo = todo
+        .into_inner()
+        .validate()
+        .map_err(ApiError::UnprocessableEntity)?;
+    let updated = app_data
+        .repository
+        .update_todo(user.id, *id, todo, &if_match)
+        .await?;
+    updated_response(&app_data, updated).await
+}
+
+#[utoipa::path(
+    tag = "todos",
+    params(("id" = i64, Path, description = "Todo id"), ("If-Match" = Option<String>, Header, description = "Versions of the todo the change is allowed on")),
+    request_body(
+        content = Object,
+        content_type = "application/merge-patch+json",
+        description = "JSON Merge Patch of the todo fields",
+    ),
+    responses(
+        (status = 200, description = "Patched todo, along with the next occurrence it created", body = UpdatedTodo, headers(("ETag" = String, description = "Version of the todo"))),
+        (status = 400, description = "Malformed patch"),
+        (status = 404, description = "Todo not found"),
+        (status = 412, description = "Todo version not matched"),
+        (status = 422, description = "Invalid todo"),
+    ),
+)]
+#[patch("/todos/{id}")]
+pub async fn patch_todo(
+    app_data: Data<AppData>,
+    user: AuthUser,
+    id: Path<i64>,
+    if_match: IfMatch,
+    patch: Json<Value>,
+) -> Result<HttpResponse, ApiError> {
+    let patched = app_data
+        .repository
+        .patch_todo(user.id, *id, &if_match, &|current| {
+            UpdateTodo::patched(current, &patch)?
+                .validate()
+                .map_err(ApiError::UnprocessableEntity)
+        })
+        .await??;
+    updated_response(&app_data, patched).await
+}
+
+/// Answers a change of a todo, publishing it along with the next occurrence it
+/// created, if any.
+async fn updated_response(app_data: &AppData, updated: Updated) -> Result<HttpResponse, ApiError> {
+    let etag = etag::todo(&updated.todo);
+    let todo = app_data.repository.tag_todo(updated.todo).await?;
+    app_data.feed.publish(EventKind::Update, todo.clone());
+    let next_occurrence = match updated.next {
+        Some(next) => {
+            let next = app_data.repository.tag_todo(next).await?;
+            app_data.feed.publish(EventKind::Create, next.clone());
+            Some(next)
+        }
+        None => None,
+    };
+    let response = HttpResponse::Ok()
+        .insert_header(ETag(etag))
+        .json(UpdatedTodo {
+            todo,
+            next_occurrence,
+        });
     Ok(response)
 }
 
+#[utoipa::path(
+    tag = "todos",
+    params(("id" = i64, Path, description = "Todo id"), ("If-Match" = Option<String>, Header, description = "Versions of the todo the change is allowed on")),
+    responses(
+        (status = 200, description = "Todo moved to the trash", body = TaggedTodo),
+        (status = 404, description = "Todo not found"),
+        (status = 409, description = "Todo has subtasks, which the delete policy keeps it from leaving behind"),
+        (status = 412, description = "Todo version not matched"),
+    ),
+)]
 #[delete("/todos/{id}")]
-pub async fn delete_todo(app_data: Data<AppData>, id: Path<i64>) -> Result<HttpResponse, ApiError> {
-    let deleted = db::delete_todo(&app_data.db_pool, *id).await?;
+pub async fn delete_todo(
+    app_data: Data<AppData>,
+    user: AuthUser,
+    id: Path<i64>,
+    if_match: IfMatch,
+) -> Result<HttpResponse, ApiError> {
+    let policy = app_data.config.subtask_delete_policy;
+    let Deleted { todo, subtasks } = app_data
+        .repository
+        .delete_todo(user.id, *id, &if_match, policy)
+        .await?;
+    // Subtasks were either trashed along with the todo or made top-level
+    for subtask in app_data.repository.tag_todos(subtasks).await? {
+        let kind = match subtask.todo.deleted_at {
+            Some(_) => EventKind::Delete,
+            None => EventKind::Update,
+        };
+        app_data.feed.publish(kind, subtask);
+    }
+    let deleted = app_data.repository.tag_todo(todo).await?;
+    app_data.feed.publish(EventKind::Delete, deleted.clone());
     let response = HttpResponse::Ok().json(deleted);
     Ok(response)
 }
 
-#[cfg(test)]
-mod test {
-    use crate::{
-        test::{make_request, BoxBodyTest},
-        todo::{CreateTodo, Todo, UpdateTodo},
-    };
-    use actix_web::{http::StatusCode, test};
-    use sqlx::SqlitePool;
-
-    #[sqlx::test(fixtures("test/fixtures/todos.sql"))]
-    async fn list_todos(pool: SqlitePool) {
-        let request = test::TestRequest::get().uri("/todos");
-        let response = make_request(pool, request).await;
+#[utoipa::path(
+    tag = "todos",
+    params(("id" = i64, Path, description = "Todo id")),
+    responses(
+        (status = 200, description = "Changes made to the todo, oldest first", body = Vec<TodoEvent>),
+        (status = 404, description = "Todo not found"),
+    ),
+)]
+#[get("/todos/{id}/history")]
+pub async fn list_history(
+    app_data: Data<AppData>,
+    user: AuthUser,
+    id: Path<i64>,
+) -> Result<HttpResponse, ApiError> {
+    let events = app_data.repository.list_history(user.id, *id).await?;
+    let response = HttpResponse::Ok().json(events);
+    Ok(response)
+}
 
-        let status_code = response.status();
-        let body: Vec<Todo> = response.into_body().deserialize().await;
-        assert_eq!(status_code, StatusCode::OK);
-        assert_eq!(
-            body,
-            vec![
-                Todo {
-                    id: 1,
-                    title: "todo1".to_string(),
-                    description: "description1".to_string()
-                },
-                Todo {
-                    id: 2,
-                    title: "todo2".to_string(),
-                    description: "description2".to_string()
-                },
-                Todo {
-                    id: 3,
-                    title: "todo3".to_string(),
-                    description: "description3".to_string()
-                }
-            ]
-        );
-    }
+#[utoipa::path(
+    tag = "todos",
+    params(("id" = i64, Path, description = "Todo id"), ("If-Match" = Option<String>, Header, description = "Versions of the todo the change is allowed on")),
+    request_body = Revert,
+    responses(
+        (status = 200, description = "Reverted todo", body = TaggedTodo, headers(("ETag" = String, description = "Version of the todo"))),
+        (status = 400, description = "Malformed version"),
+        (status = 404, description = "Todo not found"),
+        (status = 412, description = "Todo version not matched"),
+        (status = 422, description = "Unknown version"),
+    ),
+)]
+#[post("/todos/{id}/revert")]
+pub async fn revert_todo(
+    app_data: Data<AppData>,
+    user: AuthUser,
+    id: Path<i64>,
+    if_match: IfMatch,
+    revert: Json<Revert>,
+) -> Result<HttpResponse, ApiError> {
+    let reverted = app_data
+        .repository
+        .revert_todo(user.id, *id, revert.version, &if_match)
+        .await?;
+    let etag = etag::todo(&reverted);
+    let reverted = app_data.repository.tag_todo(reverted).await?;
+    app_data.feed.publish(EventKind::Revert, reverted.clone());
+    let response = HttpResponse::Ok().insert_header(ETag(etag)).json(reverted);
+    Ok(response)
+}
 
-    #[sqlx::test]
-    async fn list_todos_empty(pool: SqlitePool) {
-        let request = test::TestRequest::get().uri("/todos");
-        let response = make_request(pool, request).await;
+#[utoipa::path(
+    tag = "todos",
+    responses(
+        (status = 200, description = "Trashed todos, most recently deleted first", body = Vec<TaggedTodo>),
+    ),
+)]
+#[get("/trash")]
+pub async fn list_trash(app_data: Data<AppData>, user: AuthUser) -> Result<HttpResponse, ApiError> {
+    let todos = app_data.repository.list_trash(user.id).await?;
+    let todos = app_data.repository.tag_todos(todos).await?;
+    let response = HttpResponse::Ok().json(todos);
+    Ok(response)
+}
 
-        let status_code = response.status();
-        let body: Vec<Todo> = response.into_body().deserialize().await;
-        assert_eq!(status_code, StatusCode::OK);
-        assert_eq!(body, vec![]);
-    }
+#[utoipa::path(
+    tag = "todos",
+    params(("id" = i64, Path, description = "Todo id")),
+    responses(
+        (status = 200, description = "Restored todo", body = TaggedTodo, headers(("ETag" = String, description = "Version of the todo"))),
+        (status = 404, description = "Todo not found in the trash"),
+    ),
+)]
+#[post("/todos/{id}/restore")]
+pub async fn restore_todo(
+    app_data: Data<AppData>,
+    user: AuthUser,
+    id: Path<i64>,
+) -> Result<HttpResponse, ApiError> {
+    let restored = app_data.repository.restore_todo(user.id, *id).await?;
+    let etag = etag::todo(&restored);
+    let restored = app_data.repository.tag_todo(restored).await?;
+    app_data.feed.publish(EventKind::Restore, restored.clone());
+    let response = HttpResponse::Ok().insert_header(ETag(etag)).json(restored);
+    Ok(response)
+}
 
-    #[sqlx::test(fixtures("test/fixtures/todos.sql"))]
-    async fn get_todo(pool: SqlitePool) {
-        let request = test::TestRequest::get().uri("/todos/2");
-        let response = make_request(pool, request).await;
+#[utoipa::path(
+    tag = "todos",
+    params(("id" = i64, Path, description = "Todo id")),
+    responses(
+        (status = 200, description = "Subtasks of the todo, oldest first", body = Vec<TaggedTodo>),
+        (status = 404, description = "Todo not found"),
+    ),
+)]
+#[get("/todos/{id}/children")]
+pub async fn list_children(
+    app_data: Data<AppData>,
+    user: AuthUser,
+    id: Path<i64>,
+) -> Result<HttpResponse, ApiError> {
+    let children = app_data.repository.list_children(user.id, *id).await?;
+    let children = app_data.repository.tag_todos(children).await?;
+    let response = HttpResponse::Ok().json(children);
+    Ok(response)
+}
 
-        let status_code = response.status();
-        let body: Todo = response.into_body().deserialize().await;
-        assert_eq!(status_code, StatusCode::OK);
-        assert_eq!(
-            body,
-            Todo {
-                id: 2,
-                title: "todo2".to_string(),
-                description: "description2".to_string()
-            }
-        );
+#[utoipa::path(
+    tag = "todos",
+    params(("id" = i64, Path, description = "Todo id"), OccurrenceQuery),
+    responses(
+        (status = 200, description = "Upcoming occurrences of the todo, at most 1000 of them", body = Vec<Occurrence>),
+        (status = 400, description = "Invalid range"),
+        (status = 404, description = "Todo not found"),
+    ),
+)]
+#[get("/todos/{id}/occurrences")]
+pub async fn list_occurrences(
+    app_data: Data<AppData>,
+    user: AuthUser,
+    id: Path<i64>,
+    query: Query<OccurrenceQuery>,
+) -> Result<HttpResponse, ApiError> {
+    let OccurrenceQuery { from, to } = query.into_inner();
+    if from.zip(to).is_some_and(|(from, to)| from > to) {
+        return Err(ApiError::BadRequest(
+            "Invalid range, from must not be after to".to_string(),
+        ));
     }
+    let todo = app_data.repository.get_todo(user.id, *id).await?;
+    let occurrences = recurrence::preview(&todo, from, to);
+    let response = HttpResponse::Ok().json(occurrences);
+    Ok(response)
+}
 
-    #[sqlx::test]
-    async fn get_todo_not_found(pool: SqlitePool) {
-        let request = test::TestRequest::get().uri("/todos/2");
-        let response = make_request(pool, request).await;
+#[utoipa::path(
+    tag = "reminders",
+    params(("id" = i64, Path, description = "Todo id")),
+    responses(
+        (status = 200, description = "Reminders of the todo, oldest first", body = Vec<Reminder>),
+        (status = 404, description = "Todo not found"),
+    ),
+)]
+#[get("/todos/{id}/reminders")]
+pub async fn list_reminders(
+    app_data: Data<AppData>,
+    user: AuthUser,
+    id: Path<i64>,
+) -> Result<HttpResponse, ApiError> {
+    let reminders = app_data.repository.list_reminders(user.id, *id).await?;
+    let response = HttpResponse::Ok().json(reminders);
+    Ok(response)
+}
 
-        let status_code = response.status();
-        let body = response.into_body().as_str().await;
-        assert_eq!(status_code, StatusCode::NOT_FOUND);
-        assert_eq!(body, "Not Found");
-    }
+/// Adds a reminder firing some time before the todo falls due, through the
+/// log, the webhooks of the user and the change feed.
+#[utoipa::path(
+    tag = "reminders",
+    params(("id" = i64, Path, description = "Todo id")),
+    request_body = CreateReminder,
+    responses(
+        (status = 200, description = "Created reminder", body = Reminder),
+        (status = 400, description = "Malformed reminder"),
+        (status = 404, description = "Todo not found"),
+        (status = 422, description = "Invalid reminder"),
+    ),
+)]
+#[post("/todos/{id}/reminders")]
+pub async fn create_reminder(
+    app_data: Data<AppData>,
+    user: AuthUser,
+    id: Path<i64>,
+    reminder: Json<CreateReminder>,
+) -> Result<HttpResponse, ApiError> {
+    let reminder = reminder
+        .into_inner()
+        .validate()
+        .map_err(ApiError::UnprocessableEntity)?;
+    let created = app_data
+        .repository
+        .create_reminder(user.id, *id, reminder)
+        .await?;
+    let response = HttpResponse::Ok().json(created);
+    Ok(response)
+}
 
-    #[sqlx::test]
-    async fn create_todo(pool: SqlitePool) {
-        let request = test::TestRequest::post()
-            .uri("/todos")
-            .set_json(CreateTodo {
-                title: "title".to_string(),
-                description: "description".to_string(),
-            });
-        let response = make_request(pool, request).await;
+#[utoipa::path(
+    tag = "reminders",
+    params(
+        ("id" = i64, Path, description = "Todo id"),
+        ("reminder_id" = i64, Path, description = "Reminder id"),
+    ),
+    responses(
+        (status = 200, description = "Deleted reminder", body = Reminder),
+        (status = 404, description = "Todo or reminder not found"),
+    ),
+)]
+#[delete("/todos/{id}/reminders/{reminder_id}")]
+pub async fn delete_reminder(
+    app_data: Data<AppData>,
+    user: AuthUser,
+    path: Path<(i64, i64)>,
+) -> Result<HttpResponse, ApiError> {
+    let (id, reminder_id) = path.into_inner();
+    let deleted = app_data
+        .repository
+        .delete_reminder(user.id, id, reminder_id)
+        .await?;
+    let response = HttpResponse::Ok().json(deleted);
+    Ok(response)
+}
 
-        let status_code = response.status();
-        let body: Todo = response.into_body().deserialize().await;
-        assert_eq!(status_code, StatusCode::OK);
-        assert_eq!(
-            body,
-            Todo {
-                id: 1,
-                title: "title".to_string(),
-                description: "description".to_string()
-            }
-        );
-    }
+#[utoipa::path(
+    tag = "tags",
+    params(("id" = i64, Path, description = "Todo id")),
+    responses(
+        (status = 200, description = "Tags of the todo", body = Vec<Tag>),
+        (status = 404, description = "Todo not found"),
+    ),
+)]
+#[get("/todos/{id}/tags")]
+pub async fn list_todo_tags(
+    app_data: Data<AppData>,
+    user: AuthUser,
+    id: Path<i64>,
+) -> Result<HttpResponse, ApiError> {
+    let tags = app_data.repository.list_todo_tags(user.id, *id).await?;
+    let response = HttpResponse::Ok().json(tags);
+    Ok(response)
+}
 
-    #[sqlx::test(fixtures("test/fixtures/todos.sql"))]
-    async fn update_todo(pool: SqlitePool) {
-        let request = test::TestRequest::put()
-            .uri("/todos/2")
-            .set_json(UpdateTodo {
-                title: "title".to_string(),
-                description: "description".to_string(),
-            });
-        let response = make_request(pool, request).await;
+#[utoipa::path(
+    tag = "tags",
+    params(("id" = i64, Path, description = "Todo id")),
+    request_body = AttachTag,
+    responses(
+        (status = 200, description = "Tagged todo", body = TaggedTodo),
+        (status = 400, description = "Malformed tag"),
+        (status = 404, description = "Todo or tag not found"),
+    ),
+)]
+#[post("/todos/{id}/tags")]
+pub async fn attach_tag(
+    app_data: Data<AppData>,
+    user: AuthUser,
+    id: Path<i64>,
+    tag: Json<AttachTag>,
+) -> Result<HttpResponse, ApiError> {
+    let todo = app_data
+        .repository
+        .attach_tag(user.id, *id, tag.tag_id)
+        .await?;
+    app_data.feed.publish(EventKind::Update, todo.clone());
+    let response = HttpResponse::Ok().json(todo);
+    Ok(response)
+}
 
-        let status_code = response.status();
-        let body: Todo = response.into_body().deserialize().await;
-        assert_eq!(status_code, StatusCode::OK);
-        assert_eq!(
-            body,
-            Todo {
-                id: 2,
-                title: "title".to_string(),
-                description: "description".to_string()
-            }
-        );
-    }
+#[utoipa::path(
+    tag = "tags",
+    params(
+        ("id" = i64, Path, description = "Todo id"),
+        ("tag_id" = i64, Path, description = "Tag id"),
+    ),
+    responses(
+        (status = 200, description = "Untagged todo", body = TaggedTodo),
+        (status = 404, description = "Todo or tag not found"),
+    ),
+)]
+#[delete("/todos/{id}/tags/{tag_id}")]
+pub async fn detach_tag(
+    app_data: Data<AppData>,
+    user: AuthUser,
+    path: Path<(i64, i64)>,
+) -> Result<HttpResponse, ApiError> {
+    let (id, tag_id) = path.into_inner();
+    let todo = app_data.repository.detach_tag(user.id, id, tag_id).await?;
+    app_data.feed.publish(EventKind::Update, todo.clone());
+    let response = HttpResponse::Ok().json(todo);
+    Ok(response)
+}
 
-    #[sqlx::test]
-    async fn update_todo_not_found(pool: SqlitePool) {
-        let request = test::TestRequest::put()
-            .uri("/todos/999")
-            .set_json(UpdateTodo {
-                title: "title".to_string(),
-                description: "description".to_string(),
-            });
-        let response = make_request(pool, request).await;
+#[utoipa::path(
+    tag = "tags",
+    responses(
+        (status = 200, description = "Tags, by name", body = Vec<Tag>),
+    ),
+)]
+#[get("/tags")]
+pub async fn list_tags(app_data: Data<AppData>, user: AuthUser) -> Result<HttpResponse, ApiError> {
+    let tags = app_data.repository.list_tags(user.id).await?;
+    let response = HttpResponse::Ok().json(tags);
+    Ok(response)
+}
 
-        let status_code = response.status();
-        let body = response.into_body().as_str().await;
-        assert_eq!(status_code, StatusCode::NOT_FOUND);
-        assert_eq!(body, "Not Found");
-    }
+#[utoipa::path(
+    tag = "tags",
+    params(("id" = i64, Path, description = "Tag id")),
+    responses(
+        (status = 200, description = "Tag", body = Tag),
+        (status = 404, description = "Tag not found"),
+    ),
+)]
+#[get("/tags/{id}")]
+pub async fn get_tag(
+    app_data: Data<AppData>,
+    user: AuthUser,
+    id: Path<i64>,
+) -> Result<HttpResponse, ApiError> {
+    let tag = app_data.repository.get_tag(user.id, *id).await?;
+    let response = HttpResponse::Ok().json(tag);
+    Ok(response)
+}
 
-    #[sqlx::test(fixtures("test/fixtures/todos.sql"))]
-    async fn delete_todo(pool: SqlitePool) {
-        let request = test::TestRequest::delete().uri("/todos/2");
-        let response = make_request(pool, request).await;
+#[utoipa::path(
+    tag = "tags",
+    request_body = CreateTag,
+    responses(
+        (status = 200, description = "Created tag", body = Tag),
+        (status = 400, description = "Malformed tag"),
+        (status = 409, description = "Tag name already taken"),
+        (status = 422, description = "Invalid tag"),
+    ),
+)]
+#[post("/tags")]
+pub async fn create_tag(
+    app_data: Data<AppData>,
+    user: AuthUser,
+    tag: Json<CreateTag>,
+) -> Result<HttpResponse, ApiError> {
+    let tag = tag
+        .into_inner()
+        .validate()
+        .map_err(ApiError::UnprocessableEntity)?;
+    let created = app_data.repository.create_tag(user.id, tag).await?;
+    let response = HttpResponse::Ok().json(created);
+    Ok(response)
+}
 
-        let status_code = response.status();
-        let body: Todo = response.into_body().deserialize().await;
-        assert_eq!(status_code, StatusCode::OK);
-        assert_eq!(
-            body,
-            Todo {
-                id: 2,
-                title: "todo2".to_string(),
-                description: "description2".to_string()
-            }
-        );
-    }
+#[utoipa::path(
+    tag = "tags",
+    params(("id" = i64, Path, description = "Tag id")),
+    request_body = UpdateTag,
+    responses(
+        (status = 200, description = "Updated tag", body = Tag),
+        (status = 400, description = "Malformed tag"),
+        (status = 404, description = "Tag not found"),
+        (status = 409, description = "Tag name already taken"),
+        (status = 422, description = "Invalid tag"),
+    ),
+)]
+#[put("/tags/{id}")]
+pub async fn update_tag(
+    app_data: Data<AppData>,
+    user: AuthUser,
+    id: Path<i64>,
+    tag: Json<UpdateTag>,
+) -> Result<HttpResponse, ApiError> {
+    let tag = tag
+        .into_inner()
+        .validate()
+        .map_err(ApiError::UnprocessableEntity)?;
+    let updated = app_data.repository.update_tag(user.id, *id, tag).await?;
+    publish_tagged(&app_data, updated.todos).await?;
+    let response = HttpResponse::Ok().json(updated.tag);
+    Ok(response)
+}
 
-    #[sqlx::test]
+#[utoipa::path(
+    tag = "tags",
+    params(("id" = i64, Path, description = "Tag id")),
+    responses(
+        (status = 200, description = "Deleted tag", body = Tag),
+        (status = 404, description = "Tag not found"),
+    ),
+)]
+#[delete("/tags/{id}")]
+pub async fn delete_tag(
+    app_data: Data<AppData>,
+    user: AuthUser,
+    id: Path<i64>,
+) -> Result<HttpResponse, ApiError> {
+    let deleted = app_data.repository.delete_tag(user.id, *id).await?;
+    publish_tagged(&app_data, deleted.todos).await?;
+    let response = HttpResponse::Ok().json(deleted.tag);
+    Ok(response)
+}
+
+/// Publishes the todos whose version a change to one of their tags bumped.
+async fn publish_tagged(app_data: &AppData, todos: Vec<Todo>) -> Result<(), ApiError> {
+    for todo in app_data.repository.tag_todos(todos).await? {
+        app_data.feed.publish(EventKind::Update, todo);
+    }
+    Ok(())
+}
+
+#[utoipa::path(
+    tag = "webhooks",
+    responses((status = 200, description = "Webhooks of the user", body = Vec<Webhook>)),
+)]
+#[get("/webhooks")]
+pub async fn list_webhooks(
+    app_data: Data<AppData>,
+    user: AuthUser,
+) -> Result<HttpResponse, ApiError> {
+    let webhooks = app_data.repository.list_webhooks(user.id).await?;
+    let response = HttpResponse::Ok().json(webhooks);
+    Ok(response)
+}
+
+#[utoipa::path(
+    tag = "webhooks",
+    params(("id" = i64, Path, description = "Webhook id")),
+    responses(
+        (status = 200, description = "Webhook", body = Webhook),
+        (status = 404, description = "Webhook not found"),
+    ),
+)]
+#[get("/webhooks/{id}")]
+pub async fn get_webhook(
+    app_data: Data<AppData>,
+    user: AuthUser,
+    id: Path<i64>,
+) -> Result<HttpResponse, ApiError> {
+    let webhook = app_data.repository.get_webhook(user.id, *id).await?;
+    let response = HttpResponse::Ok().json(webhook);
+    Ok(response)
+}
+
+/// Registers a URL to post the events of the user's todos to, signed with a
+/// secret generated for it.
+#[utoipa::path(
+    tag = "webhooks",
+    request_body = CreateWebhook,
+    responses(
+        (status = 200, description = "Created webhook, along with its secret", body = CreatedWebhook),
+        (status = 400, description = "Malformed webhook"),
+        (status = 422, description = "Invalid webhook"),
+    ),
+)]
+#[post("/webhooks")]
+pub async fn create_webhook(
+    app_data: Data<AppData>,
+    user: AuthUser,
+    webhook: Json<CreateWebhook>,
+) -> Result<HttpResponse, ApiError> {
+    let webhook = webhook
+        .into_inner()
+        .validate()
+        .map_err(ApiError::UnprocessableEntity)?;
+    if !app_data.config.webhook_policy.permits_url(&webhook.url) {
+        let error = FieldError::new("url", "must not be a denied address");
+        return Err(ApiError::UnprocessableEntity(vec![error]));
+    }
+    let secret = auth::random_secret();
+    let created = app_data
+        .repository
+        .create_webhook(user.id, webhook, &secret)
+        .await?;
+    let response = HttpResponse::Ok().json(CreatedWebhook {
+        webhook: created,
+        secret,
+    });
+    Ok(response)
+}
+
+#[utoipa::path(
+    tag = "webhooks",
+    params(("id" = i64, Path, description = "Webhook id")),
+    responses(
+        (status = 200, description = "Deleted webhook", body = Webhook),
+        (status = 404, description = "Webhook not found"),
+    ),
+)]
+#[delete("/webhooks/{id}")]
+pub async fn delete_webhook(
+    app_data: Data<AppData>,
+    user: AuthUser,
+    id: Path<i64>,
+) -> Result<HttpResponse, ApiError> {
+    let deleted = app_data.repository.delete_webhook(user.id, *id).await?;
+    let response = HttpResponse::Ok().json(deleted);
+    Ok(response)
+}
+
+#[utoipa::path(
+    tag = "webhooks",
+    params(("id" = i64, Path, description = "Webhook id")),
+    responses(
+        (status = 200, description = "Deliveries given up after too many failed attempts, oldest first", body = Vec<Delivery>),
+        (status = 404, description = "Webhook not found"),
+    ),
+)]
+#[get("/webhooks/{id}/dead-letters")]
+pub async fn list_dead_letters(
+    app_data: Data<AppData>,
+    user: AuthUser,
+    id: Path<i64>,
+) -> Result<HttpResponse, ApiError> {
+    let deliveries = app_data.repository.list_dead_letters(user.id, *id).await?;
+    let response = HttpResponse::Ok().json(deliveries);
+    Ok(response)
+}
+
+#[utoipa::path(
+    tag = "webhooks",
+    params(("id" = i64, Path, description = "Webhook id"), ("delivery_id" = i64, Path, description = "Delivery id")),
+    responses(
+        (status = 200, description = "Delivery queued again", body = Delivery),
+        (status = 404, description = "Dead letter not found"),
+    ),
+)]
+#[post("/webhooks/{id}/dead-letters/{delivery_id}/retry")]
+pub async fn retry_dead_letter(
+    app_data: Data<AppData>,
+    user: AuthUser,
+    path: Path<(i64, i64)>,
+) -> Result<HttpResponse, ApiError> {
+    let (id, delivery_id) = path.into_inner();
+    let delivery = app_data
+        .repository
+        .retry_dead_letter(user.id, id, delivery_id)
+        .await?;
+    let response = HttpResponse::Ok().json(delivery);
+    Ok(response)
+}
+
+#[get("/openapi.json")]
+pub async fn openapi() -> HttpResponse {
+    HttpResponse::Ok().json(ApiDoc::openapi())
+}
+
+/// Interactive documentation of the API, rendered from `/openapi.json`.
+#[get("/docs")]
+pub async fn docs() -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(include_str!("../static/docs.html"))
+}
+
+/// Assets of Swagger UI 5.17.14, served along with the documentation rather
+/// than from a CDN.
+#[get("/docs/{file}")]
+pub async fn docs_asset(file: Path<String>) -> Result<HttpResponse, ApiError> {
+    let (content_type, body): (_, &'static str) = match file.as_str() {
+        "swagger-ui.css" => (
+            "text/css; charset=utf-8",
+            include_str!("../static/swagger-ui/swagger-ui.css"),
+        ),
+        "swagger-ui-bundle.js" => (
+            "text/javascript; charset=utf-8",
+            include_str!("../static/swagger-ui/swagger-ui-bundle.js"),
+        ),
+        _ => return Err(ApiError::NotFound),
+    };
+    let response = HttpResponse::Ok()
+        .content_type(content_type)
+        .insert_header(CacheControl(vec![CacheDirective::MaxAge(24 * 60 * 60)]))
+        .body(body);
+    Ok(response)
+}
+
+pub async fn not_found() -> Result<HttpResponse, ApiError> {
+    Err(ApiError::NotFound)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        batch::BatchResult,
+        config::Config,
+        error::{FieldError, Problem, PROBLEM_JSON},
+        feed::{Change, Follow},
+        health::{self, Health},
+        history::{EventKind, Revert, TodoEvent},
+        memory::MemoryRepository,
+        openapi::ApiDoc,
+        recurrence::UpdatedTodo,
+        reminder::Reminder,
+        repository::{SqliteRepository, TodoRepository},
+        routes::{LAST_EVENT_ID, NEXT_CURSOR},
+        search::SearchHit,
+        subtask::{DeletePolicy, TodoNode},
+        tag::{AttachTag, CreateTag, Tag, TaggedTodo, UpdateTag, NAME_TAKEN},
+        test::{
+            bearer, fixture_todos, make_request, make_requests, problem, repositories, server,
+            test_config, timestamp, BoxBodyTest, ALICE, BOB,
+        },
+        todo::{Todo, UpdateTodo},
+        transfer::{ImportResult, RowStatus},
+        user::{Token, User, USERNAME_TAKEN},
+        webhook::{CreateWebhook, CreatedWebhook, Delivery, Payload, Webhook},
+    };
+    use actix_web::{
+        body::to_bytes,
+        dev::ServiceResponse,
+        http::{
+            header::{
+                ACCESS_CONTROL_ALLOW_METHODS, ACCESS_CONTROL_ALLOW_ORIGIN,
+                ACCESS_CONTROL_EXPOSE_HEADERS, ACCESS_CONTROL_REQUEST_HEADERS,
+                ACCESS_CONTROL_REQUEST_METHOD, AUTHORIZATION, CACHE_CONTROL, CONNECTION,
+                CONTENT_DISPOSITION, CONTENT_TYPE, ETAG, IF_MATCH, IF_NONE_MATCH, LINK, ORIGIN,
+                RETRY_AFTER, SEC_WEBSOCKET_ACCEPT, SEC_WEBSOCKET_KEY, SEC_WEBSOCKET_VERSION,
+                UPGRADE, WWW_AUTHENTICATE,
+            },
+            Method, StatusCode,
+        },
+        test,
+    };
+    use awc::{
+        error::WsProtocolError,
+        ws::{self, Frame},
+    };
+    use futures_util::{future::join_all, SinkExt, Stream, StreamExt};
+    use serde::de::DeserializeOwned;
+    use serde_json::{json, Value};
+    use sqlx::SqlitePool;
+    use std::{
+        collections::{HashMap, HashSet},
+        sync::Arc,
+    };
+    use tokio::task::LocalSet;
+    use utoipa::OpenApi;
+
+    #[sqlx::test(fixtures("test/fixtures/users.sql", "test/fixtures/todos.sql"))]
+    async fn list_todos(pool: SqlitePool) {
+        for repository in repositories(pool).await {
+            let request = test::TestRequest::get()
+                .insert_header(bearer(ALICE))
+                .uri("/todos");
+            let response = make_request(repository.clone(), request).await;
+
+            let status_code = response.status();
+            let body: Vec<Todo> = response.into_body().deserialize().await;
+            assert_eq!(status_code, StatusCode::OK);
+            assert_eq!(body, fixture_todos());
+        }
+    }
+
+    #[sqlx::test]
+    async fn list_todos_empty(pool: SqlitePool) {
+        for repository in repositories(pool).await {
+            let request = test::TestRequest::get()
+                .insert_header(bearer(ALICE))
+                .uri("/todos");
+            let response = make_request(repository.clone(), request).await;
+
+            let status_code = response.status();
+            let body: Vec<Todo> = response.into_body().deserialize().await;
+            assert_eq!(status_code, StatusCode::OK);
+            assert_eq!(body, vec![]);
+        }
+    }
+
+    #[sqlx::test(fixtures("test/fixtures/users.sql", "test/fixtures/todos.sql"))]
+    async fn list_todos_paginated(pool: SqlitePool) {
+        for repository in repositories(pool).await {
+            let request = test::TestRequest::get()
+                .insert_header(bearer(ALICE))
+                .uri("/todos?limit=2&sort=-id");
+            let response = make_request(repository.clone(), request).await;
+
+            let status_code = response.status();
+            let cursor = response
+                .headers()
+                .get(NEXT_CURSOR)
+                .unwrap()
+                .to_str()
+                .unwrap();
+            let link = response.headers().get(LINK).unwrap().to_str().unwrap();
+            assert_eq!(
+                link,
+                format!("</todos?limit=2&cursor={cursor}&sort=-id>; rel=\"next\"")
+            );
+            let uri = format!("/todos?limit=2&sort=-id&cursor={cursor}");
+            let body: Vec<Todo> = response.into_body().deserialize().await;
+            assert_eq!(status_code, StatusCode::OK);
+            let ids: Vec<i64> = body.iter().map(|todo| todo.id).collect();
+            assert_eq!(ids, vec![3, 2]);
+
+            let request = test::TestRequest::get()
+                .insert_header(bearer(ALICE))
+                .uri(&uri);
+            let response = make_request(repository.clone(), request).await;
+
+            let status_code = response.status();
+            assert!(response.headers().get(LINK).is_none());
+            let body: Vec<Todo> = response.into_body().deserialize().await;
+            assert_eq!(status_code, StatusCode::OK);
+            let ids: Vec<i64> = body.iter().map(|todo| todo.id).collect();
+            assert_eq!(ids, vec![1]);
+        }
+    }
+
+    #[sqlx::test(fixtures("test/fixtures/users.sql", "test/fixtures/todos.sql"))]
+    async fn list_todos_offset(pool: SqlitePool) {
+        for repository in repositories(pool).await {
+            let request = test::TestRequest::get()
+                .insert_header(bearer(ALICE))
+                .uri("/todos?limit=1&offset=1&title=todo");
+            let response = make_request(repository.clone(), request).await;
+
+            let status_code = response.status();
+            let link = response.headers().get(LINK).unwrap().to_str().unwrap();
+            assert_eq!(link, "</todos?limit=1&offset=2&title=todo>; rel=\"next\"");
+            assert!(response.headers().get(NEXT_CURSOR).is_none());
+            let body: Vec<Todo> = response.into_body().deserialize().await;
+            assert_eq!(status_code, StatusCode::OK);
+            let ids: Vec<i64> = body.iter().map(|todo| todo.id).collect();
+            assert_eq!(ids, vec![2]);
+        }
+    }
+
+    #[sqlx::test(fixtures("test/fixtures/users.sql", "test/fixtures/todos.sql"))]
+    async fn list_todos_overdue(pool: SqlitePool) {
+        for repository in repositories(pool).await {
+            let request = test::TestRequest::get()
+                .insert_header(bearer(ALICE))
+                .uri("/todos?completed=false&due_before=2024-07-01T00:00:00Z&sort=-due_at");
+            let response = make_request(repository.clone(), request).await;
+
+            let status_code = response.status();
+            let body: Vec<Todo> = response.into_body().deserialize().await;
+            assert_eq!(status_code, StatusCode::OK);
+            assert_eq!(body, vec![fixture_todos()[2].clone()]);
+        }
+    }
+
+    #[sqlx::test]
+    async fn list_todos_bad_request(pool: SqlitePool) {
+        for repository in repositories(pool).await {
+            for uri in [
+                "/todos?limit=abc",
+                "/todos?limit=0",
+                "/todos?sort=name",
+                "/todos?cursor=invalid",
+                "/todos?offset=1&cursor=invalid",
+                "/todos?completed=maybe",
+                "/todos?due_before=yesterday",
+            ] {
+                let request = test::TestRequest::get()
+                    .insert_header(bearer(ALICE))
+                    .uri(uri);
+                let response = make_request(repository.clone(), request).await;
+                assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{uri}");
+            }
+        }
+    }
+
+    #[sqlx::test(fixtures("test/fixtures/users.sql", "test/fixtures/todos.sql"))]
+    async fn get_todo(pool: SqlitePool) {
+        for repository in repositories(pool).await {
+            let request = test::TestRequest::get()
+                .insert_header(bearer(ALICE))
+                .uri("/todos/2");
+            let response = make_request(repository.clone(), request).await;
+
+            let status_code = response.status();
+            let body: Todo = response.into_body().deserialize().await;
+            assert_eq!(status_code, StatusCode::OK);
+            assert_eq!(body, fixture_todos()[1]);
+        }
+    }
+
+    #[sqlx::test]
+    async fn get_todo_not_found(pool: SqlitePool) {
+        for repository in repositories(pool).await {
+            let request = test::TestRequest::get()
+                .insert_header(bearer(ALICE))
+                .uri("/todos/2");
+            let response = make_request(repository.clone(), request).await;
+
+            let status_code = response.status();
+            let body: Problem = response.into_body().deserialize().await;
+            assert_eq!(status_code, StatusCode::NOT_FOUND);
+            assert_eq!(body, problem(StatusCode::NOT_FOUND));
+        }
+    }
+
+    #[sqlx::test(fixtures("test/fixtures/users.sql"))]
+    async fn create_todo(pool: SqlitePool) {
+        for repository in repositories(pool).await {
+            let request = test::TestRequest::post()
+                .insert_header(bearer(ALICE))
+                .uri("/todos")
+                .set_json(json!({
+                    "title": "title",
+                    "description": "description",
+                    "due_at": "2024-06-30T20:00:00+02:00",
+                }));
+            let response = make_request(repository.clone(), request).await;
+
+            let status_code = response.status();
+            let body: Todo = response.into_body().deserialize().await;
+            assert_eq!(status_code, StatusCode::OK);
+            assert_eq!(
+                body,
+                Todo {
+                    id: 1,
+                    user_id: Some(ALICE),
+                    title: "title".to_string(),
+                    description: "description".to_string(),
+                    completed: false,
+                    due_at: Some(timestamp("2024-06-30 18:00:00")),
+                    created_at: body.created_at,
+                    updated_at: body.created_at,
+                    version: 1,
+                    deleted_at: None,
+                    parent_id: None,
+                    recurrence: None,
+                }
+            );
+        }
+    }
+
+    #[sqlx::test(fixtures("test/fixtures/users.sql"))]
+    async fn create_todo_trimmed(pool: SqlitePool) {
+        for repository in repositories(pool).await {
+            let request = test::TestRequest::post()
+                .insert_header(bearer(ALICE))
+                .uri("/todos")
+                .set_json(json!({
+                    "title": "  title ",
+                    "description": "\tdescription\n",
+                }));
+            let response = make_request(repository.clone(), request).await;
+
+            let status_code = response.status();
+            let body: Todo = response.into_body().deserialize().await;
+            assert_eq!(status_code, StatusCode::OK);
+            assert_eq!(body.title, "title");
+            assert_eq!(body.description, "description");
+        }
+    }
+
+    #[sqlx::test]
+    async fn create_todo_invalid(pool: SqlitePool) {
+        for repository in repositories(pool).await {
+            let request = test::TestRequest::post()
+                .insert_header(bearer(ALICE))
+                .uri("/todos")
+                .set_json(json!({
+                    "title": " ",
+                    "description": "d".repeat(201),
+                }));
+            let response = make_request(repository.clone(), request).await;
+
+            let status_code = response.status();
+            let content_type = response.headers().get(CONTENT_TYPE).unwrap().clone();
+            let body: Problem = response.into_body().deserialize().await;
+            assert_eq!(status_code, StatusCode::UNPROCESSABLE_ENTITY);
+            assert_eq!(content_type, PROBLEM_JSON);
+            assert_eq!(
+                body,
+                Problem {
+                    detail: Some("One or more fields are invalid".to_string()),
+                    errors: vec![
+                        FieldError::new("title", "must not be empty"),
+                        FieldError::new("description", "must be at most 200 characters long"),
+                    ],
+                    ..problem(StatusCode::UNPROCESSABLE_ENTITY)
+                }
+            );
+        }
+    }
+
+    #[sqlx::test]
+    async fn create_todo_malformed(pool: SqlitePool) {
+        for repository in repositories(pool).await {
+            let request = test::TestRequest::post()
+                .insert_header(bearer(ALICE))
+                .uri("/todos")
+                .insert_header((CONTENT_TYPE, "application/json"))
+                .set_payload(r#"{"title": "title""#);
+            let response = make_request(repository.clone(), request).await;
+
+            let status_code = response.status();
+            let body: Problem = response.into_body().deserialize().await;
+            assert_eq!(status_code, StatusCode::BAD_REQUEST);
+            assert_eq!(body.status, 400);
+            assert_eq!(body.title, "Bad Request");
+            assert!(body.detail.is_some());
+        }
+    }
+
+    #[sqlx::test(fixtures("test/fixtures/users.sql", "test/fixtures/todos.sql"))]
+    async fn update_todo(pool: SqlitePool) {
+        for repository in repositories(pool).await {
+            let request = test::TestRequest::put()
+                .insert_header(bearer(ALICE))
+                .uri("/todos/2")
+                .set_json(UpdateTodo {
+                    title: "title".to_string(),
+                    description: "description".to_string(),
+                    completed: true,
+                    due_at: None,
+                    parent_id: None,
+                    recurrence: None,
+                });
+            let response = make_request(repository.clone(), request).await;
+
+            let status_code = response.status();
+            let body: Todo = response.into_body().deserialize().await;
+            assert_eq!(status_code, StatusCode::OK);
+            assert_eq!(
+                body,
+                Todo {
+                    id: 2,
+                    user_id: Some(ALICE),
+                    title: "title".to_string(),
+                    description: "description".to_string(),
+                    completed: true,
+                    due_at: None,
+                    created_at: fixture_todos()[1].created_at,
+                    updated_at: body.updated_at,
+                    version: 2,
+                    deleted_at: None,
+                    parent_id: None,
+                    recurrence: None,
+                }
+            );
+        }
+    }
+
+    #[sqlx::test]
+    async fn update_todo_not_found(pool: SqlitePool) {
+        for repository in repositories(pool).await {
+            let request = test::TestRequest::put()
+                .insert_header(bearer(ALICE))
+                .uri("/todos/999")
+                .set_json(UpdateTodo {
+                    title: "title".to_string(),
+                    description: "description".to_string(),
+                    completed: false,
+                    due_at: None,
+                    parent_id: None,
+                    recurrence: None,
+                });
+            let response = make_request(repository.clone(), request).await;
+
+            let status_code = response.status();
+            let body: Problem = response.into_body().deserialize().await;
+            assert_eq!(status_code, StatusCode::NOT_FOUND);
+            assert_eq!(body, problem(StatusCode::NOT_FOUND));
+        }
+    }
+
+    #[sqlx::test(fixtures("test/fixtures/users.sql", "test/fixtures/todos.sql"))]
+    async fn patch_todo(pool: SqlitePool) {
+        for repository in repositories(pool).await {
+            let request = test::TestRequest::patch()
+                .insert_header(bearer(ALICE))
+                .uri("/todos/2")
+                .insert_header((CONTENT_TYPE, "application/merge-patch+json"))
+                .set_payload(r#"{"title": "title", "due_at": null}"#);
+            let response = make_request(repository.clone(), request).await;
+
+            let status_code = response.status();
+            let body: Todo = response.into_body().deserialize().await;
+            assert_eq!(status_code, StatusCode::OK);
+            assert_eq!(
+                body,
+                Todo {
+                    title: "title".to_string(),
+                    due_at: None,
+                    updated_at: body.updated_at,
+                    version: 2,
+                    ..fixture_todos()[1].clone()
+                }
+            );
+        }
+    }
+
+    #[sqlx::test(fixtures("test/fixtures/users.sql", "test/fixtures/todos.sql"))]
+    async fn patch_todo_bad_request(pool: SqlitePool) {
+        for repository in repositories(pool).await {
+            for (patch, detail) in [
+                (json!({"title": null}), "missing field `title`"),
+                (json!({"titel": "title"}), "unknown field `titel`"),
+            ] {
+                let request = test::TestRequest::patch()
+                    .insert_header(bearer(ALICE))
+                    .uri("/todos/2")
+                    .set_json(patch);
+                let response = make_request(repository.clone(), request).await;
+
+                let status_code = response.status();
+                let body: Problem = response.into_body().deserialize().await;
+                assert_eq!(status_code, StatusCode::BAD_REQUEST);
+                assert_eq!(body.detail.as_deref(), Some(detail));
+            }
+            let todo = repository.get_todo(ALICE, 2).await.unwrap();
+            assert_eq!(todo, fixture_todos()[1]);
+        }
+    }
+
+    #[sqlx::test(fixtures("test/fixtures/users.sql", "test/fixtures/todos.sql"))]
+    async fn patch_todo_concurrent(pool: SqlitePool) {
+        LocalSet::new()
+            .run_until(async move {
+                for repository in repositories(pool).await {
+                    let server = server(repository.clone());
+                    let client = awc::Client::new();
+                    // Without If-Match, changes landing while a patch is
+                    // applied are no reason to refuse it
+                    let patches = (0..10).map(|i| {
+                        let request = client
+                            .patch(server.url("/todos/2"))
+                            .insert_header(bearer(ALICE));
+                        async move {
+                            let patch = json!({"description": format!("description{i}")});
+                            request.send_json(&patch).await.unwrap().status()
+                        }
+                    });
+                    let statuses = join_all(patches).await;
+                    assert_eq!(statuses, vec![StatusCode::OK; 10]);
+
+                    let todo = repository.get_todo(ALICE, 2).await.unwrap();
+                    assert_eq!(todo.version, 11);
+                }
+            })
+            .await;
+    }
+
+    #[sqlx::test(fixtures("test/fixtures/users.sql", "test/fixtures/todos.sql"))]
+    async fn patch_todo_invalid(pool: SqlitePool) {
+        for repository in repositories(pool).await {
+            let request = test::TestRequest::patch()
+                .insert_header(bearer(ALICE))
+                .uri("/todos/2")
+                .set_json(json!({"title": "t".repeat(21)}));
+            let response = make_request(repository.clone(), request).await;
+
+            let status_code = response.status();
+            let body: Problem = response.into_body().deserialize().await;
+            assert_eq!(status_code, StatusCode::UNPROCESSABLE_ENTITY);
+            assert_eq!(
+                body.errors,
+                vec![FieldError::new(
+                    "title",
+                    "must be at most 20 characters long"
+                )]
+            );
+        }
+    }
+
+    #[sqlx::test]
+    async fn patch_todo_not_found(pool: SqlitePool) {
+        for repository in repositories(pool).await {
+            let request = test::TestRequest::patch()
+                .insert_header(bearer(ALICE))
+                .uri("/todos/999")
+                .set_json(json!({"completed": true}));
+            let response = make_request(repository.clone(), request).await;
+
+            let status_code = response.status();
+            let body: Problem = response.into_body().deserialize().await;
+            assert_eq!(status_code, StatusCode::NOT_FOUND);
+            assert_eq!(body, problem(StatusCode::NOT_FOUND));
+        }
+    }
+
+    #[sqlx::test(fixtures("test/fixtures/users.sql", "test/fixtures/todos.sql"))]
+    async fn delete_todo(pool: SqlitePool) {
+        for repository in repositories(pool).await {
+            let request = test::TestRequest::delete()
+                .insert_header(bearer(ALICE))
+                .uri("/todos/2");
+            let response = make_request(repository.clone(), request).await;
+
+            let status_code = response.status();
+            let body: Todo = response.into_body().deserialize().await;
+            assert_eq!(status_code, StatusCode::OK);
+            assert_eq!(
+                body,
+                Todo {
+                    version: 2,
+                    deleted_at: body.deleted_at,
+                    ..fixture_todos()[1].clone()
+                }
+            );
+            assert!(body.deleted_at.is_some());
+        }
+    }
+
+    #[sqlx::test]
     async fn delete_todo_not_found(pool: SqlitePool) {
-        let request = test::TestRequest::delete().uri("/todos/999");
-        let response = make_request(pool, request).await;
+        for repository in repositories(pool).await {
+            let request = test::TestRequest::delete()
+                .insert_header(bearer(ALICE))
+                .uri("/todos/999");
+            let response = make_request(repository.clone(), request).await;
+
+            let status_code = response.status();
+            let body: Problem = response.into_body().deserialize().await;
+            assert_eq!(status_code, StatusCode::NOT_FOUND);
+            assert_eq!(body, problem(StatusCode::NOT_FOUND));
+        }
+    }
+
+    #[sqlx::test]
+    async fn unknown_route(pool: SqlitePool) {
+        for repository in repositories(pool).await {
+            for uri in ["/unknown", "/todos/abc"] {
+                let request = test::TestRequest::get()
+                    .insert_header(bearer(ALICE))
+                    .uri(uri);
+                let response = make_request(repository.clone(), request).await;
+
+                let status_code = response.status();
+                let body: Problem = response.into_body().deserialize().await;
+                assert_eq!(status_code, StatusCode::NOT_FOUND, "{uri}");
+                assert_eq!(body, problem(StatusCode::NOT_FOUND), "{uri}");
+            }
+        }
+    }
+
+    #[sqlx::test]
+    async fn health_probes(pool: SqlitePool) {
+        for repository in repositories(pool).await {
+            for (uri, status) in [("/healthz", health::LIVE), ("/readyz", health::READY)] {
+                let request = test::TestRequest::get().uri(uri);
+                let response = make_request(repository.clone(), request).await;
+
+                let status_code = response.status();
+                let body: Health = response.into_body().deserialize().await;
+                assert_eq!(status_code, StatusCode::OK, "{uri}");
+                assert_eq!(body, Health::new(status), "{uri}");
+            }
+        }
+    }
+
+    #[sqlx::test(fixtures("test/fixtures/users.sql", "test/fixtures/todos.sql"))]
+    async fn metrics(pool: SqlitePool) {
+        let max_connections = pool.options().get_max_connections();
+        let [sqlite, memory] = repositories(pool).await;
+        let requests = vec![
+            test::TestRequest::get()
+                .uri("/todos/1")
+                .insert_header(bearer(ALICE)),
+            test::TestRequest::get()
+                .uri("/todos/2")
+                .insert_header(bearer(ALICE)),
+            test::TestRequest::get()
+                .uri("/todos/1")
+                .insert_header(bearer(BOB)),
+            test::TestRequest::get().uri("/nowhere"),
+            test::TestRequest::get().uri("/metrics"),
+        ];
+        let mut responses = make_requests(sqlite, test_config(), requests).await;
+        let response = responses.pop().unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers().get(CONTENT_TYPE).unwrap(),
+            prometheus::TEXT_FORMAT
+        );
+        let body = test::read_body(response).await;
+        let body = std::str::from_utf8(&body).unwrap();
+        let lines: HashSet<_> = body.lines().collect();
+        for line in [
+            r#"http_requests_total{method="GET",route="/todos/{id}",status="2xx"} 2"#,
+            r#"http_requests_total{method="GET",route="/todos/{id}",status="4xx"} 1"#,
+            r#"http_requests_total{method="GET",route="unmatched",status="4xx"} 1"#,
+            r#"http_request_duration_seconds_count{method="GET",route="/todos/{id}"} 3"#,
+        ] {
+            assert!(lines.contains(line), "{line} not in {body}");
+        }
+        let line = format!("db_pool_max_connections {max_connections}");
+        assert!(lines.contains(line.as_str()), "{line} not in {body}");
+        assert!(body.contains("db_pool_connections "), "{body}");
+        assert!(body.contains("db_pool_idle_connections "), "{body}");
+
+        // Only repositories with a pool report its connections
+        let request = test::TestRequest::get().uri("/metrics");
+        let response = make_request(memory, request).await;
+        let body = test::read_body(response).await;
+        let body = std::str::from_utf8(&body).unwrap();
+        assert!(body.contains("db_pool_max_connections 0"), "{body}");
+    }
+
+    #[sqlx::test(fixtures("test/fixtures/users.sql", "test/fixtures/todos.sql"))]
+    async fn rate_limit(pool: SqlitePool) {
+        let config = Config {
+            rate_limit_burst: 2,
+            ..test_config()
+        };
+        for repository in repositories(pool).await {
+            let alice = || {
+                test::TestRequest::get()
+                    .uri("/todos/1")
+                    .insert_header(bearer(ALICE))
+                    .peer_addr("10.0.0.1:1234".parse().unwrap())
+            };
+            let anonymous = || {
+                test::TestRequest::get()
+                    .uri("/todos/1")
+                    .peer_addr("10.0.0.1:1234".parse().unwrap())
+            };
+            let requests = vec![
+                alice(),
+                alice(),
+                alice(),
+                // Anonymous clients are limited by address, apart from users
+                anonymous(),
+                anonymous(),
+                anonymous(),
+                test::TestRequest::get().uri("/healthz"),
+            ];
+            let responses = make_requests(repository.clone(), config.clone(), requests).await;
+            let statuses: Vec<_> = responses.iter().map(|response| response.status()).collect();
+            assert_eq!(
+                statuses,
+                [
+                    StatusCode::OK,
+                    StatusCode::OK,
+                    StatusCode::TOO_MANY_REQUESTS,
+                    StatusCode::UNAUTHORIZED,
+                    StatusCode::UNAUTHORIZED,
+                    StatusCode::TOO_MANY_REQUESTS,
+                    StatusCode::OK,
+                ]
+            );
+            fn headers(response: &ServiceResponse) -> [Option<&str>; 3] {
+                ["ratelimit-limit", "ratelimit-remaining", "ratelimit-reset"].map(|name| {
+                    response
+                        .headers()
+                        .get(name)
+                        .map(|value| value.to_str().unwrap())
+                })
+            }
+            assert_eq!(headers(&responses[0]), [Some("2"), Some("1"), Some("1")]);
+            assert_eq!(headers(&responses[6]), [None, None, None]);
+
+            let mut responses = responses.into_iter();
+            let response = responses.nth(2).unwrap();
+            assert_eq!(headers(&response), [Some("2"), Some("0"), Some("2")]);
+            assert_eq!(response.headers().get(RETRY_AFTER).unwrap(), "1");
+            let body: Problem = response.into_body().deserialize().await;
+            assert_eq!(
+                body,
+                Problem {
+                    detail: Some("Rate limit exceeded, retry in 1s".to_string()),
+                    ..problem(StatusCode::TOO_MANY_REQUESTS)
+                }
+            );
+        }
+    }
+
+    /// Splits a Server-Sent Event into its id, name and change.
+    fn parse_event(event: &str) -> (u64, String, Change) {
+        let fields: HashMap<_, _> = event
+            .lines()
+            .filter_map(|line| line.split_once(": "))
+            .collect();
+        (
+            fields["id"].parse().unwrap(),
+            fields["event"].to_string(),
+            serde_json::from_str(fields["data"]).unwrap(),
+        )
+    }
+
+    #[sqlx::test(fixtures("test/fixtures/users.sql", "test/fixtures/todos.sql"))]
+    async fn cors(pool: SqlitePool) {
+        let config = Config {
+            cors_origins: vec!["https://app.example.com".to_string()],
+            ..test_config()
+        };
+        for repository in repositories(pool).await {
+            let preflight = |origin| {
+                test::TestRequest::default()
+                    .method(Method::OPTIONS)
+                    .uri("/todos/1")
+                    .insert_header((ORIGIN, origin))
+                    .insert_header((ACCESS_CONTROL_REQUEST_METHOD, "PATCH"))
+                    .insert_header((ACCESS_CONTROL_REQUEST_HEADERS, "authorization, if-match"))
+            };
+            let requests = vec![
+                preflight("https://app.example.com"),
+                preflight("https://evil.example.com"),
+                test::TestRequest::get()
+                    .uri("/todos/1")
+                    .insert_header(bearer(ALICE))
+                    .insert_header((ORIGIN, "https://app.example.com")),
+            ];
+            let mut responses = make_requests(repository.clone(), config.clone(), requests)
+                .await
+                .into_iter();
+
+            let response = responses.next().unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            let header = |name| response.headers().get(name).unwrap().to_str().unwrap();
+            assert_eq!(
+                header(ACCESS_CONTROL_ALLOW_ORIGIN),
+                "https://app.example.com"
+            );
+            assert!(header(ACCESS_CONTROL_ALLOW_METHODS).contains("PATCH"));
+
+            let response = responses.next().unwrap();
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+            assert!(!response.headers().contains_key(ACCESS_CONTROL_ALLOW_ORIGIN));
+
+            let response = responses.next().unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            let exposed = response.headers().get(ACCESS_CONTROL_EXPOSE_HEADERS);
+            assert!(exposed.unwrap().to_str().unwrap().contains("etag"));
+        }
+    }
+
+    #[sqlx::test(fixtures("test/fixtures/users.sql"))]
+    async fn body_limit(pool: SqlitePool) {
+        let config = Config {
+            body_limit: 64,
+            ..test_config()
+        };
+        for repository in repositories(pool).await {
+            let description = "a".repeat(64);
+            let requests = vec![
+                test::TestRequest::post()
+                    .insert_header(bearer(ALICE))
+                    .uri("/todos")
+                    .set_json(json!({"title": "title", "description": description})),
+                test::TestRequest::post()
+                    .insert_header(bearer(ALICE))
+                    .insert_header((CONTENT_TYPE, "text/csv"))
+                    .uri("/todos/import")
+                    .set_payload(format!("title,description\ntitle,{description}\n")),
+                test::TestRequest::post()
+                    .insert_header(bearer(ALICE))
+                    .uri("/todos")
+                    .set_json(json!({"title": "title", "description": ""})),
+            ];
+            let mut responses = make_requests(repository.clone(), config.clone(), requests)
+                .await
+                .into_iter();
+
+            for _ in 0..2 {
+                let response = responses.next().unwrap();
+                assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+                let body: Problem = response.into_body().deserialize().await;
+                assert_eq!(
+                    body.detail.as_deref(),
+                    Some("Request body larger than 64 bytes")
+                );
+            }
+            let response = responses.next().unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+    }
+
+    #[sqlx::test(fixtures("test/fixtures/users.sql", "test/fixtures/todos.sql"))]
+    async fn todo_events(pool: SqlitePool) {
+        for repository in repositories(pool.clone()).await {
+            let patch = |id: i64, title: &str| {
+                test::TestRequest::patch()
+                    .insert_header(bearer(ALICE))
+                    .uri(&format!("/todos/{id}"))
+                    .set_json(json!({ "title": title }))
+            };
+            let requests = vec![
+                test::TestRequest::get()
+                    .uri("/todos/events")
+                    .insert_header(bearer(ALICE)),
+                test::TestRequest::get()
+                    .uri("/todos/events?todo_id=2")
+                    .insert_header(bearer(ALICE)),
+                patch(1, "first"),
+                // Changes of other users are not sent
+                test::TestRequest::post()
+                    .uri("/todos")
+                    .insert_header(bearer(BOB))
+                    .set_json(json!({"title": "bob's", "description": ""})),
+                patch(2, "second"),
+                test::TestRequest::delete()
+                    .uri("/todos/2")
+                    .insert_header(bearer(ALICE)),
+            ];
+            let mut responses = make_requests(repository.clone(), test_config(), requests)
+                .await
+                .into_iter();
+            let all = responses.next().unwrap();
+            let second = responses.next().unwrap();
+            assert_eq!(all.status(), StatusCode::OK);
+            assert_eq!(
+                all.headers().get(CONTENT_TYPE).unwrap(),
+                "text/event-stream"
+            );
+            assert_eq!(all.headers().get(CACHE_CONTROL).unwrap(), "no-cache");
+
+            let mut all = all.into_body();
+            let (id, event, change) = parse_event(&all.next_event().await);
+            assert_eq!((id, event.as_str()), (1, "update"));
+            assert_eq!(change.kind, EventKind::Update);
+            assert_eq!(change.todo.todo.id, 1);
+            assert_eq!(change.todo.todo.title, "first");
+            let (id, event, _) = parse_event(&all.next_event().await);
+            assert_eq!((id, event.as_str()), (3, "update"));
+            let (id, event, _) = parse_event(&all.next_event().await);
+            assert_eq!((id, event.as_str()), (4, "delete"));
+
+            let mut second = second.into_body();
+            let (id, _, change) = parse_event(&second.next_event().await);
+            assert_eq!(id, 3);
+            assert_eq!(change.todo.todo.title, "second");
+            let (id, _, _) = parse_event(&second.next_event().await);
+            assert_eq!(id, 4);
+        }
+    }
+
+    #[sqlx::test(fixtures("test/fixtures/users.sql", "test/fixtures/todos.sql"))]
+    async fn todo_events_resume(pool: SqlitePool) {
+        for repository in repositories(pool.clone()).await {
+            let patch = |id: i64| {
+                test::TestRequest::patch()
+                    .insert_header(bearer(ALICE))
+                    .uri(&format!("/todos/{id}"))
+                    .set_json(json!({"completed": true}))
+            };
+            let requests = vec![
+                patch(1),
+                patch(2),
+                patch(3),
+                test::TestRequest::get()
+                    .uri("/todos/events")
+                    .insert_header(bearer(ALICE))
+                    .insert_header((LAST_EVENT_ID, "1")),
+                test::TestRequest::get()
+                    .uri("/todos/events?last_event_id=2")
+                    .insert_header(bearer(ALICE)),
+                test::TestRequest::get()
+                    .uri("/todos/events")
+                    .insert_header(bearer(ALICE))
+                    .insert_header((LAST_EVENT_ID, "one")),
+            ];
+            let mut responses = make_requests(repository.clone(), test_config(), requests)
+                .await
+                .into_iter()
+                .skip(3);
+
+            let mut header = responses.next().unwrap().into_body();
+            assert_eq!(parse_event(&header.next_event().await).0, 2);
+            assert_eq!(parse_event(&header.next_event().await).0, 3);
+            let mut query = responses.next().unwrap().into_body();
+            assert_eq!(parse_event(&query.next_event().await).0, 3);
+
+            let response = responses.next().unwrap();
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        }
+    }
+
+    /// Handshakes only, as the connection is served by a task which needs the
+    /// runtime of Actix. `todo_events_ws_follow` talks to a running server.
+    #[actix_web::test]
+    async fn todo_events_ws() {
+        let repository: Arc<dyn TodoRepository> = Arc::new(MemoryRepository::new());
+        let request = test::TestRequest::get()
+            .uri("/todos/events/ws?todo_id=1")
+            .insert_header(bearer(ALICE))
+            .insert_header((CONNECTION, "upgrade"))
+            .insert_header((UPGRADE, "websocket"))
+            .insert_header((SEC_WEBSOCKET_VERSION, "13"))
+            .insert_header((SEC_WEBSOCKET_KEY, "dGhlIHNhbXBsZSBub25jZQ=="));
+        let response = make_request(repository.clone(), request).await;
+        assert_eq!(response.status(), StatusCode::SWITCHING_PROTOCOLS);
+        assert_eq!(
+            response.headers().get(SEC_WEBSOCKET_ACCEPT).unwrap(),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+
+        let request = test::TestRequest::get()
+            .uri("/todos/events/ws")
+            .insert_header(bearer(ALICE));
+        let response = make_request(repository, request).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_web::test]
+    async fn todo_events_ws_follow() {
+        let server = server(Arc::new(MemoryRepository::new()));
+        let client = awc::Client::new();
+        let change = |id: i64| {
+            let request = client
+                .patch(server.url(&format!("/todos/{id}")))
+                .insert_header(bearer(ALICE));
+            async move {
+                let response = request.send_json(&json!({"title": "changed"})).await;
+                assert_eq!(response.unwrap().status(), StatusCode::OK);
+            }
+        };
+        for title in ["first", "second"] {
+            let response = client
+                .post(server.url("/todos"))
+                .insert_header(bearer(ALICE))
+                .send_json(&json!({"title": title, "description": ""}))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+        let (name, token) = bearer(ALICE);
+        let (_, mut socket) = client
+            .ws(server.url("/todos/events/ws?todo_id=1"))
+            .set_header(name, token)
+            .connect()
+            .await
+            .unwrap();
+
+        // Changes of the todos not followed are not sent
+        change(2).await;
+        change(1).await;
+        let sent: Change = next_json(&mut socket).await;
+        assert_eq!((sent.kind, sent.todo.todo.id), (EventKind::Update, 1));
+
+        // Messages are handled in order, so the problem also tells the
+        // following message was
+        let follow = serde_json::to_string(&Follow { todo_ids: vec![2] }).unwrap();
+        socket.send(ws::Message::Text(follow.into())).await.unwrap();
+        let malformed = json!({"todo_ids": "2"}).to_string();
+        socket
+            .send(ws::Message::Text(malformed.into()))
+            .await
+            .unwrap();
+        let sent: Problem = next_json(&mut socket).await;
+        assert_eq!(sent.status, StatusCode::BAD_REQUEST.as_u16());
+
+        change(1).await;
+        change(2).await;
+        let sent: Change = next_json(&mut socket).await;
+        assert_eq!(sent.todo.todo.id, 2);
+    }
+
+    /// Reads the next text message of a WebSocket as JSON, skipping the pings
+    /// keeping the connection alive.
+    async fn next_json<T: DeserializeOwned>(
+        socket: &mut (impl Stream<Item = Result<Frame, WsProtocolError>> + Unpin),
+    ) -> T {
+        loop {
+            match socket.next().await {
+                Some(Ok(Frame::Ping(_))) => {}
+                Some(Ok(Frame::Text(text))) => return serde_json::from_slice(&text).unwrap(),
+                frame => panic!("Expected a text frame, got {frame:?}"),
+            }
+        }
+    }
+
+    #[sqlx::test]
+    async fn readyz_pending_migrations(pool: SqlitePool) {
+        sqlx::query("DELETE FROM _sqlx_migrations WHERE version = (SELECT MAX(version) FROM _sqlx_migrations)")
+            .execute(&pool)
+            .await
+            .unwrap();
+        let repository = Arc::new(SqliteRepository::new(pool.clone()));
+        let request = test::TestRequest::get().uri("/readyz");
+        let response = make_request(repository.clone(), request).await;
+
+        let status_code = response.status();
+        let body: Problem = response.into_body().deserialize().await;
+        assert_eq!(status_code, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(
+            body.detail.as_deref(),
+            Some("Database has 1 pending migration(s)")
+        );
+
+        pool.close().await;
+        let request = test::TestRequest::get().uri("/readyz");
+        let response = make_request(repository, request).await;
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    #[actix_web::test]
+    async fn openapi() {
+        let repository = Arc::new(MemoryRepository::new());
+        let request = test::TestRequest::get().uri("/openapi.json");
+        let response = make_request(repository, request).await;
 
         let status_code = response.status();
-        let body = response.into_body().as_str().await;
-        assert_eq!(status_code, StatusCode::NOT_FOUND);
-        assert_eq!(body, "Not Found");
+        let body: Value = response.into_body().deserialize().await;
+        assert_eq!(status_code, StatusCode::OK);
+        assert_eq!(body, serde_json::to_value(ApiDoc::openapi()).unwrap());
+    }
+
+    /// Requests every method on every documented path without a token, which
+    /// only routes are able to reject with something else than 404.
+    #[actix_web::test]
+    async fn openapi_matches_routes() {
+        let spec = serde_json::to_value(ApiDoc::openapi()).unwrap();
+        let paths = spec["paths"].as_object().unwrap();
+        let documented = |method: &str, uri: &str| {
+            paths.iter().any(|(path, item)| {
+                let segments = path.split('/').zip(uri.split('/'));
+                item.get(method).is_some()
+                    && path.split('/').count() == uri.split('/').count()
+                    && segments
+                        .into_iter()
+                        .all(|(pattern, segment)| pattern == segment || pattern.starts_with('{'))
+            })
+        };
+
+        let repository: Arc<dyn TodoRepository> = Arc::new(MemoryRepository::new());
+        for path in paths.keys() {
+            let uri = path
+                .split('/')
+                .map(|segment| {
+                    if segment.starts_with('{') {
+                        "1"
+                    } else {
+                        segment
+                    }
+                })
+                .collect::<Vec<_>>()
+                .join("/");
+            for method in [
+                Method::GET,
+                Method::POST,
+                Method::PUT,
+                Method::PATCH,
+                Method::DELETE,
+            ] {
+                let request = test::TestRequest::default()
+                    .method(method.clone())
+                    .uri(&uri);
+                let response = make_request(repository.clone(), request).await;
+
+                let routed = response.status() != StatusCode::NOT_FOUND;
+                let documented = documented(&method.as_str().to_lowercase(), &uri);
+                assert_eq!(routed, documented, "{method} {path}");
+            }
+        }
+
+        // Paths the spec misses entirely cannot be requested above, so the
+        // services registered by the app are compared with the operations
+        let registered: HashSet<&str> = include_str!("app.rs")
+            .split(".service(routes::")
+            .skip(1)
+            .filter_map(|service| service.split(')').next())
+            .filter(|service| !["openapi", "docs", "docs_asset"].contains(service))
+            .collect();
+        let operations: HashSet<&str> = paths
+            .values()
+            .flat_map(|item| item.as_object().unwrap().values())
+            .map(|operation| operation["operationId"].as_str().unwrap())
+            .collect();
+        assert_eq!(registered, operations);
+    }
+
+    #[actix_web::test]
+    async fn docs() {
+        let repository: Arc<dyn TodoRepository> = Arc::new(MemoryRepository::new());
+        let uris = [
+            "/docs",
+            "/docs/swagger-ui.css",
+            "/docs/swagger-ui-bundle.js",
+            "/docs/swagger-ui.js",
+        ];
+        let requests = uris
+            .into_iter()
+            .map(|uri| test::TestRequest::get().uri(uri))
+            .collect();
+        let mut responses = make_requests(repository, test_config(), requests)
+            .await
+            .into_iter();
+
+        let response = responses.next().unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = to_bytes(response.into_body()).await.unwrap();
+        let body = std::str::from_utf8(&body).unwrap();
+        // Assets are served locally, not loaded from a third party
+        assert!(body.contains(r#"src="/docs/swagger-ui-bundle.js""#));
+        assert!(!body.contains("https://"));
+
+        for content_type in ["text/css", "text/javascript"] {
+            let response = responses.next().unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            let header = response.headers().get(CONTENT_TYPE).unwrap();
+            assert!(header.to_str().unwrap().starts_with(content_type));
+        }
+        assert_eq!(responses.next().unwrap().status(), StatusCode::NOT_FOUND);
+    }
+
+    #[sqlx::test]
+    async fn register_login(pool: SqlitePool) {
+        for repository in repositories(pool).await {
+            let credentials = json!({"username": " carol ", "password": "correct horse"});
+            let request = test::TestRequest::post()
+                .uri("/users")
+                .set_json(&credentials);
+            let response = make_request(repository.clone(), request).await;
+
+            let status_code = response.status();
+            let user: User = response.into_body().deserialize().await;
+            assert_eq!(status_code, StatusCode::OK);
+            assert_eq!(user.username, "carol");
+
+            let request = test::TestRequest::post()
+                .uri("/login")
+                .set_json(&credentials);
+            let response = make_request(repository.clone(), request).await;
+
+            let status_code = response.status();
+            let token: Token = response.into_body().deserialize().await;
+            assert_eq!(status_code, StatusCode::OK);
+            assert_eq!(token.token_type, "Bearer");
+
+            let request = test::TestRequest::post()
+                .uri("/todos")
+                .insert_header((AUTHORIZATION, format!("Bearer {}", token.access_token)))
+                .set_json(json!({"title": "title", "description": "description"}));
+            let response = make_request(repository.clone(), request).await;
+
+            let status_code = response.status();
+            let todo: Todo = response.into_body().deserialize().await;
+            assert_eq!(status_code, StatusCode::OK);
+            assert_eq!(todo.user_id, Some(user.id));
+        }
+    }
+
+    #[sqlx::test(fixtures("test/fixtures/users.sql"))]
+    async fn register_conflict(pool: SqlitePool) {
+        for repository in repositories(pool).await {
+            let request = test::TestRequest::post()
+                .uri("/users")
+                .set_json(json!({"username": "alice", "password": "password"}));
+            let response = make_request(repository.clone(), request).await;
+
+            let status_code = response.status();
+            let body: Problem = response.into_body().deserialize().await;
+            assert_eq!(status_code, StatusCode::CONFLICT);
+            assert_eq!(body.status, 409);
+            assert_eq!(body.detail.as_deref(), Some(USERNAME_TAKEN));
+        }
+    }
+
+    #[sqlx::test]
+    async fn register_invalid(pool: SqlitePool) {
+        for repository in repositories(pool).await {
+            let request = test::TestRequest::post()
+                .uri("/users")
+                .set_json(json!({"username": "a b", "password": "short"}));
+            let response = make_request(repository.clone(), request).await;
+
+            let status_code = response.status();
+            let body: Problem = response.into_body().deserialize().await;
+            assert_eq!(status_code, StatusCode::UNPROCESSABLE_ENTITY);
+            let fields: Vec<&str> = body.errors.iter().map(|err| err.field.as_str()).collect();
+            assert_eq!(fields, vec!["username", "password"]);
+        }
+    }
+
+    #[sqlx::test]
+    async fn login_unauthorized(pool: SqlitePool) {
+        for repository in repositories(pool).await {
+            let request = test::TestRequest::post()
+                .uri("/users")
+                .set_json(json!({"username": "carol", "password": "correct horse"}));
+            make_request(repository.clone(), request).await;
+
+            for credentials in [
+                json!({"username": "carol", "password": "wrong horse"}),
+                json!({"username": "dave", "password": "correct horse"}),
+            ] {
+                let request = test::TestRequest::post()
+                    .uri("/login")
+                    .set_json(&credentials);
+                let response = make_request(repository.clone(), request).await;
+
+                let status_code = response.status();
+                let www_authenticate = response.headers().get(WWW_AUTHENTICATE).unwrap().clone();
+                let body: Problem = response.into_body().deserialize().await;
+                assert_eq!(status_code, StatusCode::UNAUTHORIZED);
+                assert_eq!(www_authenticate, "Bearer");
+                assert_eq!(body, problem(StatusCode::UNAUTHORIZED));
+            }
+        }
+    }
+
+    #[sqlx::test(fixtures("test/fixtures/users.sql", "test/fixtures/todos.sql"))]
+    async fn todos_unauthorized(pool: SqlitePool) {
+        for repository in repositories(pool).await {
+            for authorization in [None, Some("Bearer invalid"), Some("Basic YWxpY2U6")] {
+                let mut request = test::TestRequest::get().uri("/todos/1");
+                if let Some(authorization) = authorization {
+                    request = request.insert_header((AUTHORIZATION, authorization));
+                }
+                let response = make_request(repository.clone(), request).await;
+                assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+            }
+        }
+    }
+
+    #[sqlx::test(fixtures("test/fixtures/users.sql", "test/fixtures/todos.sql"))]
+    async fn todos_cross_user(pool: SqlitePool) {
+        for repository in repositories(pool).await {
+            let requests = [
+                test::TestRequest::get().uri("/todos/1"),
+                test::TestRequest::put()
+                    .uri("/todos/1")
+                    .set_json(UpdateTodo {
+                        title: "title".to_string(),
+                        description: "description".to_string(),
+                        completed: true,
+                        due_at: None,
+                        parent_id: None,
+                        recurrence: None,
+                    }),
+                test::TestRequest::patch()
+                    .uri("/todos/1")
+                    .set_json(json!({"completed": true})),
+                test::TestRequest::delete().uri("/todos/1"),
+            ];
+            for request in requests {
+                let response =
+                    make_request(repository.clone(), request.insert_header(bearer(BOB))).await;
+
+                let status_code = response.status();
+                let body: Problem = response.into_body().deserialize().await;
+                assert_eq!(status_code, StatusCode::NOT_FOUND);
+                assert_eq!(body, problem(StatusCode::NOT_FOUND));
+            }
+
+            let request = test::TestRequest::get()
+                .uri("/todos")
+                .insert_header(bearer(BOB));
+            let response = make_request(repository.clone(), request).await;
+            let body: Vec<Todo> = response.into_body().deserialize().await;
+            assert_eq!(body, vec![]);
+
+            let request = test::TestRequest::get()
+                .uri("/todos/1")
+                .insert_header(bearer(ALICE));
+            let response = make_request(repository.clone(), request).await;
+            let body: Todo = response.into_body().deserialize().await;
+            assert_eq!(body, fixture_todos()[0]);
+        }
+    }
+
+    #[sqlx::test(fixtures(
+        "test/fixtures/users.sql",
+        "test/fixtures/todos.sql",
+        "test/fixtures/tags.sql"
+    ))]
+    async fn list_todos_tagged(pool: SqlitePool) {
+        for repository in repositories(pool).await {
+            let request = test::TestRequest::get()
+                .insert_header(bearer(ALICE))
+                .uri("/todos?tag=home&limit=1&tag=work");
+            let response = make_request(repository.clone(), request).await;
+
+            let status_code = response.status();
+            let link = response.headers().get(LINK).map(|link| link.to_owned());
+            let body: Vec<TaggedTodo> = response.into_body().deserialize().await;
+            assert_eq!(status_code, StatusCode::OK);
+            assert_eq!(link, None);
+            assert_eq!(
+                body,
+                vec![TaggedTodo {
+                    todo: fixture_todos()[1].clone(),
+                    tags: vec![
+                        Tag {
+                            id: 1,
+                            name: "home".to_string()
+                        },
+                        Tag {
+                            id: 2,
+                            name: "work".to_string()
+                        },
+                    ],
+                }]
+            );
+
+            let request = test::TestRequest::get()
+                .insert_header(bearer(ALICE))
+                .uri("/todos?tag=home&limit=1");
+            let response = make_request(repository.clone(), request).await;
+            let cursor = response
+                .headers()
+                .get(NEXT_CURSOR)
+                .unwrap()
+                .to_str()
+                .unwrap();
+            let link = response.headers().get(LINK).unwrap().to_str().unwrap();
+            assert_eq!(
+                link,
+                format!("</todos?limit=1&cursor={cursor}&tag=home>; rel=\"next\"")
+            );
+        }
+    }
+
+    #[sqlx::test(fixtures("test/fixtures/users.sql", "test/fixtures/todos.sql"))]
+    async fn tag_todo(pool: SqlitePool) {
+        for repository in repositories(pool).await {
+            let request = test::TestRequest::post()
+                .insert_header(bearer(ALICE))
+                .uri("/tags")
+                .set_json(CreateTag {
+                    name: " urgent ".to_string(),
+                });
+            let response = make_request(repository.clone(), request).await;
+            let tag: Tag = response.into_body().deserialize().await;
+            assert_eq!(tag.name, "urgent");
+
+            let request = test::TestRequest::post()
+                .insert_header(bearer(ALICE))
+                .uri("/todos/3/tags")
+                .set_json(AttachTag { tag_id: tag.id });
+            let response = make_request(repository.clone(), request).await;
+            let status_code = response.status();
+            let body: TaggedTodo = response.into_body().deserialize().await;
+            assert_eq!(status_code, StatusCode::OK);
+            assert_eq!(body.tags, vec![tag.clone()]);
+
+            let request = test::TestRequest::get()
+                .insert_header(bearer(ALICE))
+                .uri("/todos/3");
+            let response = make_request(repository.clone(), request).await;
+            let body: TaggedTodo = response.into_body().deserialize().await;
+            assert_eq!(body.tags, vec![tag.clone()]);
+
+            let request = test::TestRequest::delete()
+                .insert_header(bearer(ALICE))
+                .uri(&format!("/todos/3/tags/{}", tag.id));
+            let response = make_request(repository.clone(), request).await;
+            let body: TaggedTodo = response.into_body().deserialize().await;
+            assert_eq!(body.tags, vec![]);
+
+            let request = test::TestRequest::get()
+                .insert_header(bearer(ALICE))
+                .uri("/todos/3/tags");
+            let response = make_request(repository.clone(), request).await;
+            let body: Vec<Tag> = response.into_body().deserialize().await;
+            assert_eq!(body, vec![]);
+        }
+    }
+
+    #[sqlx::test(fixtures(
+        "test/fixtures/users.sql",
+        "test/fixtures/todos.sql",
+        "test/fixtures/tags.sql"
+    ))]
+    async fn delete_todo_tagged(pool: SqlitePool) {
+        for repository in repositories(pool).await {
+            let request = test::TestRequest::delete()
+                .insert_header(bearer(ALICE))
+                .uri("/todos/1");
+            let response = make_request(repository.clone(), request).await;
+            let body: TaggedTodo = response.into_body().deserialize().await;
+            assert_eq!(body.tags.len(), 1);
+
+            // The tag itself outlives the todo
+            let request = test::TestRequest::get()
+                .insert_header(bearer(ALICE))
+                .uri("/tags/1");
+            let response = make_request(repository.clone(), request).await;
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+    }
+
+    #[sqlx::test(fixtures(
+        "test/fixtures/users.sql",
+        "test/fixtures/todos.sql",
+        "test/fixtures/tags.sql"
+    ))]
+    async fn tag_changes(pool: SqlitePool) {
+        for repository in repositories(pool.clone()).await {
+            let requests = vec![
+                test::TestRequest::get()
+                    .insert_header(bearer(ALICE))
+                    .uri("/todos/events"),
+                test::TestRequest::put()
+                    .insert_header(bearer(ALICE))
+                    .uri("/tags/1")
+                    .set_json(UpdateTag {
+                        name: "house".to_string(),
+                    }),
+                test::TestRequest::delete()
+                    .insert_header(bearer(ALICE))
+                    .uri("/tags/2"),
+            ];
+            let mut responses = make_requests(repository.clone(), test_config(), requests)
+                .await
+                .into_iter();
+            let mut events = responses.next().unwrap().into_body();
+            assert_eq!(responses.next().unwrap().status(), StatusCode::OK);
+            assert_eq!(responses.next().unwrap().status(), StatusCode::OK);
+
+            // Todos carrying the tag are changed, as they embed it
+            let mut changes = Vec::new();
+            for _ in 0..3 {
+                let (_, _, change) = parse_event(&events.next_event().await);
+                assert_eq!(change.kind, EventKind::Update);
+                let names: Vec<_> = change.todo.tags.into_iter().map(|tag| tag.name).collect();
+                changes.push((change.todo.todo.id, change.todo.todo.version, names));
+            }
+            let names = |names: &[&str]| names.iter().map(|name| name.to_string()).collect();
+            assert_eq!(
+                changes,
+                [
+                    (1, 2, names(&["house"])),
+                    (2, 2, names(&["house", "work"])),
+                    (2, 3, names(&["house"])),
+                ]
+            );
+        }
+    }
+
+    #[sqlx::test(fixtures(
+        "test/fixtures/users.sql",
+        "test/fixtures/todos.sql",
+        "test/fixtures/tags.sql"
+    ))]
+    async fn tag_changes_webhooks(pool: SqlitePool) {
+        for repository in repositories(pool.clone()).await {
+            repository
+                .create_webhook(
+                    ALICE,
+                    CreateWebhook {
+                        url: "https://example.com/hook".to_string(),
+                    },
+                    "secret",
+                )
+                .await
+                .unwrap();
+            let requests = vec![
+                test::TestRequest::delete()
+                    .insert_header(bearer(ALICE))
+                    .uri("/todos/1"),
+                test::TestRequest::put()
+                    .insert_header(bearer(ALICE))
+                    .uri("/tags/1")
+                    .set_json(UpdateTag {
+                        name: "house".to_string(),
+                    }),
+            ];
+            for response in make_requests(repository.clone(), test_config(), requests).await {
+                assert_eq!(response.status(), StatusCode::OK);
+            }
+
+            // Tag changes reach webhooks, but not for todos in the trash
+            let now = timestamp("2100-01-01 00:00:00");
+            let events: Vec<_> = repository
+                .due_deliveries(now, 10)
+                .await
+                .unwrap()
+                .into_iter()
+                .map(|(_, delivery)| match delivery.event {
+                    Payload::Event(event) => (event.kind, event.todo_id, event.version),
+                    Payload::Reminder(_) => panic!("unexpected reminder"),
+                })
+                .collect();
+            assert_eq!(
+                events,
+                [(EventKind::Delete, 1, 2), (EventKind::Update, 2, 2)]
+            );
+            let trash = repository.list_trash(ALICE).await.unwrap();
+            assert_eq!(trash[0].version, 2);
+        }
+    }
+
+    #[sqlx::test(fixtures(
+        "test/fixtures/users.sql",
+        "test/fixtures/todos.sql",
+        "test/fixtures/tags.sql"
+    ))]
+    async fn create_tag_conflict(pool: SqlitePool) {
+        for repository in repositories(pool).await {
+            let request = test::TestRequest::post()
+                .insert_header(bearer(ALICE))
+                .uri("/tags")
+                .set_json(CreateTag {
+                    name: "home".to_string(),
+                });
+            let response = make_request(repository.clone(), request).await;
+
+            let status_code = response.status();
+            let body: Problem = response.into_body().deserialize().await;
+            assert_eq!(status_code, StatusCode::CONFLICT);
+            assert_eq!(body.status, 409);
+            assert_eq!(body.detail.as_deref(), Some(NAME_TAKEN));
+
+            let request = test::TestRequest::put()
+                .insert_header(bearer(ALICE))
+                .uri("/tags/2")
+                .set_json(UpdateTag {
+                    name: "home".to_string(),
+                });
+            let response = make_request(repository.clone(), request).await;
+
+            let status_code = response.status();
+            let body: Problem = response.into_body().deserialize().await;
+            assert_eq!(status_code, StatusCode::CONFLICT);
+            assert_eq!(body.detail.as_deref(), Some(NAME_TAKEN));
+        }
+    }
+
+    #[sqlx::test(fixtures(
+        "test/fixtures/users.sql",
+        "test/fixtures/todos.sql",
+        "test/fixtures/tags.sql"
+    ))]
+    async fn tags_cross_user(pool: SqlitePool) {
+        for repository in repositories(pool).await {
+            let requests = [
+                test::TestRequest::get().uri("/tags/1"),
+                test::TestRequest::put()
+                    .uri("/tags/1")
+                    .set_json(json!({"name": "mine"})),
+                test::TestRequest::delete().uri("/tags/1"),
+                test::TestRequest::get().uri("/todos/1/tags"),
+                test::TestRequest::post()
+                    .uri("/todos/3/tags")
+                    .set_json(AttachTag { tag_id: 3 }),
+                test::TestRequest::delete().uri("/todos/1/tags/1"),
+            ];
+            for request in requests {
+                let response =
+                    make_request(repository.clone(), request.insert_header(bearer(BOB))).await;
+                assert_eq!(response.status(), StatusCode::NOT_FOUND);
+            }
+
+            let request = test::TestRequest::get()
+                .uri("/tags")
+                .insert_header(bearer(BOB));
+            let response = make_request(repository.clone(), request).await;
+            let body: Vec<Tag> = response.into_body().deserialize().await;
+            assert_eq!(
+                body,
+                vec![Tag {
+                    id: 3,
+                    name: "work".to_string()
+                }]
+            );
+        }
+    }
+
+    #[sqlx::test(fixtures(
+        "test/fixtures/users.sql",
+        "test/fixtures/todos.sql",
+        "test/fixtures/tags.sql"
+    ))]
+    async fn search_todos(pool: SqlitePool) {
+        for repository in repositories(pool).await {
+            let request = test::TestRequest::get()
+                .insert_header(bearer(ALICE))
+                .uri("/todos/search?q=description2%20todo*");
+            let response = make_request(repository.clone(), request).await;
+
+            let status_code = response.status();
+            let body: Vec<SearchHit> = response.into_body().deserialize().await;
+            assert_eq!(status_code, StatusCode::OK);
+            assert_eq!(body.len(), 1);
+            assert_eq!(body[0].todo.todo, fixture_todos()[1]);
+            assert_eq!(body[0].todo.tags.len(), 2);
+            assert_eq!(body[0].highlights.title, "<mark>todo2</mark>");
+            assert_eq!(body[0].highlights.description, "<mark>description2</mark>");
+        }
+    }
+
+    #[sqlx::test]
+    async fn search_todos_bad_request(pool: SqlitePool) {
+        for repository in repositories(pool).await {
+            for uri in [
+                "/todos/search",
+                "/todos/search?q=%20",
+                "/todos/search?q=a&limit=0",
+            ] {
+                let request = test::TestRequest::get()
+                    .insert_header(bearer(ALICE))
+                    .uri(uri);
+                let response = make_request(repository.clone(), request).await;
+                assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+            }
+        }
+    }
+
+    #[sqlx::test(fixtures("test/fixtures/users.sql", "test/fixtures/todos.sql"))]
+    async fn get_todo_not_modified(pool: SqlitePool) {
+        for repository in repositories(pool).await {
+            let request = test::TestRequest::get()
+                .insert_header(bearer(ALICE))
+                .uri("/todos/1");
+            let response = make_request(repository.clone(), request).await;
+            let etag = response.headers().get(ETAG).unwrap().to_owned();
+            assert_eq!(etag, "\"1\"");
+
+            let request = test::TestRequest::get()
+                .insert_header(bearer(ALICE))
+                .insert_header((IF_NONE_MATCH, etag.clone()))
+                .uri("/todos/1");
+            let response = make_request(repository.clone(), request).await;
+            assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+            assert_eq!(response.headers().get(ETAG), Some(&etag));
+
+            let request = test::TestRequest::get()
+                .insert_header(bearer(ALICE))
+                .insert_header((IF_NONE_MATCH, "\"0\""))
+                .uri("/todos/1");
+            let response = make_request(repository.clone(), request).await;
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+    }
+
+    #[sqlx::test(fixtures("test/fixtures/users.sql", "test/fixtures/todos.sql"))]
+    async fn list_todos_not_modified(pool: SqlitePool) {
+        for repository in repositories(pool).await {
+            let request = test::TestRequest::get()
+                .insert_header(bearer(ALICE))
+                .uri("/todos");
+            let response = make_request(repository.clone(), request).await;
+            let etag = response.headers().get(ETAG).unwrap().to_owned();
+
+            let request = test::TestRequest::get()
+                .insert_header(bearer(ALICE))
+                .insert_header((IF_NONE_MATCH, etag.clone()))
+                .uri("/todos");
+            let response = make_request(repository.clone(), request).await;
+            assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+
+            // Any change to a listed todo changes the list's tag
+            let request = test::TestRequest::patch()
+                .insert_header(bearer(ALICE))
+                .uri("/todos/1")
+                .set_json(json!({"completed": true}));
+            make_request(repository.clone(), request).await;
+            let request = test::TestRequest::get()
+                .insert_header(bearer(ALICE))
+                .insert_header((IF_NONE_MATCH, etag.clone()))
+                .uri("/todos");
+            let response = make_request(repository.clone(), request).await;
+            assert_eq!(response.status(), StatusCode::OK);
+            assert_ne!(response.headers().get(ETAG), Some(&etag));
+        }
+    }
+
+    #[sqlx::test(fixtures("test/fixtures/users.sql", "test/fixtures/todos.sql"))]
+    async fn update_todo_if_match(pool: SqlitePool) {
+        for repository in repositories(pool).await {
+            let update = UpdateTodo {
+                title: "title".to_string(),
+                description: "description".to_string(),
+                completed: true,
+                due_at: None,
+                parent_id: None,
+                recurrence: None,
+            };
+            let request = test::TestRequest::put()
+                .insert_header(bearer(ALICE))
+                .insert_header((IF_MATCH, "\"1\""))
+                .uri("/todos/2")
+                .set_json(&update);
+            let response = make_request(repository.clone(), request).await;
+            assert_eq!(response.status(), StatusCode::OK);
+            assert_eq!(response.headers().get(ETAG).unwrap(), "\"2\"");
+
+            // A second client still holding the first version loses the race
+            let requests = [
+                test::TestRequest::put().uri("/todos/2").set_json(&update),
+                test::TestRequest::patch()
+                    .uri("/todos/2")
+                    .set_json(json!({"completed": false})),
+                test::TestRequest::delete().uri("/todos/2"),
+            ];
+            for request in requests {
+                let request = request
+                    .insert_header(bearer(ALICE))
+                    .insert_header((IF_MATCH, "\"1\""));
+                let response = make_request(repository.clone(), request).await;
+
+                let status_code = response.status();
+                let body: Problem = response.into_body().deserialize().await;
+                assert_eq!(status_code, StatusCode::PRECONDITION_FAILED);
+                assert_eq!(body, problem(StatusCode::PRECONDITION_FAILED));
+            }
+
+            let request = test::TestRequest::delete()
+                .insert_header(bearer(ALICE))
+                .insert_header((IF_MATCH, "W/\"2\", \"2\""))
+                .uri("/todos/2");
+            let response = make_request(repository.clone(), request).await;
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+    }
+
+    #[sqlx::test(fixtures("test/fixtures/users.sql", "test/fixtures/todos.sql"))]
+    async fn update_todo_if_match_not_found(pool: SqlitePool) {
+        for repository in repositories(pool).await {
+            let request = test::TestRequest::delete()
+                .insert_header(bearer(ALICE))
+                .insert_header((IF_MATCH, "*"))
+                .uri("/todos/999");
+            let response = make_request(repository.clone(), request).await;
+            assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+            let request = test::TestRequest::delete()
+                .insert_header(bearer(ALICE))
+                .insert_header((IF_MATCH, "not a tag"))
+                .uri("/todos/1");
+            let response = make_request(repository.clone(), request).await;
+            assert_eq!(response.status(), StatusCode::PRECONDITION_FAILED);
+        }
+    }
+
+    #[sqlx::test(fixtures(
+        "test/fixtures/users.sql",
+        "test/fixtures/todos.sql",
+        "test/fixtures/tags.sql"
+    ))]
+    async fn tags_change_etag(pool: SqlitePool) {
+        for repository in repositories(pool).await {
+            let etag = |repository: Arc<dyn TodoRepository>| async move {
+                let request = test::TestRequest::get()
+                    .insert_header(bearer(ALICE))
+                    .uri("/todos/2");
+                let response = make_request(repository.clone(), request).await;
+                response.headers().get(ETAG).unwrap().to_owned()
+            };
+            assert_eq!(etag(repository.clone()).await, "\"1\"");
+
+            let request = test::TestRequest::put()
+                .insert_header(bearer(ALICE))
+                .uri("/tags/1")
+                .set_json(json!({"name": "house"}));
+            make_request(repository.clone(), request).await;
+            assert_eq!(etag(repository.clone()).await, "\"2\"");
+
+            let request = test::TestRequest::delete()
+                .insert_header(bearer(ALICE))
+                .uri("/todos/2/tags/2");
+            make_request(repository.clone(), request).await;
+            assert_eq!(etag(repository.clone()).await, "\"3\"");
+
+            let request = test::TestRequest::post()
+                .insert_header(bearer(ALICE))
+                .uri("/todos/2/tags")
+                .set_json(AttachTag { tag_id: 2 });
+            make_request(repository.clone(), request).await;
+            let request = test::TestRequest::delete()
+                .insert_header(bearer(ALICE))
+                .uri("/tags/1");
+            make_request(repository.clone(), request).await;
+            assert_eq!(etag(repository.clone()).await, "\"5\"");
+
+            // Every change of the tags is kept in the history
+            let request = test::TestRequest::get()
+                .insert_header(bearer(ALICE))
+                .uri("/todos/2/history");
+            let response = make_request(repository.clone(), request).await;
+            let events: Vec<TodoEvent> = response.into_body().deserialize().await;
+            let versions: Vec<(EventKind, i64)> = events
+                .iter()
+                .map(|event| (event.kind, event.version))
+                .collect();
+            assert_eq!(
+                versions,
+                vec![
+                    (EventKind::Update, 2),
+                    (EventKind::Update, 3),
+                    (EventKind::Update, 4),
+                    (EventKind::Update, 5),
+                ]
+            );
+            let before = &events[0].before.as_ref().unwrap().0;
+            assert_eq!(
+                events[0].after.as_ref().unwrap().0.version,
+                before.version + 1
+            );
+        }
+    }
+
+    #[sqlx::test(fixtures(
+        "test/fixtures/users.sql",
+        "test/fixtures/todos.sql",
+        "test/fixtures/tags.sql"
+    ))]
+    async fn trash_restore(pool: SqlitePool) {
+        for repository in repositories(pool).await {
+            let request = test::TestRequest::delete()
+                .insert_header(bearer(ALICE))
+                .uri("/todos/2");
+            make_request(repository.clone(), request).await;
+
+            let request = test::TestRequest::get()
+                .insert_header(bearer(ALICE))
+                .uri("/trash");
+            let response = make_request(repository.clone(), request).await;
+            let body: Vec<TaggedTodo> = response.into_body().deserialize().await;
+            assert_eq!(body.len(), 1);
+            assert_eq!(body[0].todo.id, 2);
+            assert_eq!(body[0].tags.len(), 2);
+
+            let request = test::TestRequest::get()
+                .insert_header(bearer(ALICE))
+                .uri("/todos/2");
+            let response = make_request(repository.clone(), request).await;
+            assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+            let request = test::TestRequest::post()
+                .insert_header(bearer(BOB))
+                .uri("/todos/2/restore");
+            let response = make_request(repository.clone(), request).await;
+            assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+            let request = test::TestRequest::post()
+                .insert_header(bearer(ALICE))
+                .uri("/todos/2/restore");
+            let response = make_request(repository.clone(), request).await;
+            let status_code = response.status();
+            let body: TaggedTodo = response.into_body().deserialize().await;
+            assert_eq!(status_code, StatusCode::OK);
+            assert_eq!(body.todo.deleted_at, None);
+            assert_eq!(body.tags.len(), 2);
+
+            let request = test::TestRequest::get()
+                .insert_header(bearer(ALICE))
+                .uri("/trash");
+            let response = make_request(repository.clone(), request).await;
+            let body: Vec<TaggedTodo> = response.into_body().deserialize().await;
+            assert_eq!(body, vec![]);
+        }
+    }
+
+    #[sqlx::test(fixtures("test/fixtures/users.sql", "test/fixtures/todos.sql"))]
+    async fn history_revert(pool: SqlitePool) {
+        for repository in repositories(pool).await {
+            let request = test::TestRequest::patch()
+                .insert_header(bearer(ALICE))
+                .uri("/todos/1")
+                .set_json(json!({"title": "changed"}));
+            make_request(repository.clone(), request).await;
+
+            let request = test::TestRequest::get()
+                .insert_header(bearer(ALICE))
+                .uri("/todos/1/history");
+            let response = make_request(repository.clone(), request).await;
+            let status_code = response.status();
+            let body: Vec<TodoEvent> = response.into_body().deserialize().await;
+            assert_eq!(status_code, StatusCode::OK);
+            assert_eq!(body.len(), 1);
+            assert_eq!(body[0].kind, EventKind::Update);
+            assert_eq!(body[0].before.as_ref().unwrap().0, fixture_todos()[0]);
+
+            // Fixture todos have no recorded creation to revert to
+            let request = test::TestRequest::post()
+                .insert_header(bearer(ALICE))
+                .uri("/todos/1/revert")
+                .set_json(Revert { version: 1 });
+            let response = make_request(repository.clone(), request).await;
+            let status_code = response.status();
+            let body: Problem = response.into_body().deserialize().await;
+            assert_eq!(status_code, StatusCode::UNPROCESSABLE_ENTITY);
+            assert_eq!(
+                body.errors,
+                vec![FieldError::new("version", "Unknown version")]
+            );
+
+            let request = test::TestRequest::patch()
+                .insert_header(bearer(ALICE))
+                .uri("/todos/1")
+                .set_json(json!({"title": "changed again"}));
+            make_request(repository.clone(), request).await;
+            let request = test::TestRequest::post()
+                .insert_header(bearer(ALICE))
+                .insert_header((IF_MATCH, "\"3\""))
+                .uri("/todos/1/revert")
+                .set_json(Revert { version: 2 });
+            let response = make_request(repository.clone(), request).await;
+            let status_code = response.status();
+            let etag = response.headers().get(ETAG).unwrap().to_owned();
+            let body: Todo = response.into_body().deserialize().await;
+            assert_eq!(status_code, StatusCode::OK);
+            assert_eq!(etag, "\"4\"");
+            assert_eq!(body.title, "changed");
+
+            let request = test::TestRequest::get()
+                .insert_header(bearer(BOB))
+                .uri("/todos/1/history");
+            let response = make_request(repository.clone(), request).await;
+            assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        }
+    }
+
+    #[sqlx::test(fixtures("test/fixtures/users.sql", "test/fixtures/todos.sql"))]
+    async fn batch_todos(pool: SqlitePool) {
+        for repository in repositories(pool).await {
+            let operations = json!([
+                {"op": "create", "todo": {"title": " title ", "description": "description"}},
+                {"op": "create", "todo": {"title": "", "description": "description"}},
+                {"op": "delete", "id": 1},
+                {"op": "update", "id": 999, "todo": {"title": "title", "description": ""}},
+            ]);
+            let request = test::TestRequest::post()
+                .insert_header(bearer(ALICE))
+                .uri("/todos/batch")
+                .set_json(json!({"mode": "best_effort", "operations": operations}));
+            let response = make_request(repository.clone(), request).await;
+
+            let status_code = response.status();
+            let body: BatchResult = response.into_body().deserialize().await;
+            assert_eq!(status_code, StatusCode::OK);
+            assert!(body.committed);
+            let statuses: Vec<u16> = body.results.iter().map(|result| result.status).collect();
+            assert_eq!(statuses, vec![200, 422, 200, 404]);
+            assert_eq!(body.results[0].todo.as_ref().unwrap().todo.title, "title");
+            assert_eq!(
+                body.results[1].error.as_ref().unwrap().errors,
+                vec![FieldError::new("title", "must not be empty")]
+            );
+            assert_eq!(body.results[3].error, Some(problem(StatusCode::NOT_FOUND)));
+
+            // Atomic is the default, rolling back operations around a failing one
+            let request = test::TestRequest::post()
+                .insert_header(bearer(ALICE))
+                .uri("/todos/batch")
+                .set_json(json!({"operations": operations}));
+            let response = make_request(repository.clone(), request).await;
+
+            let body: BatchResult = response.into_body().deserialize().await;
+            assert!(!body.committed);
+            let statuses: Vec<u16> = body.results.iter().map(|result| result.status).collect();
+            assert_eq!(statuses, vec![424, 422, 424, 424]);
+            assert_eq!(
+                body.results[0].error.as_ref().unwrap().detail.as_deref(),
+                Some("Operation 1 failed, so the batch was rolled back")
+            );
+
+            let request = test::TestRequest::get()
+                .insert_header(bearer(ALICE))
+                .uri("/todos");
+            let response = make_request(repository.clone(), request).await;
+            let body: Vec<Todo> = response.into_body().deserialize().await;
+            assert_eq!(body.len(), 3);
+        }
+    }
+
+    #[sqlx::test]
+    async fn batch_todos_bad_request(pool: SqlitePool) {
+        for repository in repositories(pool).await {
+            let operations = vec![json!({"op": "delete", "id": 1}); 1001];
+            for body in [
+                json!({"operations": operations}),
+                json!({"operations": [{"op": "rename", "id": 1}]}),
+            ] {
+                let request = test::TestRequest::post()
+                    .insert_header(bearer(ALICE))
+                    .uri("/todos/batch")
+                    .set_json(body);
+                let response = make_request(repository.clone(), request).await;
+                assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+            }
+        }
+    }
+
+    #[sqlx::test(fixtures("test/fixtures/users.sql"))]
+    async fn webhooks_crud(pool: SqlitePool) {
+        for repository in repositories(pool).await {
+            let request = test::TestRequest::post()
+                .insert_header(bearer(ALICE))
+                .uri("/webhooks")
+                .set_json(CreateWebhook {
+                    url: "https://example.com/hook".to_string(),
+                });
+            let response = make_request(repository.clone(), request).await;
+            assert_eq!(response.status(), StatusCode::OK);
+            let created: CreatedWebhook = response.into_body().deserialize().await;
+            assert_eq!(created.webhook.url, "https://example.com/hook");
+            assert!(!created.secret.is_empty());
+            let webhook = created.webhook;
+
+            // The secret is only sent along with the created webhook
+            let request = test::TestRequest::get()
+                .insert_header(bearer(ALICE))
+                .uri("/webhooks");
+            let response = make_request(repository.clone(), request).await;
+            let body: Value = response.into_body().deserialize().await;
+            assert_eq!(body[0].get("secret"), None);
+            let body: Vec<Webhook> = serde_json::from_value(body).unwrap();
+            assert_eq!(body, vec![webhook.clone()]);
+
+            let uri = format!("/webhooks/{}", webhook.id);
+            let request = test::TestRequest::get()
+                .insert_header(bearer(ALICE))
+                .uri(&uri);
+            let response = make_request(repository.clone(), request).await;
+            let body: Value = response.into_body().deserialize().await;
+            assert_eq!(body.get("secret"), None);
+            let body: Webhook = serde_json::from_value(body).unwrap();
+            assert_eq!(body, webhook);
+
+            let request = test::TestRequest::get()
+                .insert_header(bearer(ALICE))
+                .uri(&format!("{uri}/dead-letters"));
+            let response = make_request(repository.clone(), request).await;
+            let body: Vec<Delivery> = response.into_body().deserialize().await;
+            assert_eq!(body, vec![]);
+
+            // Webhooks are private to their user
+            for request in [
+                test::TestRequest::get().uri(&uri),
+                test::TestRequest::delete().uri(&uri),
+                test::TestRequest::get().uri(&format!("{uri}/dead-letters")),
+            ] {
+                let response =
+                    make_request(repository.clone(), request.insert_header(bearer(BOB))).await;
+                assert_eq!(response.status(), StatusCode::NOT_FOUND);
+            }
+
+            let request = test::TestRequest::delete()
+                .insert_header(bearer(ALICE))
+                .uri(&uri);
+            let response = make_request(repository.clone(), request).await;
+            let body: Webhook = response.into_body().deserialize().await;
+            assert_eq!(body, webhook);
+
+            let request = test::TestRequest::get()
+                .insert_header(bearer(ALICE))
+                .uri(&uri);
+            let response = make_request(repository.clone(), request).await;
+            assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        }
+    }
+
+    #[sqlx::test(fixtures("test/fixtures/users.sql"))]
+    async fn create_webhook_invalid(pool: SqlitePool) {
+        for repository in repositories(pool).await {
+            let urls = [
+                "",
+                "example.com/hook",
+                "ftp://example.com",
+                "https:///hook",
+                "http://169.254.169.254/latest/meta-data",
+                "http://[::1]:8080/hook",
+            ];
+            for url in urls {
+                let request = test::TestRequest::post()
+                    .insert_header(bearer(ALICE))
+                    .uri("/webhooks")
+                    .set_json(CreateWebhook {
+                        url: url.to_string(),
+                    });
+                let response = make_request(repository.clone(), request).await;
+                let status_code = response.status();
+                let body: Problem = response.into_body().deserialize().await;
+                assert_eq!(status_code, StatusCode::UNPROCESSABLE_ENTITY, "{url}");
+                assert_eq!(body.errors.len(), 1);
+                assert_eq!(body.errors[0].field, "url");
+            }
+        }
+    }
+
+    #[sqlx::test(fixtures("test/fixtures/users.sql", "test/fixtures/todos.sql"))]
+    async fn retry_dead_letter(pool: SqlitePool) {
+        for repository in repositories(pool).await {
+            let webhook = repository
+                .create_webhook(
+                    ALICE,
+                    CreateWebhook {
+                        url: "https://example.com/hook".to_string(),
+                    },
+                    "secret",
+                )
+                .await
+                .unwrap();
+            let request = test::TestRequest::delete()
+                .insert_header(bearer(ALICE))
+                .uri("/todos/1");
+            make_request(repository.clone(), request).await;
+            let now = timestamp("2100-01-01 00:00:00");
+            let due = repository.due_deliveries(now, 10).await.unwrap();
+            let delivery = due[0].1.clone();
+            repository
+                .fail_delivery(delivery.id, "Connection refused", None)
+                .await
+                .unwrap();
+            assert_eq!(repository.due_deliveries(now, 10).await.unwrap(), vec![]);
+
+            let uri = format!("/webhooks/{}/dead-letters", webhook.id);
+            let request = test::TestRequest::get()
+                .insert_header(bearer(ALICE))
+                .uri(&uri);
+            let response = make_request(repository.clone(), request).await;
+            let body: Vec<Delivery> = response.into_body().deserialize().await;
+            assert_eq!(body.len(), 1);
+            assert_eq!(body[0].event, delivery.event);
+            assert_eq!(body[0].attempts, 1);
+            assert_eq!(body[0].last_error.as_deref(), Some("Connection refused"));
+            assert!(body[0].failed_at.is_some());
+
+            let retry_uri = format!("{uri}/{}/retry", delivery.id);
+            let request = test::TestRequest::post()
+                .insert_header(bearer(BOB))
+                .uri(&retry_uri);
+            let response = make_request(repository.clone(), request).await;
+            assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+            let request = test::TestRequest::post()
+                .insert_header(bearer(ALICE))
+                .uri(&retry_uri);
+            let response = make_request(repository.clone(), request).await;
+            let body: Delivery = response.into_body().deserialize().await;
+            assert_eq!(body.attempts, 0);
+            assert_eq!(body.failed_at, None);
+            assert_eq!(repository.due_deliveries(now, 10).await.unwrap().len(), 1);
+
+            // Only dead letters can be retried
+            let request = test::TestRequest::post()
+                .insert_header(bearer(ALICE))
+                .uri(&retry_uri);
+            let response = make_request(repository.clone(), request).await;
+            assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        }
+    }
+
+    #[sqlx::test(fixtures("test/fixtures/users.sql", "test/fixtures/todos.sql"))]
+    async fn export_todos(pool: SqlitePool) {
+        for repository in repositories(pool).await {
+            let request = test::TestRequest::delete()
+                .insert_header(bearer(ALICE))
+                .uri("/todos/3");
+            make_request(repository.clone(), request).await;
+
+            let request = test::TestRequest::get()
+                .insert_header(bearer(ALICE))
+                .uri("/todos/export");
+            let response = make_request(repository.clone(), request).await;
+            assert_eq!(response.status(), StatusCode::OK);
+            assert_eq!(
+                response.headers().get(CONTENT_DISPOSITION).unwrap(),
+                "attachment; filename=\"todos.json\""
+            );
+            let body: Vec<Todo> = response.into_body().deserialize().await;
+            assert_eq!(body, fixture_todos()[..2]);
+
+            for (format, content_type, first_line) in [
+                ("csv", "text/csv; charset=utf-8", "id,title,description"),
+                ("ics", "text/calendar; charset=utf-8", "BEGIN:VCALENDAR"),
+            ] {
+                let request = test::TestRequest::get()
+                    .insert_header(bearer(ALICE))
+                    .uri(&format!("/todos/export?format={format}"));
+                let response = make_request(repository.clone(), request).await;
+                assert_eq!(response.status(), StatusCode::OK);
+                assert_eq!(response.headers().get(CONTENT_TYPE).unwrap(), content_type);
+                let body = to_bytes(response.into_body()).await.unwrap();
+                let body = std::str::from_utf8(&body).unwrap();
+                assert!(body.starts_with(first_line), "{body}");
+                assert!(body.contains("todo2"));
+                assert!(!body.contains("todo3"));
+            }
+
+            let request = test::TestRequest::get()
+                .insert_header(bearer(BOB))
+                .uri("/todos/export?format=csv");
+            let response = make_request(repository.clone(), request).await;
+            let body = to_bytes(response.into_body()).await.unwrap();
+            assert_eq!(body.split(|byte| *byte == b'\n').count(), 2);
+
+            let request = test::TestRequest::get()
+                .insert_header(bearer(ALICE))
+                .uri("/todos/export?format=xml");
+            let response = make_request(repository.clone(), request).await;
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        }
+    }
+
+    #[sqlx::test(fixtures("test/fixtures/users.sql", "test/fixtures/todos.sql"))]
+    async fn import_todos(pool: SqlitePool) {
+        for repository in repositories(pool).await {
+            let csv = "title,description,due_at\n\
+                       todo2,description2,2024-06-10T12:00:00Z\n\
+                       new,imported,\n\
+                       new,imported,\n";
+            let request = test::TestRequest::post()
+                .insert_header(bearer(ALICE))
+                .insert_header((CONTENT_TYPE, "text/csv"))
+                .uri("/todos/import")
+                .set_payload(csv);
+            let response = make_request(repository.clone(), request).await;
+            assert_eq!(response.status(), StatusCode::OK);
+            let result: ImportResult = response.into_body().deserialize().await;
+            assert!(result.committed);
+            assert_eq!((result.created, result.duplicates), (1, 2));
+            let statuses: Vec<_> = result.rows.iter().map(|row| row.status).collect();
+            assert_eq!(
+                statuses,
+                [
+                    RowStatus::Duplicate,
+                    RowStatus::Created,
+                    RowStatus::Duplicate
+                ]
+            );
+            let todos: Vec<_> = result
+                .rows
+                .into_iter()
+                .map(|row| row.todo.unwrap())
+                .collect();
+            assert_eq!(todos[0], fixture_todos()[1]);
+            assert_eq!(todos[1].title, "new");
+            assert_eq!(todos[2], todos[1]);
+
+            let request = test::TestRequest::get()
+                .insert_header(bearer(ALICE))
+                .uri(&format!("/todos/{}/history", todos[1].id));
+            let response = make_request(repository.clone(), request).await;
+            let body: Vec<TodoEvent> = response.into_body().deserialize().await;
+            assert_eq!(body.len(), 1);
+            assert_eq!(body[0].kind, EventKind::Create);
+
+            let calendar = "BEGIN:VCALENDAR\r\nBEGIN:VTODO\r\nSUMMARY:from ics\r\n\
+                            STATUS:COMPLETED\r\nEND:VTODO\r\nEND:VCALENDAR\r\n";
+            let request = test::TestRequest::post()
+                .insert_header(bearer(BOB))
+                .uri("/todos/import?format=ics")
+                .set_payload(calendar);
+            let response = make_request(repository.clone(), request).await;
+            let result: ImportResult = response.into_body().deserialize().await;
+            let todo = result.rows[0].todo.as_ref().unwrap();
+            assert_eq!(todo.user_id, Some(BOB));
+            assert!(todo.completed);
+        }
+    }
+
+    #[sqlx::test(fixtures("test/fixtures/users.sql", "test/fixtures/todos.sql"))]
+    async fn import_todos_rejected(pool: SqlitePool) {
+        for repository in repositories(pool).await {
+            let request = test::TestRequest::post()
+                .insert_header(bearer(ALICE))
+                .uri("/todos/import")
+                .set_json(json!([
+                    {"title": "valid", "description": ""},
+                    {"title": "", "description": "", "due_at": "soon"},
+                    {"title": "too long to be a valid title", "description": ""},
+                ]));
+            let response = make_request(repository.clone(), request).await;
+            assert_eq!(response.status(), StatusCode::OK);
+            let result: ImportResult = response.into_body().deserialize().await;
+            assert!(!result.committed);
+            assert_eq!(result.created, 0);
+            let statuses: Vec<_> = result.rows.iter().map(|row| row.status).collect();
+            assert_eq!(
+                statuses,
+                [RowStatus::Skipped, RowStatus::Invalid, RowStatus::Invalid]
+            );
+            assert_eq!(result.rows[1].row, 2);
+            assert_eq!(result.rows[1].errors[0].field, "row");
+            assert_eq!(result.rows[2].errors[0].field, "title");
+
+            // Nothing was imported
+            let todos = repository.export_todos(ALICE).await.unwrap();
+            assert_eq!(todos, fixture_todos());
+
+            for request in [
+                test::TestRequest::post()
+                    .uri("/todos/import")
+                    .insert_header((CONTENT_TYPE, "text/plain"))
+                    .set_payload("todo"),
+                test::TestRequest::post()
+                    .uri("/todos/import?format=csv")
+                    .set_payload("name\ntodo\n"),
+                test::TestRequest::post()
+                    .uri("/todos/import?format=ics")
+                    .set_payload("SUMMARY:todo"),
+            ] {
+                let response =
+                    make_request(repository.clone(), request.insert_header(bearer(ALICE))).await;
+                assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+            }
+        }
+    }
+
+    /// Requests nesting todo 3 under todo 2, itself under todo 1.
+    fn nest_fixture_todos() -> Vec<test::TestRequest> {
+        [(2, 1), (3, 2)]
+            .into_iter()
+            .map(|(id, parent_id)| {
+                test::TestRequest::patch()
+                    .insert_header(bearer(ALICE))
+                    .uri(&format!("/todos/{id}"))
+                    .set_json(json!({"parent_id": parent_id}))
+            })
+            .collect()
+    }
+
+    #[sqlx::test(fixtures("test/fixtures/users.sql", "test/fixtures/todos.sql"))]
+    async fn subtasks(pool: SqlitePool) {
+        for repository in repositories(pool).await {
+            let mut requests = nest_fixture_todos();
+            requests.extend([
+                test::TestRequest::get()
+                    .insert_header(bearer(ALICE))
+                    .uri("/todos/1/children"),
+                test::TestRequest::get()
+                    .insert_header(bearer(ALICE))
+                    .uri("/todos?tree=true"),
+                test::TestRequest::get()
+                    .insert_header(bearer(BOB))
+                    .uri("/todos/1/children"),
+            ]);
+            let mut responses = make_requests(repository.clone(), test_config(), requests)
+                .await
+                .into_iter();
+            for _ in 0..2 {
+                assert_eq!(responses.next().unwrap().status(), StatusCode::OK);
+            }
+
+            let response = responses.next().unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            let children: Vec<TaggedTodo> = response.into_body().deserialize().await;
+            let ids: Vec<_> = children.iter().map(|child| child.todo.id).collect();
+            assert_eq!(ids, [2]);
+            assert_eq!(children[0].todo.parent_id, Some(1));
+
+            let response = responses.next().unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            assert!(response.headers().contains_key(ETAG));
+            let tree: Value = response.into_body().deserialize().await;
+            assert_eq!(tree.as_array().unwrap().len(), 1);
+            assert_eq!(tree[0]["id"], 1);
+            assert_eq!(tree[0]["children"][0]["id"], 2);
+            assert_eq!(tree[0]["children"][0]["children"][0]["id"], 3);
+            assert_eq!(tree[0]["children"][0]["children"][0]["children"], json!([]));
+
+            let response = responses.next().unwrap();
+            assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        }
+    }
+
+    #[sqlx::test(fixtures("test/fixtures/users.sql", "test/fixtures/todos.sql"))]
+    async fn subtasks_invalid_parent(pool: SqlitePool) {
+        for repository in repositories(pool).await {
+            let mut requests = nest_fixture_todos();
+            requests.extend([
+                // Todo 3 is a subtask of todo 2, itself a subtask of todo 1
+                test::TestRequest::patch()
+                    .insert_header(bearer(ALICE))
+                    .uri("/todos/1")
+                    .set_json(json!({"parent_id": 3})),
+                test::TestRequest::patch()
+                    .insert_header(bearer(ALICE))
+                    .uri("/todos/1")
+                    .set_json(json!({"parent_id": 1})),
+                test::TestRequest::post()
+                    .insert_header(bearer(ALICE))
+                    .uri("/todos")
+                    .set_json(json!({"title": "subtask", "description": "", "parent_id": 999})),
+                // Todos of other users cannot be parents
+                test::TestRequest::post()
+                    .insert_header(bearer(BOB))
+                    .uri("/todos")
+                    .set_json(json!({"title": "subtask", "description": "", "parent_id": 1})),
+            ]);
+            let responses = make_requests(repository.clone(), test_config(), requests).await;
+            let mut errors = Vec::new();
+            for response in responses.into_iter().skip(2) {
+                let status = response.status();
+                let body: Problem = response.into_body().deserialize().await;
+                assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY, "{body:?}");
+                errors.extend(body.errors);
+            }
+            let cycle = FieldError::new(
+                "parent_id",
+                "must not be the todo itself or one of its subtasks",
+            );
+            let unknown = FieldError::new("parent_id", "must be an existing todo");
+            assert_eq!(errors, [cycle.clone(), cycle, unknown.clone(), unknown]);
+        }
+    }
+
+    #[sqlx::test(fixtures("test/fixtures/users.sql", "test/fixtures/todos.sql"))]
+    async fn todo_tree_bad_request(pool: SqlitePool) {
+        for repository in repositories(pool).await {
+            let request = test::TestRequest::get()
+                .insert_header(bearer(ALICE))
+                .uri("/todos?tree=true&completed=false");
+            let response = make_request(repository.clone(), request).await;
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        }
+    }
+
+    /// Deletes todo 1 once nested, answering with the status of the deletion
+    /// and the ids of the todos left, in order.
+    async fn delete_nested(
+        repository: Arc<dyn TodoRepository>,
+        policy: DeletePolicy,
+    ) -> (StatusCode, Vec<i64>) {
+        let config = Config {
+            subtask_delete_policy: policy,
+            ..test_config()
+        };
+        let mut requests = nest_fixture_todos();
+        requests.extend([
+            test::TestRequest::delete()
+                .insert_header(bearer(ALICE))
+                .uri("/todos/1"),
+            test::TestRequest::get()
+                .insert_header(bearer(ALICE))
+                .uri("/todos?tree=true"),
+        ]);
+        let mut responses = make_requests(repository, config, requests)
+            .await
+            .into_iter()
+            .skip(2);
+        let status_code = responses.next().unwrap().status();
+
+        let mut nodes: Vec<TodoNode> = responses.next().unwrap().into_body().deserialize().await;
+        let mut ids = Vec::new();
+        while let Some(node) = nodes.pop() {
+            ids.push(node.todo.todo.id);
+            nodes.extend(node.children);
+        }
+        ids.sort();
+        (status_code, ids)
+    }
+
+    /// Deletes todo 1 once nested, answering with the first `count` changes
+    /// published by the deletion, as their kind and the id of their todo.
+    async fn delete_nested_changes(
+        repository: Arc<dyn TodoRepository>,
+        policy: DeletePolicy,
+        count: usize,
+    ) -> Vec<(EventKind, i64)> {
+        let config = Config {
+            subtask_delete_policy: policy,
+            ..test_config()
+        };
+        let mut requests = nest_fixture_todos();
+        requests.extend([
+            test::TestRequest::get()
+                .insert_header(bearer(ALICE))
+                .uri("/todos/events"),
+            test::TestRequest::delete()
+                .insert_header(bearer(ALICE))
+                .uri("/todos/1"),
+        ]);
+        let mut responses = make_requests(repository, config, requests)
+            .await
+            .into_iter()
+            .skip(2);
+        let mut events = responses.next().unwrap().into_body();
+        let mut changes = Vec::new();
+        for _ in 0..count {
+            let (_, _, change) = parse_event(&events.next_event().await);
+            changes.push((change.kind, change.todo.todo.id));
+        }
+        changes
+    }
+
+    #[sqlx::test(fixtures("test/fixtures/users.sql", "test/fixtures/todos.sql"))]
+    async fn delete_subtasks_reject(pool: SqlitePool) {
+        for repository in repositories(pool).await {
+            let deleted = delete_nested(repository, DeletePolicy::Reject).await;
+            assert_eq!(deleted, (StatusCode::CONFLICT, vec![1, 2, 3]));
+        }
+    }
+
+    #[sqlx::test(fixtures("test/fixtures/users.sql", "test/fixtures/todos.sql"))]
+    async fn delete_subtasks_orphan(pool: SqlitePool) {
+        for repository in repositories(pool).await {
+            let deleted = delete_nested(repository.clone(), DeletePolicy::Orphan).await;
+            assert_eq!(deleted, (StatusCode::OK, vec![2, 3]));
+
+            let orphan = repository.get_todo(ALICE, 2).await.unwrap();
+            assert_eq!(orphan.parent_id, None);
+            assert_eq!(orphan.version, fixture_todos()[1].version + 2);
+            let subtask = repository.get_todo(ALICE, 3).await.unwrap();
+            assert_eq!(subtask.parent_id, Some(2));
+        }
+    }
+
+    #[sqlx::test(fixtures("test/fixtures/users.sql", "test/fixtures/todos.sql"))]
+    async fn delete_subtasks_orphan_changes(pool: SqlitePool) {
+        for repository in repositories(pool).await {
+            let changes = delete_nested_changes(repository, DeletePolicy::Orphan, 2).await;
+            assert_eq!(changes, [(EventKind::Update, 2), (EventKind::Delete, 1)]);
+        }
+    }
+
+    #[sqlx::test(fixtures("test/fixtures/users.sql", "test/fixtures/todos.sql"))]
+    async fn delete_subtasks_cascade_changes(pool: SqlitePool) {
+        for repository in repositories(pool).await {
+            let changes = delete_nested_changes(repository, DeletePolicy::Cascade, 3).await;
+            assert_eq!(
+                changes,
+                [
+                    (EventKind::Delete, 2),
+                    (EventKind::Delete, 3),
+                    (EventKind::Delete, 1)
+                ]
+            );
+        }
+    }
+
+    #[sqlx::test(fixtures("test/fixtures/users.sql", "test/fixtures/todos.sql"))]
+    async fn delete_subtasks_cascade(pool: SqlitePool) {
+        for repository in repositories(pool).await {
+            let deleted = delete_nested(repository.clone(), DeletePolicy::Cascade).await;
+            assert_eq!(deleted, (StatusCode::OK, vec![]));
+
+            let trash = repository.list_trash(ALICE).await.unwrap();
+            let ids: HashSet<_> = trash.iter().map(|todo| todo.id).collect();
+            assert_eq!(ids, HashSet::from([1, 2, 3]));
+        }
+    }
+
+    #[sqlx::test(fixtures("test/fixtures/users.sql", "test/fixtures/todos.sql"))]
+    async fn recurring_todo(pool: SqlitePool) {
+        for repository in repositories(pool).await {
+            let requests = vec![
+                test::TestRequest::get()
+                    .uri("/todos/events")
+                    .insert_header(bearer(ALICE)),
+                test::TestRequest::patch()
+                    .insert_header(bearer(ALICE))
+                    .uri("/todos/3")
+                    .set_json(json!({"recurrence": "FREQ=WEEKLY;COUNT=3"})),
+                test::TestRequest::get()
+                    .insert_header(bearer(ALICE))
+                    .uri("/todos/3/occurrences?from=2024-06-21T00:00:00Z"),
+                test::TestRequest::patch()
+                    .insert_header(bearer(ALICE))
+                    .uri("/todos/3")
+                    .set_json(json!({"completed": true})),
+                test::TestRequest::get()
+                    .insert_header(bearer(ALICE))
+                    .uri("/todos?completed=false"),
+            ];
+            let mut responses = make_requests(repository.clone(), test_config(), requests)
+                .await
+                .into_iter();
+            let mut events = responses.next().unwrap().into_body();
+
+            let response = responses.next().unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            let body: UpdatedTodo = response.into_body().deserialize().await;
+            assert_eq!(
+                body.todo.todo.recurrence.as_deref(),
+                Some("FREQ=WEEKLY;COUNT=3")
+            );
+            assert_eq!(body.next_occurrence, None);
+
+            let response = responses.next().unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            let body: Value = response.into_body().deserialize().await;
+            assert_eq!(
+                body,
+                json!([
+                    {"due_at": "2024-06-27T12:00:00Z"},
+                    {"due_at": "2024-07-04T12:00:00Z"},
+                ])
+            );
+
+            // The schedule moves on to the next occurrence
+            let response = responses.next().unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            let body: UpdatedTodo = response.into_body().deserialize().await;
+            assert!(body.todo.todo.completed);
+            assert_eq!(body.todo.todo.recurrence, None);
+            let next = body.next_occurrence.unwrap();
+
+            let body: Vec<TaggedTodo> = responses.next().unwrap().into_body().deserialize().await;
+            assert_eq!(body.last(), Some(&next));
+            assert_eq!(next.todo.title, fixture_todos()[2].title);
+            assert_eq!(next.todo.due_at, Some(timestamp("2024-06-27 12:00:00")));
+            assert_eq!(next.todo.recurrence.as_deref(), Some("FREQ=WEEKLY;COUNT=2"));
+
+            // The next occurrence is published as created
+            let mut changes = Vec::new();
+            for _ in 0..3 {
+                let (_, kind, change) = parse_event(&events.next_event().await);
+                changes.push((kind, change.todo.todo.id));
+            }
+            let kinds = ["update", "update", "create"].map(String::from);
+            assert_eq!(
+                changes,
+                kinds
+                    .into_iter()
+                    .zip([3, 3, next.todo.id])
+                    .collect::<Vec<_>>()
+            );
+        }
+    }
+
+    #[sqlx::test(fixtures("test/fixtures/users.sql", "test/fixtures/todos.sql"))]
+    async fn recurring_todo_batch(pool: SqlitePool) {
+        for repository in repositories(pool).await {
+            let todo = json!({
+                "title": "todo3",
+                "description": "description3",
+                "completed": true,
+                "due_at": "2024-06-20T12:00:00Z",
+                "recurrence": "FREQ=WEEKLY;COUNT=3",
+            });
+            let requests = vec![
+                test::TestRequest::get()
+                    .uri("/todos/events")
+                    .insert_header(bearer(ALICE)),
+                test::TestRequest::post()
+                    .insert_header(bearer(ALICE))
+                    .uri("/todos/batch")
+                    .set_json(json!({"operations": [{"op": "update", "id": 3, "todo": todo}]})),
+                test::TestRequest::get()
+                    .insert_header(bearer(ALICE))
+                    .uri("/todos?completed=false"),
+            ];
+            let mut responses = make_requests(repository.clone(), test_config(), requests)
+                .await
+                .into_iter();
+            let mut events = responses.next().unwrap().into_body();
+
+            let body: BatchResult = responses.next().unwrap().into_body().deserialize().await;
+            assert!(body.committed);
+            let result = &body.results[0];
+            assert!(result.todo.as_ref().unwrap().todo.completed);
+            let next = result.next_occurrence.clone().unwrap();
+            assert_eq!(next.todo.due_at, Some(timestamp("2024-06-27 12:00:00")));
+
+            let body: Vec<TaggedTodo> = responses.next().unwrap().into_body().deserialize().await;
+            assert_eq!(body.last(), Some(&next));
+
+            // The next occurrence is published as created, like outside a batch
+            let mut changes = Vec::new();
+            for _ in 0..2 {
+                let (_, kind, change) = parse_event(&events.next_event().await);
+                changes.push((kind, change.todo.todo.id));
+            }
+            assert_eq!(
+                changes,
+                vec![
+                    ("update".to_string(), 3),
+                    ("create".to_string(), next.todo.id)
+                ]
+            );
+        }
+    }
+
+    #[sqlx::test(fixtures("test/fixtures/users.sql", "test/fixtures/todos.sql"))]
+    async fn recurring_todo_invalid(pool: SqlitePool) {
+        for repository in repositories(pool).await {
+            let requests = vec![
+                test::TestRequest::put()
+                    .insert_header(bearer(ALICE))
+                    .uri("/todos/1")
+                    .set_json(json!({
+                        "title": "todo1",
+                        "description": "",
+                        "recurrence": "FREQ=YEARLY",
+                    })),
+                test::TestRequest::get()
+                    .insert_header(bearer(ALICE))
+                    .uri("/todos/3/occurrences?from=2024-07-01T00:00:00Z&to=2024-06-01T00:00:00Z"),
+                test::TestRequest::get()
+                    .insert_header(bearer(BOB))
+                    .uri("/todos/3/occurrences"),
+            ];
+            let mut responses = make_requests(repository.clone(), test_config(), requests)
+                .await
+                .into_iter();
+
+            let response = responses.next().unwrap();
+            assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+            let body: Problem = response.into_body().deserialize().await;
+            assert_eq!(
+                body.errors,
+                vec![
+                    FieldError::new("recurrence", "requires a due date"),
+                    FieldError::new(
+                        "recurrence",
+                        "must be a supported RRULE: Unsupported FREQ 'YEARLY', must be DAILY, WEEKLY or MONTHLY"
+                    ),
+                ]
+            );
+            let response = responses.next().unwrap();
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+            let response = responses.next().unwrap();
+            assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        }
+    }
+
+    #[sqlx::test(fixtures("test/fixtures/users.sql", "test/fixtures/todos.sql"))]
+    async fn reminders(pool: SqlitePool) {
+        for repository in repositories(pool).await {
+            let requests = vec![
+                test::TestRequest::post()
+                    .insert_header(bearer(ALICE))
+                    .uri("/todos/3/reminders")
+                    .set_json(json!({"offset_seconds": 3600})),
+                test::TestRequest::get()
+                    .insert_header(bearer(ALICE))
+                    .uri("/todos/3/reminders"),
+                test::TestRequest::get()
+                    .insert_header(bearer(BOB))
+                    .uri("/todos/3/reminders"),
+                test::TestRequest::delete()
+                    .insert_header(bearer(ALICE))
+                    .uri("/todos/1/reminders/1"),
+                test::TestRequest::delete()
+                    .insert_header(bearer(ALICE))
+                    .uri("/todos/3/reminders/1"),
+                test::TestRequest::get()
+                    .insert_header(bearer(ALICE))
+                    .uri("/todos/3/reminders"),
+            ];
+            let mut responses = make_requests(repository.clone(), test_config(), requests)
+                .await
+                .into_iter();
+
+            let response = responses.next().unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            let created: Reminder = response.into_body().deserialize().await;
+            assert_eq!(created.todo_id, 3);
+            assert_eq!(created.offset_seconds, 3600);
+            assert_eq!(created.fired_at, None);
+
+            let response = responses.next().unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            let body: Vec<Reminder> = response.into_body().deserialize().await;
+            assert_eq!(body, vec![created.clone()]);
+
+            // Other users' todos and reminders of other todos are not found
+            let response = responses.next().unwrap();
+            assert_eq!(response.status(), StatusCode::NOT_FOUND);
+            let response = responses.next().unwrap();
+            assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+            let response = responses.next().unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            let body: Reminder = response.into_body().deserialize().await;
+            assert_eq!(body, created);
+
+            let response = responses.next().unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            let body: Vec<Reminder> = response.into_body().deserialize().await;
+            assert_eq!(body, vec![]);
+        }
+    }
+
+    #[sqlx::test(fixtures("test/fixtures/users.sql", "test/fixtures/todos.sql"))]
+    async fn create_reminder_invalid(pool: SqlitePool) {
+        for repository in repositories(pool).await {
+            let request = test::TestRequest::post()
+                .insert_header(bearer(ALICE))
+                .uri("/todos/3/reminders")
+                .set_json(json!({"offset_seconds": -60}));
+            let response = make_request(repository.clone(), request).await;
+            assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+            let body: Problem = response.into_body().deserialize().await;
+            assert_eq!(body.errors.len(), 1);
+            assert_eq!(body.errors[0].field, "offset_seconds");
+        }
     }
 }
@@ -0,0 +1,142 @@
+use crate::{
+    error::ApiError,
+    query::{DEFAULT_LIMIT, MAX_LIMIT},
+    tag::TaggedTodo,
+};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+pub const HIGHLIGHT_START: &str = "<mark>";
+pub const HIGHLIGHT_END: &str = "</mark>";
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct SearchQuery {
+    /// Words to search for, a trailing `*` matching any word they begin.
+    pub q: Option<String>,
+    pub limit: Option<i64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SearchParams {
+    /// Terms that all have to be found in a todo for it to match.
+    pub terms: Vec<SearchTerm>,
+    pub limit: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SearchTerm {
+    pub text: String,
+    /// Whether the term also matches words it is the beginning of.
+    pub prefix: bool,
+}
+
+impl TryFrom<SearchQuery> for SearchParams {
+    type Error = ApiError;
+
+    fn try_from(query: SearchQuery) -> Result<Self, Self::Error> {
+        let limit = query.limit.unwrap_or(DEFAULT_LIMIT);
+        if !(1..=MAX_LIMIT).contains(&limit) {
+            return Err(ApiError::BadRequest(format!(
+                "Invalid 'limit' value '{limit}', must be between 1 and {MAX_LIMIT}"
+            )));
+        }
+
+        // A trailing `*` makes a term a prefix query
+        let terms: Vec<SearchTerm> = query
+            .q
+            .as_deref()
+            .unwrap_or_default()
+            .split_whitespace()
+            .filter_map(|term| {
+                let prefix = term.ends_with('*');
+                let text = term.trim_end_matches('*');
+                (!text.is_empty()).then(|| SearchTerm {
+                    text: text.to_string(),
+                    prefix,
+                })
+            })
+            .collect();
+        if terms.is_empty() {
+            return Err(ApiError::BadRequest("'q' must not be empty".to_string()));
+        }
+
+        Ok(Self { terms, limit })
+    }
+}
+
+impl SearchParams {
+    /// FTS5 expression matching todos containing every term. Terms are quoted
+    /// so that FTS5 operators are searched for literally.
+    pub fn expression(&self) -> String {
+        let terms: Vec<String> = self
+            .terms
+            .iter()
+            .map(|term| {
+                let prefix = if term.prefix { "*" } else { "" };
+                format!("\"{}\"{prefix}", term.text.replace('"', "\"\""))
+            })
+            .collect();
+        terms.join(" ")
+    }
+}
+
+/// A todo matching a search, along with the matched terms highlighted.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, ToSchema)]
+pub struct SearchHit {
+    #[serde(flatten)]
+    pub todo: TaggedTodo,
+    pub highlights: Highlights,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, ToSchema)]
+pub struct Highlights {
+    /// The whole title, with matches wrapped in `<mark>` tags.
+    pub title: String,
+    /// A short excerpt of the description around its matches.
+    pub description: String,
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        error::ApiError,
+        search::{SearchParams, SearchQuery},
+    };
+    use assert_matches::assert_matches;
+
+    fn params(q: &str) -> Result<SearchParams, ApiError> {
+        let query = SearchQuery {
+            q: Some(q.to_string()),
+            limit: None,
+        };
+        SearchParams::try_from(query)
+    }
+
+    #[test]
+    fn expression_quotes_terms() {
+        let expression = |q| params(q).unwrap().expression();
+        assert_eq!(expression("buy  milk"), "\"buy\" \"milk\"");
+        assert_eq!(
+            expression("mil** \"x\" OR -y"),
+            "\"mil\"* \"\"\"x\"\"\" \"OR\" \"-y\""
+        );
+        assert_matches!(params("**"), Err(ApiError::BadRequest(_)));
+        assert_matches!(params("  "), Err(ApiError::BadRequest(_)));
+    }
+
+    #[test]
+    fn params_invalid() {
+        let query = SearchQuery {
+            q: None,
+            limit: None,
+        };
+        assert_matches!(SearchParams::try_from(query), Err(ApiError::BadRequest(_)));
+
+        let query = SearchQuery {
+            q: Some("milk".to_string()),
+            limit: Some(0),
+        };
+        assert_matches!(SearchParams::try_from(query), Err(ApiError::BadRequest(_)));
+    }
+}
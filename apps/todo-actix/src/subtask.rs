@@ -0,0 +1,156 @@
+use crate::{
+    error::{FieldError, InternalError},
+    tag::TaggedTodo,
+    todo::Todo,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    str::FromStr,
+};
+use utoipa::ToSchema;
+
+/// Levels of todos a tree may have, which keeps walking it cheap.
+pub const MAX_DEPTH: usize = 32;
+
+pub const HAS_SUBTASKS: &str = "Todo has subtasks, which must be deleted or moved first";
+
+/// What becomes of the subtasks of a deleted todo.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DeletePolicy {
+    /// Subtasks are moved to the trash along with their parent.
+    Cascade,
+    /// Subtasks are kept as top-level todos.
+    Orphan,
+    /// Todos cannot be deleted while they have subtasks.
+    #[default]
+    Reject,
+}
+
+impl FromStr for DeletePolicy {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "cascade" => Ok(Self::Cascade),
+            "orphan" => Ok(Self::Orphan),
+            "reject" => Ok(Self::Reject),
+            _ => Err(format!("Unknown delete policy '{value}'")),
+        }
+    }
+}
+
+/// A todo moved to the trash, along with the subtasks the delete policy
+/// changed: trashed along with it, or made top-level todos.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Deleted {
+    pub todo: Todo,
+    pub subtasks: Vec<Todo>,
+}
+
+/// A todo along with its subtasks, themselves along with theirs.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, ToSchema)]
+pub struct TodoNode {
+    #[serde(flatten)]
+    pub todo: TaggedTodo,
+    #[schema(no_recursion)]
+    pub children: Vec<TodoNode>,
+}
+
+pub fn unknown_parent() -> FieldError {
+    FieldError::new("parent_id", "must be an existing todo")
+}
+
+pub fn cycle() -> FieldError {
+    FieldError::new(
+        "parent_id",
+        "must not be the todo itself or one of its subtasks",
+    )
+}
+
+pub fn too_deep() -> FieldError {
+    FieldError::new(
+        "parent_id",
+        format!("must not nest subtasks more than {MAX_DEPTH} levels deep"),
+    )
+}
+
+pub fn check_depth(depth: usize) -> Result<(), InternalError> {
+    if depth > MAX_DEPTH {
+        return Err(InternalError::Constraint(too_deep()));
+    }
+    Ok(())
+}
+
+/// Nests todos under their parent, keeping them in the given order. Todos
+/// whose parent is not given are roots.
+pub fn tree(todos: Vec<TaggedTodo>) -> Vec<TodoNode> {
+    let ids: HashSet<i64> = todos.iter().map(|todo| todo.todo.id).collect();
+    let mut roots = Vec::new();
+    let mut children: HashMap<i64, Vec<TaggedTodo>> = HashMap::new();
+    for todo in todos {
+        match todo
+            .todo
+            .parent_id
+            .filter(|parent_id| ids.contains(parent_id))
+        {
+            Some(parent_id) => children.entry(parent_id).or_default().push(todo),
+            None => roots.push(todo),
+        }
+    }
+    roots
+        .into_iter()
+        .map(|todo| node(todo, &mut children))
+        .collect()
+}
+
+fn node(todo: TaggedTodo, children: &mut HashMap<i64, Vec<TaggedTodo>>) -> TodoNode {
+    let nodes = children
+        .remove(&todo.todo.id)
+        .unwrap_or_default()
+        .into_iter()
+        .map(|child| node(child, children))
+        .collect();
+    TodoNode {
+        todo,
+        children: nodes,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        subtask::{tree, DeletePolicy},
+        tag::TaggedTodo,
+        test::fixture_todos,
+    };
+
+    #[test]
+    fn parse_policy() {
+        assert_eq!("cascade".parse(), Ok(DeletePolicy::Cascade));
+        assert_eq!("orphan".parse(), Ok(DeletePolicy::Orphan));
+        assert!("delete".parse::<DeletePolicy>().is_err());
+    }
+
+    #[test]
+    fn nest_todos() {
+        let mut todos: Vec<TaggedTodo> = fixture_todos()
+            .into_iter()
+            .map(|todo| TaggedTodo { todo, tags: vec![] })
+            .collect();
+        todos[1].todo.parent_id = Some(1);
+        todos[2].todo.parent_id = Some(2);
+        let mut orphan = todos[0].clone();
+        orphan.todo.id = 4;
+        orphan.todo.parent_id = Some(99);
+        todos.push(orphan);
+
+        let nodes = tree(todos.clone());
+        let ids: Vec<_> = nodes.iter().map(|node| node.todo.todo.id).collect();
+        assert_eq!(ids, [1, 4]);
+        assert_eq!(nodes[0].children[0].todo, todos[1]);
+        assert_eq!(nodes[0].children[0].children[0].todo, todos[2]);
+        assert_eq!(nodes[0].children[0].children[0].children, vec![]);
+    }
+}
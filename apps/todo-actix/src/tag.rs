@@ -0,0 +1,72 @@
+use crate::{
+    error::FieldError,
+    todo::Todo,
+    validate::{self, Validate},
+};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use utoipa::ToSchema;
+
+pub const NAME_MAX_LENGTH: usize = 32;
+
+pub const NAME_TAKEN: &str = "Tag already exists";
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, FromRow, ToSchema)]
+pub struct Tag {
+    pub id: i64,
+    pub name: String,
+}
+
+#[derive(Debug, PartialEq, Eq, Deserialize, Serialize, ToSchema)]
+pub struct CreateTag {
+    pub name: String,
+}
+
+#[derive(Debug, PartialEq, Eq, Deserialize, Serialize, ToSchema)]
+pub struct UpdateTag {
+    pub name: String,
+}
+
+#[derive(Debug, PartialEq, Eq, Deserialize, Serialize, ToSchema)]
+pub struct AttachTag {
+    pub tag_id: i64,
+}
+
+/// A tag once renamed or deleted, along with the todos carrying it, whose
+/// version was bumped.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChangedTag {
+    pub tag: Tag,
+    pub todos: Vec<Todo>,
+}
+
+/// A todo along with the tags attached to it, as returned by the API.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, ToSchema)]
+pub struct TaggedTodo {
+    #[serde(flatten)]
+    pub todo: Todo,
+    pub tags: Vec<Tag>,
+}
+
+impl Validate for CreateTag {
+    fn validate(self) -> Result<Self, Vec<FieldError>> {
+        let name = validate_name(self.name)?;
+        Ok(Self { name })
+    }
+}
+
+impl Validate for UpdateTag {
+    fn validate(self) -> Result<Self, Vec<FieldError>> {
+        let name = validate_name(self.name)?;
+        Ok(Self { name })
+    }
+}
+
+fn validate_name(name: String) -> Result<String, Vec<FieldError>> {
+    let mut errors = Vec::new();
+    let name = validate::text(&mut errors, "name", name, true, NAME_MAX_LENGTH);
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    Ok(name)
+}
@@ -0,0 +1,6 @@
+INSERT INTO tags (user_id, name) VALUES (1, "home");
+INSERT INTO tags (user_id, name) VALUES (1, "work");
+INSERT INTO tags (user_id, name) VALUES (2, "work");
+INSERT INTO todo_tags (todo_id, tag_id) VALUES (1, 1);
+INSERT INTO todo_tags (todo_id, tag_id) VALUES (2, 1);
+INSERT INTO todo_tags (todo_id, tag_id) VALUES (2, 2);
@@ -1,3 +1,3 @@
-INSERT INTO todos (title, description) VALUES ("todo1", "description1");
-INSERT INTO todos (title, description) VALUES ("todo2", "description2");
-INSERT INTO todos (title, description) VALUES ("todo3", "description3");
+INSERT INTO todos (user_id, title, description, completed, due_at, created_at, updated_at) VALUES (1, "todo1", "description1", FALSE, NULL, "2024-06-01 10:00:00", "2024-06-01 10:00:00");
+INSERT INTO todos (user_id, title, description, completed, due_at, created_at, updated_at) VALUES (1, "todo2", "description2", TRUE, "2024-06-10 12:00:00", "2024-06-02 10:00:00", "2024-06-03 10:00:00");
+INSERT INTO todos (user_id, title, description, completed, due_at, created_at, updated_at) VALUES (1, "todo3", "description3", FALSE, "2024-06-20 12:00:00", "2024-06-03 10:00:00", "2024-06-03 10:00:00");
@@ -0,0 +1,2 @@
+INSERT INTO users (username, password_hash, created_at) VALUES ("alice", "", "2024-06-01 09:00:00");
+INSERT INTO users (username, password_hash, created_at) VALUES ("bob", "", "2024-06-01 09:00:00");
@@ -1,15 +1,41 @@
-use crate::app::configure_app;
+use crate::{
+    app::configure_app,
+    auth,
+    config::Config,
+    cors::cors,
+    error::Problem,
+    etag::IfMatch,
+    memory::MemoryRepository,
+    metrics::{Metrics, RequestMetrics},
+    rate_limit::{RateLimit, RateLimiter},
+    reminder::Clock,
+    repository::{SqliteRepository, TodoRepository},
+    todo::{Todo, UpdateTodo},
+    validate::Validate,
+    webhook::{AddressPolicy, SIGNATURE, TIMESTAMP},
+};
 use actix_web::{
-    body::{to_bytes, BoxBody},
+    body::{to_bytes, BoxBody, MessageBody},
     dev::ServiceResponse,
-    test, App,
+    http::{header::AUTHORIZATION, StatusCode},
+    middleware::Condition,
+    test, web, App, HttpRequest, HttpResponse,
 };
+use chrono::{Duration, NaiveDateTime};
 use serde::de::DeserializeOwned;
+use serde_json::Value;
 use sqlx::SqlitePool;
+use std::{
+    future::poll_fn,
+    pin::Pin,
+    sync::{Arc, Mutex},
+};
 
 pub trait BoxBodyTest {
     async fn deserialize<T: DeserializeOwned>(&mut self) -> T;
-    async fn as_str(&mut self) -> String;
+
+    /// Reads the next Server-Sent Event of a stream, skipping comments.
+    async fn next_event(&mut self) -> String;
 }
 
 impl BoxBodyTest for BoxBody {
@@ -22,15 +48,203 @@ impl BoxBodyTest for BoxBody {
         serde_json::from_str(&data).unwrap()
     }
 
-    async fn as_str(&mut self) -> String {
-        let body = to_bytes(self).await.unwrap();
-        std::str::from_utf8(&body).unwrap().to_string()
+    async fn next_event(&mut self) -> String {
+        loop {
+            let chunk = poll_fn(|cx| Pin::new(&mut *self).poll_next(cx)).await;
+            let chunk = chunk.unwrap().unwrap();
+            let event = std::str::from_utf8(&chunk).unwrap();
+            if !event.starts_with(':') {
+                return event.to_string();
+            }
+        }
+    }
+}
+
+/// Users inserted by `fixtures/users.sql`.
+pub const ALICE: i64 = 1;
+pub const BOB: i64 = 2;
+
+pub fn test_config() -> Config {
+    Config {
+        token_secret: "secret".to_string(),
+        // Webhooks are delivered to servers started by the tests
+        webhook_policy: AddressPolicy {
+            allowed: vec!["127.0.0.1".parse().unwrap()],
+            ..Default::default()
+        },
+        ..Default::default()
     }
 }
 
-pub async fn make_request(pool: SqlitePool, request: test::TestRequest) -> ServiceResponse {
-    let app = App::new().configure(|config| configure_app(config, pool));
+pub fn bearer(user_id: i64) -> (actix_web::http::header::HeaderName, String) {
+    let config = test_config();
+    let token = auth::sign_token(&config.token_secret, user_id, config.token_ttl);
+    (AUTHORIZATION, format!("Bearer {token}"))
+}
+
+/// Both repositories, holding the data loaded into the database by fixtures.
+pub async fn repositories(pool: SqlitePool) -> [Arc<dyn TodoRepository>; 2] {
+    let memory = MemoryRepository::copy(&pool).await.unwrap();
+    [Arc::new(SqliteRepository::new(pool)), Arc::new(memory)]
+}
+
+/// Applies a JSON Merge Patch onto a todo the way its route does.
+pub async fn patch(repository: &dyn TodoRepository, user_id: i64, id: i64, patch: &Value) -> Todo {
+    let current = repository.get_todo(user_id, id).await.unwrap();
+    let todo = UpdateTodo::patched(current, patch)
+        .unwrap()
+        .validate()
+        .unwrap();
+    repository
+        .update_todo(user_id, id, todo, &IfMatch::Any)
+        .await
+        .unwrap()
+        .todo
+}
+
+pub async fn make_request(
+    repository: Arc<dyn TodoRepository>,
+    request: test::TestRequest,
+) -> ServiceResponse {
+    let mut responses = make_requests(repository, test_config(), vec![request]).await;
+    responses.pop().unwrap()
+}
+
+/// Sends the requests in order to a single app, wrapped in the same middleware
+/// as in production.
+pub async fn make_requests(
+    repository: Arc<dyn TodoRepository>,
+    app_config: Config,
+    requests: Vec<test::TestRequest>,
+) -> Vec<ServiceResponse> {
+    let metrics = Arc::new(Metrics::new().unwrap());
+    let rate_limiter = Arc::new(RateLimiter::new(&app_config));
+    let origins = &app_config.cors_origins;
+    let app = App::new()
+        .wrap(RateLimit::new(rate_limiter))
+        .wrap(RequestMetrics::new(metrics.clone()))
+        .wrap(Condition::new(!origins.is_empty(), cors(origins)))
+        .configure(|config| configure_app(config, repository, app_config, metrics, Arc::default()));
     let app = test::init_service(app).await;
-    let response = test::call_service(&app, request.to_request()).await;
-    response
+    let mut responses = Vec::new();
+    for request in requests {
+        let response = test::call_service(&app, request.to_request()).await;
+        responses.push(response.map_into_boxed_body());
+    }
+    responses
+}
+
+/// Starts a server serving the app over HTTP, for clients to make real
+/// requests to.
+pub fn server(repository: Arc<dyn TodoRepository>) -> actix_test::TestServer {
+    actix_test::start(move || {
+        let repository = repository.clone();
+        let metrics = Arc::new(Metrics::new().unwrap());
+        App::new().configure(|config| {
+            configure_app(config, repository, test_config(), metrics, Arc::default())
+        })
+    })
+}
+
+pub fn timestamp(value: &str) -> NaiveDateTime {
+    NaiveDateTime::parse_from_str(value, "%F %T").unwrap()
+}
+
+/// A clock which only moves when told to.
+pub struct ManualClock(Mutex<NaiveDateTime>);
+
+impl ManualClock {
+    pub fn new(now: &str) -> Self {
+        Self(Mutex::new(timestamp(now)))
+    }
+
+    pub fn advance(&self, by: Duration) {
+        *self.0.lock().unwrap() += by;
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> NaiveDateTime {
+        *self.0.lock().unwrap()
+    }
+}
+
+/// The todos inserted by `fixtures/todos.sql`.
+pub fn fixture_todos() -> Vec<Todo> {
+    vec![
+        Todo {
+            id: 1,
+            user_id: Some(ALICE),
+            title: "todo1".to_string(),
+            description: "description1".to_string(),
+            completed: false,
+            due_at: None,
+            created_at: timestamp("2024-06-01 10:00:00"),
+            updated_at: timestamp("2024-06-01 10:00:00"),
+            version: 1,
+            deleted_at: None,
+            parent_id: None,
+            recurrence: None,
+        },
+        Todo {
+            id: 2,
+            user_id: Some(ALICE),
+            title: "todo2".to_string(),
+            description: "description2".to_string(),
+            completed: true,
+            due_at: Some(timestamp("2024-06-10 12:00:00")),
+            created_at: timestamp("2024-06-02 10:00:00"),
+            updated_at: timestamp("2024-06-03 10:00:00"),
+            version: 1,
+            deleted_at: None,
+            parent_id: None,
+            recurrence: None,
+        },
+        Todo {
+            id: 3,
+            user_id: Some(ALICE),
+            title: "todo3".to_string(),
+            description: "description3".to_string(),
+            completed: false,
+            due_at: Some(timestamp("2024-06-20 12:00:00")),
+            created_at: timestamp("2024-06-03 10:00:00"),
+            updated_at: timestamp("2024-06-03 10:00:00"),
+            version: 1,
+            deleted_at: None,
+            parent_id: None,
+            recurrence: None,
+        },
+    ]
+}
+
+pub fn problem(status: StatusCode) -> Problem {
+    Problem {
+        kind: "about:blank".to_string(),
+        title: status.canonical_reason().unwrap().to_string(),
+        status: status.as_u16(),
+        detail: None,
+        errors: vec![],
+    }
+}
+
+/// Requests received by a test server, as their signature, timestamp and body.
+pub type Received = Arc<Mutex<Vec<(String, String, String)>>>;
+
+/// Starts a server standing for a webhook, answering every request with the
+/// given status.
+pub fn receiver(status: StatusCode, received: Received) -> actix_test::TestServer {
+    actix_test::start(move || {
+        let received = received.clone();
+        App::new().default_service(web::to(move |request: HttpRequest, body: String| {
+            let header = |name| {
+                let value = request.headers().get(name).unwrap();
+                value.to_str().unwrap().to_string()
+            };
+            received
+                .lock()
+                .unwrap()
+                .push((header(SIGNATURE), header(TIMESTAMP), body));
+            async move { HttpResponse::new(status) }
+        }))
+    })
 }
@@ -0,0 +1,346 @@
+use crate::error::InternalError;
+use actix_web::{
+    http::{
+        header::LOCATION,
+        uri::{Authority, PathAndQuery},
+        StatusCode,
+    },
+    rt::time,
+    web::{self, Data, ServiceConfig},
+    HttpRequest, HttpResponse,
+};
+use log::{error, info};
+use rustls::{
+    crypto::{ring, CryptoProvider},
+    pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer},
+    server::{ClientHello, ResolvesServerCert},
+    sign::CertifiedKey,
+    ServerConfig,
+};
+use std::{
+    fs,
+    future::poll_fn,
+    path::{Path, PathBuf},
+    sync::{Arc, PoisonError, RwLock},
+    task::Poll,
+    time::SystemTime,
+};
+
+/// How often the certificate files are checked for changes.
+const RELOAD_INTERVAL: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// Serves the certificate last loaded from a pair of PEM files. Reloading
+/// only affects the handshakes which follow, so connections are never dropped.
+#[derive(Debug)]
+pub struct CertResolver {
+    cert_path: PathBuf,
+    key_path: PathBuf,
+    current: RwLock<Loaded>,
+}
+
+#[derive(Debug)]
+struct Loaded {
+    key: Arc<CertifiedKey>,
+    /// Modification times of the certificate and key files when loaded.
+    modified: [Option<SystemTime>; 2],
+}
+
+impl CertResolver {
+    pub fn new(
+        cert_path: impl Into<PathBuf>,
+        key_path: impl Into<PathBuf>,
+    ) -> Result<Self, InternalError> {
+        let cert_path = cert_path.into();
+        let key_path = key_path.into();
+        let loaded = load(&cert_path, &key_path)?;
+        Ok(Self {
+            cert_path,
+            key_path,
+            current: RwLock::new(loaded),
+        })
+    }
+
+    /// Loads the certificate again, keeping the current one when the files
+    /// are invalid.
+    pub fn reload(&self) -> Result<(), InternalError> {
+        let loaded = load(&self.cert_path, &self.key_path)?;
+        *self.current.write().unwrap_or_else(PoisonError::into_inner) = loaded;
+        Ok(())
+    }
+
+    /// Loads the certificate again if either file changed since it was
+    /// loaded, returning whether it did.
+    pub fn reload_if_changed(&self) -> Result<bool, InternalError> {
+        let modified = modified(&self.cert_path, &self.key_path);
+        let current = self.current.read().unwrap_or_else(PoisonError::into_inner);
+        if current.modified == modified {
+            return Ok(false);
+        }
+        drop(current);
+        self.reload()?;
+        Ok(true)
+    }
+}
+
+impl ResolvesServerCert for CertResolver {
+    fn resolve(&self, _client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        let current = self.current.read().unwrap_or_else(PoisonError::into_inner);
+        Some(current.key.clone())
+    }
+}
+
+fn modified(cert_path: &Path, key_path: &Path) -> [Option<SystemTime>; 2] {
+    [cert_path, key_path].map(|path| fs::metadata(path).and_then(|m| m.modified()).ok())
+}
+
+/// Reads a certificate chain and its private key, checking they match.
+fn load(cert_path: &Path, key_path: &Path) -> Result<Loaded, InternalError> {
+    // Read before the files, so that a change made meanwhile is not missed
+    let modified = modified(cert_path, key_path);
+    let invalid = |path: &Path, err: &dyn std::fmt::Display| {
+        InternalError::Tls(format!("Invalid '{}': {err}", path.display()))
+    };
+    let certs = CertificateDer::pem_file_iter(cert_path)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|err| invalid(cert_path, &err))?;
+    if certs.is_empty() {
+        return Err(invalid(cert_path, &"no certificate found"));
+    }
+    let key = PrivateKeyDer::from_pem_file(key_path).map_err(|err| invalid(key_path, &err))?;
+    let key = CertifiedKey::from_der(certs, key, &provider())
+        .map_err(|err| InternalError::Tls(err.to_string()))?;
+    Ok(Loaded {
+        key: Arc::new(key),
+        modified,
+    })
+}
+
+fn provider() -> CryptoProvider {
+    ring::default_provider()
+}
+
+/// TLS settings of the server, serving the certificates of the resolver.
+/// Actix sets the ALPN protocols when binding.
+pub fn server_config(resolver: Arc<CertResolver>) -> Result<ServerConfig, InternalError> {
+    let config = ServerConfig::builder_with_provider(Arc::new(provider()))
+        .with_safe_default_protocol_versions()
+        .map_err(|err| InternalError::Tls(err.to_string()))?
+        .with_no_client_auth()
+        .with_cert_resolver(resolver);
+    Ok(config)
+}
+
+/// Reloads the certificate when its files change, or on SIGHUP on Unix.
+pub async fn reload_certificates(resolver: Arc<CertResolver>) {
+    #[cfg(unix)]
+    let mut hangup =
+        actix_web::rt::signal::unix::signal(actix_web::rt::signal::unix::SignalKind::hangup()).ok();
+    let mut interval = time::interval(RELOAD_INTERVAL);
+    loop {
+        let forced = poll_fn(|cx| {
+            #[cfg(unix)]
+            if let Some(hangup) = &mut hangup {
+                if hangup.poll_recv(cx).is_ready() {
+                    return Poll::Ready(true);
+                }
+            }
+            interval.poll_tick(cx).map(|_| false)
+        })
+        .await;
+        let result = if forced {
+            resolver.reload().map(|()| true)
+        } else {
+            resolver.reload_if_changed()
+        };
+        match result {
+            Ok(true) => info!("Reloaded the TLS certificate"),
+            Ok(false) => {}
+            Err(err) => {
+                error!("Failed to reload the TLS certificate, keeping the current one: {err}")
+            }
+        }
+    }
+}
+
+/// Port the HTTPS server listens at, for the redirect listener.
+struct HttpsPort(u16);
+
+/// Answers every request with a permanent redirect to the same URL over
+/// HTTPS, keeping the method and body.
+pub fn configure_redirect(config: &mut ServiceConfig, https_port: u16) {
+    config
+        .app_data(Data::new(HttpsPort(https_port)))
+        .default_service(web::to(redirect));
+}
+
+async fn redirect(request: HttpRequest, https_port: Data<HttpsPort>) -> HttpResponse {
+    let info = request.connection_info();
+    let Ok(authority) = info.host().parse::<Authority>() else {
+        return HttpResponse::BadRequest().finish();
+    };
+    let host = authority.host();
+    let path = request
+        .uri()
+        .path_and_query()
+        .map_or("/", PathAndQuery::as_str);
+    let location = match https_port.0 {
+        443 => format!("https://{host}{path}"),
+        port => format!("https://{host}:{port}{path}"),
+    };
+    HttpResponse::build(StatusCode::PERMANENT_REDIRECT)
+        .insert_header((LOCATION, location))
+        .finish()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::tls::{configure_redirect, server_config, CertResolver};
+    use actix_web::{
+        http::{header::LOCATION, StatusCode},
+        test::{call_service, init_service, TestRequest},
+        web, App, HttpServer,
+    };
+    use awc::{Client, Connector};
+    use rcgen::{BasicConstraints, CertificateParams, IsCa, KeyPair};
+    use rustls::{pki_types::CertificateDer, ClientConfig, RootCertStore};
+    use std::{env, fs, net::TcpListener, path::PathBuf, sync::Arc, time::SystemTime};
+
+    /// A certificate authority, and a certificate for `localhost` it signed.
+    struct Issued {
+        ca: CertificateDer<'static>,
+        cert: String,
+        key: String,
+    }
+
+    fn issue() -> Issued {
+        let ca_key = KeyPair::generate().unwrap();
+        let mut params = CertificateParams::new(Vec::new()).unwrap();
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let ca = params.self_signed(&ca_key).unwrap();
+        let key = KeyPair::generate().unwrap();
+        let params = CertificateParams::new(["localhost".to_string()]).unwrap();
+        let cert = params.signed_by(&key, &ca, &ca_key).unwrap();
+        Issued {
+            ca: ca.der().clone(),
+            cert: cert.pem(),
+            key: key.serialize_pem(),
+        }
+    }
+
+    /// Paths of certificate and key files unique to the test.
+    fn files(name: &str) -> (PathBuf, PathBuf) {
+        let prefix = format!("todo-actix-{}-{name}", std::process::id());
+        let dir = env::temp_dir();
+        (
+            dir.join(format!("{prefix}.crt")),
+            dir.join(format!("{prefix}.key")),
+        )
+    }
+
+    fn write(files: &(PathBuf, PathBuf), issued: &Issued) {
+        fs::write(&files.0, &issued.cert).unwrap();
+        fs::write(&files.1, &issued.key).unwrap();
+    }
+
+    /// A client trusting only the given authority.
+    fn client(ca: &CertificateDer<'static>) -> Client {
+        let mut roots = RootCertStore::empty();
+        roots.add(ca.clone()).unwrap();
+        let config =
+            ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+                .with_safe_default_protocol_versions()
+                .unwrap()
+                .with_root_certificates(roots)
+                .with_no_client_auth();
+        Client::builder()
+            .connector(Connector::new().rustls_0_23(Arc::new(config)))
+            .finish()
+    }
+
+    #[actix_web::test]
+    async fn reload() {
+        let first = issue();
+        let second = issue();
+        let files = files("reload");
+        write(&files, &first);
+        let resolver = Arc::new(CertResolver::new(&files.0, &files.1).unwrap());
+        assert!(!resolver.reload_if_changed().unwrap());
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = HttpServer::new(|| App::new().route("/", web::get().to(|| async { "ok" })))
+            .workers(1)
+            .listen_rustls_0_23(listener, server_config(resolver.clone()).unwrap())
+            .unwrap()
+            .run();
+        let handle = server.handle();
+        actix_web::rt::spawn(server);
+        let url = format!("https://localhost:{port}/");
+
+        let kept = client(&first.ca);
+        assert_eq!(
+            kept.get(&url).send().await.unwrap().status(),
+            StatusCode::OK
+        );
+        assert!(client(&second.ca).get(&url).send().await.is_err());
+
+        // Invalid files keep the current certificate
+        fs::write(&files.0, &second.cert).unwrap();
+        assert!(resolver.reload().is_err());
+        fs::write(&files.1, &second.key).unwrap();
+        let later = SystemTime::now() + std::time::Duration::from_secs(1);
+        fs::File::options()
+            .write(true)
+            .open(&files.1)
+            .unwrap()
+            .set_modified(later)
+            .unwrap();
+        assert!(resolver.reload_if_changed().unwrap());
+        assert!(!resolver.reload_if_changed().unwrap());
+
+        let response = client(&second.ca).get(&url).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        // Connections made before the reload are kept
+        assert_eq!(
+            kept.get(&url).send().await.unwrap().status(),
+            StatusCode::OK
+        );
+        handle.stop(false).await;
+    }
+
+    #[test]
+    fn invalid_files() {
+        let files = files("invalid");
+        let first = issue();
+        let second = issue();
+        fs::write(&files.0, &first.cert).unwrap();
+        fs::write(&files.1, &second.key).unwrap();
+        assert!(CertResolver::new(&files.0, &files.1).is_err());
+        fs::write(&files.0, "").unwrap();
+        assert!(CertResolver::new(&files.0, &files.1).is_err());
+        assert!(CertResolver::new("missing.crt", &files.1).is_err());
+    }
+
+    #[actix_web::test]
+    async fn redirect() {
+        let app = init_service(App::new().configure(|c| configure_redirect(c, 8443))).await;
+        let request = TestRequest::post()
+            .uri("/todos?page=2")
+            .insert_header(("Host", "example.com:8080"))
+            .to_request();
+        let response = call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::PERMANENT_REDIRECT);
+        assert_eq!(
+            response.headers().get(LOCATION).unwrap(),
+            "https://example.com:8443/todos?page=2"
+        );
+
+        let app = init_service(App::new().configure(|c| configure_redirect(c, 443))).await;
+        let request = TestRequest::get()
+            .uri("/")
+            .insert_header(("Host", "[::1]"))
+            .to_request();
+        let response = call_service(&app, request).await;
+        assert_eq!(response.headers().get(LOCATION).unwrap(), "https://[::1]/");
+    }
+}
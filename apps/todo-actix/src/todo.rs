@@ -1,21 +1,200 @@
-use serde::{Deserialize, Serialize};
+use crate::{
+    error::{FieldError, InternalError},
+    patch, recurrence,
+    validate::{self, Validate},
+};
+use chrono::NaiveDateTime;
+use serde::{de, Deserialize, Serialize};
+use serde_json::Value;
 use sqlx::FromRow;
+use utoipa::ToSchema;
 
-#[derive(Debug, PartialEq, Eq, Deserialize, Serialize, FromRow)]
+pub const TITLE_MAX_LENGTH: usize = 20;
+pub const DESCRIPTION_MAX_LENGTH: usize = 200;
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, FromRow, ToSchema)]
 pub struct Todo {
     pub id: i64,
+    pub user_id: Option<i64>,
+    /// The todo this one is a subtask of.
+    pub parent_id: Option<i64>,
     pub title: String,
     pub description: String,
+    pub completed: bool,
+    #[serde(with = "utc::option")]
+    pub due_at: Option<NaiveDateTime>,
+    /// iCalendar RRULE the todo recurs on, starting from its due date.
+    pub recurrence: Option<String>,
+    #[serde(with = "utc")]
+    pub created_at: NaiveDateTime,
+    #[serde(with = "utc")]
+    pub updated_at: NaiveDateTime,
+    pub version: i64,
+    #[serde(with = "utc::option")]
+    pub deleted_at: Option<NaiveDateTime>,
 }
 
-#[derive(Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[derive(Debug, PartialEq, Eq, Deserialize, Serialize, ToSchema)]
 pub struct CreateTodo {
     pub title: String,
     pub description: String,
+    #[serde(default)]
+    pub completed: bool,
+    #[serde(default, with = "utc::option")]
+    pub due_at: Option<NaiveDateTime>,
+    /// Todo to create this one as a subtask of.
+    #[serde(default)]
+    pub parent_id: Option<i64>,
+    /// iCalendar RRULE the todo recurs on, such as `FREQ=WEEKLY;BYDAY=MO`.
+    #[serde(default)]
+    pub recurrence: Option<String>,
 }
 
-#[derive(Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[derive(Debug, PartialEq, Eq, Deserialize, Serialize, ToSchema)]
 pub struct UpdateTodo {
     pub title: String,
     pub description: String,
+    #[serde(default)]
+    pub completed: bool,
+    #[serde(default, with = "utc::option")]
+    pub due_at: Option<NaiveDateTime>,
+    /// Todo to move this one under, or none to make it a top-level todo.
+    #[serde(default)]
+    pub parent_id: Option<i64>,
+    /// iCalendar RRULE the todo recurs on, or none for a one-off todo.
+    #[serde(default)]
+    pub recurrence: Option<String>,
+}
+
+impl Validate for CreateTodo {
+    fn validate(self) -> Result<Self, Vec<FieldError>> {
+        let (title, description, recurrence) =
+            validate_fields(self.title, self.description, self.recurrence, &self.due_at)?;
+        Ok(Self {
+            title,
+            description,
+            recurrence,
+            ..self
+        })
+    }
+}
+
+impl Validate for UpdateTodo {
+    fn validate(self) -> Result<Self, Vec<FieldError>> {
+        let (title, description, recurrence) =
+            validate_fields(self.title, self.description, self.recurrence, &self.due_at)?;
+        Ok(Self {
+            title,
+            description,
+            recurrence,
+            ..self
+        })
+    }
+}
+
+fn validate_fields(
+    title: String,
+    description: String,
+    recurrence: Option<String>,
+    due_at: &Option<NaiveDateTime>,
+) -> Result<(String, String, Option<String>), Vec<FieldError>> {
+    let mut errors = Vec::new();
+    let title = validate::text(&mut errors, "title", title, true, TITLE_MAX_LENGTH);
+    let description = validate::text(
+        &mut errors,
+        "description",
+        description,
+        false,
+        DESCRIPTION_MAX_LENGTH,
+    );
+    let recurrence = recurrence::validate(&mut errors, recurrence, due_at.as_ref());
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    Ok((title, description, recurrence))
+}
+
+impl From<Todo> for UpdateTodo {
+    fn from(todo: Todo) -> Self {
+        Self {
+            title: todo.title,
+            description: todo.description,
+            completed: todo.completed,
+            due_at: todo.due_at,
+            parent_id: todo.parent_id,
+            recurrence: todo.recurrence,
+        }
+    }
+}
+
+impl UpdateTodo {
+    /// The fields of a todo once a JSON Merge Patch is applied onto them,
+    /// which are left to validate. Patching a field todos do not have is
+    /// rejected rather than ignored.
+    pub fn patched(todo: Todo, patch: &Value) -> Result<Self, InternalError> {
+        let mut target = serde_json::to_value(Self::from(todo))?;
+        if let (Value::Object(fields), Value::Object(patch)) = (&target, patch) {
+            if let Some(key) = patch.keys().find(|key| !fields.contains_key(*key)) {
+                let err = de::Error::custom(format!("unknown field `{key}`"));
+                return Err(InternalError::Patch(err));
+            }
+        }
+        patch::merge(&mut target, patch);
+        serde_json::from_value(target).map_err(InternalError::Patch)
+    }
+}
+
+/// Timestamps are stored as naive UTC date times and exchanged as RFC 3339
+/// strings, accepting any offset on input.
+pub mod utc {
+    use chrono::{DateTime, NaiveDateTime, SecondsFormat};
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        value: &NaiveDateTime,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&format(value))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<NaiveDateTime, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        parse(&value).map_err(serde::de::Error::custom)
+    }
+
+    pub fn format(value: &NaiveDateTime) -> String {
+        value.and_utc().to_rfc3339_opts(SecondsFormat::AutoSi, true)
+    }
+
+    pub fn parse(value: &str) -> Result<NaiveDateTime, String> {
+        DateTime::parse_from_rfc3339(value)
+            .map(|value| value.naive_utc())
+            .map_err(|_| format!("Invalid RFC 3339 timestamp '{value}'"))
+    }
+
+    pub mod option {
+        use chrono::NaiveDateTime;
+        use serde::{Deserialize, Deserializer, Serializer};
+
+        pub fn serialize<S: Serializer>(
+            value: &Option<NaiveDateTime>,
+            serializer: S,
+        ) -> Result<S::Ok, S::Error> {
+            match value {
+                Some(value) => super::serialize(value, serializer),
+                None => serializer.serialize_none(),
+            }
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(
+            deserializer: D,
+        ) -> Result<Option<NaiveDateTime>, D::Error> {
+            let value = Option::<String>::deserialize(deserializer)?;
+            value
+                .map(|value| super::parse(&value).map_err(serde::de::Error::custom))
+                .transpose()
+        }
+    }
 }
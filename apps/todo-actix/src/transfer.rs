@@ -0,0 +1,436 @@
+use crate::{
+    error::{ApiError, FieldError, InternalError},
+    ical,
+    todo::{utc, CreateTodo, Todo},
+    validate::Validate,
+};
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use utoipa::{IntoParams, ToSchema};
+
+pub const MAX_ROWS: usize = 10_000;
+
+/// Columns of exported CSV files. Imported ones only need a `title` column,
+/// the others being optional and unknown ones ignored.
+const CSV_COLUMNS: [&str; 7] = [
+    "id",
+    "title",
+    "description",
+    "completed",
+    "due_at",
+    "created_at",
+    "updated_at",
+];
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    #[default]
+    Json,
+    Csv,
+    /// iCalendar, holding a VTODO component per todo.
+    Ics,
+}
+
+impl Format {
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Self::Json => "application/json",
+            Self::Csv => "text/csv; charset=utf-8",
+            Self::Ics => "text/calendar; charset=utf-8",
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Json => "json",
+            Self::Csv => "csv",
+            Self::Ics => "ics",
+        }
+    }
+
+    /// The format of a body with the given `Content-Type`, if it is known.
+    pub fn from_content_type(content_type: &str) -> Option<Self> {
+        let essence = content_type.split(';').next().unwrap_or_default().trim();
+        [Self::Json, Self::Csv, Self::Ics]
+            .into_iter()
+            .find(|format| {
+                let known = format.content_type().split(';').next().unwrap_or_default();
+                essence.eq_ignore_ascii_case(known)
+            })
+    }
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ExportQuery {
+    #[serde(default)]
+    #[param(inline)]
+    pub format: Format,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ImportQuery {
+    /// Format of the body, taken from its `Content-Type` when omitted.
+    #[param(inline)]
+    pub format: Option<Format>,
+}
+
+/// What became of an imported todo.
+#[derive(Debug)]
+pub enum Imported {
+    Created(Todo),
+    /// A todo with the same title, description and due date already existed,
+    /// or was imported earlier on.
+    Duplicate(Todo),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum RowStatus {
+    Created,
+    /// Matches an existing todo, which was left as it is.
+    Duplicate,
+    Invalid,
+    /// Valid, but not imported since other rows are invalid.
+    Skipped,
+}
+
+#[derive(Debug, PartialEq, Eq, Deserialize, Serialize, ToSchema)]
+pub struct RowResult {
+    /// Position of the row, from 1, not counting the header of CSV files.
+    pub row: usize,
+    pub status: RowStatus,
+    /// The created todo, or the existing one the row duplicates.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub todo: Option<Todo>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub errors: Vec<FieldError>,
+}
+
+#[derive(Debug, PartialEq, Eq, Deserialize, Serialize, ToSchema)]
+pub struct ImportResult {
+    /// Whether the todos were imported at all, which only happens when every
+    /// row is valid.
+    pub committed: bool,
+    pub created: usize,
+    pub duplicates: usize,
+    /// One result per row, in the order they were given.
+    pub rows: Vec<RowResult>,
+}
+
+impl ImportResult {
+    /// Result of an import which was rejected as some rows are invalid.
+    pub fn rejected(rows: Vec<Result<CreateTodo, Vec<FieldError>>>) -> Self {
+        let rows = rows
+            .into_iter()
+            .enumerate()
+            .map(|(index, row)| {
+                let (status, errors) = match row {
+                    Ok(_) => (RowStatus::Skipped, vec![]),
+                    Err(errors) => (RowStatus::Invalid, errors),
+                };
+                RowResult {
+                    row: index + 1,
+                    status,
+                    todo: None,
+                    errors,
+                }
+            })
+            .collect();
+        Self {
+            committed: false,
+            created: 0,
+            duplicates: 0,
+            rows,
+        }
+    }
+
+    pub fn committed(imported: Vec<Imported>) -> Self {
+        let mut result = Self {
+            committed: true,
+            created: 0,
+            duplicates: 0,
+            rows: Vec::with_capacity(imported.len()),
+        };
+        for (index, imported) in imported.into_iter().enumerate() {
+            let (status, todo) = match imported {
+                Imported::Created(todo) => {
+                    result.created += 1;
+                    (RowStatus::Created, todo)
+                }
+                Imported::Duplicate(todo) => {
+                    result.duplicates += 1;
+                    (RowStatus::Duplicate, todo)
+                }
+            };
+            result.rows.push(RowResult {
+                row: index + 1,
+                status,
+                todo: Some(todo),
+                errors: vec![],
+            });
+        }
+        result
+    }
+}
+
+/// Whether a todo to import matches an existing one.
+pub fn is_duplicate(todo: &CreateTodo, existing: &Todo) -> bool {
+    existing.title == todo.title
+        && existing.description == todo.description
+        && existing.due_at == todo.due_at
+}
+
+pub fn export(
+    format: Format,
+    todos: &[Todo],
+    now: NaiveDateTime,
+) -> Result<Vec<u8>, InternalError> {
+    match format {
+        Format::Json => Ok(serde_json::to_vec(todos)?),
+        Format::Csv => write_csv(todos),
+        Format::Ics => Ok(ical::write(todos, now).into_bytes()),
+    }
+}
+
+fn write_csv(todos: &[Todo]) -> Result<Vec<u8>, InternalError> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    writer.write_record(CSV_COLUMNS)?;
+    for todo in todos {
+        writer.write_record([
+            todo.id.to_string(),
+            todo.title.clone(),
+            todo.description.clone(),
+            todo.completed.to_string(),
+            todo.due_at.as_ref().map(utc::format).unwrap_or_default(),
+            utc::format(&todo.created_at),
+            utc::format(&todo.updated_at),
+        ])?;
+    }
+    let csv = writer
+        .into_inner()
+        .map_err(|err| csv::Error::from(err.into_error()))?;
+    Ok(csv)
+}
+
+/// Reads the todos of an imported file, each validated on its own. Fails only
+/// when the file as a whole cannot be read.
+pub fn parse(
+    format: Format,
+    body: &[u8],
+) -> Result<Vec<Result<CreateTodo, Vec<FieldError>>>, ApiError> {
+    let rows = match format {
+        Format::Json => parse_json(body)?,
+        Format::Csv => parse_csv(body)?,
+        Format::Ics => {
+            let text = std::str::from_utf8(body)
+                .map_err(|_| ApiError::BadRequest("Invalid UTF-8 in iCalendar body".to_string()))?;
+            let todos = ical::parse(text).map_err(ApiError::BadRequest)?;
+            todos.into_iter().map(Ok).collect()
+        }
+    };
+    let rows = rows
+        .into_iter()
+        .map(|row| {
+            let (todo, mut errors) = row.map_err(|err| vec![err])?;
+            match todo.validate() {
+                Ok(todo) if errors.is_empty() => Ok(todo),
+                Ok(_) => Err(errors),
+                Err(mut invalid) => {
+                    invalid.append(&mut errors);
+                    Err(invalid)
+                }
+            }
+        })
+        .collect();
+    Ok(rows)
+}
+
+/// A todo read from a row along with the errors of its fields, or the error
+/// of a row which could not be read at all.
+type Row = Result<(CreateTodo, Vec<FieldError>), FieldError>;
+
+fn parse_json(body: &[u8]) -> Result<Vec<Row>, ApiError> {
+    let values: Vec<Value> =
+        serde_json::from_slice(body).map_err(|err| ApiError::BadRequest(err.to_string()))?;
+    let rows = values
+        .into_iter()
+        .map(|value| match serde_json::from_value::<CreateTodo>(value) {
+            // Imported todos get new ids, which the parents of exported ones
+            // would not refer to
+            Ok(todo) => Ok((
+                CreateTodo {
+                    parent_id: None,
+                    ..todo
+                },
+                vec![],
+            )),
+            Err(err) => Err(FieldError::new("row", err.to_string())),
+        })
+        .collect();
+    Ok(rows)
+}
+
+fn parse_csv(body: &[u8]) -> Result<Vec<Row>, ApiError> {
+    let mut reader = csv::ReaderBuilder::new().flexible(true).from_reader(body);
+    let headers = reader
+        .headers()
+        .map_err(|err| ApiError::BadRequest(format!("Invalid CSV header: {err}")))?;
+    let column = |name: &str| {
+        headers
+            .iter()
+            .position(|header| header.trim().eq_ignore_ascii_case(name))
+    };
+    let title = column("title")
+        .ok_or_else(|| ApiError::BadRequest("CSV header has no title column".to_string()))?;
+    let description = column("description");
+    let completed = column("completed");
+    let due_at = column("due_at");
+
+    let mut rows = Vec::new();
+    for record in reader.records() {
+        let record = match record {
+            Ok(record) => record,
+            Err(err) => {
+                rows.push(Err(FieldError::new("row", err.to_string())));
+                continue;
+            }
+        };
+        let field = |index: Option<usize>| index.and_then(|index| record.get(index)).unwrap_or("");
+        let mut errors = Vec::new();
+        let todo = CreateTodo {
+            title: field(Some(title)).to_string(),
+            description: field(description).to_string(),
+            completed: match field(completed).trim().to_ascii_lowercase().as_str() {
+                "" | "false" | "0" | "no" => false,
+                "true" | "1" | "yes" => true,
+                _ => {
+                    errors.push(FieldError::new("completed", "must be true or false"));
+                    false
+                }
+            },
+            due_at: match field(due_at).trim() {
+                "" => None,
+                value => utc::parse(value)
+                    .map_err(|_| {
+                        errors.push(FieldError::new("due_at", "must be an RFC 3339 timestamp"))
+                    })
+                    .ok(),
+            },
+            parent_id: None,
+            recurrence: None,
+        };
+        rows.push(Ok((todo, errors)));
+    }
+    Ok(rows)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        error::FieldError,
+        test::{fixture_todos, timestamp},
+        todo::CreateTodo,
+        transfer::{export, parse, Format},
+    };
+
+    fn todo(title: &str, completed: bool, due_at: Option<&str>) -> CreateTodo {
+        CreateTodo {
+            title: title.to_string(),
+            description: String::new(),
+            completed,
+            due_at: due_at.map(timestamp),
+            parent_id: None,
+            recurrence: None,
+        }
+    }
+
+    #[test]
+    fn content_type() {
+        assert_eq!(
+            Format::from_content_type("text/CSV; charset=utf-8"),
+            Some(Format::Csv)
+        );
+        assert_eq!(
+            Format::from_content_type("application/json"),
+            Some(Format::Json)
+        );
+        assert_eq!(Format::from_content_type("text/plain"), None);
+    }
+
+    #[test]
+    fn csv_round_trip() {
+        let todos = fixture_todos();
+        let csv = export(Format::Csv, &todos, timestamp("2024-07-01 10:00:00")).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        let mut lines = csv.lines();
+        assert_eq!(
+            lines.next(),
+            Some("id,title,description,completed,due_at,created_at,updated_at")
+        );
+        assert_eq!(
+            lines.next(),
+            Some("1,todo1,description1,false,,2024-06-01T10:00:00Z,2024-06-01T10:00:00Z")
+        );
+
+        let rows = parse(Format::Csv, csv.as_bytes()).unwrap();
+        let expected: Vec<_> = todos
+            .into_iter()
+            .map(|todo| {
+                Ok(CreateTodo {
+                    title: todo.title,
+                    description: todo.description,
+                    completed: todo.completed,
+                    due_at: todo.due_at,
+                    parent_id: None,
+                    recurrence: None,
+                })
+            })
+            .collect();
+        assert_eq!(rows, expected);
+    }
+
+    #[test]
+    fn csv_invalid_rows() {
+        let csv = "Title,Completed,Due_At\n\
+                   \" first \",yes,2024-06-10T14:00:00+02:00\n\
+                   second\n\
+                   ,maybe,tomorrow\n";
+        let rows = parse(Format::Csv, csv.as_bytes()).unwrap();
+        assert_eq!(
+            rows,
+            vec![
+                Ok(todo("first", true, Some("2024-06-10 12:00:00"))),
+                Ok(todo("second", false, None)),
+                Err(vec![
+                    FieldError::new("title", "must not be empty"),
+                    FieldError::new("completed", "must be true or false"),
+                    FieldError::new("due_at", "must be an RFC 3339 timestamp"),
+                ]),
+            ]
+        );
+
+        assert!(parse(Format::Csv, b"name\nfirst\n").is_err());
+    }
+
+    #[test]
+    fn json_invalid_rows() {
+        let json = r#"[
+            {"title": "first", "description": "", "completed": true},
+            {"title": "second"},
+            {"title": "", "description": ""}
+        ]"#;
+        let rows = parse(Format::Json, json.as_bytes()).unwrap();
+        assert_eq!(rows[0], Ok(todo("first", true, None)));
+        assert_eq!(rows[1].as_ref().unwrap_err()[0].field, "row");
+        assert_eq!(
+            rows[2],
+            Err(vec![FieldError::new("title", "must not be empty")])
+        );
+
+        assert!(parse(Format::Json, br#"{"title": "first"}"#).is_err());
+    }
+}
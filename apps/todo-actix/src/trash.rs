@@ -0,0 +1,22 @@
+use crate::repository::TodoRepository;
+use actix_web::rt::time;
+use chrono::{Duration, Utc};
+use log::{error, info};
+use std::sync::Arc;
+
+const PURGE_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60 * 60);
+
+/// Purges todos that have been in the trash for longer than `retention`
+/// seconds, once at startup and then every hour.
+pub async fn purge_periodically(repository: Arc<dyn TodoRepository>, retention: i64) {
+    let mut interval = time::interval(PURGE_INTERVAL);
+    loop {
+        interval.tick().await;
+        let before = (Utc::now() - Duration::seconds(retention)).naive_utc();
+        match repository.purge_trash(before).await {
+            Ok(0) => {}
+            Ok(purged) => info!("Purged {purged} todos from the trash"),
+            Err(err) => error!("Failed to purge the trash: {err}"),
+        }
+    }
+}
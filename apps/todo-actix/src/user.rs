@@ -0,0 +1,84 @@
+use crate::{
+    error::FieldError,
+    todo::utc,
+    validate::{self, Validate},
+};
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use utoipa::ToSchema;
+
+pub const USERNAME_MIN_LENGTH: usize = 3;
+pub const USERNAME_MAX_LENGTH: usize = 32;
+pub const PASSWORD_MIN_LENGTH: usize = 8;
+pub const PASSWORD_MAX_LENGTH: usize = 128;
+
+pub const USERNAME_TAKEN: &str = "Username already taken";
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, FromRow, ToSchema)]
+pub struct User {
+    pub id: i64,
+    pub username: String,
+    #[serde(with = "utc")]
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Debug, PartialEq, Eq, Deserialize, Serialize, ToSchema)]
+pub struct Credentials {
+    pub username: String,
+    pub password: String,
+}
+
+#[derive(Debug, PartialEq, Eq, Deserialize, Serialize, ToSchema)]
+pub struct Token {
+    pub access_token: String,
+    pub token_type: String,
+    pub expires_in: i64,
+}
+
+impl Validate for Credentials {
+    fn validate(self) -> Result<Self, Vec<FieldError>> {
+        let mut errors = Vec::new();
+        let username = validate::text(
+            &mut errors,
+            "username",
+            self.username,
+            true,
+            USERNAME_MAX_LENGTH,
+        );
+        if !username.is_empty() && username.chars().count() < USERNAME_MIN_LENGTH {
+            errors.push(FieldError::new(
+                "username",
+                format!("must be at least {USERNAME_MIN_LENGTH} characters long"),
+            ));
+        }
+        if !username
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'))
+        {
+            errors.push(FieldError::new(
+                "username",
+                "must only contain letters, digits, '_', '-' or '.'",
+            ));
+        }
+
+        // Passwords are taken verbatim, whitespace included
+        let length = self.password.chars().count();
+        if !(PASSWORD_MIN_LENGTH..=PASSWORD_MAX_LENGTH).contains(&length) {
+            errors.push(FieldError::new(
+                "password",
+                format!(
+                    "must be between {PASSWORD_MIN_LENGTH} and {PASSWORD_MAX_LENGTH} characters long"
+                ),
+            ));
+        }
+
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+        Ok(Self {
+            username,
+            password: self.password,
+        })
+    }
+}
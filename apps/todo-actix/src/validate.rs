@@ -0,0 +1,54 @@
+use crate::error::FieldError;
+
+pub trait Validate: Sized {
+    /// Normalizes the value and checks it, reporting every invalid field at once.
+    fn validate(self) -> Result<Self, Vec<FieldError>>;
+}
+
+/// Trims surrounding whitespace and checks the remaining length in characters.
+pub fn text(
+    errors: &mut Vec<FieldError>,
+    field: &str,
+    value: String,
+    required: bool,
+    max_length: usize,
+) -> String {
+    let value = value.trim().to_string();
+    if required && value.is_empty() {
+        errors.push(FieldError::new(field, "must not be empty"));
+    }
+    if value.chars().count() > max_length {
+        errors.push(FieldError::new(
+            field,
+            format!("must be at most {max_length} characters long"),
+        ));
+    }
+    value
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{error::FieldError, validate::text};
+
+    #[test]
+    fn text_trims() {
+        let mut errors = Vec::new();
+        let value = text(&mut errors, "title", "  title \n".to_string(), true, 5);
+        assert_eq!(value, "title");
+        assert_eq!(errors, vec![]);
+    }
+
+    #[test]
+    fn text_invalid() {
+        let mut errors = Vec::new();
+        text(&mut errors, "title", "   ".to_string(), true, 5);
+        text(&mut errors, "description", "ééééé€".to_string(), false, 5);
+        assert_eq!(
+            errors,
+            vec![
+                FieldError::new("title", "must not be empty"),
+                FieldError::new("description", "must be at most 5 characters long"),
+            ]
+        );
+    }
+}
@@ -0,0 +1,589 @@
+use crate::{
+    auth,
+    error::{FieldError, InternalError},
+    history::TodoEvent,
+    reminder::ReminderEvent,
+    repository::TodoRepository,
+    todo::utc,
+    validate::{self, Validate},
+};
+use actix_tls::connect::{ConnectError, ConnectInfo, Connection, Connector as TcpConnector};
+use actix_web::{
+    dev::{forward_ready, Service},
+    http::{header::CONTENT_TYPE, Uri},
+    rt::{net::TcpStream, time},
+};
+use awc::{Client, ClientRequest, Connector};
+use chrono::{Duration, NaiveDateTime, SubsecRound, Utc};
+use futures_util::future::{join_all, LocalBoxFuture};
+use hmac::Mac;
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt::Write,
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    rc::Rc,
+    str::FromStr,
+    sync::Arc,
+};
+use utoipa::ToSchema;
+
+pub const URL_MAX_LENGTH: usize = 2048;
+
+/// Headers sent along with every delivery.
+pub const WEBHOOK_ID: &str = "X-Webhook-Id";
+pub const DELIVERY_ID: &str = "X-Webhook-Delivery";
+pub const TIMESTAMP: &str = "X-Webhook-Timestamp";
+pub const SIGNATURE: &str = "X-Webhook-Signature";
+/// Header sent along with reminders, holding the id of the reminder.
+pub const REMINDER_ID: &str = "X-Webhook-Reminder";
+
+/// Attempts made at a delivery before it is given up as a dead letter.
+pub const MAX_ATTEMPTS: i64 = 10;
+/// Delay before the first retry, doubled after every failed attempt.
+const RETRY_DELAY: i64 = 30;
+/// Deliveries attempted at once by the worker.
+const BATCH_SIZE: u32 = 50;
+const DELIVERY_INTERVAL: std::time::Duration = std::time::Duration::from_secs(5);
+const DELIVERY_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// Networks denied by default: the loopback, link-local and unspecified
+/// addresses, which reach the server itself or its cloud metadata service.
+const DENIED_NETWORKS: [Network; 6] = [
+    Network::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 0)), 8),
+    Network::new(IpAddr::V4(Ipv4Addr::new(169, 254, 0, 0)), 16),
+    Network::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 8),
+    Network::new(IpAddr::V6(Ipv6Addr::LOCALHOST), 128),
+    Network::new(IpAddr::V6(Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 0)), 10),
+    Network::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), 128),
+];
+
+/// URL the events of a user's todos are posted to.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, ToSchema)]
+pub struct Webhook {
+    pub id: i64,
+    pub url: String,
+    /// Secret the payloads are signed with, generated by the server. Only
+    /// sent once, along with the created webhook.
+    #[serde(skip)]
+    pub secret: String,
+    #[serde(with = "utc")]
+    pub created_at: NaiveDateTime,
+}
+
+/// A webhook just created, along with its secret.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, ToSchema)]
+pub struct CreatedWebhook {
+    #[serde(flatten)]
+    pub webhook: Webhook,
+    /// Secret the payloads are signed with, generated by the server.
+    pub secret: String,
+}
+
+#[derive(Debug, PartialEq, Eq, Deserialize, Serialize, ToSchema)]
+pub struct CreateWebhook {
+    pub url: String,
+}
+
+/// What a delivery posts to its webhook.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, ToSchema)]
+#[serde(untagged)]
+pub enum Payload {
+    /// A change recorded in the history of a todo.
+    Event(TodoEvent),
+    /// A reminder of a todo which fired.
+    Reminder(ReminderEvent),
+}
+
+/// An event queued for a webhook. Deliveries are removed once they succeed.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, ToSchema)]
+pub struct Delivery {
+    pub id: i64,
+    pub webhook_id: i64,
+    /// The event posted to the webhook.
+    pub event: Payload,
+    pub attempts: i64,
+    #[serde(with = "utc")]
+    pub next_attempt_at: NaiveDateTime,
+    /// Why the last attempt failed.
+    pub last_error: Option<String>,
+    /// When the delivery was given up, making it a dead letter.
+    #[serde(with = "utc::option")]
+    pub failed_at: Option<NaiveDateTime>,
+}
+
+/// A range of IP addresses, written like `10.0.0.0/8`, or as a single address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Network {
+    address: IpAddr,
+    prefix: u8,
+}
+
+impl Network {
+    const fn new(address: IpAddr, prefix: u8) -> Self {
+        Self { address, prefix }
+    }
+
+    pub fn contains(&self, address: IpAddr) -> bool {
+        match (self.address, address.to_canonical()) {
+            (IpAddr::V4(network), IpAddr::V4(address)) => {
+                let shift = 32 - u32::from(self.prefix);
+                let bits = |address: Ipv4Addr| u32::from(address).checked_shr(shift).unwrap_or(0);
+                bits(network) == bits(address)
+            }
+            (IpAddr::V6(network), IpAddr::V6(address)) => {
+                let shift = 128 - u32::from(self.prefix);
+                let bits = |address: Ipv6Addr| u128::from(address).checked_shr(shift).unwrap_or(0);
+                bits(network) == bits(address)
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for Network {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (address, prefix) = match value.split_once('/') {
+            Some((address, prefix)) => (address, Some(prefix)),
+            None => (value, None),
+        };
+        let address: IpAddr = address.parse().map_err(|_| ())?;
+        let max = if address.is_ipv4() { 32 } else { 128 };
+        let prefix = prefix.map_or(Ok(max), str::parse).map_err(|_| ())?;
+        if prefix > max {
+            return Err(());
+        }
+        Ok(Self::new(address, prefix))
+    }
+}
+
+/// Addresses webhooks may be delivered to: any but those in a denied network,
+/// unless also in an allowed one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AddressPolicy {
+    pub denied: Vec<Network>,
+    pub allowed: Vec<Network>,
+}
+
+impl AddressPolicy {
+    pub fn permits(&self, address: IpAddr) -> bool {
+        let within = |networks: &[Network]| networks.iter().any(|net| net.contains(address));
+        !within(&self.denied) || within(&self.allowed)
+    }
+
+    /// Whether a webhook URL may be delivered to, as far as can be told
+    /// without resolving its host, which is only checked on delivery.
+    pub fn permits_url(&self, url: &str) -> bool {
+        let host = url.parse::<Uri>().ok().and_then(|uri| {
+            let host = uri.host()?.trim_start_matches('[').trim_end_matches(']');
+            host.parse().ok()
+        });
+        host.is_none_or(|address| self.permits(address))
+    }
+}
+
+impl Default for AddressPolicy {
+    fn default() -> Self {
+        Self {
+            denied: DENIED_NETWORKS.to_vec(),
+            allowed: Vec::new(),
+        }
+    }
+}
+
+/// Connects like the default connector, but drops connections to addresses
+/// the policy denies before anything is sent. Checking the address connected
+/// to rather than the host of the URL covers hosts resolving to them.
+#[derive(Clone)]
+struct PolicyConnector<S> {
+    connector: S,
+    policy: Rc<AddressPolicy>,
+}
+
+impl<S> Service<ConnectInfo<Uri>> for PolicyConnector<S>
+where
+    S: Service<ConnectInfo<Uri>, Response = Connection<Uri, TcpStream>, Error = ConnectError>,
+    S::Future: 'static,
+{
+    type Response = Connection<Uri, TcpStream>;
+    type Error = ConnectError;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(connector);
+
+    fn call(&self, info: ConnectInfo<Uri>) -> Self::Future {
+        let connection = self.connector.call(info);
+        let policy = self.policy.clone();
+        Box::pin(async move {
+            let connection = connection.await?;
+            let address = connection.io_ref().peer_addr().map_err(ConnectError::Io)?;
+            if !policy.permits(address.ip()) {
+                let message = format!("address {} is denied", address.ip());
+                let err = io::Error::new(io::ErrorKind::PermissionDenied, message);
+                return Err(ConnectError::Io(err));
+            }
+            Ok(connection)
+        })
+    }
+}
+
+impl Validate for CreateWebhook {
+    fn validate(self) -> Result<Self, Vec<FieldError>> {
+        let mut errors = Vec::new();
+        let url = validate::text(&mut errors, "url", self.url, true, URL_MAX_LENGTH);
+        let valid = url.parse::<Uri>().is_ok_and(|uri| {
+            matches!(uri.scheme_str(), Some("http" | "https")) && uri.host().is_some()
+        });
+        if !url.is_empty() && !valid {
+            errors.push(FieldError::new("url", "must be an absolute HTTP(S) URL"));
+        }
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+        Ok(Self { url })
+    }
+}
+
+/// Signs a payload as `sha256=hex(HMAC-SHA256(secret, "{timestamp}.{body}"))`,
+/// so that receivers can tell it comes from the server and reject replays.
+pub fn sign(secret: &str, timestamp: i64, body: &[u8]) -> String {
+    let timestamp = timestamp.to_string();
+    let mac = auth::hmac_sha256(secret, &[timestamp.as_bytes(), b".", body]);
+    let signature = mac.finalize().into_bytes();
+    signature
+        .iter()
+        .fold(String::from("sha256="), |mut hex, byte| {
+            let _ = write!(hex, "{byte:02x}");
+            hex
+        })
+}
+
+/// Delay before the next attempt at a delivery which failed `attempts` times.
+pub fn backoff(attempts: i64) -> Duration {
+    let exponent = u32::try_from(attempts.saturating_sub(1))
+        .unwrap_or(0)
+        .min(20);
+    Duration::seconds(RETRY_DELAY << exponent)
+}
+
+/// Client delivering webhooks to the addresses the policy permits.
+pub fn client(policy: &AddressPolicy) -> Client {
+    let connector = PolicyConnector {
+        connector: TcpConnector::default().service(),
+        policy: Rc::new(policy.clone()),
+    };
+    Client::builder()
+        .connector(Connector::new().connector(connector))
+        .timeout(DELIVERY_TIMEOUT)
+        .finish()
+}
+
+/// Attempts every delivery due at the given time, returning how many
+/// succeeded. Failed ones are retried later with an exponential backoff, until
+/// they are given up after `MAX_ATTEMPTS`.
+pub async fn deliver_due(
+    repository: &dyn TodoRepository,
+    client: &Client,
+    now: NaiveDateTime,
+) -> Result<usize, InternalError> {
+    let deliveries = repository.due_deliveries(now, BATCH_SIZE).await?;
+    let attempts = deliveries
+        .iter()
+        .map(|(webhook, delivery)| deliver(client, webhook, delivery, now));
+    let results = join_all(attempts).await;
+
+    let mut delivered = 0;
+    for ((_, delivery), result) in deliveries.iter().zip(results) {
+        match result {
+            Ok(()) => {
+                repository.complete_delivery(delivery.id).await?;
+                delivered += 1;
+            }
+            Err(err) => {
+                let attempts = delivery.attempts + 1;
+                let retry_at = (attempts < MAX_ATTEMPTS).then(|| now + backoff(attempts));
+                repository
+                    .fail_delivery(delivery.id, &err, retry_at)
+                    .await?;
+            }
+        }
+    }
+    Ok(delivered)
+}
+
+async fn deliver(
+    client: &Client,
+    webhook: &Webhook,
+    delivery: &Delivery,
+    now: NaiveDateTime,
+) -> Result<(), String> {
+    let body = serde_json::to_vec(&delivery.event).map_err(|err| err.to_string())?;
+    let mut request =
+        signed_request(client, webhook, &body, now).insert_header((DELIVERY_ID, delivery.id));
+    if let Payload::Reminder(event) = &delivery.event {
+        request = request.insert_header((REMINDER_ID, event.reminder.id));
+    }
+    send(request, body).await
+}
+
+/// Prepares a `POST` of a JSON body to a webhook, signed at the given time.
+fn signed_request(
+    client: &Client,
+    webhook: &Webhook,
+    body: &[u8],
+    now: NaiveDateTime,
+) -> ClientRequest {
+    let timestamp = now.and_utc().timestamp();
+    client
+        .post(&webhook.url)
+        .insert_header((CONTENT_TYPE, "application/json"))
+        .insert_header((WEBHOOK_ID, webhook.id))
+        .insert_header((TIMESTAMP, timestamp))
+        .insert_header((SIGNATURE, sign(&webhook.secret, timestamp, body)))
+}
+
+/// Sends a request to a webhook, failing unless it answers with a 2xx status.
+async fn send(request: ClientRequest, body: Vec<u8>) -> Result<(), String> {
+    let response = request
+        .send_body(body)
+        .await
+        .map_err(|err| err.to_string())?;
+    let status = response.status();
+    if !status.is_success() {
+        return Err(format!("Unexpected response status {status}"));
+    }
+    Ok(())
+}
+
+/// Delivers the queued events every few seconds.
+pub async fn deliver_periodically(repository: Arc<dyn TodoRepository>, policy: AddressPolicy) {
+    let client = client(&policy);
+    let mut interval = time::interval(DELIVERY_INTERVAL);
+    loop {
+        interval.tick().await;
+        let now = Utc::now().naive_utc().trunc_subsecs(0);
+        match deliver_due(repository.as_ref(), &client, now).await {
+            Ok(0) => {}
+            Ok(delivered) => info!("Delivered {delivered} webhook events"),
+            Err(err) => error!("Failed to deliver webhook events: {err}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        etag::IfMatch,
+        history::EventKind,
+        subtask::DeletePolicy,
+        test::{receiver, repositories, test_config, timestamp, Received, ALICE},
+        webhook::{
+            backoff, client, deliver_due, sign, AddressPolicy, CreateWebhook, Network, MAX_ATTEMPTS,
+        },
+    };
+    use actix_web::http::StatusCode;
+    use chrono::Duration;
+    use sqlx::SqlitePool;
+    use tokio::task::LocalSet;
+
+    #[test]
+    fn signature() {
+        assert_eq!(
+            sign("secret", 1_700_000_000, br#"{"a":1}"#),
+            "sha256=49f24e537407743fa4a0242bb63b94b9a47ee99cbbe071ccd8a22550ae411686"
+        );
+    }
+
+    #[test]
+    fn exponential_backoff() {
+        let delays: Vec<_> = (1..=4)
+            .map(|attempts| backoff(attempts).num_seconds())
+            .collect();
+        assert_eq!(delays, [30, 60, 120, 240]);
+    }
+
+    #[sqlx::test(fixtures("test/fixtures/users.sql", "test/fixtures/todos.sql"))]
+    async fn deliver_signed(pool: SqlitePool) {
+        LocalSet::new()
+            .run_until(async move {
+                for repository in repositories(pool).await {
+                    let received = Received::default();
+                    let server = receiver(StatusCode::NO_CONTENT, received.clone());
+                    let webhook = repository
+                        .create_webhook(
+                            ALICE,
+                            CreateWebhook {
+                                url: server.url("/hook"),
+                            },
+                            "secret",
+                        )
+                        .await
+                        .unwrap();
+                    repository
+                        .delete_todo(ALICE, 1, &IfMatch::Any, DeletePolicy::Reject)
+                        .await
+                        .unwrap();
+
+                    let now = timestamp("2100-01-01 00:00:00");
+                    let delivered = deliver_due(
+                        repository.as_ref(),
+                        &client(&test_config().webhook_policy),
+                        now,
+                    )
+                    .await;
+                    assert_eq!(delivered.unwrap(), 1);
+                    assert_eq!(repository.due_deliveries(now, 10).await.unwrap(), vec![]);
+
+                    let (signature, timestamp, body) = received.lock().unwrap().pop().unwrap();
+                    assert_eq!(timestamp, now.and_utc().timestamp().to_string());
+                    let expected =
+                        sign(&webhook.secret, timestamp.parse().unwrap(), body.as_bytes());
+                    assert_eq!(signature, expected);
+                    let event: serde_json::Value = serde_json::from_str(&body).unwrap();
+                    assert_eq!(
+                        event["kind"],
+                        serde_json::to_value(EventKind::Delete).unwrap()
+                    );
+                    assert_eq!(event["todo_id"], 1);
+                }
+            })
+            .await;
+    }
+
+    #[test]
+    fn address_policy() {
+        let network = |value: &str| value.parse::<Network>();
+        for value in [
+            "10.0.0.0/33",
+            "::/129",
+            "10.0.0.0/",
+            "example.com",
+            "10.0.0.0/8/8",
+        ] {
+            assert_eq!(network(value), Err(()), "{value}");
+        }
+
+        let policy = AddressPolicy::default();
+        let address = |value: &str| value.parse().unwrap();
+        for denied in [
+            "127.0.0.1",
+            "127.1.2.3",
+            "169.254.169.254",
+            "0.0.0.0",
+            "::1",
+            "fe80::1",
+        ] {
+            assert!(!policy.permits(address(denied)), "{denied}");
+        }
+        assert!(!policy.permits(address("::ffff:127.0.0.1")));
+        for permitted in ["93.184.216.34", "10.0.0.1", "128.0.0.1", "2001:db8::1"] {
+            assert!(policy.permits(address(permitted)), "{permitted}");
+        }
+
+        let policy = AddressPolicy {
+            denied: vec![network("10.0.0.0/8").unwrap()],
+            allowed: vec![network("10.1.0.0/16").unwrap()],
+        };
+        assert!(!policy.permits(address("10.2.0.1")));
+        assert!(policy.permits(address("10.1.0.1")));
+        assert!(policy.permits(address("127.0.0.1")));
+
+        let policy = AddressPolicy::default();
+        assert!(!policy.permits_url("http://127.0.0.1:8080/hook"));
+        assert!(!policy.permits_url("http://[::1]/hook"));
+        assert!(policy.permits_url("https://example.com/hook"));
+    }
+
+    #[sqlx::test(fixtures("test/fixtures/users.sql", "test/fixtures/todos.sql"))]
+    async fn deliver_denied(pool: SqlitePool) {
+        LocalSet::new()
+            .run_until(async move {
+                for repository in repositories(pool).await {
+                    let received = Received::default();
+                    let server = receiver(StatusCode::NO_CONTENT, received.clone());
+                    // A name resolving to a denied address
+                    let url = server.url("/hook").replace("127.0.0.1", "localhost");
+                    repository
+                        .create_webhook(ALICE, CreateWebhook { url }, "secret")
+                        .await
+                        .unwrap();
+                    repository
+                        .delete_todo(ALICE, 1, &IfMatch::Any, DeletePolicy::Reject)
+                        .await
+                        .unwrap();
+
+                    let now = timestamp("2100-01-01 00:00:00");
+                    let client = client(&AddressPolicy::default());
+                    let delivered = deliver_due(repository.as_ref(), &client, now).await;
+                    assert_eq!(delivered.unwrap(), 0);
+                    assert_eq!(received.lock().unwrap().len(), 0);
+
+                    let due = repository
+                        .due_deliveries(now + backoff(1), 10)
+                        .await
+                        .unwrap();
+                    let error = due[0].1.last_error.as_deref().unwrap();
+                    assert!(error.contains("is denied"), "{error}");
+                }
+            })
+            .await;
+    }
+
+    #[sqlx::test(fixtures("test/fixtures/users.sql", "test/fixtures/todos.sql"))]
+    async fn retry_until_dead(pool: SqlitePool) {
+        LocalSet::new()
+            .run_until(async move {
+                for repository in repositories(pool).await {
+                    let received = Received::default();
+                    let server = receiver(StatusCode::SERVICE_UNAVAILABLE, received.clone());
+                    let webhook = repository
+                        .create_webhook(
+                            ALICE,
+                            CreateWebhook {
+                                url: server.url("/hook"),
+                            },
+                            "secret",
+                        )
+                        .await
+                        .unwrap();
+                    repository
+                        .delete_todo(ALICE, 1, &IfMatch::Any, DeletePolicy::Reject)
+                        .await
+                        .unwrap();
+
+                    let mut now = timestamp("2100-01-01 00:00:00");
+                    for attempts in 1..=MAX_ATTEMPTS {
+                        let delivered = deliver_due(
+                            repository.as_ref(),
+                            &client(&test_config().webhook_policy),
+                            now,
+                        )
+                        .await;
+                        assert_eq!(delivered.unwrap(), 0);
+                        // Not retried before the backoff
+                        let due = repository.due_deliveries(now, 10).await.unwrap();
+                        assert_eq!(due, vec![]);
+                        now += backoff(attempts);
+                    }
+                    assert_eq!(received.lock().unwrap().len(), MAX_ATTEMPTS as usize);
+
+                    now += Duration::days(365);
+                    assert_eq!(repository.due_deliveries(now, 10).await.unwrap(), vec![]);
+                    let dead = repository
+                        .list_dead_letters(ALICE, webhook.id)
+                        .await
+                        .unwrap();
+                    assert_eq!(dead.len(), 1);
+                    assert_eq!(dead[0].attempts, MAX_ATTEMPTS);
+                    assert_eq!(
+                        dead[0].last_error.as_deref(),
+                        Some("Unexpected response status 503 Service Unavailable")
+                    );
+                }
+            })
+            .await;
+    }
+}